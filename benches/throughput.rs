@@ -0,0 +1,148 @@
+//! Decode, encode and checksum throughput
+//!
+//! ```text
+//! cargo bench
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use electricui_embedded::decoder::decode_in_place;
+use electricui_embedded::encoder;
+use electricui_embedded::prelude::*;
+use electricui_embedded::wire::checksum::{checksum, checksum_of_parts};
+use electricui_embedded::wire::limits::ProtocolLimits;
+use std::hint::black_box;
+
+const PAYLOAD_SIZES: [usize; 3] = [4, 64, 1000];
+const FRAMES: usize = 32;
+const FRAME_CAPACITY: usize = Framing::max_encoded_len(Packet::<&[u8]>::MAX_PACKET_SIZE) + 1;
+
+/// Writes a U8 `speed` packet, returns its size
+fn packet(payload: &[u8], buf: &mut [u8]) -> usize {
+    let mut p = Packet::new_unchecked(&mut buf[..]);
+    p.set_typ(MessageType::U8);
+    p.set_internal(false);
+    p.set_offset(false);
+    p.set_msg_id(MessageId::new(b"speed").unwrap()).unwrap();
+    p.set_response(false);
+    p.set_acknum(AckNum::NONE);
+    p.set_payload(payload).unwrap();
+    p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+    p.wire_size().unwrap()
+}
+
+fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| i as u8).collect()
+}
+
+/// `FRAMES` back to back frames
+fn stream(payload_size: usize) -> Vec<u8> {
+    let mut raw = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+    let size = packet(&payload(payload_size), &mut raw);
+    let mut frame = [0_u8; FRAME_CAPACITY];
+    let frame_size = Framing::encode_buf(&raw[..size], &mut frame);
+    frame[..frame_size].repeat(FRAMES)
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for size in PAYLOAD_SIZES {
+        let input = stream(size);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function(format!("bytes/{}", size), |b| {
+            let mut storage = [0_u8; ProtocolLimits::DECODER_STORAGE_SIZE];
+            let mut dec = Decoder::new(&mut storage);
+            b.iter(|| {
+                for byte in input.iter() {
+                    black_box(dec.decode(*byte).unwrap());
+                }
+            })
+        });
+        group.bench_function(format!("chunk/{}", size), |b| {
+            let mut storage = [0_u8; ProtocolLimits::DECODER_STORAGE_SIZE];
+            let mut dec = Decoder::new(&mut storage);
+            b.iter(|| {
+                let summary = dec.feed_chunk(&input, |p| {
+                    black_box(p);
+                });
+                assert_eq!(summary.packets, FRAMES);
+            })
+        });
+        group.bench_function(format!("in_place/{}", size), |b| {
+            b.iter_batched_ref(
+                || input.clone(),
+                |buf| {
+                    for p in decode_in_place(buf) {
+                        black_box(p.unwrap());
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in PAYLOAD_SIZES {
+        let payload = payload(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(format!("frame/{}", size), |b| {
+            let mut raw = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+            let mut frame = [0_u8; FRAME_CAPACITY];
+            b.iter(|| {
+                let size = packet(black_box(&payload), &mut raw);
+                black_box(Framing::encode_buf(&raw[..size], &mut frame))
+            })
+        });
+        group.bench_function(format!("stream/{}", size), |b| {
+            let id = MessageId::new(b"speed").unwrap();
+            let mut frame = [0_u8; FRAME_CAPACITY];
+            b.iter(|| {
+                let mut len = 0;
+                encoder::encode_with::<(), _>(
+                    id,
+                    MessageType::U8,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    black_box(&payload),
+                    |bytes| {
+                        frame[len..len + bytes.len()].copy_from_slice(bytes);
+                        len += bytes.len();
+                        Ok(())
+                    },
+                )
+                .unwrap();
+                black_box(&frame[..len]);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc");
+    for size in PAYLOAD_SIZES {
+        let mut raw = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+        let wire_size = packet(&payload(size), &mut raw);
+        let covered = &raw[..wire_size - Packet::<&[u8]>::CHECKSUM_SIZE];
+        group.throughput(Throughput::Bytes(covered.len() as u64));
+        group.bench_function(format!("contiguous/{}", size), |b| {
+            b.iter(|| checksum(black_box(covered)))
+        });
+        group.bench_function(format!("parts/{}", size), |b| {
+            b.iter(|| {
+                checksum_of_parts(
+                    black_box(&covered[..3]),
+                    black_box(&covered[3..8]),
+                    black_box(&covered[8..]),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode, crc);
+criterion_main!(benches);
@@ -1,46 +1,20 @@
 //! Simple host-side protocol example
 #![deny(warnings, clippy::all)]
 
-use byteorder::ReadBytesExt;
 use electricui_embedded::prelude::*;
-use err_derive::Error;
 use serial::prelude::*;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
-    mpsc::{self, channel},
+    mpsc::channel,
     Arc, Mutex,
 };
 use std::time::Duration;
 use std::{process, str, thread};
 use structopt::StructOpt;
 
-#[derive(Debug, Error)]
-enum Error {
-    #[error(display = "Control-C error")]
-    Ctrlc(#[error(source)] ctrlc::Error),
-
-    #[error(display = "Serial error")]
-    Serial(#[error(source)] serial::Error),
-
-    #[error(display = "EUI packet error")]
-    Packet(#[source] electricui_embedded::wire::packet::Error),
-
-    #[error(display = "EUI framing error")]
-    Framing(#[source] electricui_embedded::wire::framing::Error),
-
-    #[error(display = "EUI decoder error")]
-    Decoder(#[source] electricui_embedded::decoder::Error),
-
-    #[error(display = "IO error")]
-    Io(#[source] io::Error),
-
-    #[error(display = "Recv timeout error")]
-    RecvTimeout(#[source] mpsc::RecvTimeoutError),
-
-    #[error(display = "Thread join error")]
-    Join,
-}
+/// The library's error types implement `core::error::Error`
+type Error = Box<dyn std::error::Error>;
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = "ElectricUI host example.")]
@@ -97,11 +71,14 @@ fn main() -> Result<(), Error> {
                 continue;
             };
 
-            match rd.read_u8() {
-                Ok(b) => match dec.decode(b) {
+            let mut byte = [0_u8];
+            match rd.read_exact(&mut byte) {
+                Ok(()) => match dec.decode(byte[0]) {
                     Ok(Some(pkt)) => {
                         println!("<< {}", pkt);
-                        tx.send(pkt.as_ref().to_vec()).unwrap();
+                        let mut bytes = vec![0_u8; pkt.wire_size()];
+                        pkt.write_to(&mut bytes);
+                        tx.send(bytes).unwrap();
                     }
                     Err(e) => eprint!("{}", e),
                     _ => (),
@@ -158,7 +135,7 @@ fn main() -> Result<(), Error> {
         }
     }
 
-    sender.join().map_err(|_| Error::Join)?;
+    sender.join().map_err(|_| "Thread join error")?;
 
     Ok(())
 }
@@ -173,24 +150,37 @@ enum State {
     Done,
 }
 
-fn board_id_req(buf: &mut [u8]) -> Result<usize, Error> {
-    let mut pkt = [0_u8; 6];
-    let mut p = Packet::new_unchecked(&mut pkt[..]);
-    p.set_data_length(0)?;
-    p.set_typ(MessageType::U16);
-    p.set_internal(true);
-    p.set_offset(false);
-    p.set_id_length(1)?;
-    p.set_response(true);
-    p.set_acknum(0);
-    p.msg_id_mut()?
-        .copy_from_slice(MessageId::INTERNAL_BOARD_ID.as_bytes());
-    p.set_checksum(p.compute_checksum()?)?;
-    println!("Requesting board ID");
+/// Frames a request, an internal one when `id` is internal
+fn request(
+    id: MessageId<'_>,
+    typ: MessageType,
+    payload: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut pkt = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+    let size = Packet::builder(id, typ)
+        .internal(id.is_internal())
+        .response(true)
+        .payload(payload)
+        .build_into(&mut pkt)?;
+    let p = Packet::new_unchecked(&pkt[..size]);
+    println!(">> {p}");
+    Ok(Framing::encode_buf(p.as_ref(), buf))
+}
+
+fn query(id: MessageId<'_>, typ: MessageType, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut pkt = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+    let size = Packet::query_into(&mut pkt, id, typ)?;
+    let p = Packet::new_unchecked(&pkt[..size]);
     println!(">> {p}");
     Ok(Framing::encode_buf(p.as_ref(), buf))
 }
 
+fn board_id_req(buf: &mut [u8]) -> Result<usize, Error> {
+    println!("Requesting board ID");
+    query(MessageId::INTERNAL_BOARD_ID, MessageType::U16, buf)
+}
+
 fn board_id_resp(buf: &[u8]) -> Result<(), Error> {
     let p = Packet::new(buf)?;
     let id = p.payload()?;
@@ -199,21 +189,8 @@ fn board_id_resp(buf: &[u8]) -> Result<(), Error> {
 }
 
 fn name_req(buf: &mut [u8]) -> Result<usize, Error> {
-    let mut pkt = [0_u8; 9];
-    let mut p = Packet::new_unchecked(&mut pkt[..]);
-    p.set_data_length(0)?;
-    p.set_typ(MessageType::Callback);
-    p.set_internal(false);
-    p.set_offset(false);
-    p.set_id_length(4)?;
-    p.set_response(true);
-    p.set_acknum(0);
-    p.msg_id_mut()?
-        .copy_from_slice(MessageId::BOARD_NAME.as_bytes());
-    p.set_checksum(p.compute_checksum()?)?;
     println!("Requesting name");
-    println!(">> {p}");
-    Ok(Framing::encode_buf(p.as_ref(), buf))
+    query(MessageId::BOARD_NAME, MessageType::Callback, buf)
 }
 
 fn name_resp(buf: &[u8]) -> Result<(), Error> {
@@ -228,21 +205,8 @@ fn name_resp(buf: &[u8]) -> Result<(), Error> {
 }
 
 fn am_req(buf: &mut [u8]) -> Result<usize, Error> {
-    let mut pkt = [0_u8; 6];
-    let mut p = Packet::new_unchecked(&mut pkt[..]);
-    p.set_data_length(0)?;
-    p.set_typ(MessageType::Callback);
-    p.set_internal(true);
-    p.set_offset(false);
-    p.set_id_length(1)?;
-    p.set_response(true);
-    p.set_acknum(0);
-    p.msg_id_mut()?
-        .copy_from_slice(MessageId::INTERNAL_AM.as_bytes());
-    p.set_checksum(p.compute_checksum()?)?;
     println!("Requesting writable IDs announcement");
-    println!(">> {p}");
-    Ok(Framing::encode_buf(p.as_ref(), buf))
+    query(MessageId::INTERNAL_AM, MessageType::Callback, buf)
 }
 
 fn am_list_resp(buf: &[u8]) -> Result<(), Error> {
@@ -270,21 +234,8 @@ fn am_end_resp(buf: &[u8]) -> Result<usize, Error> {
 }
 
 fn tracked_vars_req(buf: &mut [u8]) -> Result<usize, Error> {
-    let mut pkt = [0_u8; 6];
-    let mut p = Packet::new_unchecked(&mut pkt[..]);
-    p.set_data_length(0)?;
-    p.set_typ(MessageType::Callback);
-    p.set_internal(true);
-    p.set_offset(false);
-    p.set_id_length(1)?;
-    p.set_response(true);
-    p.set_acknum(0);
-    p.msg_id_mut()?
-        .copy_from_slice(MessageId::INTERNAL_AV.as_bytes());
-    p.set_checksum(p.compute_checksum()?)?;
     println!("Requesting tracked variables");
-    println!(">> {p}");
-    Ok(Framing::encode_buf(p.as_ref(), buf))
+    query(MessageId::INTERNAL_AV, MessageType::Callback, buf)
 }
 
 fn tracked_vars_resp(buf: &[u8]) -> Result<(), Error> {
@@ -297,22 +248,8 @@ fn tracked_vars_resp(buf: &[u8]) -> Result<(), Error> {
 }
 
 fn heartbeat_req(val: u8, buf: &mut [u8]) -> Result<usize, Error> {
-    let mut pkt = [0_u8; 7];
-    let mut p = Packet::new_unchecked(&mut pkt[..]);
-    p.set_data_length(1)?;
-    p.set_typ(MessageType::U8);
-    p.set_internal(true);
-    p.set_offset(false);
-    p.set_id_length(1)?;
-    p.set_response(true);
-    p.set_acknum(0);
-    p.msg_id_mut()?
-        .copy_from_slice(MessageId::INTERNAL_HEARTBEAT.as_bytes());
-    p.payload_mut()?[0] = val;
-    p.set_checksum(p.compute_checksum()?)?;
     println!("Requesting heartbeat val={val}");
-    println!(">> {p}");
-    Ok(Framing::encode_buf(p.as_ref(), buf))
+    request(MessageId::INTERNAL_HEARTBEAT, MessageType::U8, &[val], buf)
 }
 
 fn heartbeat_resp(buf: &[u8]) -> Result<u8, Error> {
@@ -0,0 +1,138 @@
+//! Passive protocol sniffer, prints every packet seen on the tapped lines
+//! or in a capture file
+#![deny(warnings, clippy::all)]
+
+use electricui_embedded::host::{self, Direction, Output, Sniffer};
+use serial::prelude::*;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use std::{fmt, fs, io};
+use structopt::StructOpt;
+
+#[derive(Debug)]
+enum Error {
+    Ctrlc(ctrlc::Error),
+    Serial(serial::Error),
+    Host(host::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::Ctrlc(_) => "Control-C error",
+            Error::Serial(_) => "Serial error",
+            Error::Host(_) => "Host error",
+            Error::Io(_) => "IO error",
+        })
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ctrlc(e) => Some(e),
+            Error::Serial(e) => Some(e),
+            Error::Host(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<ctrlc::Error> for Error {
+    fn from(e: ctrlc::Error) -> Self {
+        Error::Ctrlc(e)
+    }
+}
+
+impl From<serial::Error> for Error {
+    fn from(e: serial::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+
+impl From<host::Error> for Error {
+    fn from(e: host::Error) -> Self {
+        Error::Host(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "ElectricUI protocol sniffer.")]
+struct Opts {
+    /// Serial device tapping the device's transmit line
+    #[structopt(name = "device", required_unless = "capture")]
+    device: Option<String>,
+
+    /// Decode a raw byte dump, pcap file or session log instead of a live port
+    #[structopt(long, conflicts_with = "device")]
+    capture: Option<String>,
+
+    /// Serial device tapping the host's transmit line
+    #[structopt(long = "host-tap", name = "host-device")]
+    host_tap: Option<String>,
+
+    /// Baud rate
+    #[structopt(short = "b", long, default_value = "115200")]
+    baud: usize,
+}
+
+fn open(path: &str, baud: usize) -> Result<serial::SystemPort, Error> {
+    let mut port = serial::open(path)?;
+    port.reconfigure(&|settings| {
+        settings.set_baud_rate(serial::BaudRate::from_speed(baud))?;
+        settings.set_char_size(serial::Bits8);
+        settings.set_parity(serial::ParityNone);
+        settings.set_stop_bits(serial::Stop1);
+        settings.set_flow_control(serial::FlowNone);
+        Ok(())
+    })?;
+    port.set_timeout(Duration::from_millis(10))?;
+    Ok(port)
+}
+
+fn main() -> Result<(), Error> {
+    let opts = Opts::from_args();
+
+    if let Some(path) = opts.capture.as_ref() {
+        let capture = fs::read(path)?;
+        let stdout = io::stdout();
+        host::decode_capture(
+            &capture,
+            Direction::DeviceToHost,
+            Output::Text,
+            &mut stdout.lock(),
+        )?;
+        return Ok(());
+    }
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.fetch_add(1, Ordering::SeqCst);
+    })?;
+
+    // Ports are only ever read from
+    let device = opts.device.as_deref().unwrap_or_default();
+    let mut sniffer = Sniffer::new(open(device, opts.baud)?);
+    if let Some(path) = opts.host_tap.as_ref() {
+        sniffer.tap(open(path, opts.baud)?, Direction::HostToDevice);
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    while running.load(Ordering::SeqCst) == 0 {
+        sniffer.poll(&mut out)?;
+    }
+
+    Ok(())
+}
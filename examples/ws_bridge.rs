@@ -0,0 +1,139 @@
+//! Serves a serial-attached device to WebSocket clients, one at a time
+#![deny(warnings, clippy::all)]
+
+use electricui_embedded::host::{self, Bridge, WebSocketClient};
+use serial::prelude::*;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use std::{fmt, io};
+use structopt::StructOpt;
+
+#[derive(Debug)]
+enum Error {
+    Ctrlc(ctrlc::Error),
+    Serial(serial::Error),
+    Host(host::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::Ctrlc(_) => "Control-C error",
+            Error::Serial(_) => "Serial error",
+            Error::Host(_) => "Host error",
+            Error::Io(_) => "IO error",
+        })
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ctrlc(e) => Some(e),
+            Error::Serial(e) => Some(e),
+            Error::Host(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<ctrlc::Error> for Error {
+    fn from(e: ctrlc::Error) -> Self {
+        Error::Ctrlc(e)
+    }
+}
+
+impl From<serial::Error> for Error {
+    fn from(e: serial::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+
+impl From<host::Error> for Error {
+    fn from(e: host::Error) -> Self {
+        Error::Host(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "ElectricUI serial-to-WebSocket bridge.")]
+struct Opts {
+    /// Serial device path
+    #[structopt(name = "device")]
+    device: String,
+
+    /// Address to listen on
+    #[structopt(short, long, default_value = "0.0.0.0:4000")]
+    listen: SocketAddr,
+}
+
+const POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+fn main() -> Result<(), Error> {
+    let opts = Opts::from_args();
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.fetch_add(1, Ordering::SeqCst);
+    })?;
+
+    let mut port = serial::open(&opts.device)?;
+    port.reconfigure(&|settings| {
+        settings.set_baud_rate(serial::Baud115200)?;
+        settings.set_char_size(serial::Bits8);
+        settings.set_parity(serial::ParityNone);
+        settings.set_stop_bits(serial::Stop1);
+        settings.set_flow_control(serial::FlowNone);
+        Ok(())
+    })?;
+    port.set_timeout(POLL_TIMEOUT)?;
+
+    let listener = TcpListener::bind(opts.listen)?;
+    println!("Listening on {}", opts.listen);
+
+    let mut port = Some(port);
+    for client in listener.incoming() {
+        let client = client?;
+        println!("Client connected from {}", client.peer_addr()?);
+        client.set_nodelay(true)?;
+        let client = match WebSocketClient::accept(client) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Handshake failed, {}", e);
+                continue;
+            }
+        };
+        client
+            .get_ref()
+            .get_ref()
+            .set_read_timeout(Some(POLL_TIMEOUT))?;
+
+        let mut bridge = Bridge::new(port.take().unwrap(), client);
+        while running.load(Ordering::SeqCst) == 0 {
+            match bridge.poll() {
+                Ok(_) => (),
+                Err(host::Error::Disconnected) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        println!("Client disconnected, {:?}", bridge.stats());
+        if running.load(Ordering::SeqCst) != 0 {
+            break;
+        }
+        port = Some(bridge.into_inner().0);
+    }
+
+    Ok(())
+}
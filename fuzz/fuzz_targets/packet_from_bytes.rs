@@ -5,10 +5,9 @@ use electricui_embedded::prelude::*;
 use libfuzzer_sys::fuzz_target;
 
 fuzz_target!(|data: &[u8]| {
-    let p = match Packet::new(data) {
-        Ok(p) => p,
-        Err(_) => return,
-    };
+    let _ = Packet::new(data);
+    // Accessors must not panic, even on buffers that failed validation
+    let p = Packet::new_unchecked(data);
     let _ = p.data_length();
     let _ = p.typ();
     let _ = p.internal();
@@ -18,6 +17,7 @@ fuzz_target!(|data: &[u8]| {
     let _ = p.acknum();
     let _ = p.msg_id();
     let _ = p.msg_id_raw();
+    let _ = p.offset_address();
     let _ = p.payload();
     let _ = p.checksum();
     let _ = p.compute_checksum();
@@ -0,0 +1,101 @@
+#![no_main]
+#![deny(warnings, clippy::all)]
+
+use electricui_embedded::prelude::*;
+use electricui_embedded::wire::PacketView;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    typ: u8,
+    internal: bool,
+    response: bool,
+    acknum: u8,
+    offset_address: Option<u16>,
+    msg_id: Vec<u8>,
+    payload: Vec<u8>,
+    /// Fed to the decoder ahead of the frames, ended by a delimiter
+    noise: Vec<u8>,
+    /// Number of back to back copies of the frame
+    copies: u8,
+    /// Where the frames are split between chunks
+    split: usize,
+}
+
+fuzz_target!(|input: Input| {
+    let Input {
+        typ,
+        internal,
+        response,
+        acknum,
+        offset_address,
+        mut msg_id,
+        mut payload,
+        noise,
+        copies,
+        split,
+    } = input;
+    let (typ, acknum) = (MessageType::from_bits(typ), AckNum::from_bits(acknum));
+    msg_id.truncate(Packet::<&[u8]>::MAX_MSG_ID_SIZE);
+    let id = match MessageId::new_binary(&msg_id) {
+        Some(id) => id,
+        None => return,
+    };
+    payload.truncate(Packet::<&[u8]>::MAX_PAYLOAD_SIZE);
+    let offset_size = offset_address.map_or(0, |_| Packet::<&[u8]>::OFFSET_SIZE);
+
+    let mut raw = vec![0_u8; Packet::<&[u8]>::buffer_len(id.len(), payload.len()) + offset_size];
+    let mut p = Packet::new_unchecked(&mut raw[..]);
+    p.set_typ(typ);
+    p.set_internal(internal);
+    p.set_offset(offset_address.is_some());
+    p.set_msg_id(id).unwrap();
+    p.set_response(response);
+    p.set_acknum(acknum);
+    if let Some(address) = offset_address {
+        let start = Packet::<&[u8]>::HEADER_SIZE + id.len();
+        raw[start..start + offset_size].copy_from_slice(&address.to_le_bytes());
+    }
+    let mut p = Packet::new_unchecked(&mut raw[..]);
+    p.set_payload(&payload).unwrap();
+    p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+    // Inconsistent header flags are rejected by design
+    if p.check_header().is_err() {
+        return;
+    }
+
+    let mut frame = vec![0_u8; Framing::max_encoded_len(raw.len()) + 1];
+    let size = Framing::encode_buf(&raw, &mut frame);
+    frame.truncate(size);
+
+    let mut storage = [0_u8; Packet::<&[u8]>::MAX_PACKET_SIZE];
+    let mut dec = Decoder::new(&mut storage);
+
+    // Whatever the noise decodes to, the delimiter after it resyncs
+    dec.feed_chunk(&noise, |_| ());
+    dec.feed_chunk(&[Framing::ZERO], |_| ());
+
+    let copies = usize::from(copies % 4) + 1;
+    let stream = frame.repeat(copies);
+    let split = split % (stream.len() + 1);
+    let mut decoded = 0;
+    let check = |p: PacketView<'_>| {
+        assert_eq!(p.typ(), typ);
+        assert_eq!(p.internal(), internal);
+        assert_eq!(p.response(), response);
+        assert_eq!(p.acknum(), acknum);
+        assert_eq!(p.offset_address(), offset_address);
+        assert_eq!(p.msg_id_raw(), id.as_bytes());
+        assert_eq!(p.payload(), &payload[..]);
+        assert_eq!(p.wire_size(), raw.len());
+    };
+    for chunk in [&stream[..split], &stream[split..]] {
+        let summary = dec.feed_chunk(chunk, |p| {
+            check(p);
+            decoded += 1;
+        });
+        assert_eq!(summary.errors, 0);
+    }
+    assert_eq!(decoded, copies);
+});
@@ -0,0 +1,200 @@
+//! Acknowledgement tracking, shared by the device and host sides
+//!
+//! A write requesting an ack carries a non-zero acknum, the receiver
+//! answers with a packet carrying the same message ID and acknum, see
+//! [`Packet::make_ack_into`](crate::wire::Packet::make_ack_into).
+//!
+//! Time is in caller-defined ticks (e.g. milliseconds from a monotonic timer).
+
+use crate::message::AckNum;
+
+/// Largest acknum, it's a 3 bit field and zero means no ack requested
+pub const MAX_ACKNUM: u8 = AckNum::MAX.get();
+
+const NUM_SLOTS: usize = MAX_ACKNUM as usize;
+
+/// What to do with an outstanding acknum, see [`AckManager::poll`]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    /// The ack timed out, send the packet again
+    Retransmit(AckNum),
+    /// The ack timed out and no retries are left, the acknum is free again
+    Expired(AckNum),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Slot {
+    sent_at: u64,
+    timeout: u64,
+    retries_left: usize,
+}
+
+/// Allocates acknums for outgoing packets and tracks which are awaiting
+/// an ack, up to [`MAX_ACKNUM`] at once.
+///
+/// The packets themselves are kept by the caller, keyed by acknum.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AckManager {
+    slots: [Option<Slot>; NUM_SLOTS],
+    last: AckNum,
+}
+
+impl Default for AckManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AckManager {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; NUM_SLOTS],
+            last: AckNum::NONE,
+        }
+    }
+
+    /// Returns the next free acknum, cycling through 1..=7, or `None`
+    /// if all of them are awaiting an ack.
+    ///
+    /// The packet is retransmitted up to `retries` times, waiting
+    /// `timeout` ticks for the ack after each transmission.
+    pub fn allocate(&mut self, now: u64, timeout: u64, retries: usize) -> Option<AckNum> {
+        let mut acknum = self.last;
+        for _ in 0..NUM_SLOTS {
+            acknum = acknum.next();
+            if let Some(slot @ None) = self.slot_mut(acknum) {
+                *slot = Some(Slot {
+                    sent_at: now,
+                    timeout,
+                    retries_left: retries,
+                });
+                self.last = acknum;
+                return Some(acknum);
+            }
+        }
+        None
+    }
+
+    /// Marks an acknum as acknowledged, returns false if it wasn't outstanding
+    pub fn acknowledge(&mut self, acknum: AckNum) -> bool {
+        match self.slot_mut(acknum) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_outstanding(&self, acknum: AckNum) -> bool {
+        Self::slot_index(acknum)
+            .and_then(|idx| self.slots.get(idx))
+            .is_some_and(Option::is_some)
+    }
+
+    /// Number of acknums awaiting an ack
+    pub fn outstanding(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Returns the next action due at `now`, call until it returns `None`
+    pub fn poll(&mut self, now: u64) -> Option<Action> {
+        let mut acknum = AckNum::NONE;
+        for slot in self.slots.iter_mut() {
+            acknum = acknum.next();
+            if let Some(s) = slot {
+                if now.saturating_sub(s.sent_at) < s.timeout {
+                    continue;
+                }
+                if s.retries_left == 0 {
+                    warn!("Acknum {} expired", acknum);
+                    *slot = None;
+                    return Some(Action::Expired(acknum));
+                }
+                s.retries_left -= 1;
+                s.sent_at = now;
+                debug!(
+                    "Retransmitting acknum {}, {} retries left",
+                    acknum, s.retries_left
+                );
+                return Some(Action::Retransmit(acknum));
+            }
+        }
+        None
+    }
+
+    /// Forgets all outstanding acknums
+    pub fn clear(&mut self) {
+        self.slots = [None; NUM_SLOTS];
+    }
+
+    fn slot_index(acknum: AckNum) -> Option<usize> {
+        usize::from(acknum.get()).checked_sub(1)
+    }
+
+    fn slot_mut(&mut self, acknum: AckNum) -> Option<&mut Option<Slot>> {
+        Self::slot_index(acknum).and_then(|idx| self.slots.get_mut(idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn allocation_cycles() {
+        let mut m = AckManager::new();
+        let mut nums = [0; 9];
+        for n in nums.iter_mut() {
+            let acknum = m.allocate(0, 10, 0).unwrap();
+            m.acknowledge(acknum);
+            *n = acknum.get();
+        }
+        assert_eq!(nums, [1, 2, 3, 4, 5, 6, 7, 1, 2]);
+    }
+
+    #[test]
+    fn allocation_skips_outstanding() {
+        let mut m = AckManager::new();
+        for n in 1..=MAX_ACKNUM {
+            assert_eq!(m.allocate(0, 10, 0), AckNum::new(n));
+        }
+        assert_eq!(m.outstanding(), 7);
+        assert_eq!(m.allocate(0, 10, 0), None);
+        let three = AckNum::new(3).unwrap();
+        assert!(m.acknowledge(three));
+        assert!(!m.acknowledge(three));
+        assert!(!m.acknowledge(AckNum::NONE));
+        assert!(!m.is_outstanding(AckNum::NONE));
+        assert_eq!(m.allocate(0, 10, 0), Some(three));
+    }
+
+    #[test]
+    fn retransmit_then_expire() {
+        let mut m = AckManager::new();
+        let n = m.allocate(0, 10, 2).unwrap();
+        assert_eq!(m.poll(9), None);
+        assert_eq!(m.poll(10), Some(Action::Retransmit(n)));
+        assert_eq!(m.poll(10), None);
+        assert_eq!(m.poll(20), Some(Action::Retransmit(n)));
+        assert_eq!(m.poll(30), Some(Action::Expired(n)));
+        assert!(!m.is_outstanding(n));
+        assert_eq!(m.poll(100), None);
+    }
+
+    #[test]
+    fn acknowledged_before_timeout() {
+        let mut m = AckManager::new();
+        let n = m.allocate(0, 10, 2).unwrap();
+        assert!(m.is_outstanding(n));
+        assert!(m.acknowledge(n));
+        assert_eq!(m.poll(100), None);
+        m.allocate(0, 10, 2).unwrap();
+        m.clear();
+        assert_eq!(m.outstanding(), 0);
+    }
+}
@@ -0,0 +1,462 @@
+//! Owned packets and frame queues, backed by [heapless] with the
+//! `heapless` feature or by the global allocator with the `alloc` feature
+//!
+//! [heapless]: https://crates.io/crates/heapless
+
+#[cfg(feature = "alloc")]
+pub use self::alloc_impl::*;
+#[cfg(feature = "heapless")]
+pub use self::heapless_impl::*;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::{packet, Packet};
+
+#[cfg(feature = "alloc")]
+mod alloc_impl {
+    use super::*;
+    use crate::error::Error;
+    use crate::wire::Framing;
+    use alloc::vec::Vec;
+    use core::mem;
+
+    /// Largest encoded frame accepted, including the delimiter
+    pub const MAX_FRAME_SIZE: usize =
+        Framing::max_encoded_len(Packet::<&[u8]>::MAX_PACKET_SIZE) + 1;
+
+    /// A packet that owns its bytes in a `Vec`
+    pub type PacketBuf = Packet<Vec<u8>>;
+
+    /// Builds a non-offset packet
+    pub fn packet_buf(
+        id: MessageId<'_>,
+        typ: MessageType,
+        internal: bool,
+        response: bool,
+        acknum: AckNum,
+        payload: &[u8],
+    ) -> Result<PacketBuf, packet::Error> {
+        let mut bytes = alloc::vec![0; Packet::<&[u8]>::buffer_len(id.len(), payload.len())];
+        packet::emit(&mut bytes, id, typ, internal, response, acknum, payload)?;
+        Ok(Packet::new_unchecked(bytes))
+    }
+
+    /// Copies a packet, e.g. one borrowed from a
+    /// [`Decoder`](crate::decoder::Decoder), so it can be kept around
+    pub fn to_packet_buf<T: AsRef<[u8]>>(packet: &Packet<T>) -> PacketBuf {
+        Packet::new_unchecked(packet.as_ref().into())
+    }
+
+    /// Replaces the contents of `frame` with the framed `packet` bytes,
+    /// including the delimiter
+    pub fn encode_frame(packet: &[u8], frame: &mut Vec<u8>) {
+        frame.resize(Framing::max_encoded_len(packet.len()), 0);
+        let size = Framing::encode_buf(packet, frame);
+        frame.truncate(size);
+    }
+
+    /// Accumulates bytes up to each frame delimiter, then decodes the
+    /// framed packet.
+    ///
+    /// Unlike the [`Decoder`](crate::decoder::Decoder) it has no fixed
+    /// storage to borrow, so the decoded packets are owned.
+    #[derive(Debug)]
+    pub struct Deframer {
+        frame: Vec<u8>,
+        discard: bool,
+    }
+
+    impl Default for Deframer {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Deframer {
+        pub fn new() -> Self {
+            Self {
+                frame: Vec::with_capacity(MAX_FRAME_SIZE),
+                discard: false,
+            }
+        }
+
+        /// Resets the frame in progress
+        pub fn reset(&mut self) {
+            self.frame.clear();
+            self.discard = false;
+        }
+
+        /// Feeds a byte, returns the result of decoding a frame once its
+        /// delimiter arrives.
+        /// Empty frames (consecutive delimiters) are skipped.
+        pub fn push(&mut self, byte: u8) -> Option<Result<PacketBuf, Error>> {
+            if byte != Framing::ZERO {
+                if self.frame.len() >= MAX_FRAME_SIZE {
+                    // Oversized frame, drop it up to the next delimiter
+                    self.discard = true;
+                    self.frame.clear();
+                } else if !self.discard {
+                    self.frame.push(byte);
+                }
+                return None;
+            }
+
+            if self.discard {
+                self.discard = false;
+                return Some(Err(Error::FrameTooLarge));
+            }
+            if self.frame.is_empty() {
+                return None;
+            }
+            self.frame.push(byte);
+            let mut frame = mem::replace(&mut self.frame, Vec::with_capacity(MAX_FRAME_SIZE));
+            let res = Framing::decode_in_place(&mut frame)
+                .map_err(Error::from)
+                .and_then(|size| {
+                    frame.truncate(size);
+                    Packet::new(frame).map_err(Error::from)
+                });
+            Some(res)
+        }
+
+        /// Feeds bytes up to and including the next delimiter, returns
+        /// the number of bytes consumed along with the result of decoding
+        /// a frame if one ended.
+        ///
+        /// The delimiter is found with a word at a time search and the
+        /// frame bytes are copied in one go.
+        pub fn push_slice(&mut self, bytes: &[u8]) -> (usize, Option<Result<PacketBuf, Error>>) {
+            let (data, used) = match Framing::find_delimiter(bytes) {
+                Some(idx) => (&bytes[..idx], idx + 1),
+                None => (bytes, bytes.len()),
+            };
+            if self.frame.len() + data.len() > MAX_FRAME_SIZE {
+                self.discard = true;
+                self.frame.clear();
+            } else if !self.discard {
+                self.frame.extend_from_slice(data);
+            }
+            if used > data.len() {
+                (used, self.push(Framing::ZERO))
+            } else {
+                (used, None)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "heapless")]
+mod heapless_impl {
+    use super::*;
+    use crate::transport::Transport;
+    use crate::wire::Framing;
+    use core::fmt;
+    use heapless::{Deque, Vec};
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[non_exhaustive]
+    pub enum Error {
+        Capacity,
+        Packet(packet::Error),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Error::Capacity => f.write_str("Not enough capacity"),
+                Error::Packet(e) => write!(f, "Packet error. {}", e),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {
+        fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+            match self {
+                Error::Capacity => None,
+                Error::Packet(e) => Some(e),
+            }
+        }
+    }
+
+    impl From<packet::Error> for Error {
+        fn from(e: packet::Error) -> Self {
+            Error::Packet(e)
+        }
+    }
+
+    impl Error {
+        /// Stable code, see [`crate::error`]
+        pub const fn code(&self) -> u8 {
+            match self {
+                Error::Capacity => 0x02,
+                Error::Packet(e) => e.code(),
+            }
+        }
+    }
+
+    impl From<Error> for crate::Error {
+        fn from(e: Error) -> Self {
+            match e {
+                Error::Capacity => crate::Error::Capacity,
+                Error::Packet(e) => crate::Error::Packet(e),
+            }
+        }
+    }
+
+    /// A packet that owns up to `N` bytes
+    pub type PacketVec<const N: usize> = Packet<Vec<u8, N>>;
+
+    /// Builds a non-offset packet
+    pub fn packet_vec<const N: usize>(
+        id: MessageId<'_>,
+        typ: MessageType,
+        internal: bool,
+        response: bool,
+        acknum: AckNum,
+        payload: &[u8],
+    ) -> Result<PacketVec<N>, Error> {
+        let mut bytes = Vec::new();
+        bytes
+            .resize(Packet::<&[u8]>::buffer_len(id.len(), payload.len()), 0)
+            .map_err(|_| Error::Capacity)?;
+        packet::emit(&mut bytes, id, typ, internal, response, acknum, payload)?;
+        Ok(Packet::new_unchecked(bytes))
+    }
+
+    /// Copies a packet, e.g. one borrowed from a
+    /// [`Decoder`](crate::decoder::Decoder), so it can be kept around
+    pub fn to_packet_vec<const N: usize, T: AsRef<[u8]>>(
+        packet: &Packet<T>,
+    ) -> Result<PacketVec<N>, Error> {
+        let bytes = Vec::from_slice(packet.as_ref()).map_err(|_| Error::Capacity)?;
+        Ok(Packet::new_unchecked(bytes))
+    }
+
+    /// A queue of up to `D` outbound frames of at most `F` bytes each,
+    /// including the delimiter
+    #[derive(Debug)]
+    pub struct FrameQueue<const F: usize, const D: usize> {
+        frames: Deque<Vec<u8, F>, D>,
+    }
+
+    impl<const F: usize, const D: usize> Default for FrameQueue<F, D> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<const F: usize, const D: usize> FrameQueue<F, D> {
+        const VALID: () = assert!(
+            F >= Framing::MIN_FRAME_SIZE && D >= 1,
+            "FrameQueue must hold at least 1 frame of at least Framing::MIN_FRAME_SIZE bytes"
+        );
+
+        pub const fn new() -> Self {
+            let () = Self::VALID;
+            Self {
+                frames: Deque::new(),
+            }
+        }
+
+        /// Frames and queues a packet
+        pub fn push_packet<T: AsRef<[u8]>>(&mut self, packet: &Packet<T>) -> Result<(), Error> {
+            let bytes = packet.as_ref();
+            let mut frame = Vec::new();
+            frame
+                .resize(Framing::max_encoded_len(bytes.len()), 0)
+                .map_err(|_| Error::Capacity)?;
+            let size = Framing::encode_buf(bytes, &mut frame);
+            frame.truncate(size);
+            self.frames.push_back(frame).map_err(|_| Error::Capacity)
+        }
+
+        /// Queues an already framed packet
+        pub fn push_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+            let frame = Vec::from_slice(frame).map_err(|_| Error::Capacity)?;
+            self.frames.push_back(frame).map_err(|_| Error::Capacity)
+        }
+
+        pub fn front(&self) -> Option<&[u8]> {
+            self.frames.front().map(|f| &f[..])
+        }
+
+        pub fn pop(&mut self) -> Option<Vec<u8, F>> {
+            self.frames.pop_front()
+        }
+
+        pub fn len(&self) -> usize {
+            self.frames.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.frames.is_empty()
+        }
+
+        pub fn is_full(&self) -> bool {
+            self.frames.is_full()
+        }
+
+        /// Writes the queued frames in order, a frame stays queued if
+        /// writing it fails.
+        /// Returns the number of frames written.
+        pub fn send<T: Transport>(&mut self, transport: &mut T) -> Result<usize, T::Error> {
+            let mut cnt = 0;
+            while let Some(frame) = self.frames.front() {
+                transport.write(frame)?;
+                self.frames.pop_front();
+                cnt += 1;
+            }
+            Ok(cnt)
+        }
+
+        /// Copies as many whole frames from the front of the queue as fit
+        /// in `buf`, leaving them queued.
+        /// Returns the number of frames and bytes copied.
+        pub fn peek_batch(&self, buf: &mut [u8]) -> (usize, usize) {
+            let (mut cnt, mut size) = (0, 0);
+            for frame in self.frames.iter() {
+                let Some(dst) = buf.get_mut(size..size + frame.len()) else {
+                    break;
+                };
+                dst.copy_from_slice(frame);
+                size += frame.len();
+                cnt += 1;
+            }
+            (cnt, size)
+        }
+
+        /// Writes the frames at the front of the queue as one transfer of
+        /// at most `buf.len()` bytes, assembled in `buf`, so a DMA driven
+        /// link issues one transfer per batch rather than per frame.
+        /// A frame larger than `buf` is written by itself, frames stay
+        /// queued if the write fails.
+        /// Returns the number of frames written.
+        pub fn send_batch<T: Transport>(
+            &mut self,
+            transport: &mut T,
+            buf: &mut [u8],
+        ) -> Result<usize, T::Error> {
+            let (cnt, size) = self.peek_batch(buf);
+            match self.frames.front() {
+                None => return Ok(0),
+                Some(frame) if cnt == 0 => transport.write(frame)?,
+                Some(_) => transport.write(&buf[..size])?,
+            }
+            let cnt = cnt.max(1);
+            for _ in 0..cnt {
+                self.frames.pop_front();
+            }
+            Ok(cnt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    #[cfg(feature = "heapless")]
+    use crate::transport::test_util::Buffers;
+    #[cfg(feature = "heapless")]
+    use crate::wire::Framing;
+    use pretty_assertions::assert_eq;
+
+    static MSG_F32: [u8; 14] = [
+        0x00, 0x0D, 0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+    ];
+
+    fn decode(bytes: &mut [u8; 64]) -> Packet<&[u8]> {
+        let mut storage = [0_u8; 16];
+        let mut dec = Decoder::new(&mut storage);
+        let (used, p) = dec.decode_slice(&MSG_F32);
+        assert_eq!(used, MSG_F32.len());
+        let size = p.unwrap().unwrap().write_to(bytes);
+        Packet::new_unchecked(&bytes[..size])
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn vec_packets() {
+        let id = MessageId::new(b"abc").unwrap();
+        let payload = 42.42_f32.to_le_bytes();
+        let p = packet_buf(id, MessageType::F32, false, false, AckNum::NONE, &payload).unwrap();
+        assert_eq!(p.msg_id(), Ok(id));
+        assert_eq!(p.payload(), Ok(&payload[..]));
+        assert!(p.check_checksum().is_ok());
+
+        let mut storage = [0_u8; 64];
+        assert_eq!(to_packet_buf(&decode(&mut storage)).as_ref(), p.as_ref());
+
+        let big = [0_u8; Packet::<&[u8]>::MAX_PAYLOAD_SIZE + 1];
+        assert_eq!(
+            packet_buf(id, MessageType::Custom, false, false, AckNum::NONE, &big).unwrap_err(),
+            packet::Error::InvalidDataLength
+        );
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn owned_packets() {
+        let id = MessageId::new(b"abc").unwrap();
+        let payload = 42.42_f32.to_le_bytes();
+        let p =
+            packet_vec::<16>(id, MessageType::F32, false, false, AckNum::NONE, &payload).unwrap();
+        assert_eq!(p.msg_id(), Ok(id));
+        assert_eq!(p.payload(), Ok(&payload[..]));
+        assert!(p.check_checksum().is_ok());
+
+        assert_eq!(
+            packet_vec::<8>(id, MessageType::F32, false, false, AckNum::NONE, &payload)
+                .unwrap_err(),
+            Error::Capacity
+        );
+
+        let mut storage = [0_u8; 64];
+        let decoded = to_packet_vec::<12, _>(&decode(&mut storage)).unwrap();
+        assert_eq!(decoded.as_ref(), p.as_ref());
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn frame_queue() {
+        let id = MessageId::new(b"a").unwrap();
+        let mut q = FrameQueue::<16, 2>::new();
+        let p = packet_vec::<16>(id, MessageType::U8, false, false, AckNum::NONE, &[1]).unwrap();
+        q.push_packet(&p).unwrap();
+        q.push_frame(&[1, 2, 0]).unwrap();
+        assert!(q.is_full());
+        assert_eq!(q.push_frame(&[1, 0]), Err(Error::Capacity));
+        let frame_len = q.front().unwrap().len();
+        assert_eq!(q.front().unwrap()[frame_len - 1], Framing::ZERO);
+
+        let mut t = Buffers::new(&[]);
+        assert_eq!(q.send(&mut t), Ok(2));
+        assert!(q.is_empty());
+        assert_eq!(&t.written()[frame_len..], &[1, 2, 0]);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn frame_queue_batches() {
+        let mut q = FrameQueue::<8, 4>::new();
+        q.push_frame(&[1, 2, 0]).unwrap();
+        q.push_frame(&[3, 4, 5, 0]).unwrap();
+        q.push_frame(&[2, 6, 0]).unwrap();
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(q.peek_batch(&mut buf), (2, 7));
+        assert_eq!(&buf[..7], &[1, 2, 0, 3, 4, 5, 0]);
+        assert_eq!(q.len(), 3);
+
+        let mut t = Buffers::new(&[]);
+        assert_eq!(q.send_batch(&mut t, &mut buf), Ok(2));
+        assert_eq!(t.written(), &[1, 2, 0, 3, 4, 5, 0]);
+        assert_eq!(q.front(), Some(&[2, 6, 0][..]));
+
+        // Frames that don't fit the transfer size go out alone
+        let mut t = Buffers::new(&[]);
+        assert_eq!(q.send_batch(&mut t, &mut buf[..2]), Ok(1));
+        assert_eq!(t.written(), &[2, 6, 0]);
+        assert_eq!(q.send_batch(&mut t, &mut buf), Ok(0));
+        assert!(q.is_empty());
+    }
+}
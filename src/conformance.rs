@@ -0,0 +1,396 @@
+//! Byte-exact wire format vectors
+//!
+//! Each [`Vector`] is a frame as it's sent on the wire, including the
+//! delimiter, along with the fields it carries. [`verify`] runs every
+//! vector through [`Framing`], [`Packet`], the [`Decoder`] and the
+//! [`encoder`], so a change to the crate's wire format fails here rather
+//! than against a device.
+//!
+//! The frames cover the handshake, acks, offsets and arrays. They were
+//! worked out by hand from the protocol documentation, not captured from
+//! the official JS and C libraries, so they pin down this crate's reading
+//! of the protocol but don't show it interoperates with ElectricUI. Each
+//! vector records its [`Source`], frames captured from those libraries
+//! belong here as [`Source::Captured`] vectors.
+//!
+//! `verify` needs no allocator and only a few hundred bytes of stack, so
+//! it can run on the target as well as in the host tests.
+
+use crate::decoder::{decode_in_place, Decoder};
+use crate::encoder;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::{Framing, Packet, PacketView};
+use core::convert::TryFrom;
+
+/// Largest frame in [`VECTORS`], the size of the buffers [`verify`] uses
+const MAX_FRAME_SIZE: usize = 64;
+
+/// Where a vector's frame came from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Source {
+    /// Worked out by hand from the protocol documentation
+    Derived,
+    /// Captured from an official library, named along with its version
+    Captured(&'static str),
+}
+
+/// A framed packet and the fields it carries
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Vector {
+    pub name: &'static str,
+    pub source: Source,
+    /// COBS encoded, ending with the delimiter
+    pub frame: &'static [u8],
+    pub typ: MessageType,
+    pub internal: bool,
+    pub response: bool,
+    pub acknum: AckNum,
+    pub offset_address: Option<u16>,
+    pub msg_id: &'static [u8],
+    pub payload: &'static [u8],
+}
+
+impl Vector {
+    /// Whether `p` carries this vector's fields
+    pub fn matches(&self, p: &PacketView<'_>) -> bool {
+        p.typ() == self.typ
+            && p.internal() == self.internal
+            && p.response() == self.response
+            && p.acknum() == self.acknum
+            && p.offset_address() == self.offset_address
+            && p.msg_id_raw() == self.msg_id
+            && p.payload() == self.payload
+    }
+}
+
+/// The check a vector failed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Stage {
+    /// [`Framing::decode_buf`] rejected the frame
+    Deframe,
+    /// [`Packet::new`] rejected the packet
+    Parse,
+    /// The packet's fields differ from the vector's
+    Fields,
+    /// The [`Decoder`] didn't produce exactly the vector's packet
+    Decode,
+    /// [`decode_in_place`] didn't produce exactly the vector's packet
+    DecodeInPlace,
+    /// Encoding the fields again didn't produce the same frame
+    Encode,
+}
+
+/// The first vector that failed and where
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mismatch {
+    pub vector: &'static str,
+    pub stage: Stage,
+}
+
+/// Checks every vector in [`VECTORS`], see [`verify_vector`]
+pub fn verify() -> Result<(), Mismatch> {
+    VECTORS.iter().try_for_each(|v| {
+        verify_vector(v).map_err(|stage| Mismatch {
+            vector: v.name,
+            stage,
+        })
+    })
+}
+
+/// Deframes and parses the vector's frame and checks its fields, decodes
+/// it with the [`Decoder`] and [`decode_in_place`], then encodes the
+/// fields again and checks the frame is byte for byte the same
+pub fn verify_vector(v: &Vector) -> Result<(), Stage> {
+    if v.frame.len() > MAX_FRAME_SIZE {
+        return Err(Stage::Deframe);
+    }
+
+    let mut raw = [0_u8; MAX_FRAME_SIZE];
+    let size = Framing::decode_buf(v.frame, &mut raw).map_err(|_| Stage::Deframe)?;
+    let p = Packet::new(&raw[..size]).map_err(|_| Stage::Parse)?;
+    let view = PacketView::try_from(&p).map_err(|_| Stage::Parse)?;
+    if !v.matches(&view) || p.wire_size() != Ok(size) {
+        return Err(Stage::Fields);
+    }
+
+    let mut storage = [0_u8; MAX_FRAME_SIZE];
+    let mut dec = Decoder::new(&mut storage);
+    let mut packets = 0;
+    let summary = dec.feed_chunk(v.frame, |p| {
+        if v.matches(&p) {
+            packets += 1;
+        }
+    });
+    if packets != 1 || summary.packets != 1 || summary.errors != 0 {
+        return Err(Stage::Decode);
+    }
+
+    let mut frame = [0_u8; MAX_FRAME_SIZE];
+    frame[..v.frame.len()].copy_from_slice(v.frame);
+    let mut frames = decode_in_place(&mut frame[..v.frame.len()]);
+    match frames.next() {
+        Some(Ok(p)) if PacketView::try_from(&p).is_ok_and(|p| v.matches(&p)) => (),
+        _ => return Err(Stage::DecodeInPlace),
+    }
+    if frames.next().is_some() {
+        return Err(Stage::DecodeInPlace);
+    }
+
+    let mut rebuilt = [0_u8; MAX_FRAME_SIZE];
+    let size = view.write_to(&mut rebuilt);
+    let mut encoded = [0_u8; MAX_FRAME_SIZE];
+    let size = Framing::encode_buf(&rebuilt[..size], &mut encoded);
+    if &encoded[..size] != v.frame {
+        return Err(Stage::Encode);
+    }
+    if v.offset_address.is_none() {
+        let id = MessageId::new(v.msg_id).ok_or(Stage::Encode)?;
+        let mut size = 0;
+        encoder::encode_with::<(), _>(
+            id,
+            v.typ,
+            v.internal,
+            v.response,
+            v.acknum,
+            v.payload,
+            |bytes| {
+                let dst = encoded.get_mut(size..size + bytes.len()).ok_or(())?;
+                dst.copy_from_slice(bytes);
+                size += bytes.len();
+                Ok(())
+            },
+        )
+        .map_err(|_| Stage::Encode)?;
+        if &encoded[..size] != v.frame {
+            return Err(Stage::Encode);
+        }
+    }
+    Ok(())
+}
+
+/// Frames derived from the protocol documentation, see the module docs
+pub static VECTORS: &[Vector] = &[
+    Vector {
+        name: "i8",
+        source: Source::Derived,
+        frame: &[
+            0x0A, 0x01, 0x14, 0x63, 0x61, 0x62, 0x63, 0x2A, 0xB8, 0xA3, 0x00,
+        ],
+        typ: MessageType::I8,
+        internal: false,
+        response: false,
+        acknum: AckNum::from_bits(3),
+        offset_address: None,
+        msg_id: b"abc",
+        payload: &[0x2A],
+    },
+    Vector {
+        name: "f32",
+        source: Source::Derived,
+        frame: &[
+            0x0D, 0x04, 0x2C, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D, 0x00,
+        ],
+        typ: MessageType::F32,
+        internal: false,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"abc",
+        // 42.42
+        payload: &[0x14, 0xAE, 0x29, 0x42],
+    },
+    Vector {
+        name: "offset",
+        source: Source::Derived,
+        frame: &[
+            0x01, 0x07, 0x98, 0x03, 0x61, 0x62, 0x63, 0x10, 0x03, 0x8E, 0x36, 0x00,
+        ],
+        typ: MessageType::U8,
+        internal: false,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: Some(0x10),
+        msg_id: b"abc",
+        payload: &[],
+    },
+    Vector {
+        name: "board_id_request",
+        source: Source::Derived,
+        frame: &[0x01, 0x06, 0x60, 0x11, 0x69, 0x66, 0xD2, 0x00],
+        typ: MessageType::U16,
+        internal: true,
+        response: true,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"i",
+        payload: &[],
+    },
+    Vector {
+        name: "board_id",
+        source: Source::Derived,
+        frame: &[0x09, 0x02, 0x60, 0x01, 0x69, 0xEF, 0xBE, 0x5B, 0xA9, 0x00],
+        typ: MessageType::U16,
+        internal: true,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"i",
+        payload: &[0xEF, 0xBE],
+    },
+    Vector {
+        name: "name_request",
+        source: Source::Derived,
+        frame: &[
+            0x01, 0x01, 0x08, 0x14, 0x6E, 0x61, 0x6D, 0x65, 0x34, 0x6A, 0x00,
+        ],
+        typ: MessageType::Callback,
+        internal: false,
+        response: true,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"name",
+        payload: &[],
+    },
+    Vector {
+        name: "name",
+        source: Source::Derived,
+        frame: &[
+            0x0D, 0x03, 0x10, 0x04, 0x6E, 0x61, 0x6D, 0x65, 0x65, 0x55, 0x49, 0x42, 0x12, 0x00,
+        ],
+        typ: MessageType::Char,
+        internal: false,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"name",
+        payload: b"eUI",
+    },
+    Vector {
+        name: "am_list",
+        source: Source::Derived,
+        frame: &[
+            0x08, 0x0A, 0x44, 0x01, 0x75, 0x6C, 0x65, 0x64, 0x06, 0x73, 0x70, 0x65, 0x65, 0x64,
+            0x03, 0x1A, 0xBF, 0x00,
+        ],
+        typ: MessageType::Custom,
+        internal: true,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"u",
+        payload: b"led\0speed\0",
+    },
+    Vector {
+        name: "am_end",
+        source: Source::Derived,
+        frame: &[0x08, 0x01, 0x58, 0x01, 0x76, 0x02, 0x28, 0xFE, 0x00],
+        typ: MessageType::U8,
+        internal: true,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"v",
+        payload: &[2],
+    },
+    Vector {
+        name: "heartbeat",
+        source: Source::Derived,
+        frame: &[0x08, 0x01, 0x58, 0x11, 0x68, 0x03, 0x16, 0x8D, 0x00],
+        typ: MessageType::U8,
+        internal: true,
+        response: true,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"h",
+        payload: &[3],
+    },
+    Vector {
+        name: "acked_write",
+        source: Source::Derived,
+        frame: &[
+            0x0A, 0x01, 0x18, 0x53, 0x6C, 0x65, 0x64, 0x01, 0x3D, 0x14, 0x00,
+        ],
+        typ: MessageType::U8,
+        internal: false,
+        response: true,
+        acknum: AckNum::from_bits(2),
+        offset_address: None,
+        msg_id: b"led",
+        payload: &[1],
+    },
+    Vector {
+        name: "ack",
+        source: Source::Derived,
+        frame: &[0x01, 0x08, 0x18, 0x43, 0x6C, 0x65, 0x64, 0xD0, 0xCB, 0x00],
+        typ: MessageType::U8,
+        internal: false,
+        response: false,
+        acknum: AckNum::from_bits(2),
+        offset_address: None,
+        msg_id: b"led",
+        payload: &[],
+    },
+    Vector {
+        name: "u16_array",
+        source: Source::Derived,
+        frame: &[
+            0x09, 0x08, 0x20, 0x05, 0x73, 0x70, 0x65, 0x65, 0x64, 0x01, 0x02, 0x01, 0x01, 0x06,
+            0x01, 0xFF, 0xFF, 0xD5, 0xAF, 0x00,
+        ],
+        typ: MessageType::U16,
+        internal: false,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: None,
+        msg_id: b"speed",
+        // [0, 1, 256, 65535]
+        payload: &[0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0xFF, 0xFF],
+    },
+    Vector {
+        name: "f32_array_offset",
+        source: Source::Derived,
+        frame: &[
+            0x0A, 0x08, 0xAC, 0x05, 0x67, 0x61, 0x69, 0x6E, 0x73, 0x08, 0x01, 0x01, 0x03, 0xC0,
+            0x3F, 0x01, 0x05, 0x80, 0xBE, 0x45, 0x0F, 0x00,
+        ],
+        typ: MessageType::F32,
+        internal: false,
+        response: false,
+        acknum: AckNum::NONE,
+        offset_address: Some(8),
+        msg_id: b"gains",
+        // [1.5, -0.25]
+        payload: &[0x00, 0x00, 0xC0, 0x3F, 0x00, 0x00, 0x80, 0xBE],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn vectors() {
+        assert_eq!(verify(), Ok(()));
+        assert!(VECTORS.iter().all(|v| v.frame.len() <= MAX_FRAME_SIZE));
+    }
+
+    #[test]
+    fn mismatches() {
+        let v = VECTORS[0];
+        // Corrupt checksum
+        let frame = &[
+            0x0A, 0x01, 0x14, 0x63, 0x61, 0x62, 0x63, 0x2A, 0x47, 0xA3, 0x00,
+        ];
+        assert_eq!(verify_vector(&Vector { frame, ..v }), Err(Stage::Parse));
+        assert_eq!(
+            verify_vector(&Vector {
+                acknum: AckNum::new(1).unwrap(),
+                ..v
+            }),
+            Err(Stage::Fields)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! `MessageType::Custom` payloads encoded with
+//! [postcard](https://crates.io/crates/postcard), enabled by the `postcard`
+//! feature
+//!
+//! Both ends must agree on the struct definition, postcard isn't self
+//! describing.
+
+use crate::message::MessageType;
+use crate::wire::Packet;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    Postcard(postcard::Error),
+    PayloadTooLarge,
+    UnexpectedType(MessageType),
+    Packet(crate::wire::packet::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Postcard(e) => write!(f, "Postcard error. {}", e),
+            Error::PayloadTooLarge => {
+                f.write_str("The encoded value exceeds the maximum payload size")
+            }
+            Error::UnexpectedType(t) => write!(f, "Expected a Custom payload, found {}", t),
+            Error::Packet(e) => write!(f, "Packet error. {}", e),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Packet(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<crate::wire::packet::Error> for Error {
+    fn from(e: crate::wire::packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+impl Error {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Postcard(_) => 0x40,
+            Error::PayloadTooLarge => 0x41,
+            Error::UnexpectedType(_) => 0x42,
+            Error::Packet(e) => e.code(),
+        }
+    }
+}
+
+/// Encodes `value` into the start of `buf`, returning the payload bytes.
+///
+/// At most [`Packet::MAX_PAYLOAD_SIZE`] bytes of `buf` are used.
+pub fn encode<'b, T: Serialize + ?Sized>(
+    value: &T,
+    buf: &'b mut [u8],
+) -> Result<&'b mut [u8], Error> {
+    let limit = buf.len().min(Packet::<&[u8]>::MAX_PAYLOAD_SIZE);
+    postcard::to_slice(value, &mut buf[..limit]).map_err(|e| match e {
+        postcard::Error::SerializeBufferFull if limit == Packet::<&[u8]>::MAX_PAYLOAD_SIZE => {
+            Error::PayloadTooLarge
+        }
+        e => Error::Postcard(e),
+    })
+}
+
+/// Decodes a value from a payload, borrowing from it where possible
+pub fn decode<'a, T: Deserialize<'a>>(payload: &'a [u8]) -> Result<T, Error> {
+    postcard::from_bytes(payload).map_err(Error::Postcard)
+}
+
+/// Decodes the payload of a `Custom` packet
+pub fn decode_packet<'a, T, B>(packet: &'a Packet<B>) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+    B: AsRef<[u8]>,
+{
+    match packet.typ() {
+        MessageType::Custom => decode(packet.payload()?),
+        typ => Err(Error::UnexpectedType(typ)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Calibration<'a> {
+        name: &'a str,
+        gain: i16,
+        offsets: [u8; 3],
+        enabled: bool,
+    }
+
+    #[test]
+    fn round_trip() {
+        let cal = Calibration {
+            name: "imu",
+            gain: -2,
+            offsets: [1, 2, 3],
+            enabled: true,
+        };
+        let mut buf = [0_u8; 32];
+        let payload = encode(&cal, &mut buf).unwrap();
+        assert_eq!(payload, &[3, b'i', b'm', b'u', 3, 1, 2, 3, 1]);
+        assert_eq!(decode::<Calibration>(payload).unwrap(), cal);
+
+        let mut buf = [0_u8; 4];
+        assert_eq!(
+            encode(&cal, &mut buf),
+            Err(Error::Postcard(postcard::Error::SerializeBufferFull))
+        );
+    }
+
+    #[test]
+    fn payload_size_limit() {
+        let big = [0_u8; 2048];
+        let mut buf = [0_u8; 2048];
+        assert_eq!(
+            encode(&big[..Packet::<&[u8]>::MAX_PAYLOAD_SIZE], &mut buf),
+            Err(Error::PayloadTooLarge)
+        );
+        let payload = encode(&big[..1000], &mut buf).unwrap();
+        assert_eq!(payload.len(), 1002);
+    }
+
+    #[test]
+    fn custom_packets_only() {
+        let mut buf = [0_u8; 32];
+        let size = Packet::<&[u8]>::buffer_len(1, 2);
+        let mut p = Packet::new_unchecked(&mut buf[..size]);
+        p.set_data_length(2).unwrap();
+        p.set_typ(MessageType::U8);
+        p.set_id_length(1).unwrap();
+        p.msg_id_mut().unwrap()[0] = b'c';
+        p.payload_mut().unwrap().copy_from_slice(&[1, 7]);
+        assert_eq!(
+            decode_packet::<[u8; 2], _>(&p),
+            Err(Error::UnexpectedType(MessageType::U8))
+        );
+        p.set_typ(MessageType::Custom);
+        assert_eq!(decode_packet::<(bool, u8), _>(&p), Ok((true, 7)));
+    }
+}
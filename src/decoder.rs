@@ -1,17 +1,89 @@
-use crate::sealed;
-use crate::wire::{packet, Packet};
-use err_derive::Error;
+use crate::transport::{AsyncTransport, ReadError, Transport};
+use crate::wire::{packet, CrcAccumulator, Framer, Framing, Header, Packet, PacketView};
+use core::convert::TryFrom;
+use core::{fmt, mem};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Error {
-    #[error(display = "Not enough bytes in the decoder buffer to store the frame")]
     InsufficientBufferSize,
+    PacketError(packet::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InsufficientBufferSize => {
+                f.write_str("Not enough bytes in the decoder buffer to store the frame")
+            }
+            Error::PacketError(e) => write!(f, "Encountered a packet error. {}", e),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::InsufficientBufferSize => None,
+            Error::PacketError(e) => Some(e),
+        }
+    }
+}
+
+impl From<packet::Error> for Error {
+    fn from(e: packet::Error) -> Self {
+        Error::PacketError(e)
+    }
+}
+
+impl Error {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::InsufficientBufferSize => 0x30,
+            Error::PacketError(e) => e.code(),
+        }
+    }
+}
+
+/// What [`Decoder::feed_chunk`] got through
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChunkSummary {
+    /// Valid packets passed to the callback
+    pub packets: usize,
+    /// Invalid packets dropped
+    pub errors: usize,
+    /// Message ID and payload bytes of an incomplete packet held for
+    /// the next chunk
+    pub pending: usize,
+}
+
+/// 32 bit FNV-1a, for [`Decoder::digest`]
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    const fn new() -> Self {
+        Fnv1a(0x811C_9DC5)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ u32::from(*b)).wrapping_mul(0x0100_0193);
+        }
+    }
 
-    #[error(display = "Encountered a packet error. {}", _0)]
-    PacketError(#[error(source)] packet::Error),
+    const fn finish(&self) -> u32 {
+        self.0
+    }
 }
 
+type DecodeResult<'a> = Result<Option<PacketView<'a>>, Error>;
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 enum State {
     FrameOffset,
     HeaderB0,
@@ -25,38 +97,76 @@ enum State {
     CrcB1,
 }
 
+/// Decodes framed packets a byte at a time.
+///
+/// The header, offset address and checksum are kept as fields as they're
+/// parsed, only the message ID and payload go into the `N` bytes of
+/// storage, see [`Limits::DECODER_STORAGE_SIZE`](crate::wire::Limits::DECODER_STORAGE_SIZE).
+/// Decoded packets are returned as a [`PacketView`] over the two, as soon
+/// as their checksum arrives, the rest of the frame is dropped.
+///
+/// Every byte costs a bounded amount of work, the checksum is computed
+/// as bytes arrive rather than over the whole packet once its last
+/// byte does, so [`Decoder::decode`] suits a UART receive interrupt.
 #[derive(Debug)]
 pub struct Decoder<'buf, const N: usize> {
     state: State,
 
     frame_offset: u8,
+    /// The last COBS code was a maximum length block, with no zero after it
+    full_block: bool,
     id_bytes_read: u8,
     data_bytes_read: u16,
+    /// Message ID and payload bytes stored so far
     bytes_read: usize,
+    /// Checksum of the header, message ID, offset and payload bytes so far
+    crc: CrcAccumulator,
+    #[cfg(feature = "stats")]
     valid_pkt_count: usize,
+    #[cfg(feature = "stats")]
     invalid_pkt_count: usize,
+    /// Dropping the rest of a bad frame up to the next delimiter
+    skip: bool,
 
-    data_len: u16,
-    offset: bool,
-    id_len: u8,
+    /// Header bytes read so far
+    header_bytes: [u8; Header::SIZE],
+    header: Header,
+    offset_address: u16,
+    checksum: u16,
 
     packet_storage: &'buf mut [u8; N],
 }
 
 impl<'buf, const N: usize> Decoder<'buf, N> {
+    const VALID: () = assert!(N >= 1, "Decoder storage must hold at least 1 byte");
+
+    /// Storage of no bytes fails to compile
+    ///
+    /// ```compile_fail
+    /// use electricui_embedded::prelude::*;
+    ///
+    /// let mut storage = [0_u8; 0];
+    /// let dec = Decoder::new(&mut storage);
+    /// ```
     pub fn new(packet_storage: &'buf mut [u8; N]) -> Self {
-        sealed::greater_than_eq::<N, { Packet::<&[u8]>::BASE_PACKET_SIZE }>();
+        let () = Self::VALID;
         Self {
             state: State::FrameOffset,
             frame_offset: 0,
+            full_block: false,
             id_bytes_read: 0,
             data_bytes_read: 0,
             bytes_read: 0,
+            crc: CrcAccumulator::new(),
+            #[cfg(feature = "stats")]
             valid_pkt_count: 0,
+            #[cfg(feature = "stats")]
             invalid_pkt_count: 0,
-            data_len: 0,
-            offset: false,
-            id_len: 0,
+            skip: false,
+            header_bytes: [0; Header::SIZE],
+            header: Header::from_bytes([0; Header::SIZE]),
+            offset_address: 0,
+            checksum: 0,
             packet_storage,
         }
     }
@@ -65,28 +175,251 @@ impl<'buf, const N: usize> Decoder<'buf, N> {
     pub fn reset(&mut self) {
         self.state = State::FrameOffset;
         self.frame_offset = 0;
+        self.full_block = false;
         self.bytes_read = 0;
+        self.crc = CrcAccumulator::new();
+        self.skip = false;
     }
 
+    #[cfg(feature = "stats")]
     pub fn count(&self) -> usize {
         self.valid_pkt_count
     }
 
+    #[cfg(feature = "stats")]
     pub fn invalid_count(&self) -> usize {
         self.invalid_pkt_count
     }
 
-    pub fn decode(&mut self, mut byte: u8) -> Result<Option<Packet<&[u8]>>, Error> {
+    /// Deterministic hash of the decoder's state
+    ///
+    /// Covers the parse state, counters, the header fields read so far and
+    /// the bytes buffered for the current packet. The same byte stream
+    /// gives the same sequence of digests on any target, so a recording
+    /// from hardware can be replayed on the host and compared step by step.
+    /// The packet counters are only included with the `stats` feature.
+    pub fn digest(&self) -> u32 {
+        let mut h = Fnv1a::new();
+        h.write(&[
+            self.state as u8,
+            self.frame_offset,
+            u8::from(self.full_block),
+            u8::from(self.skip),
+            self.id_bytes_read,
+        ]);
+        h.write(&self.data_bytes_read.to_le_bytes());
+        h.write(&(self.bytes_read as u64).to_le_bytes());
+        #[cfg(feature = "stats")]
+        {
+            h.write(&(self.valid_pkt_count as u64).to_le_bytes());
+            h.write(&(self.invalid_pkt_count as u64).to_le_bytes());
+        }
+        h.write(&self.header_bytes);
+        h.write(&self.offset_address.to_le_bytes());
+        h.write(&self.checksum.to_le_bytes());
+        h.write(&self.packet_storage[..self.bytes_read.min(N)]);
+        h.finish()
+    }
+
+    #[inline(always)]
+    fn count_valid(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.valid_pkt_count = self.valid_pkt_count.saturating_add(1);
+        }
+    }
+
+    #[inline(always)]
+    fn count_invalid(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.invalid_pkt_count = self.invalid_pkt_count.saturating_add(1);
+        }
+    }
+
+    /// The last decoded packet
+    fn packet(&self) -> PacketView<'_> {
+        let h = &self.header;
+        let (msg_id, rest) = self.packet_storage.split_at(usize::from(h.id_length));
+        PacketView {
+            typ: h.typ.into(),
+            internal: h.internal,
+            response: h.response,
+            acknum: h.acknum,
+            offset_address: h.offset.then_some(self.offset_address),
+            checksum: self.checksum,
+            msg_id,
+            payload: &rest[..usize::from(h.data_length)],
+        }
+    }
+
+    pub fn decode(&mut self, byte: u8) -> Result<Option<PacketView<'_>>, Error> {
+        let complete = self.decode_byte(byte)?;
+        Ok(complete.then(|| self.packet()))
+    }
+
+    /// Decodes bytes from `bytes` until a packet is complete, returns the
+    /// number of bytes consumed along with the result.
+    ///
+    /// The rest of a bad frame is skipped with a word at a time
+    /// delimiter search rather than byte by byte, and payload bytes
+    /// within a COBS block are copied as a run.
+    pub fn decode_slice(&mut self, bytes: &[u8]) -> (usize, DecodeResult<'_>) {
+        let mut pos = 0;
+        while pos < bytes.len() {
+            if self.skip {
+                match Framing::find_delimiter(&bytes[pos..]) {
+                    Some(idx) => pos += idx,
+                    None => return (bytes.len(), Ok(None)),
+                }
+            } else {
+                pos += self.copy_payload_run(&bytes[pos..]);
+                if pos == bytes.len() {
+                    break;
+                }
+            }
+            let byte = bytes[pos];
+            pos += 1;
+            match self.decode_byte(byte) {
+                Ok(true) => return (pos, Ok(Some(self.packet()))),
+                Ok(false) => (),
+                Err(e) => return (pos, Err(e)),
+            }
+        }
+        (pos, Ok(None))
+    }
+
+    /// Decodes a whole chunk, such as a DMA half or full transfer, calling
+    /// `on_packet` for each valid packet and counting the invalid ones.
+    ///
+    /// Cheap enough to run from the DMA interrupt when `on_packet` is.
+    pub fn feed_chunk<F>(&mut self, mut chunk: &[u8], mut on_packet: F) -> ChunkSummary
+    where
+        F: FnMut(PacketView<'_>),
+    {
+        let mut summary = ChunkSummary::default();
+        while !chunk.is_empty() {
+            let (used, res) = self.decode_slice(chunk);
+            chunk = &chunk[used..];
+            match res {
+                Ok(Some(p)) => {
+                    summary.packets += 1;
+                    on_packet(p);
+                }
+                Ok(None) => (),
+                Err(_) => summary.errors += 1,
+            }
+        }
+        summary.pending = self.bytes_read;
+        summary
+    }
+
+    /// Decodes a whole packet that arrived without COBS framing, such as
+    /// a UDP datagram, bypassing the framing state machine
+    pub fn decode_unframed(&mut self, packet: &[u8]) -> Result<PacketView<'_>, Error> {
+        self.reset();
+        let p = match Packet::new(packet) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Dropped an invalid packet: {}", e);
+                self.count_invalid();
+                return Err(e.into());
+            }
+        };
+        let view = PacketView::try_from(&p)?;
+        let (id_len, data_len) = (view.msg_id.len(), view.payload.len());
+        let storage = self
+            .packet_storage
+            .get_mut(..id_len + data_len)
+            .ok_or(Error::InsufficientBufferSize)?;
+        storage[..id_len].copy_from_slice(view.msg_id);
+        storage[id_len..].copy_from_slice(view.payload);
+        self.header = p.header();
+        self.header_bytes = self.header.to_bytes();
+        self.offset_address = view.offset_address.unwrap_or(0);
+        self.checksum = view.checksum;
+        self.count_valid();
+        Ok(self.packet())
+    }
+
+    /// Decodes a whole frame of framer `F`, for links with their own
+    /// framing, see [`Framer`]. The frame is decoded in place.
+    pub fn decode_frame<F: Framer>(
+        &mut self,
+        frame: &mut [u8],
+    ) -> Result<PacketView<'_>, crate::Error> {
+        let size = F::decode_in_place(frame)?;
+        Ok(self.decode_unframed(&frame[..size])?)
+    }
+
+    /// Reads bytes from the transport until a packet is decoded or no more
+    /// bytes are available
+    pub fn decode_from<T: Transport>(
+        &mut self,
+        transport: &mut T,
+    ) -> Result<Option<PacketView<'_>>, ReadError<T::Error>> {
+        let mut byte = [0_u8];
+        loop {
+            if transport.read(&mut byte).map_err(ReadError::Transport)? == 0 {
+                return Ok(None);
+            }
+            if self.decode_byte(byte[0])? {
+                return Ok(Some(self.packet()));
+            }
+        }
+    }
+
+    /// Waits on `transport` until a whole packet is decoded
+    pub async fn decode_from_async<T: AsyncTransport>(
+        &mut self,
+        transport: &mut T,
+    ) -> Result<PacketView<'_>, ReadError<T::Error>> {
+        let mut byte = [0_u8];
+        loop {
+            if transport
+                .read(&mut byte)
+                .await
+                .map_err(ReadError::Transport)?
+                == 0
+            {
+                return Err(ReadError::Closed);
+            }
+            if self.decode_byte(byte[0])? {
+                return Ok(self.packet());
+            }
+        }
+    }
+
+    /// Returns true once the last byte of a valid packet is decoded.
+    /// After an error the rest of the frame is dropped.
+    fn decode_byte(&mut self, byte: u8) -> Result<bool, Error> {
         // COBS framing
         if byte == 0x00 {
             self.reset();
-            return Ok(None);
-        } else if self.frame_offset > 1 {
+            return Ok(false);
+        } else if self.skip {
+            return Ok(false);
+        }
+        let res = self.decode_frame_byte(byte);
+        if res.is_err() {
+            self.reset();
+            self.skip = true;
+        }
+        res
+    }
+
+    fn decode_frame_byte(&mut self, mut byte: u8) -> Result<bool, Error> {
+        if self.frame_offset > 1 {
             // One byte closer to the next offset
             self.frame_offset -= 1;
         } else {
             // Offset has expired, this inbound byte should be the next data framing byte
+            let implied_zero = !self.full_block;
             self.frame_offset = byte;
+            self.full_block = byte == 0xFF;
+            if !implied_zero {
+                return Ok(false);
+            }
             byte = 0x00;
         }
 
@@ -96,29 +429,46 @@ impl<'buf, const N: usize> Decoder<'buf, N> {
                 self.state = State::HeaderB0;
             }
             State::HeaderB0 => {
-                self.feed(byte)?;
-                self.data_len = byte as _;
+                self.crc.update(&[byte]);
+                self.header_bytes[0] = byte;
                 self.state = State::HeaderB1;
             }
             State::HeaderB1 => {
-                self.feed(byte)?;
-                self.data_len |= ((byte as u16) << 8) & 0x0300;
-                self.offset = ((byte >> 7) & 0x01) != 0;
+                self.crc.update(&[byte]);
+                self.header_bytes[1] = byte;
                 self.state = State::HeaderB2;
             }
             State::HeaderB2 => {
-                self.feed(byte)?;
-                self.id_len = byte & 0x0F;
+                self.crc.update(&[byte]);
+                self.header_bytes[2] = byte;
+                self.header = Header::from_bytes(self.header_bytes);
+                if self.header.id_length == 0 {
+                    debug!("Dropped a packet with no message ID");
+                    self.count_invalid();
+                    return Err(packet::Error::InvalidMessageIdLength.into());
+                }
+                if let Err(e) = packet::check_flags(self.header.typ, self.header.offset) {
+                    debug!("Dropped a packet with inconsistent header flags: {}", e);
+                    self.count_invalid();
+                    return Err(e.into());
+                }
                 self.id_bytes_read = 0;
+                self.check_size()?;
                 self.state = State::MsgId;
             }
             State::MsgId => {
                 self.feed(byte)?;
                 self.id_bytes_read = self.id_bytes_read.saturating_add(1);
-                if self.id_bytes_read >= self.id_len {
-                    if self.offset {
+                if self.id_bytes_read >= self.header.id_length {
+                    let id = &self.packet_storage[..usize::from(self.header.id_length)];
+                    if let Err(e) = packet::check_internal(self.header.internal, id) {
+                        debug!("Dropped a packet: {}", e);
+                        self.count_invalid();
+                        return Err(e.into());
+                    }
+                    if self.header.offset {
                         self.state = State::OffsetB0
-                    } else if self.data_len > 0 {
+                    } else if self.header.data_length > 0 {
                         self.data_bytes_read = 0;
                         self.state = State::Payload;
                     } else {
@@ -127,61 +477,200 @@ impl<'buf, const N: usize> Decoder<'buf, N> {
                 }
             }
             State::OffsetB0 => {
-                // TODO - Add support for split/offset packets
-                self.feed(byte)?;
+                self.crc.update(&[byte]);
+                self.offset_address = byte.into();
                 self.state = State::OffsetB1;
             }
             State::OffsetB1 => {
-                // TODO - Add support for split/offset packets
-                self.feed(byte)?;
-                self.state = State::Payload;
+                self.crc.update(&[byte]);
+                self.offset_address |= u16::from(byte) << 8;
+                if self.header.data_length > 0 {
+                    self.data_bytes_read = 0;
+                    self.state = State::Payload;
+                } else {
+                    self.state = State::CrcB0;
+                }
             }
             State::Payload => {
                 self.feed(byte)?;
                 self.data_bytes_read = self.data_bytes_read.saturating_add(1);
-                if self.data_bytes_read >= self.data_len {
+                if self.data_bytes_read >= self.header.data_length {
                     self.state = State::CrcB0;
                 }
             }
             State::CrcB0 => {
-                self.feed(byte)?;
+                self.checksum = byte.into();
                 self.state = State::CrcB1;
             }
             State::CrcB1 => {
-                self.feed(byte)?;
-                let bytes_read = self.bytes_read;
+                self.checksum |= u16::from(byte) << 8;
+                let crc = mem::take(&mut self.crc).finalize();
                 self.reset();
-                match Packet::new(&self.packet_storage[..bytes_read]) {
-                    Ok(p) => {
-                        self.valid_pkt_count = self.valid_pkt_count.saturating_add(1);
-                        return Ok(p.into());
-                    }
-                    Err(e) => {
-                        self.invalid_pkt_count = self.invalid_pkt_count.saturating_add(1);
-                        return Err(e.into());
-                    }
+                if crc == self.checksum {
+                    trace!(
+                        "Decoded a packet with a {} byte payload",
+                        self.header.data_length
+                    );
+                    self.count_valid();
+                    // Anything between the checksum and the delimiter isn't a packet
+                    self.skip = true;
+                    return Ok(true);
+                } else {
+                    let e = packet::Error::InvalidChecksum {
+                        expected: self.checksum,
+                        computed: crc,
+                    };
+                    debug!("Dropped an invalid packet: {}", e);
+                    self.count_invalid();
+                    return Err(e.into());
                 }
             }
         }
 
-        Ok(None)
+        Ok(false)
     }
 
+    /// Copies the payload bytes at the start of `bytes` that are in the
+    /// current COBS block, up to the end of the payload or a delimiter.
+    /// Returns the number of bytes copied.
+    #[inline]
+    fn copy_payload_run(&mut self, bytes: &[u8]) -> usize {
+        if self.state != State::Payload || self.frame_offset <= 1 {
+            return 0;
+        }
+        let len = usize::from(self.frame_offset - 1)
+            .min(usize::from(self.header.data_length - self.data_bytes_read))
+            .min(self.packet_storage.len() - self.bytes_read)
+            .min(bytes.len());
+        let run = &bytes[..len];
+        // A delimiter ends the frame, leave it to decode_byte
+        let run = &run[..Framing::find_delimiter(run).unwrap_or(len)];
+        self.packet_storage[self.bytes_read..self.bytes_read + run.len()].copy_from_slice(run);
+        self.crc.update(run);
+        self.bytes_read += run.len();
+        self.frame_offset -= run.len() as u8;
+        self.data_bytes_read += run.len() as u16;
+        if self.data_bytes_read >= self.header.data_length {
+            self.state = State::CrcB0;
+        }
+        run.len()
+    }
+
+    /// Fails early when the header describes a message ID and payload
+    /// larger than the storage
+    #[inline]
+    fn check_size(&self) -> Result<(), Error> {
+        let size = usize::from(self.header.id_length) + usize::from(self.header.data_length);
+        if size > self.packet_storage.len() {
+            warn!(
+                "A {} byte message ID and payload exceed the {} byte storage",
+                size,
+                self.packet_storage.len()
+            );
+            Err(Error::InsufficientBufferSize)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stores a message ID or payload byte
     #[inline]
     fn feed(&mut self, byte: u8) -> Result<(), Error> {
         if self.bytes_read >= self.packet_storage.len() {
+            warn!(
+                "Packet exceeds the {} byte storage",
+                self.packet_storage.len()
+            );
             Err(Error::InsufficientBufferSize)
         } else {
             self.packet_storage[self.bytes_read] = byte;
             self.bytes_read = self.bytes_read.saturating_add(1);
+            self.crc.update(&[byte]);
             Ok(())
         }
     }
 }
 
+/// Decodes the frames in `buf` in place, without copying them into a
+/// [`Decoder`]'s storage, e.g. straight out of a DMA buffer.
+///
+/// A frame is decoded once its trailing `0x00` delimiter is in `buf`,
+/// see [`InPlaceFrames::consumed`] for what to keep for the next call.
+pub fn decode_in_place(buf: &mut [u8]) -> InPlaceFrames<'_> {
+    InPlaceFrames {
+        rest: buf,
+        consumed: 0,
+    }
+}
+
+/// Iterator returned by [`decode_in_place`], the packets borrow the
+/// decoded bytes from the input buffer
+#[derive(Debug)]
+pub struct InPlaceFrames<'a> {
+    rest: &'a mut [u8],
+    consumed: usize,
+}
+
+impl<'a> InPlaceFrames<'a> {
+    /// Number of bytes up to and including the last delimiter seen so
+    /// far, the bytes after it are the start of an incomplete frame
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+}
+
+impl<'a> Iterator for InPlaceFrames<'a> {
+    type Item = Result<Packet<&'a [u8]>, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let end = Framing::find_delimiter(self.rest)?;
+            let (frame, rest) = mem::take(&mut self.rest).split_at_mut(end + 1);
+            self.rest = rest;
+            self.consumed += end + 1;
+            // Back to back delimiters
+            if end != 0 {
+                return Some(decode_frame(&mut frame[..end]));
+            }
+        }
+    }
+}
+
+fn decode_frame(frame: &mut [u8]) -> Result<Packet<&[u8]>, crate::Error> {
+    let size = Framing::decode_in_place(frame)?;
+    let frame: &[u8] = frame;
+    let wire_size = Packet::new(&frame[..size])?.wire_size()?;
+    trace!("Decoded a {} byte packet in place", wire_size);
+    Ok(Packet::new_unchecked(&frame[..wire_size]))
+}
+
+#[cfg(feature = "defmt")]
+impl<'buf, const N: usize> defmt::Format for Decoder<'buf, N> {
+    fn format(&self, f: defmt::Formatter) {
+        #[cfg(feature = "stats")]
+        defmt::write!(
+            f,
+            "Decoder {{ state: {}, bytes_read: {}, valid: {}, invalid: {} }}",
+            self.state,
+            self.bytes_read,
+            self.valid_pkt_count,
+            self.invalid_pkt_count
+        );
+        #[cfg(not(feature = "stats"))]
+        defmt::write!(
+            f,
+            "Decoder {{ state: {}, bytes_read: {} }}",
+            self.state,
+            self.bytes_read
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::message::{AckNum, MessageType};
+    use crate::transport::test_util::{block_on, Buffers};
     use pretty_assertions::assert_eq;
 
     // TODO - happy/sad path tests
@@ -194,6 +683,400 @@ mod tests {
         0x8B, 0x1D, // crc
     ];
 
+    static MSG_OFFSET: [u8; 10 + 2] = [
+        0x00, 0x01, // framing
+        0x07, 0x98, 0x03, // header
+        0x61, 0x62, 0x63, // msgid
+        0x10, 0x03, // offset, COBS encoded
+        0x8E, 0x36, // crc
+    ];
+
+    #[test]
+    fn offset_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+
+        for byte in MSG_OFFSET[..MSG_OFFSET.len() - 1].iter() {
+            assert!(dec.decode(*byte).unwrap().is_none());
+        }
+        let p = dec
+            .decode(MSG_OFFSET[MSG_OFFSET.len() - 1])
+            .unwrap()
+            .unwrap();
+        assert_eq!(p.data_length(), 0);
+        assert_eq!(p.offset(), true);
+        assert_eq!(p.offset_address(), Some(0x10));
+        assert!(p.payload().is_empty());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 1);
+    }
+
+    #[test]
+    fn replay_digest() {
+        let mut small = [0_u8; 16];
+        let mut large = [0_u8; 512];
+        let mut a = Decoder::new(&mut small);
+        let mut b = Decoder::new(&mut large);
+        let start = a.digest();
+        assert_eq!(start, b.digest());
+
+        let mut seen = [0_u32; MSG_F32.len() + MSG_OFFSET.len()];
+        for (byte, digest) in MSG_F32.iter().chain(MSG_OFFSET.iter()).zip(&mut seen) {
+            let _ = a.decode(*byte);
+            let _ = b.decode(*byte);
+            assert_eq!(a.digest(), b.digest());
+            *digest = a.digest();
+        }
+        assert_ne!(seen[MSG_F32.len() - 1], start);
+
+        let mut buffer = [0_u8; 512];
+        let mut c = Decoder::new(&mut buffer);
+        let (used, res) = c.decode_slice(&MSG_F32);
+        assert_eq!(used, MSG_F32.len());
+        assert!(res.unwrap().is_some());
+        assert_eq!(c.digest(), seen[MSG_F32.len() - 1]);
+    }
+
+    #[test]
+    fn transport_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let mut input = [0_u8; MSG_F32.len() * 2];
+        input[..MSG_F32.len()].copy_from_slice(&MSG_F32);
+        input[MSG_F32.len()..].copy_from_slice(&MSG_F32);
+        let mut t = Buffers::new(&input[..input.len() - 1]);
+
+        let p = dec.decode_from(&mut t).unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert!(dec.decode_from(&mut t).unwrap().is_none());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 1);
+
+        let mut t = Buffers::new(&MSG_F32[MSG_F32.len() - 1..]);
+        assert!(dec.decode_from(&mut t).unwrap().is_some());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 2);
+    }
+
+    #[test]
+    fn unframed_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let mut frame = MSG_F32;
+        let size = crate::wire::Framing::decode_in_place(&mut frame[1..]).unwrap();
+        let packet = &frame[1..1 + size];
+
+        let p = dec.decode_unframed(packet).unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert_eq!(p.wire_size(), packet.len());
+        assert!(dec.decode_unframed(&packet[..size - 1]).is_err());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 1);
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.invalid_count(), 1);
+
+        let mut buffer = [0_u8; 6];
+        let mut dec = Decoder::new(&mut buffer);
+        assert_eq!(
+            dec.decode_unframed(packet).unwrap_err(),
+            Error::InsufficientBufferSize
+        );
+    }
+
+    #[test]
+    fn framer_decoding() {
+        use crate::wire::framing::{self, LengthPrefixed, Passthrough};
+
+        let mut raw = MSG_F32;
+        let size = Framing::decode_in_place(&mut raw[1..]).unwrap();
+        let packet = &raw[1..1 + size];
+        let mut buffer = [0_u8; 64];
+        let mut dec = Decoder::new(&mut buffer);
+
+        let mut frame = MSG_F32;
+        let p = dec.decode_frame::<Framing>(&mut frame[1..]).unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+
+        let mut frame = [0_u8; 16];
+        let len = Passthrough::encode_buf(packet, &mut frame);
+        let p = dec.decode_frame::<Passthrough>(&mut frame[..len]).unwrap();
+        assert_eq!(p.payload(), &[0x14, 0xAE, 0x29, 0x42]);
+
+        let len = LengthPrefixed::encode_buf(packet, &mut frame);
+        let p = dec
+            .decode_frame::<LengthPrefixed>(&mut frame[..len])
+            .unwrap();
+        assert_eq!(p.wire_size(), packet.len());
+        assert!(matches!(
+            dec.decode_frame::<LengthPrefixed>(&mut frame[..len - 1]),
+            Err(crate::Error::Framing(framing::Error::Length))
+        ));
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 3);
+    }
+
+    #[test]
+    fn inconsistent_header_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let frame = |view: PacketView<'_>| {
+            let view = PacketView {
+                checksum: view.compute_checksum(),
+                ..view
+            };
+            let mut raw = [0_u8; 16];
+            let size = view.write_to(&mut raw);
+            // Leading delimiter, resyncing after the previous bad frame
+            let mut frame = [0_u8; 20];
+            let size = Framing::encode_buf(&raw[..size], &mut frame[1..]);
+            (frame, size + 1)
+        };
+        let view = PacketView {
+            typ: MessageType::Callback.into(),
+            internal: false,
+            response: false,
+            acknum: AckNum::NONE,
+            offset_address: Some(1),
+            checksum: 0,
+            msg_id: b"abc",
+            payload: &[],
+        };
+        let (f, size) = frame(view);
+        assert_eq!(
+            dec.decode_slice(&f[..size]).1.unwrap_err(),
+            packet::Error::InvalidOffset.into()
+        );
+        // An acked write of an empty string
+        let (f, size) = frame(PacketView {
+            typ: MessageType::Char.into(),
+            offset_address: None,
+            response: true,
+            acknum: AckNum::new(1).unwrap(),
+            ..view
+        });
+        let p = dec.decode_slice(&f[..size]).1.unwrap().unwrap();
+        assert_eq!(p.typ(), MessageType::Char);
+        assert_eq!(p.acknum(), AckNum::new(1).unwrap());
+        assert!(p.payload().is_empty());
+        let (f, size) = frame(PacketView {
+            internal: true,
+            offset_address: None,
+            ..view
+        });
+        assert_eq!(
+            dec.decode_slice(&f[..size]).1.unwrap_err(),
+            packet::Error::UnknownInternalId.into()
+        );
+        let (used, res) = dec.decode_slice(&MSG_F32);
+        assert_eq!(used, MSG_F32.len());
+        assert!(res.unwrap().is_some());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.invalid_count(), 2);
+    }
+
+    #[test]
+    fn in_place_decoding() {
+        let mut input = [0_u8; MSG_F32.len() * 3 + 2];
+        for chunk in input.chunks_mut(MSG_F32.len()).take(3) {
+            chunk.copy_from_slice(&MSG_F32);
+        }
+        // Corrupt the checksum of the second frame
+        input[MSG_F32.len() * 2 - 1] ^= 0xFF;
+        input[MSG_F32.len() * 3 + 1] = 0x0D;
+
+        let mut frames = decode_in_place(&mut input);
+        let p = frames.next().unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert_eq!(p.payload().unwrap(), &[0x14, 0xAE, 0x29, 0x42]);
+        assert_eq!(p.wire_size().unwrap(), p.as_ref().len());
+        assert!(matches!(
+            frames.next().unwrap().unwrap_err(),
+            crate::Error::Packet(packet::Error::InvalidChecksum {
+                expected: 0xE28B,
+                computed: 0x1D8B
+            })
+        ));
+        assert!(frames.next().unwrap().is_ok());
+        assert!(frames.next().is_none());
+        assert_eq!(frames.consumed(), MSG_F32.len() * 3 + 1);
+    }
+
+    #[test]
+    fn slice_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let mut input = [0_u8; MSG_F32.len() * 3 + 3];
+        input[..MSG_F32.len()].copy_from_slice(&MSG_F32);
+        // Bad checksum followed by junk up to the next frame
+        input[MSG_F32.len() - 1] ^= 0xFF;
+        input[MSG_F32.len()..MSG_F32.len() + 3].copy_from_slice(&[1, 2, 3]);
+        input[MSG_F32.len() + 3..MSG_F32.len() * 2 + 3].copy_from_slice(&MSG_F32);
+        input[MSG_F32.len() * 2 + 3..].copy_from_slice(&MSG_F32);
+
+        let (used, res) = dec.decode_slice(&input);
+        assert_eq!(used, MSG_F32.len());
+        let e = packet::Error::InvalidChecksum {
+            expected: 0xE28B,
+            computed: 0x1D8B,
+        };
+        assert_eq!(res.unwrap_err(), e.into());
+        let (used, res) = dec.decode_slice(&input[MSG_F32.len()..]);
+        assert_eq!(used, MSG_F32.len() + 3);
+        assert_eq!(res.unwrap().unwrap().msg_id().unwrap(), b"abc");
+
+        let rest = &input[MSG_F32.len() * 2 + 3..];
+        let (used, res) = dec.decode_slice(&rest[..5]);
+        assert_eq!(used, 5);
+        assert!(res.unwrap().is_none());
+        let (used, res) = dec.decode_slice(&rest[5..]);
+        assert_eq!(used, rest.len() - 5);
+        assert!(res.unwrap().is_some());
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 2);
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.invalid_count(), 1);
+    }
+
+    #[test]
+    fn chunk_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let mut input = [0_u8; MSG_F32.len() * 3];
+        for chunk in input.chunks_mut(MSG_F32.len()) {
+            chunk.copy_from_slice(&MSG_F32);
+        }
+        input[MSG_F32.len() - 1] ^= 0xFF;
+
+        let mut ids = 0;
+        let summary = dec.feed_chunk(&input[..MSG_F32.len() * 2 + 6], |p| {
+            assert_eq!(p.msg_id().unwrap(), b"abc");
+            ids += 1;
+        });
+        assert_eq!(
+            summary,
+            ChunkSummary {
+                packets: 1,
+                errors: 1,
+                pending: 1,
+            }
+        );
+        assert_eq!(ids, 1);
+
+        let summary = dec.feed_chunk(&input[MSG_F32.len() * 2 + 6..], |_| ids += 1);
+        assert_eq!(summary.packets, 1);
+        assert_eq!(summary.pending, 0);
+        assert_eq!(ids, 2);
+    }
+
+    #[test]
+    fn long_block_decoding() {
+        let mut raw = [0_u8; 512];
+        let mut p = Packet::new_unchecked(&mut raw[..]);
+        p.set_data_length(300).unwrap();
+        p.set_typ(MessageType::Custom);
+        p.set_internal(false);
+        p.set_offset(false);
+        p.set_id_length(3).unwrap();
+        p.set_response(false);
+        p.set_acknum(AckNum::NONE);
+        p.msg_id_mut().unwrap().copy_from_slice(b"abc");
+        for (idx, b) in p.payload_mut().unwrap().iter_mut().enumerate() {
+            *b = (idx % 255) as u8 + 1;
+        }
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        let size = p.wire_size().unwrap();
+        let mut frame = [0_u8; 520];
+        let frame_size = Framing::encode_buf(&raw[..size], &mut frame);
+
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let (used, res) = dec.decode_slice(&frame[..frame_size]);
+        assert_eq!(used, frame_size - 1);
+        let p = res.unwrap().unwrap();
+        let mut bytes = [0_u8; 520];
+        assert_eq!(p.write_to(&mut bytes), size);
+        assert_eq!(&bytes[..size], &raw[..size]);
+    }
+
+    #[test]
+    fn bounded_completion() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let (last, rest) = MSG_F32.split_last().unwrap();
+        for byte in rest {
+            assert!(dec.decode(*byte).unwrap().is_none());
+        }
+        // The checksum is already computed, completing the packet
+        // doesn't read back the stored payload
+        dec.packet_storage[6] ^= 0xFF;
+        let p = dec.decode(*last).unwrap().unwrap();
+        assert_eq!(p.payload(), &[0x14, 0xAE, 0x29, 0x42 ^ 0xFF]);
+    }
+
+    #[test]
+    fn payload_run_decoding() {
+        let mut raw = [0_u8; 1024];
+        let mut p = Packet::new_unchecked(&mut raw[..]);
+        p.set_data_length(700).unwrap();
+        p.set_typ(MessageType::Custom);
+        p.set_internal(false);
+        p.set_offset(false);
+        p.set_id_length(3).unwrap();
+        p.set_response(false);
+        p.set_acknum(AckNum::NONE);
+        p.msg_id_mut().unwrap().copy_from_slice(b"abc");
+        for (idx, b) in p.payload_mut().unwrap().iter_mut().enumerate() {
+            *b = (idx % 97) as u8;
+        }
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        let size = p.wire_size().unwrap();
+        let mut frame = [0_u8; 1024];
+        let frame_size = Framing::encode_buf(&raw[..size], &mut frame);
+        let frame = &frame[..frame_size];
+
+        let mut buffer = [0_u8; 1024];
+        let mut dec = Decoder::new(&mut buffer);
+        for chunk_size in [1, 7, 64, 255, frame_size] {
+            let mut packets = 0;
+            for chunk in frame.chunks(chunk_size) {
+                let summary = dec.feed_chunk(chunk, |p| {
+                    assert_eq!(p.msg_id_raw(), b"abc");
+                    assert_eq!(p.payload(), &raw[6..size - 2]);
+                    packets += 1;
+                });
+                assert_eq!(summary.errors, 0);
+            }
+            assert_eq!(packets, 1);
+        }
+
+        // A delimiter part way through a payload run restarts decoding
+        let mut input = [0_u8; 2048];
+        input[..300].copy_from_slice(&frame[..300]);
+        input[300] = 0;
+        input[301..301 + frame_size].copy_from_slice(frame);
+        let summary = dec.feed_chunk(&input[..301 + frame_size], |p| {
+            assert_eq!(p.payload(), &raw[6..size - 2]);
+        });
+        assert_eq!(summary.packets, 1);
+        assert_eq!(summary.errors, 0);
+    }
+
+    #[test]
+    fn async_transport_decoding() {
+        let mut buffer = [0_u8; 512];
+        let mut dec = Decoder::new(&mut buffer);
+        let mut t = Buffers::new(&MSG_F32[..]);
+
+        let p = block_on(dec.decode_from_async(&mut t)).unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert_eq!(
+            block_on(dec.decode_from_async(&mut t)).unwrap_err(),
+            ReadError::Closed
+        );
+        #[cfg(feature = "stats")]
+        assert_eq!(dec.count(), 1);
+    }
+
     #[test]
     fn basic_decoding() {
         let mut buffer = [0_u8; 512];
@@ -215,7 +1098,10 @@ mod tests {
             assert!(dec.decode(2).unwrap().is_none());
         }
 
+        #[cfg(feature = "stats")]
+
         assert_eq!(dec.count(), 4);
+        #[cfg(feature = "stats")]
         assert_eq!(dec.invalid_count(), 0);
     }
 }
@@ -0,0 +1,138 @@
+//! Compile-time message ID lookup
+//!
+//! An [`IdTable`] is built in a `const` from the message IDs a device
+//! handles. Each ID is packed into a single `u128` key, its bytes and
+//! length, and the keys are sorted at compile time, so finding the handler
+//! for a received packet is a binary search over integers rather than a
+//! linear scan comparing ID bytes.
+//!
+//! ```
+//! use electricui_embedded::dispatch::IdTable;
+//! use electricui_embedded::message::MessageId;
+//!
+//! const IDS: IdTable<3> = IdTable::new([b"led_blink", b"speed", MessageId::BOARD_NAME.as_bytes()]);
+//!
+//! assert_eq!(IDS.index_of(b"speed"), Some(1));
+//! assert_eq!(IDS.index_of(b"name"), Some(2));
+//! assert_eq!(IDS.index_of(b"led"), None);
+//! ```
+
+use crate::message::MessageId;
+
+/// Message IDs in lookup order, indices refer to the list the table
+/// was built from
+#[derive(Debug, Copy, Clone)]
+pub struct IdTable<const N: usize> {
+    keys: [u128; N],
+    indices: [usize; N],
+}
+
+impl<const N: usize> IdTable<N> {
+    /// Builds the table, panics on an invalid or duplicate ID, which
+    /// fails the build when used in a `const`
+    pub const fn new(ids: [&[u8]; N]) -> Self {
+        let mut keys = [0; N];
+        let mut indices = [0; N];
+        let mut i = 0;
+        while i < N {
+            let key = match (MessageId::new_binary(ids[i]), key(ids[i])) {
+                (Some(_), Some(key)) => key,
+                _ => panic!("invalid message ID"),
+            };
+            // Insertion sort
+            let mut j = i;
+            while j > 0 && keys[j - 1] > key {
+                keys[j] = keys[j - 1];
+                indices[j] = indices[j - 1];
+                j -= 1;
+            }
+            if j > 0 && keys[j - 1] == key {
+                panic!("duplicate message ID");
+            }
+            keys[j] = key;
+            indices[j] = i;
+            i += 1;
+        }
+        IdTable { keys, indices }
+    }
+
+    /// Position of `id` in the list the table was built from
+    #[inline]
+    pub fn index_of(&self, id: &[u8]) -> Option<usize> {
+        let key = key(id)?;
+        let i = self.keys.binary_search(&key).ok()?;
+        Some(self.indices[i])
+    }
+
+    #[inline]
+    pub fn contains(&self, id: &[u8]) -> bool {
+        self.index_of(id).is_some()
+    }
+
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+}
+
+/// Packs an ID's bytes and length into one integer, distinct for every
+/// ID up to [`MessageId::MAX_SIZE`] bytes
+#[inline]
+const fn key(id: &[u8]) -> Option<u128> {
+    if id.len() > MessageId::MAX_SIZE {
+        return None;
+    }
+    let mut key = (id.len() as u128) << 120;
+    let mut i = 0;
+    while i < id.len() {
+        key |= (id[i] as u128) << (8 * i);
+        i += 1;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const IDS: IdTable<6> = IdTable::new([
+        b"speed",
+        b"s",
+        b"led_state",
+        b"abcdefghijklmno",
+        b"ab\0",
+        b"ab",
+    ]);
+
+    #[test]
+    fn lookup() {
+        assert_eq!(IDS.len(), 6);
+        assert_eq!(IDS.index_of(b"speed"), Some(0));
+        assert_eq!(IDS.index_of(b"s"), Some(1));
+        assert_eq!(IDS.index_of(b"led_state"), Some(2));
+        assert_eq!(IDS.index_of(b"abcdefghijklmno"), Some(3));
+        assert_eq!(IDS.index_of(b"ab\0"), Some(4));
+        assert_eq!(IDS.index_of(b"ab"), Some(5));
+        assert_eq!(IDS.index_of(b"a"), None);
+        assert_eq!(IDS.index_of(b"speed2"), None);
+        assert_eq!(IDS.index_of(b""), None);
+        assert_eq!(IDS.index_of(b"abcdefghijklmnop"), None);
+        assert!(!IdTable::new([]).contains(b"s"));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate message ID")]
+    fn duplicate() {
+        IdTable::new([b"a", b"b", b"a"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid message ID")]
+    fn invalid() {
+        IdTable::new([b"a", b"abcdefghijklmnop"]);
+    }
+}
@@ -0,0 +1,407 @@
+//! Streaming packet encoder
+//!
+//! Frames a packet straight from its message ID and payload slices,
+//! COBS-encoding the header, message ID, payload and checksum on the fly
+//! and computing the checksum as the bytes are passed. Encoding a packet
+//! with [`encode_with`] needs neither a packet buffer nor an encoded frame
+//! buffer.
+//!
+//! [`Transport::write`] takes whole frames, so [`send`] stages the frame
+//! in a stack buffer, leaving out only the packet buffer.
+
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use crate::wire::{packet, CrcAccumulator, Framing, Packet};
+use core::convert::Infallible;
+use core::fmt;
+
+/// Error returned by [`encode_with`] and [`send`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error<E> {
+    Packet(packet::Error),
+    Write(E),
+}
+
+impl<E> Error<E> {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Packet(e) => e.code(),
+            Error::Write(_) => 0x62,
+        }
+    }
+}
+
+impl<E> From<packet::Error> for Error<E> {
+    fn from(e: packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Packet(e) => write!(f, "Encountered a packet error. {}", e),
+            Error::Write(e) => write!(f, "Write error. {}", e),
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Packet(e) => Some(e),
+            Error::Write(e) => Some(e),
+        }
+    }
+}
+
+/// Longest run of non-zero bytes in one COBS block
+const MAX_BLOCK: usize = 254;
+
+/// The unencoded packet, as the header, message ID and payload parts
+/// followed by the checksum, with a cursor at the next byte to encode
+struct Source<'a> {
+    parts: [&'a [u8]; 3],
+    /// Taken once every checksummed byte has been scanned
+    crc: Option<CrcAccumulator>,
+    checksum: [u8; Packet::<&[u8]>::CHECKSUM_SIZE],
+    part: usize,
+    pos: usize,
+}
+
+impl<'a> Source<'a> {
+    const CHECKSUM: usize = 3;
+    const END: usize = 4;
+
+    fn new(header: &'a [u8], msg_id: &'a [u8], payload: &'a [u8]) -> Self {
+        Source {
+            parts: [header, msg_id, payload],
+            crc: Some(CrcAccumulator::new()),
+            checksum: [0; Packet::<&[u8]>::CHECKSUM_SIZE],
+            part: 0,
+            pos: 0,
+        }
+    }
+
+    fn bytes(&self, part: usize) -> &[u8] {
+        if part < Self::CHECKSUM {
+            self.parts[part]
+        } else {
+            &self.checksum
+        }
+    }
+
+    /// Finds the block at the cursor, returns its number of non-zero bytes
+    /// and whether a zero ends it.
+    /// Every byte is scanned once, ahead of being written, so this is
+    /// where the checksum is computed.
+    fn scan(&mut self) -> (usize, bool) {
+        let (mut part, mut pos, mut len) = (self.part, self.pos, 0);
+        while part < Self::END && len < MAX_BLOCK {
+            if part == Self::CHECKSUM {
+                if let Some(crc) = self.crc.take() {
+                    self.checksum = crc.finalize().to_le_bytes();
+                }
+            }
+            let bytes = if part < Self::CHECKSUM {
+                self.parts[part]
+            } else {
+                &self.checksum[..]
+            };
+            let rest = &bytes[pos..];
+            let rest = &rest[..rest.len().min(MAX_BLOCK - len)];
+            let zero = rest.iter().position(|&b| b == 0);
+            let scanned = zero.map_or(rest.len(), |i| i + 1);
+            if let Some(crc) = self.crc.as_mut() {
+                crc.update(&rest[..scanned]);
+            }
+            if let Some(i) = zero {
+                return (len + i, true);
+            }
+            len += scanned;
+            pos += scanned;
+            if pos == bytes.len() {
+                part += 1;
+                pos = 0;
+            }
+        }
+        (len, false)
+    }
+
+    /// Writes the `len` non-zero bytes at the cursor, then steps over
+    /// the zero ending the block
+    fn emit<E, W>(&mut self, mut len: usize, zero: bool, write: &mut W) -> Result<(), E>
+    where
+        W: FnMut(&[u8]) -> Result<(), E>,
+    {
+        while len > 0 {
+            let bytes = self.bytes(self.part);
+            let part_len = bytes.len();
+            let size = (part_len - self.pos).min(len);
+            if size != 0 {
+                write(&bytes[self.pos..self.pos + size])?;
+            }
+            len -= size;
+            self.pos += size;
+            if self.pos == part_len {
+                self.part += 1;
+                self.pos = 0;
+            }
+        }
+        if zero {
+            while self.pos == self.bytes(self.part).len() {
+                self.part += 1;
+                self.pos = 0;
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Frames a non-offset packet, passing the encoded bytes to `write` in
+/// order, ending with the frame delimiter.
+/// Returns the frame size.
+///
+/// `write` is called with the payload and message ID sub-slices as they
+/// are, and with the one byte COBS codes in between, so it suits FIFOs
+/// and byte-stream links rather than a [`Transport`].
+pub fn encode_with<E, W>(
+    id: MessageId<'_>,
+    typ: MessageType,
+    internal: bool,
+    response: bool,
+    acknum: AckNum,
+    payload: &[u8],
+    mut write: W,
+) -> Result<usize, Error<E>>
+where
+    W: FnMut(&[u8]) -> Result<(), E>,
+{
+    if payload.len() > Packet::<&[u8]>::MAX_PAYLOAD_SIZE {
+        return Err(packet::Error::InvalidDataLength.into());
+    }
+    let mut header = [0_u8; Packet::<&[u8]>::HEADER_SIZE];
+    let mut p = Packet::new_unchecked(&mut header[..]);
+    p.set_data_length(payload.len() as u16)?;
+    p.set_typ(typ);
+    p.set_internal(internal);
+    p.set_offset(false);
+    p.set_id_length(id.len() as u8)?;
+    p.set_response(response);
+    p.set_acknum(acknum);
+
+    let mut src = Source::new(&header, id.as_bytes(), payload);
+    let mut size = 0;
+    loop {
+        let (len, zero) = src.scan();
+        write(&[len as u8 + 1]).map_err(Error::Write)?;
+        src.emit(len, zero, &mut write).map_err(Error::Write)?;
+        size += 1 + len;
+        // A full block ending the packet needs no empty block after it
+        if !zero && (len < MAX_BLOCK || src.part == Source::END) {
+            break;
+        }
+    }
+    write(&[0]).map_err(Error::Write)?;
+    Ok(size + 1)
+}
+
+/// Frames a non-offset packet into an `N` byte stack buffer, see
+/// [`encode_with`], then writes the whole frame to `transport`.
+///
+/// Nothing is written if the frame could need more than `N` bytes,
+/// `N` of [`Framing::max_encoded_len`] of the packet size always fits.
+pub fn send<const N: usize, T: Transport>(
+    transport: &mut T,
+    id: MessageId<'_>,
+    typ: MessageType,
+    internal: bool,
+    response: bool,
+    acknum: AckNum,
+    payload: &[u8],
+) -> Result<usize, Error<T::Error>> {
+    let needed = Framing::max_encoded_len(Packet::<&[u8]>::buffer_len(id.len(), payload.len()));
+    if needed > N {
+        return Err(packet::Error::IncompletePayload { needed, got: N }.into());
+    }
+    let mut frame = [0_u8; N];
+    let mut end = 0;
+    let size = encode_with::<Infallible, _>(id, typ, internal, response, acknum, payload, |b| {
+        frame[end..end + b.len()].copy_from_slice(b);
+        end += b.len();
+        Ok(())
+    })
+    .map_err(|e| match e {
+        Error::Packet(e) => Error::Packet(e),
+        Error::Write(never) => match never {},
+    })?;
+    transport.write(&frame[..size]).map_err(Error::Write)?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::transport::test_util::Buffers;
+    use crate::wire::limits::ProtocolLimits;
+    use crate::wire::Framing;
+    use pretty_assertions::assert_eq;
+    use std::vec::Vec;
+
+    static MSG_I8: [u8; 9 + 2] = [
+        0x0A, // framing
+        0x01, 0x14, 0x63, // header
+        0x61, 0x62, 0x63, // msgid
+        0x2A, // payload
+        0xB8, 0xA3, // crc
+        0x00, // framing
+    ];
+
+    fn encode(payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        let id = MessageId::new(b"speed").unwrap();
+        let size = encode_with::<(), _>(
+            id,
+            MessageType::U8,
+            false,
+            false,
+            AckNum::new(2).unwrap(),
+            payload,
+            |b| {
+                frame.extend_from_slice(b);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(size, frame.len());
+        frame
+    }
+
+    /// The same packet built in a buffer and framed in one go
+    fn encode_buffered(payload: &[u8]) -> Vec<u8> {
+        let id = MessageId::new(b"speed").unwrap();
+        let mut raw = std::vec![0_u8; Packet::<&[u8]>::buffer_len(id.len(), payload.len())];
+        packet::emit(
+            &mut raw,
+            id,
+            MessageType::U8,
+            false,
+            false,
+            AckNum::new(2).unwrap(),
+            payload,
+        )
+        .unwrap();
+        let mut frame = std::vec![0_u8; Framing::max_encoded_len(raw.len()) + 1];
+        let size = Framing::encode_buf(&raw, &mut frame);
+        frame.truncate(size);
+        frame
+    }
+
+    #[test]
+    fn matches_buffered_encoding() {
+        let mut transport = Buffers::new(&[]);
+        let id = MessageId::new(b"abc").unwrap();
+        let size = send::<16, _>(
+            &mut transport,
+            id,
+            MessageType::I8,
+            false,
+            false,
+            AckNum::new(3).unwrap(),
+            &[0x2A],
+        )
+        .unwrap();
+        assert_eq!(size, MSG_I8.len());
+        assert_eq!(transport.written(), &MSG_I8[..]);
+        assert_eq!(transport.writes, 1);
+
+        for len in (0..=520).chain([1023]) {
+            let non_zero: Vec<u8> = (0..len).map(|i| (i % 255) as u8 + 1).collect();
+            assert_eq!(encode(&non_zero), encode_buffered(&non_zero), "{}", len);
+            let zeros = std::vec![0_u8; len];
+            assert_eq!(encode(&zeros), encode_buffered(&zeros), "{}", len);
+            let mixed: Vec<u8> = (0..len).map(|i| (i % 7) as u8).collect();
+            assert_eq!(encode(&mixed), encode_buffered(&mixed), "{}", len);
+        }
+    }
+
+    #[test]
+    fn decodes() {
+        let payload: Vec<u8> = (0..600).map(|i| (i % 256) as u8).collect();
+        let frame = encode(&payload);
+        let mut storage = [0_u8; ProtocolLimits::DECODER_STORAGE_SIZE];
+        let mut dec = Decoder::new(&mut storage);
+        let (size, p) = dec.decode_slice(&frame);
+        let p = p.unwrap().unwrap();
+        // The delimiter is left for the next call
+        assert_eq!(size, frame.len() - 1);
+        assert_eq!(p.msg_id().unwrap(), b"speed");
+        assert_eq!(p.response(), false);
+        assert_eq!(p.acknum().get(), 2);
+        assert_eq!(p.payload(), &payload[..]);
+    }
+
+    #[test]
+    fn errors() {
+        let id = MessageId::new(b"abc").unwrap();
+        let payload = [0_u8; Packet::<&[u8]>::MAX_PAYLOAD_SIZE + 1];
+        let res = encode_with::<(), _>(
+            id,
+            MessageType::I8,
+            false,
+            false,
+            AckNum::NONE,
+            &payload,
+            |_| Ok(()),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            Error::Packet(packet::Error::InvalidDataLength)
+        );
+
+        let mut writes = 0;
+        let res = encode_with(
+            id,
+            MessageType::I8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1, 2],
+            |_| {
+                writes += 1;
+                if writes == 2 {
+                    Err("full")
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        assert_eq!(res.unwrap_err(), Error::Write("full"));
+
+        let mut transport = Buffers::new(&[]);
+        let res = send::<10, _>(
+            &mut transport,
+            id,
+            MessageType::I8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            Error::Packet(packet::Error::IncompletePayload {
+                needed: 11,
+                got: 10
+            })
+        );
+        assert!(transport.written().is_empty());
+    }
+}
@@ -1,13 +1,121 @@
-use err_derive::Error;
+//! The crate's top-level error, which the module errors convert into
+//!
+//! Every error variant has a stable [`Error::code`], for reporting over the
+//! wire or to a UI where the `Display` text doesn't fit. Wrapping variants
+//! report the code of the error they wrap, so codes are unique across the
+//! crate:
+//!
+//! | Codes  | Errors                          |
+//! |--------|---------------------------------|
+//! | `0x0_` | this module, `collections`      |
+//! | `0x1_` | `wire::packet`                  |
+//! | `0x2_` | `wire::framing`                 |
+//! | `0x3_` | `decoder`                       |
+//! | `0x4_` | `custom`                        |
+//! | `0x5_` | `host::connection`              |
+//! | `0x6_` | `transport`, `encoder`          |
 
-#[derive(Copy, Clone, Debug, Error)]
+use crate::decoder;
+use crate::wire::{framing, packet};
+use core::fmt;
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Error {
-    #[error(display = "Packet error. {}", _0)]
-    Packet(#[source] crate::wire::packet::Error),
+    Packet(packet::Error),
+    Framing(framing::Error),
+    Decoder(decoder::Error),
+    FrameTooLarge,
+    Capacity,
+}
+
+impl Error {
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Packet(e) => e.code(),
+            Error::Framing(e) => e.code(),
+            Error::Decoder(e) => e.code(),
+            Error::FrameTooLarge => 0x01,
+            Error::Capacity => 0x02,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Packet(e) => write!(f, "Packet error. {}", e),
+            Error::Framing(e) => write!(f, "Framing error. {}", e),
+            Error::Decoder(e) => write!(f, "Decoder error. {}", e),
+            Error::FrameTooLarge => f.write_str("Frame exceeds the maximum frame size"),
+            Error::Capacity => f.write_str("Not enough capacity"),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Packet(e) => Some(e),
+            Error::Framing(e) => Some(e),
+            Error::Decoder(e) => Some(e),
+            Error::FrameTooLarge | Error::Capacity => None,
+        }
+    }
+}
+
+impl From<packet::Error> for Error {
+    fn from(e: packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+impl From<framing::Error> for Error {
+    fn from(e: framing::Error) -> Self {
+        Error::Framing(e)
+    }
+}
+
+impl From<decoder::Error> for Error {
+    fn from(e: decoder::Error) -> Self {
+        Error::Decoder(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const INVALID_CHECKSUM: packet::Error = packet::Error::InvalidChecksum {
+        expected: 0,
+        computed: 1,
+    };
 
-    #[error(display = "Framing error. {}", _0)]
-    Framing(#[source] crate::wire::framing::Error),
+    #[test]
+    fn codes() {
+        let e: Error = INVALID_CHECKSUM.into();
+        assert_eq!(e.code(), 0x13);
+        let e: Error = decoder::Error::from(INVALID_CHECKSUM).into();
+        assert_eq!(e.code(), 0x13);
+        let e: Error = decoder::Error::InsufficientBufferSize.into();
+        assert_eq!(e.code(), 0x30);
+        let e: Error = framing::Error::from(corncobs::CobsError::Corrupt).into();
+        assert_eq!(e.code(), 0x21);
+        assert_eq!(Error::FrameTooLarge.code(), 0x01);
+    }
 
-    #[error(display = "Decoder error. {}", _0)]
-    Decoder(#[source] crate::decoder::Error),
+    #[test]
+    fn sources() {
+        use core::error::Error as _;
+        let e: Error = decoder::Error::from(INVALID_CHECKSUM).into();
+        let source = e.source().unwrap().source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<packet::Error>(),
+            Some(&INVALID_CHECKSUM)
+        );
+        assert!(Error::Capacity.source().is_none());
+    }
 }
@@ -0,0 +1,45 @@
+//! Logging macros, forwarded to defmt when the `defmt` feature is enabled
+
+#![allow(unused_macros)]
+
+#[cfg(feature = "defmt")]
+macro_rules! trace {
+    ($($arg:expr),+ $(,)?) => {
+        ::defmt::trace!($($arg),+)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! debug {
+    ($($arg:expr),+ $(,)?) => {
+        ::defmt::debug!($($arg),+)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! warn {
+    ($($arg:expr),+ $(,)?) => {
+        ::defmt::warn!($($arg),+)
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! trace {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let _ = ($(&$arg),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! debug {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let _ = ($(&$arg),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! warn {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let _ = ($(&$arg),*);
+    }};
+}
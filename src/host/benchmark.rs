@@ -0,0 +1,176 @@
+//! Link qualification: round-trip time, throughput and loss
+
+use crate::host::connection::{Connection, Error};
+use crate::host::keepalive::{Keepalive, Stats};
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use std::io;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// Parameters for [`Connection::benchmark`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Benchmark {
+    /// Heartbeats sent one at a time to measure round-trip time
+    pub heartbeats: usize,
+    /// How long to stream pipelined heartbeats for
+    pub stream_duration: Duration,
+    /// Heartbeats in flight while streaming, at most 128
+    pub window: usize,
+    /// How long to wait for each heartbeat before counting it as lost
+    pub timeout: Duration,
+}
+
+impl Default for Benchmark {
+    fn default() -> Self {
+        Self {
+            heartbeats: 20,
+            stream_duration: Duration::from_secs(1),
+            window: 8,
+            timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Results of [`Connection::benchmark`]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Round-trip statistics of the sequential heartbeats
+    pub rtt: Stats,
+    pub stream_sent: usize,
+    pub stream_received: usize,
+    pub stream_elapsed: Duration,
+    /// Heartbeat responses received per second while streaming
+    pub packets_per_sec: f64,
+    /// Packet bytes (excluding framing) received per second while streaming
+    pub bytes_per_sec: f64,
+    /// Fraction of all heartbeats sent that went unanswered
+    pub loss_rate: f64,
+}
+
+impl<T: Transport<Error = io::Error>> Connection<T> {
+    /// Measures heartbeat round-trip time, then streams heartbeats with up to
+    /// `window` in flight to measure sustained throughput, and reports the
+    /// loss rate across both.
+    ///
+    /// Unrelated packets received meanwhile stay queued.
+    pub fn benchmark(&mut self, config: &Benchmark) -> Result<Summary, Error> {
+        let mut summary = Summary::default();
+        let mut value = 0_u8;
+
+        for _ in 0..config.heartbeats {
+            value = value.wrapping_add(1);
+            self.send_heartbeat(value)?;
+            summary.rtt.sent += 1;
+            let sent = Instant::now();
+            let reply = self.recv_matching(config.timeout, |p| {
+                Keepalive::is_response(p) && p.payload().ok() == Some(&[value][..])
+            })?;
+            if reply.is_some() {
+                summary.rtt.record_rtt(sent.elapsed());
+            } else {
+                summary.rtt.missed += 1;
+                summary.rtt.consecutive_missed += 1;
+            }
+        }
+
+        let window = config.window.clamp(1, 128);
+        let mut in_flight: Vec<(u8, Instant)> = Vec::with_capacity(window);
+        let mut bytes = 0;
+        let mut lost = 0;
+        let start = Instant::now();
+        loop {
+            let streaming = start.elapsed() < config.stream_duration;
+            if !streaming && in_flight.is_empty() {
+                break;
+            }
+            while streaming && in_flight.len() < window {
+                value = value.wrapping_add(1);
+                self.send_heartbeat(value)?;
+                in_flight.push((value, Instant::now()));
+                summary.stream_sent += 1;
+            }
+
+            let reply = self.recv_matching(Duration::ZERO, |p| {
+                Keepalive::is_response(p)
+                    && matches!(p.payload(), Ok([v]) if in_flight.iter().any(|(f, _)| f == v))
+            })?;
+            if let Some(p) = reply {
+                let v = p.payload()?[0];
+                in_flight.retain(|(f, _)| *f != v);
+                summary.stream_received += 1;
+                bytes += p.as_ref().len();
+            }
+
+            let before = in_flight.len();
+            in_flight.retain(|(_, sent)| sent.elapsed() < config.timeout);
+            lost += before - in_flight.len();
+        }
+        summary.stream_elapsed = start.elapsed();
+
+        let secs = summary.stream_elapsed.as_secs_f64();
+        if secs > 0.0 {
+            summary.packets_per_sec = summary.stream_received as f64 / secs;
+            summary.bytes_per_sec = bytes as f64 / secs;
+        }
+        let sent = summary.rtt.sent + summary.stream_sent;
+        if sent != 0 {
+            summary.loss_rate = (summary.rtt.missed + lost) as f64 / sent as f64;
+        }
+        Ok(summary)
+    }
+
+    fn send_heartbeat(&mut self, value: u8) -> Result<(), Error> {
+        self.send_message(
+            MessageId::INTERNAL_HEARTBEAT,
+            MessageType::U8,
+            true,
+            true,
+            AckNum::NONE,
+            &[value],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::device::Device;
+    use crate::host::mock::MockDevice;
+    use approx::assert_relative_eq;
+    use pretty_assertions::assert_eq;
+
+    fn config() -> Benchmark {
+        Benchmark {
+            heartbeats: 10,
+            stream_duration: Duration::from_millis(20),
+            window: 4,
+            timeout: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn lossless_link() {
+        let mut c = Connection::new(MockDevice::new(Device::new()));
+        let s = c.benchmark(&config()).unwrap();
+        assert_eq!(s.rtt.sent, 10);
+        assert_eq!(s.rtt.received, 10);
+        assert!(s.rtt.mean_rtt.is_some());
+        assert!(s.stream_sent > 0);
+        assert_eq!(s.stream_received, s.stream_sent);
+        assert!(s.packets_per_sec > 0.0);
+        // Each response is a 7 byte packet
+        assert_relative_eq!(s.bytes_per_sec, s.packets_per_sec * 7.0);
+        assert_eq!(s.loss_rate, 0.0);
+    }
+
+    #[test]
+    fn lossy_link() {
+        let mut c = Connection::new(MockDevice::new(Device::new()).with_drop_rate(1.0));
+        let s = c.benchmark(&config()).unwrap();
+        assert_eq!(s.rtt.received, 0);
+        assert_eq!(s.rtt.missed, 10);
+        assert_eq!(s.stream_received, 0);
+        assert_eq!(s.loss_rate, 1.0);
+    }
+}
@@ -0,0 +1,205 @@
+//! Frame forwarding between a device and a remote client
+
+use crate::host::connection::Error;
+use crate::host::framer::{encode_frame, Deframer, MAX_FRAME_SIZE, READ_CHUNK_SIZE};
+use crate::transport::Transport;
+use crate::wire::Packet;
+use std::io;
+use std::vec::Vec;
+
+/// Frame counts kept by a [`Bridge`]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Stats {
+    pub to_device: usize,
+    pub to_client: usize,
+    /// Invalid frames, from either side, that weren't forwarded
+    pub dropped: usize,
+}
+
+/// Forwards valid frames between a device (usually a serial port) and a
+/// client (usually a TCP socket) in both directions.
+/// Invalid frames are dropped rather than passed along.
+pub struct Bridge<D, C> {
+    device: D,
+    client: C,
+    from_device: Deframer,
+    from_client: Deframer,
+    tx_buf: Vec<u8>,
+    stats: Stats,
+}
+
+impl<D, C> Bridge<D, C>
+where
+    D: Transport<Error = io::Error>,
+    C: Transport<Error = io::Error>,
+{
+    pub fn new(device: D, client: C) -> Self {
+        Self {
+            device,
+            client,
+            from_device: Deframer::new(),
+            from_client: Deframer::new(),
+            tx_buf: Vec::with_capacity(MAX_FRAME_SIZE),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    /// Swaps in a new client, e.g. after the previous one disconnected.
+    /// Any partial frame from the previous client is discarded.
+    pub fn replace_client(&mut self, client: C) -> C {
+        self.from_client.reset();
+        core::mem::replace(&mut self.client, client)
+    }
+
+    pub fn into_inner(self) -> (D, C) {
+        (self.device, self.client)
+    }
+
+    /// Reads whatever is available on both sides and forwards complete
+    /// frames, returns the number of frames forwarded.
+    ///
+    /// Returns [`Error::Disconnected`] once either side reaches end of file.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let mut chunk = [0_u8; READ_CHUNK_SIZE];
+        let mut count = 0;
+
+        let size = read(&mut self.device, &mut chunk)?;
+        let mut bytes = &chunk[..size];
+        while !bytes.is_empty() {
+            let (used, res) = self.from_device.push_slice(bytes);
+            bytes = &bytes[used..];
+            if let Some(res) = res {
+                if forward(&mut self.client, &mut self.tx_buf, res)? {
+                    self.stats.to_client = self.stats.to_client.saturating_add(1);
+                    count += 1;
+                } else {
+                    self.stats.dropped = self.stats.dropped.saturating_add(1);
+                }
+            }
+        }
+
+        let size = read(&mut self.client, &mut chunk)?;
+        let mut bytes = &chunk[..size];
+        while !bytes.is_empty() {
+            let (used, res) = self.from_client.push_slice(bytes);
+            bytes = &bytes[used..];
+            if let Some(res) = res {
+                if forward(&mut self.device, &mut self.tx_buf, res)? {
+                    self.stats.to_device = self.stats.to_device.saturating_add(1);
+                    count += 1;
+                } else {
+                    self.stats.dropped = self.stats.dropped.saturating_add(1);
+                }
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+fn read<T: Transport<Error = io::Error>>(io: &mut T, buf: &mut [u8]) -> Result<usize, Error> {
+    match io.read(buf) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            log!(info, "Bridge peer disconnected");
+            Err(Error::Disconnected)
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Re-frames and writes valid packets, returns false if the frame was dropped
+fn forward<T: Transport<Error = io::Error>>(
+    io: &mut T,
+    tx_buf: &mut Vec<u8>,
+    res: Result<Packet<Vec<u8>>, Error>,
+) -> Result<bool, Error> {
+    let packet = match res {
+        Ok(p) => p.into_inner(),
+        Err(_) => return Ok(false),
+    };
+    encode_frame(&packet, tx_buf);
+    io.write(tx_buf)?;
+    io.flush()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use crate::message::{AckNum, MessageType};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn forward_both_directions() {
+        let req = encode_packet(b"i", MessageType::Callback, true, true, AckNum::NONE, &[]);
+        let resp = encode_packet(
+            b"i",
+            MessageType::U16,
+            true,
+            false,
+            AckNum::NONE,
+            &[0xD2, 0x04],
+        );
+        let mut bad = resp.clone();
+        bad[4] ^= 0x01;
+
+        let mut device = Loopback::default();
+        device.push_rx(&bad);
+        device.push_rx(&resp);
+        let mut client = Loopback::default();
+        client.push_rx(&req[..2]);
+
+        let mut b = Bridge::new(device, client);
+        assert_eq!(b.poll().unwrap(), 1);
+        assert_eq!(b.client().tx, resp);
+        assert!(b.device().tx.is_empty());
+
+        b.client.push_rx(&req[2..]);
+        assert_eq!(b.poll().unwrap(), 1);
+        assert_eq!(b.device().tx, req);
+        assert_eq!(
+            b.stats(),
+            Stats {
+                to_device: 1,
+                to_client: 1,
+                dropped: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn disconnected() {
+        use std::io::{Read, Write};
+
+        struct Closed;
+        impl Read for Closed {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+        }
+        impl Write for Closed {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut b = Bridge::new(Loopback::default(), Closed);
+        assert!(matches!(b.poll(), Err(Error::Disconnected)));
+    }
+}
@@ -0,0 +1,317 @@
+//! Offline decoding of recorded traffic
+
+use crate::host::connection::Error;
+use crate::host::framer::Deframer;
+use crate::host::session;
+use crate::host::sniff::{write_line, Direction, Output};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::io::Write;
+use std::time::Duration;
+
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+const LINKTYPE_USBPCAP: u32 = 249;
+
+const USB_TRANSFER_BULK: u8 = 3;
+const USB_DIR_IN: u8 = 0x80;
+
+/// Decodes the frames in a capture, writing them to `out` the same way
+/// the live [`Sniffer`](crate::host::Sniffer) does. Returns the number of frames seen.
+///
+/// Session logs written by a [`Recorder`](crate::host::session::Recorder)
+/// are decoded in both directions.
+///
+/// Captures starting with a pcap magic number are treated as pcap files,
+/// USB captures (usbmon or USBPcap) have their bulk transfer data decoded,
+/// the direction taken from the endpoint. Any other link type is decoded
+/// as-is in `direction`.
+///
+/// Anything else is a raw binary dump of the bytes seen travelling in `direction`.
+pub fn decode_capture<W: Write>(
+    capture: &[u8],
+    direction: Direction,
+    output: Output,
+    out: &mut W,
+) -> Result<usize, Error> {
+    let mut decoder = CaptureDecoder::new(output);
+    if session::is_session(capture) {
+        for r in session::parse(capture)?.iter() {
+            decoder.data(Some(r.timestamp), r.direction, &r.data, out)?;
+        }
+        return Ok(decoder.count);
+    }
+    match pcap_byte_order(capture) {
+        Some((big_endian, nanos)) => {
+            if big_endian {
+                decoder.pcap::<BigEndian, W>(capture, nanos, direction, out)
+            } else {
+                decoder.pcap::<LittleEndian, W>(capture, nanos, direction, out)
+            }
+        }
+        None => decoder.data(None, direction, capture, out),
+    }
+}
+
+/// Returns (big endian, nanosecond resolution) for pcap files
+fn pcap_byte_order(capture: &[u8]) -> Option<(bool, bool)> {
+    match capture.get(..4)? {
+        [0xD4, 0xC3, 0xB2, 0xA1] => Some((false, false)),
+        [0x4D, 0x3C, 0xB2, 0xA1] => Some((false, true)),
+        [0xA1, 0xB2, 0xC3, 0xD4] => Some((true, false)),
+        [0xA1, 0xB2, 0x3C, 0x4D] => Some((true, true)),
+        _ => None,
+    }
+}
+
+struct CaptureDecoder {
+    output: Output,
+    host_to_device: Deframer,
+    device_to_host: Deframer,
+    count: usize,
+}
+
+impl CaptureDecoder {
+    fn new(output: Output) -> Self {
+        Self {
+            output,
+            host_to_device: Deframer::new(),
+            device_to_host: Deframer::new(),
+            count: 0,
+        }
+    }
+
+    fn pcap<B: ByteOrder, W: Write>(
+        &mut self,
+        capture: &[u8],
+        nanos: bool,
+        direction: Direction,
+        out: &mut W,
+    ) -> Result<usize, Error> {
+        if capture.len() < PCAP_HEADER_SIZE {
+            return Err(Error::InvalidCapture);
+        }
+        let link_type = B::read_u32(&capture[20..24]);
+        let mut rest = &capture[PCAP_HEADER_SIZE..];
+        while !rest.is_empty() {
+            if rest.len() < PCAP_RECORD_HEADER_SIZE {
+                return Err(Error::InvalidCapture);
+            }
+            let secs = B::read_u32(&rest[0..4]);
+            let frac = B::read_u32(&rest[4..8]);
+            let incl_len = B::read_u32(&rest[8..12]) as usize;
+            let record = rest
+                .get(PCAP_RECORD_HEADER_SIZE..PCAP_RECORD_HEADER_SIZE + incl_len)
+                .ok_or(Error::InvalidCapture)?;
+            rest = &rest[PCAP_RECORD_HEADER_SIZE + incl_len..];
+
+            let timestamp = if nanos {
+                Duration::new(secs.into(), frac)
+            } else {
+                Duration::new(secs.into(), frac.saturating_mul(1000))
+            };
+            let (dir, data) = match link_type {
+                LINKTYPE_USB_LINUX => usbmon_data(record, 48)?,
+                LINKTYPE_USB_LINUX_MMAPPED => usbmon_data(record, 64)?,
+                LINKTYPE_USBPCAP => usbpcap_data(record)?,
+                _ => Some((direction, record)),
+            }
+            .unwrap_or((direction, &[]));
+            self.data(Some(timestamp), dir, data, out)?;
+        }
+        Ok(self.count)
+    }
+
+    fn data<W: Write>(
+        &mut self,
+        timestamp: Option<Duration>,
+        direction: Direction,
+        data: &[u8],
+        out: &mut W,
+    ) -> Result<usize, Error> {
+        let deframer = match direction {
+            Direction::HostToDevice => &mut self.host_to_device,
+            Direction::DeviceToHost => &mut self.device_to_host,
+        };
+        let mut bytes = data;
+        while !bytes.is_empty() {
+            let (used, res) = deframer.push_slice(bytes);
+            bytes = &bytes[used..];
+            if let Some(res) = res {
+                write_line(out, self.output, timestamp, direction, &res)?;
+                self.count += 1;
+            }
+        }
+        Ok(self.count)
+    }
+}
+
+fn endpoint_direction(endpoint: u8) -> Direction {
+    if endpoint & USB_DIR_IN != 0 {
+        Direction::DeviceToHost
+    } else {
+        Direction::HostToDevice
+    }
+}
+
+/// Returns the bulk transfer data of a Linux usbmon record
+fn usbmon_data(record: &[u8], header_size: usize) -> Result<Option<(Direction, &[u8])>, Error> {
+    if record.len() < header_size {
+        return Err(Error::InvalidCapture);
+    }
+    if record[9] != USB_TRANSFER_BULK {
+        return Ok(None);
+    }
+    Ok(Some((
+        endpoint_direction(record[10]),
+        &record[header_size..],
+    )))
+}
+
+/// Returns the bulk transfer data of a USBPcap record
+fn usbpcap_data(record: &[u8]) -> Result<Option<(Direction, &[u8])>, Error> {
+    if record.len() < 27 {
+        return Err(Error::InvalidCapture);
+    }
+    let header_size = LittleEndian::read_u16(&record[0..2]) as usize;
+    let data = record.get(header_size..).ok_or(Error::InvalidCapture)?;
+    if record[22] != USB_TRANSFER_BULK {
+        return Ok(None);
+    }
+    Ok(Some((endpoint_direction(record[21]), data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::test_io::encode_packet;
+    use crate::message::{AckNum, MessageType};
+    use pretty_assertions::assert_eq;
+    use std::string::String;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn pcap(link_type: u32, records: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = vec![0xD4, 0xC3, 0xB2, 0xA1, 2, 0, 4, 0];
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&0xFFFF_u32.to_le_bytes());
+        bytes.extend_from_slice(&link_type.to_le_bytes());
+        for (secs, usecs, data) in records.iter() {
+            bytes.extend_from_slice(&secs.to_le_bytes());
+            bytes.extend_from_slice(&usecs.to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn usbmon(xfer_type: u8, endpoint: u8, data: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 64];
+        record[8] = b'C';
+        record[9] = xfer_type;
+        record[10] = endpoint;
+        record.extend_from_slice(data);
+        record
+    }
+
+    fn decode(capture: &[u8]) -> String {
+        let mut out = Vec::new();
+        decode_capture(capture, Direction::DeviceToHost, Output::Text, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn raw_capture() {
+        let mut capture = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        capture.extend_from_slice(&encode_packet(
+            b"abc",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[2],
+        ));
+        assert_eq!(decode(&capture), "<< abc U8 = 1\n<< abc U8 = 2\n");
+    }
+
+    #[test]
+    fn pcap_capture() {
+        let frame = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        let (a, b) = frame.split_at(3);
+        let capture = pcap(147, &[(1, 500, a.to_vec()), (2, 250_000, b.to_vec())]);
+        assert_eq!(decode(&capture), "2.250000 << abc U8 = 1\n");
+    }
+
+    #[test]
+    fn usbmon_capture() {
+        let req = encode_packet(b"i", MessageType::Callback, true, true, AckNum::NONE, &[]);
+        let resp = encode_packet(
+            b"i",
+            MessageType::U16,
+            true,
+            false,
+            AckNum::NONE,
+            &[0xD2, 0x04],
+        );
+        let capture = pcap(
+            LINKTYPE_USB_LINUX_MMAPPED,
+            &[
+                (1, 0, usbmon(2, 0x00, &[0xFF; 8])),
+                (1, 10, usbmon(USB_TRANSFER_BULK, 0x02, &req)),
+                (1, 20, usbmon(USB_TRANSFER_BULK, 0x81, &resp)),
+            ],
+        );
+        assert_eq!(
+            decode(&capture),
+            "1.000010 >> i (board_id) Callback internal response\n\
+             1.000020 << i (board_id) U16 internal = 1234\n"
+        );
+    }
+
+    #[test]
+    fn truncated_pcap() {
+        let capture = pcap(147, &[(1, 0, vec![1, 2, 3])]);
+        let mut out = Vec::new();
+        let res = decode_capture(
+            &capture[..capture.len() - 1],
+            Direction::DeviceToHost,
+            Output::Text,
+            &mut out,
+        );
+        assert!(matches!(res, Err(Error::InvalidCapture)));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_output() {
+        let capture = encode_packet(
+            b"i",
+            MessageType::U16,
+            true,
+            false,
+            AckNum::NONE,
+            &[0xD2, 0x04],
+        );
+        let mut out = Vec::new();
+        decode_capture(&capture, Direction::DeviceToHost, Output::Json, &mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "direction": "<<",
+                "id": "i",
+                "name": "board_id",
+                "type": "U16",
+                "internal": true,
+                "response": false,
+                "acknum": 0,
+                "offset": null,
+                "value": {"U16": [1234]},
+                "payload": [0xD2, 0x04],
+            })
+        );
+    }
+}
@@ -0,0 +1,1459 @@
+//! A host-side connection to a device
+
+use crate::ack::{AckManager, Action};
+use crate::decoder;
+use crate::host::device::Device;
+use crate::host::framer::{build_packet, encode_frame, Deframer, MAX_FRAME_SIZE, READ_CHUNK_SIZE};
+use crate::host::reassembly::{Reassembler, Status};
+use crate::host::subscription::{IdPattern, Subscription, SubscriptionId};
+use crate::host::value::{FromValue, Value};
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::nack::{Nack, Reason};
+use crate::transport::Transport;
+use crate::wire::{framing, packet, Packet};
+use core::fmt;
+use std::boxed::Box;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// Received packets kept for [`Connection::recv`], the oldest are dropped
+/// once it's full
+const RX_QUEUE_SIZE: usize = 256;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io(io::Error),
+    Packet(packet::Error),
+    Framing(framing::Error),
+    Decoder(decoder::Error),
+    Timeout,
+    UnexpectedType(MessageType),
+    InvalidValue,
+    AnnouncementCount,
+    FrameTooLarge,
+    /// A fixed capacity buffer is full
+    Capacity,
+    InvalidCapture,
+    Disconnected,
+    UnknownDevice,
+    AckWindowFull,
+    /// The device refused the request
+    Nack(Reason),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error. {}", e),
+            Error::Packet(e) => write!(f, "Packet error. {}", e),
+            Error::Framing(e) => write!(f, "Framing error. {}", e),
+            Error::Decoder(e) => write!(f, "Decoder error. {}", e),
+            Error::Timeout => f.write_str("Timed out waiting for a response"),
+            Error::UnexpectedType(t) => write!(f, "Unexpected message type {}", t),
+            Error::InvalidValue => {
+                f.write_str("The payload could not be decoded as the requested type")
+            }
+            Error::AnnouncementCount => {
+                f.write_str("The announced message ID count doesn't match the IDs received")
+            }
+            Error::FrameTooLarge => f.write_str("Frame exceeds the maximum frame size"),
+            Error::Capacity => f.write_str("Not enough capacity"),
+            Error::InvalidCapture => f.write_str("Malformed capture file"),
+            Error::Disconnected => f.write_str("The other end closed the connection"),
+            Error::UnknownDevice => f.write_str("No device with the given handle"),
+            Error::AckWindowFull => f.write_str("All acknums are awaiting an ack"),
+            Error::Nack(r) => write!(f, "The device refused the request. {}", r),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Packet(e) => Some(e),
+            Error::Framing(e) => Some(e),
+            Error::Decoder(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<packet::Error> for Error {
+    fn from(e: packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+impl From<framing::Error> for Error {
+    fn from(e: framing::Error) -> Self {
+        Error::Framing(e)
+    }
+}
+
+impl Error {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Io(_) => 0x50,
+            Error::Packet(e) => e.code(),
+            Error::Framing(e) => e.code(),
+            Error::Decoder(e) => e.code(),
+            Error::Timeout => 0x51,
+            Error::UnexpectedType(_) => 0x52,
+            Error::InvalidValue => 0x53,
+            Error::AnnouncementCount => 0x54,
+            // The same condition as the top-level error
+            Error::FrameTooLarge => crate::Error::FrameTooLarge.code(),
+            Error::Capacity => crate::Error::Capacity.code(),
+            Error::InvalidCapture => 0x55,
+            Error::Disconnected => 0x56,
+            Error::UnknownDevice => 0x57,
+            Error::AckWindowFull => 0x58,
+            Error::Nack(_) => 0x59,
+        }
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        match e {
+            crate::Error::Packet(e) => Error::Packet(e),
+            crate::Error::Framing(e) => Error::Framing(e),
+            crate::Error::Decoder(decoder::Error::PacketError(e)) => Error::Packet(e),
+            crate::Error::Decoder(e) => Error::Decoder(e),
+            crate::Error::FrameTooLarge => Error::FrameTooLarge,
+            crate::Error::Capacity => Error::Capacity,
+        }
+    }
+}
+
+/// Result of a write sent with [`Connection::send_acked`]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AckOutcome {
+    Acked(AckNum),
+    /// No ack arrived after all the retransmissions
+    Failed(AckNum),
+    /// The device replied with a [`Nack`]
+    Rejected(AckNum, Reason),
+}
+
+impl AckOutcome {
+    pub fn acknum(&self) -> AckNum {
+        match self {
+            AckOutcome::Acked(n) | AckOutcome::Failed(n) | AckOutcome::Rejected(n, _) => *n,
+        }
+    }
+}
+
+struct PendingAck {
+    id: Vec<u8>,
+    packet: Vec<u8>,
+}
+
+/// A connection to a device over a [`Transport`], typically a serial port.
+///
+/// Any `std::io::Read + std::io::Write` stream is a transport, its reads are
+/// expected to return `ErrorKind::TimedOut` or `ErrorKind::WouldBlock`
+/// when no data is available, as serial ports configured with a timeout do.
+pub struct Connection<T> {
+    io: T,
+
+    deframer: Deframer,
+    rx_queue: VecDeque<Packet<Vec<u8>>>,
+    tx_buf: Vec<u8>,
+    valid_pkt_count: usize,
+    invalid_pkt_count: usize,
+
+    subscriptions: Vec<Subscription>,
+    next_subscription_id: usize,
+
+    acks: AckManager,
+    pending_acks: BTreeMap<AckNum, PendingAck>,
+    ack_outcomes: VecDeque<AckOutcome>,
+    epoch: Instant,
+
+    reassembler: Reassembler,
+    reassembled: VecDeque<(Vec<u8>, Value)>,
+    progress: Option<ProgressCallback>,
+}
+
+type ProgressCallback = Box<dyn FnMut(MessageId<'_>, usize, usize)>;
+
+impl<T: Transport<Error = io::Error>> Connection<T> {
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            deframer: Deframer::new(),
+            rx_queue: VecDeque::new(),
+            tx_buf: Vec::with_capacity(MAX_FRAME_SIZE),
+            valid_pkt_count: 0,
+            invalid_pkt_count: 0,
+            subscriptions: Vec::new(),
+            next_subscription_id: 0,
+            acks: AckManager::new(),
+            pending_acks: BTreeMap::new(),
+            ack_outcomes: VecDeque::new(),
+            epoch: Instant::now(),
+            reassembler: Reassembler::new(),
+            reassembled: VecDeque::new(),
+            progress: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+
+    /// Number of valid packets received
+    pub fn count(&self) -> usize {
+        self.valid_pkt_count
+    }
+
+    /// Number of frames received that didn't contain a valid packet
+    pub fn invalid_count(&self) -> usize {
+        self.invalid_pkt_count
+    }
+
+    /// Registers a callback invoked with the decoded value of every
+    /// variable update whose message ID matches `pattern`.
+    ///
+    /// Internal messages (heartbeats, announcements, etc) are not
+    /// dispatched to subscriptions.
+    /// Callbacks run from within [`Connection::recv`] and [`Connection::poll`].
+    pub fn subscribe<P, F>(&mut self, pattern: P, callback: F) -> SubscriptionId
+    where
+        P: Into<IdPattern>,
+        F: FnMut(MessageId<'_>, &Value) + 'static,
+    {
+        let id = SubscriptionId(self.next_subscription_id);
+        self.next_subscription_id = self.next_subscription_id.wrapping_add(1);
+        self.subscriptions.push(Subscription {
+            id,
+            pattern: pattern.into(),
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    /// Removes a subscription, returns false if it didn't exist
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscriptions.len();
+        self.subscriptions.retain(|s| s.id != id);
+        len != self.subscriptions.len()
+    }
+
+    /// Registers a callback invoked with the message ID, bytes received
+    /// and total size as the chunks of a large variable arrive
+    pub fn on_progress<F>(&mut self, callback: F)
+    where
+        F: FnMut(MessageId<'_>, usize, usize) + 'static,
+    {
+        self.progress = Some(Box::new(callback));
+    }
+
+    /// Returns the next large variable reassembled from offset packets.
+    ///
+    /// Offset packets are consumed by the reassembly and never returned
+    /// from [`Connection::recv`], completed variables are also dispatched
+    /// to matching subscriptions.
+    pub fn recv_reassembled(&mut self) -> Option<(Vec<u8>, Value)> {
+        self.reassembled.pop_front()
+    }
+
+    /// Frames and writes a packet
+    pub fn send<B: AsRef<[u8]>>(&mut self, packet: &Packet<B>) -> Result<(), Error> {
+        encode_frame(packet.as_ref(), &mut self.tx_buf);
+        self.io.write(&self.tx_buf)?;
+        self.io.flush()?;
+        Ok(())
+    }
+
+    /// Writes a variable without requesting an acknowledgement
+    pub fn write<V: Into<Value>>(&mut self, id: MessageId<'_>, value: V) -> Result<(), Error> {
+        let value = value.into();
+        self.send_message(
+            id,
+            value.typ(),
+            false,
+            false,
+            AckNum::NONE,
+            &value.to_bytes(),
+        )
+    }
+
+    /// Writes a variable and waits for the device to acknowledge it.
+    ///
+    /// The write is retransmitted up to `retries` times when no ack arrives
+    /// within `timeout`, returning [`Error::Timeout`] once all attempts
+    /// have gone unacknowledged, or [`Error::Nack`] if the device
+    /// refuses the write.
+    pub fn write_acked<V: Into<Value>>(
+        &mut self,
+        id: MessageId<'_>,
+        value: V,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let acknum = self.send_acked(id, value, retries, timeout)?;
+        loop {
+            if let Some(idx) = self.ack_outcomes.iter().position(|o| o.acknum() == acknum) {
+                return match self.ack_outcomes.remove(idx) {
+                    Some(AckOutcome::Acked(_)) => Ok(()),
+                    Some(AckOutcome::Rejected(_, r)) => Err(Error::Nack(r)),
+                    _ => Err(Error::Timeout),
+                };
+            }
+            let size = self.fill()?;
+            self.service_acks()?;
+            if size == 0 {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Writes a variable requesting an ack without waiting for it,
+    /// returns the acknum used.
+    ///
+    /// The write is retransmitted from [`Connection::poll`] up to `retries`
+    /// times when no ack arrives within `timeout`, the result is
+    /// available from [`Connection::take_ack_outcome`].
+    /// Outcomes not taken by the time their acknum is reused are discarded.
+    pub fn send_acked<V: Into<Value>>(
+        &mut self,
+        id: MessageId<'_>,
+        value: V,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<AckNum, Error> {
+        let value = value.into();
+        let timeout = u64::try_from(timeout.as_micros()).unwrap_or(u64::MAX);
+        // Acknums cycle through 1..=7, zero means no ack requested
+        let acknum = self
+            .acks
+            .allocate(self.ticks(), timeout, retries)
+            .ok_or(Error::AckWindowFull)?;
+        self.ack_outcomes.retain(|o| o.acknum() != acknum);
+        let res = build_packet(id, value.typ(), false, true, acknum, &value.to_bytes())
+            .and_then(|p| self.send(&p).map(|_| p));
+        match res {
+            Ok(p) => {
+                let pending = PendingAck {
+                    id: id.as_bytes().to_vec(),
+                    packet: p.into_inner(),
+                };
+                self.pending_acks.insert(acknum, pending);
+                Ok(acknum)
+            }
+            Err(e) => {
+                self.acks.acknowledge(acknum);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the result of a write sent with [`Connection::send_acked`],
+    /// in the order they were resolved
+    pub fn take_ack_outcome(&mut self) -> Option<AckOutcome> {
+        self.ack_outcomes.pop_front()
+    }
+
+    /// Number of writes awaiting an ack
+    pub fn pending_acks(&self) -> usize {
+        self.acks.outstanding()
+    }
+
+    /// Requests the current value of a variable, waiting up to `timeout`
+    /// for the device to reply, returns [`Error::Nack`] if the device
+    /// refuses the query
+    pub fn query(
+        &mut self,
+        id: MessageId<'_>,
+        typ: MessageType,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        self.send_message(id, typ, false, true, AckNum::NONE, &[])?;
+        let p = self
+            .recv_matching(timeout, |p| {
+                (!p.response() && !p.internal() && p.msg_id() == Ok(id)) || is_nack_for(p, id)
+            })?
+            .ok_or(Error::Timeout)?;
+        if let Some(nack) = Nack::from_packet(&p) {
+            return Err(Error::Nack(nack.reason));
+        }
+        Value::parse(p.typ(), p.payload()?).ok_or(Error::InvalidValue)
+    }
+
+    /// Requests the current value of a variable and decodes it as `V`,
+    /// the reply must carry `V::TYPE`
+    pub fn query_as<V: FromValue>(
+        &mut self,
+        id: MessageId<'_>,
+        timeout: Duration,
+    ) -> Result<V, Error> {
+        let value = self.query(id, V::TYPE, timeout)?;
+        if value.typ() != V::TYPE {
+            return Err(Error::UnexpectedType(value.typ()));
+        }
+        V::from_value(&value).ok_or(Error::InvalidValue)
+    }
+
+    /// Runs the discovery and handshake sequence, requesting the board ID,
+    /// the writable message ID announcement and then the tracked variables.
+    ///
+    /// `timeout` applies to each individual response.
+    pub fn handshake(&mut self, timeout: Duration) -> Result<Device, Error> {
+        let mut device = Device::new();
+
+        let p = self.request_internal(MessageId::INTERNAL_BOARD_ID, MessageType::U16, timeout)?;
+        let board_id = Value::parse(p.typ(), p.payload()?).ok_or(Error::InvalidValue)?;
+        let board_id = u16::from_value(&board_id).ok_or(Error::InvalidValue)?;
+        log!(debug, "Handshake: board ID {:#06X}", board_id);
+        device.board_id = Some(board_id);
+
+        self.send_message(
+            MessageId::INTERNAL_AM,
+            MessageType::Callback,
+            true,
+            true,
+            AckNum::NONE,
+            &[],
+        )?;
+        let mut ids: Vec<Vec<u8>> = Vec::new();
+        loop {
+            let p = self
+                .recv_matching(timeout, |p| {
+                    p.internal()
+                        && (p.msg_id() == Ok(MessageId::INTERNAL_AM_LIST)
+                            || p.msg_id() == Ok(MessageId::INTERNAL_AM_END))
+                })?
+                .ok_or(Error::Timeout)?;
+            if p.msg_id()? == MessageId::INTERNAL_AM_LIST {
+                ids.extend(
+                    p.payload()?
+                        .split(|b| *b == b'\0')
+                        .filter(|id| !id.is_empty())
+                        .map(<[u8]>::to_vec),
+                );
+            } else {
+                let count = match Value::parse(p.typ(), p.payload()?) {
+                    Some(Value::U8(v)) if v.len() == 1 => usize::from(v[0]),
+                    Some(Value::U16(v)) if v.len() == 1 => usize::from(v[0]),
+                    _ => return Err(Error::InvalidValue),
+                };
+                if count != ids.len() {
+                    log!(
+                        warn,
+                        "Handshake: {} message IDs announced, {} received",
+                        count,
+                        ids.len()
+                    );
+                    return Err(Error::AnnouncementCount);
+                }
+                log!(debug, "Handshake: {} message IDs announced", count);
+                break;
+            }
+        }
+
+        self.send_message(
+            MessageId::INTERNAL_AV,
+            MessageType::Callback,
+            true,
+            true,
+            AckNum::NONE,
+            &[],
+        )?;
+        while !ids.is_empty() {
+            let p = self
+                .recv_matching(timeout, |p| {
+                    !p.internal()
+                        && p.msg_id()
+                            .map(|id| ids.iter().any(|i| id.as_bytes() == i.as_slice()))
+                            .unwrap_or(false)
+                })?
+                .ok_or(Error::Timeout)?;
+            let id = p.msg_id()?;
+            let value = Value::parse(p.typ(), p.payload()?).ok_or(Error::InvalidValue)?;
+            ids.retain(|i| id.as_bytes() != i.as_slice());
+            device.update(id, value);
+        }
+
+        log!(
+            info,
+            "Handshake complete, {} variables",
+            device.variables.len()
+        );
+        Ok(device)
+    }
+
+    /// Writes every variable of a snapshot to the device, see
+    /// [`Connection::write_acked`].
+    /// Callback variables carry no data and are skipped.
+    pub fn restore(
+        &mut self,
+        snapshot: &Device,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        for (id, value) in snapshot
+            .variables
+            .iter()
+            .filter(|(_, v)| **v != Value::Callback)
+        {
+            let id = MessageId::new(id.as_bytes()).ok_or(packet::Error::InvalidMessageId)?;
+            self.write_acked(id, value.clone(), retries, timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next received packet, if one can be assembled
+    /// from the currently available data.
+    pub fn recv(&mut self) -> Result<Option<Packet<Vec<u8>>>, Error> {
+        while self.rx_queue.is_empty() {
+            if self.fill()? == 0 {
+                break;
+            }
+        }
+        Ok(self.rx_queue.pop_front())
+    }
+
+    /// Receives and dispatches all of the currently available packets,
+    /// returning the number of packets processed
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        self.service_acks()?;
+        let mut cnt = 0;
+        while self.recv()?.is_some() {
+            cnt += 1;
+        }
+        Ok(cnt)
+    }
+
+    /// Waits for a packet satisfying `f`, leaving any other packets
+    /// received in the meantime queued for [`Connection::recv`]
+    pub(crate) fn recv_matching<F>(
+        &mut self,
+        timeout: Duration,
+        f: F,
+    ) -> Result<Option<Packet<Vec<u8>>>, Error>
+    where
+        F: Fn(&Packet<Vec<u8>>) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut expired = false;
+        loop {
+            if let Some(idx) = self.rx_queue.iter().position(&f) {
+                return Ok(self.rx_queue.remove(idx));
+            }
+            if expired {
+                return Ok(None);
+            }
+            // Checked after every read, a device streaming other packets
+            // keeps the reads busy
+            let size = self.fill()?;
+            expired = Instant::now() >= deadline;
+            if size == 0 && !expired {
+                thread::yield_now();
+            }
+        }
+    }
+
+    fn request_internal(
+        &mut self,
+        id: MessageId<'_>,
+        typ: MessageType,
+        timeout: Duration,
+    ) -> Result<Packet<Vec<u8>>, Error> {
+        self.send_message(id, typ, true, true, AckNum::NONE, &[])?;
+        let p = self
+            .recv_matching(timeout, |p| {
+                (p.internal() && !p.response() && p.msg_id() == Ok(id)) || is_nack_for(p, id)
+            })?
+            .ok_or(Error::Timeout)?;
+        match Nack::from_packet(&p) {
+            Some(nack) => Err(Error::Nack(nack.reason)),
+            None => Ok(p),
+        }
+    }
+
+    /// Microseconds since the connection was opened
+    fn ticks(&self) -> u64 {
+        u64::try_from(self.epoch.elapsed().as_micros()).unwrap_or(u64::MAX)
+    }
+
+    /// Retransmits the writes whose ack is overdue, and gives up on
+    /// those out of retries
+    fn service_acks(&mut self) -> Result<(), Error> {
+        let now = self.ticks();
+        while let Some(action) = self.acks.poll(now) {
+            match action {
+                Action::Retransmit(acknum) => {
+                    if let Some(p) = self.pending_acks.get(&acknum) {
+                        log!(debug, "Retransmitting write, acknum {}", acknum);
+                        encode_frame(&p.packet, &mut self.tx_buf);
+                        self.io.write(&self.tx_buf)?;
+                        self.io.flush()?;
+                    }
+                }
+                Action::Expired(acknum) => {
+                    log!(warn, "Write not acknowledged, acknum {}", acknum);
+                    self.pending_acks.remove(&acknum);
+                    self.ack_outcomes.push_back(AckOutcome::Failed(acknum));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a pending write if the packet acknowledges or refuses it
+    fn handle_ack(&mut self, packet: &Packet<Vec<u8>>) -> bool {
+        let acknum = packet.acknum();
+        if packet.response() || !acknum.is_requested() {
+            return false;
+        }
+        let (id, outcome) = match Nack::from_packet(packet) {
+            Some(nack) => (Ok(nack.id), AckOutcome::Rejected(acknum, nack.reason)),
+            None if packet.internal() => return false,
+            None => (packet.msg_id(), AckOutcome::Acked(acknum)),
+        };
+        let matches = match (self.pending_acks.get(&acknum), id) {
+            (Some(pending), Ok(id)) => id.as_bytes() == pending.id.as_slice(),
+            _ => false,
+        };
+        if matches {
+            self.acks.acknowledge(acknum);
+            self.pending_acks.remove(&acknum);
+            self.ack_outcomes.push_back(outcome);
+        }
+        matches
+    }
+
+    pub(crate) fn send_message(
+        &mut self,
+        id: MessageId<'_>,
+        typ: MessageType,
+        internal: bool,
+        response: bool,
+        acknum: AckNum,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let p = build_packet(id, typ, internal, response, acknum, payload)?;
+        self.send(&p)
+    }
+
+    fn fill(&mut self) -> Result<usize, Error> {
+        let mut chunk = [0_u8; READ_CHUNK_SIZE];
+        let size = self.io.read(&mut chunk)?;
+        let mut bytes = &chunk[..size];
+        while !bytes.is_empty() {
+            let (used, packet) = self.deframer.push_slice(bytes);
+            bytes = &bytes[used..];
+            if let Some(packet) = packet {
+                self.handle_frame(packet);
+            }
+        }
+        Ok(size)
+    }
+
+    fn handle_frame(&mut self, packet: Result<Packet<Vec<u8>>, Error>) {
+        match packet {
+            Ok(p) if Reassembler::accepts(&p) => {
+                self.valid_pkt_count = self.valid_pkt_count.saturating_add(1);
+                self.reassemble(&p);
+            }
+            Ok(p) => {
+                self.valid_pkt_count = self.valid_pkt_count.saturating_add(1);
+                if self.handle_ack(&p) {
+                    return;
+                }
+                if !p.internal() {
+                    if let (Ok(id), Ok(payload)) = (p.msg_id(), p.payload()) {
+                        if let Some(value) = Value::parse(p.typ(), payload) {
+                            self.dispatch(id, &value);
+                        }
+                    }
+                }
+                if self.rx_queue.len() >= RX_QUEUE_SIZE {
+                    log!(warn, "Receive queue full, dropped the oldest packet");
+                    self.rx_queue.pop_front();
+                }
+                self.rx_queue.push_back(p);
+            }
+            Err(e) => {
+                log!(warn, "Dropped an invalid packet. {}", e);
+                self.invalid_pkt_count = self.invalid_pkt_count.saturating_add(1);
+            }
+        }
+    }
+
+    fn reassemble(&mut self, packet: &Packet<Vec<u8>>) {
+        let id = match packet.msg_id() {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+        match self.reassembler.push(packet) {
+            Ok(Some(Status::Progress { received, total })) => {
+                if let Some(f) = self.progress.as_mut() {
+                    f(id, received, total);
+                }
+            }
+            Ok(Some(Status::Complete { typ, data })) => {
+                if let Some(f) = self.progress.as_mut() {
+                    f(id, data.len(), data.len());
+                }
+                if let Some(value) = Value::parse(typ, &data) {
+                    self.dispatch(id, &value);
+                    self.reassembled.push_back((id.as_bytes().to_vec(), value));
+                }
+            }
+            Ok(Some(Status::Orphaned)) | Ok(None) | Err(_) => {
+                log!(debug, "Dropped an out of sequence offset packet");
+                self.invalid_pkt_count = self.invalid_pkt_count.saturating_add(1);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, id: MessageId<'_>, value: &Value) {
+        for sub in self
+            .subscriptions
+            .iter_mut()
+            .filter(|s| s.pattern.matches(id))
+        {
+            (sub.callback)(id, value);
+        }
+    }
+}
+
+/// Whether `p` is a [`Nack`] refusing a request for `id`
+fn is_nack_for(p: &Packet<Vec<u8>>, id: MessageId<'_>) -> bool {
+    Nack::from_packet(p).is_some_and(|nack| nack.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::reassembly::test_util::offset_transfer;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use crate::message::MessageType;
+    use crate::wire::Framing;
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::string::{String, ToString};
+    use std::vec;
+
+    #[test]
+    fn recv_packets() {
+        let mut io = Loopback::default();
+        io.push_rx(&encode_packet(
+            b"abc",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        ));
+        io.push_rx(&[0x00, 0x00]);
+        io.push_rx(&encode_packet(
+            b"abc",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[2],
+        ));
+        let mut c = Connection::new(io);
+
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert_eq!(p.payload().unwrap(), &[1]);
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(p.payload().unwrap(), &[2]);
+        assert!(c.recv().unwrap().is_none());
+        assert_eq!(c.count(), 2);
+        assert_eq!(c.invalid_count(), 0);
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let mut io = Loopback::default();
+        let mut bad = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        bad[6] ^= 0x01;
+        io.push_rx(&bad);
+        io.push_rx(&vec![0xFF; MAX_FRAME_SIZE + 1]);
+        io.push_rx(&[0x00]);
+        io.push_rx(&encode_packet(
+            b"abc",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        ));
+        let mut c = Connection::new(io);
+
+        assert_eq!(c.poll().unwrap(), 1);
+        assert_eq!(c.count(), 1);
+        assert_eq!(c.invalid_count(), 2);
+    }
+
+    #[test]
+    fn send_packet() {
+        let bytes = encode_packet(b"abc", MessageType::U8, false, true, AckNum::NONE, &[1]);
+        let mut raw = bytes.clone();
+        let size = Framing::decode_in_place(&mut raw).unwrap();
+        let p = Packet::new(&raw[..size]).unwrap();
+
+        let mut c = Connection::new(Loopback::default());
+        c.send(&p).unwrap();
+        assert_eq!(c.get_ref().tx, bytes);
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut io = Loopback::default();
+        io.push_rx(&encode_packet(
+            b"mot.speed",
+            MessageType::F32,
+            false,
+            false,
+            AckNum::NONE,
+            &[0x14, 0xAE, 0x29, 0x42],
+        ));
+        io.push_rx(&encode_packet(
+            b"led_state",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        ));
+        io.push_rx(&encode_packet(
+            b"h",
+            MessageType::U8,
+            true,
+            false,
+            AckNum::NONE,
+            &[3],
+        ));
+        io.push_rx(&encode_packet(
+            b"mot.current",
+            MessageType::I16,
+            false,
+            false,
+            AckNum::NONE,
+            &[0xFE, 0xFF],
+        ));
+        let mut c = Connection::new(io);
+
+        let all = Rc::new(RefCell::new(Vec::new()));
+        let motor = Rc::new(RefCell::new(Vec::new()));
+        let a = all.clone();
+        let all_sub = c.subscribe("*", move |id, _v: &Value| {
+            a.borrow_mut().push(id.to_string())
+        });
+        let m = motor.clone();
+        c.subscribe("mot.*", move |id, v: &Value| {
+            m.borrow_mut().push((id.to_string(), v.clone()))
+        });
+
+        assert_eq!(c.poll().unwrap(), 4);
+        assert_eq!(
+            *all.borrow(),
+            vec![
+                String::from("mot.speed"),
+                String::from("led_state"),
+                String::from("mot.current")
+            ]
+        );
+        assert_eq!(
+            *motor.borrow(),
+            vec![
+                (String::from("mot.speed"), Value::F32(vec![42.42])),
+                (String::from("mot.current"), Value::I16(vec![-2])),
+            ]
+        );
+
+        assert!(c.unsubscribe(all_sub));
+        assert!(!c.unsubscribe(all_sub));
+    }
+
+    #[test]
+    fn write_unacked() {
+        let mut c = Connection::new(Loopback::default());
+        let id = MessageId::new(b"lit_time").unwrap();
+        c.write(id, 22_u16).unwrap();
+        assert_eq!(
+            c.get_ref().tx,
+            encode_packet(
+                b"lit_time",
+                MessageType::U16,
+                false,
+                false,
+                AckNum::NONE,
+                &[22, 0]
+            )
+        );
+    }
+
+    #[test]
+    fn write_acked_first_attempt() {
+        let mut io = Loopback::default();
+        io.respond(|p, rx| {
+            if p.response() && p.acknum().is_requested() {
+                rx.extend(encode_packet(
+                    b"lit_time",
+                    MessageType::U16,
+                    false,
+                    false,
+                    p.acknum(),
+                    &[],
+                ));
+            }
+        });
+        let mut c = Connection::new(io);
+        let id = MessageId::new(b"lit_time").unwrap();
+        c.write_acked(id, 22_u16, 2, Duration::from_millis(50))
+            .unwrap();
+        c.write_acked(id, 23_u16, 2, Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(
+            c.get_ref().tx,
+            [
+                encode_packet(
+                    b"lit_time",
+                    MessageType::U16,
+                    false,
+                    true,
+                    AckNum::new(1).unwrap(),
+                    &[22, 0]
+                ),
+                encode_packet(
+                    b"lit_time",
+                    MessageType::U16,
+                    false,
+                    true,
+                    AckNum::new(2).unwrap(),
+                    &[23, 0]
+                ),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn write_acked_retries() {
+        let mut io = Loopback::default();
+        let mut writes = 0;
+        io.respond(move |p, rx| {
+            writes += 1;
+            // Drop the first write, ack the retransmission
+            if writes > 1 {
+                rx.extend(encode_packet(
+                    b"a",
+                    MessageType::U8,
+                    false,
+                    false,
+                    p.acknum(),
+                    &[],
+                ));
+            }
+        });
+        let mut c = Connection::new(io);
+        let id = MessageId::new(b"a").unwrap();
+        c.write_acked(id, 1_u8, 1, Duration::from_millis(10))
+            .unwrap();
+        let attempt = encode_packet(
+            b"a",
+            MessageType::U8,
+            false,
+            true,
+            AckNum::new(1).unwrap(),
+            &[1],
+        );
+        assert_eq!(c.get_ref().tx, [attempt.clone(), attempt].concat());
+    }
+
+    #[test]
+    fn write_acked_timeout() {
+        let mut io = Loopback::default();
+        io.respond(|_p, rx| {
+            // Ack for a different acknum and variable
+            rx.extend(encode_packet(
+                b"a",
+                MessageType::U8,
+                false,
+                false,
+                AckNum::new(5).unwrap(),
+                &[],
+            ));
+            rx.extend(encode_packet(
+                b"b",
+                MessageType::U8,
+                false,
+                false,
+                AckNum::new(1).unwrap(),
+                &[],
+            ));
+        });
+        let mut c = Connection::new(io);
+        let id = MessageId::new(b"a").unwrap();
+        let res = c.write_acked(id, 1_u8, 2, Duration::from_millis(5));
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert_eq!(
+            c.get_ref().tx.len(),
+            3 * encode_packet(
+                b"a",
+                MessageType::U8,
+                false,
+                true,
+                AckNum::new(1).unwrap(),
+                &[1]
+            )
+            .len()
+        );
+        // Unrelated packets are still delivered
+        assert_eq!(c.poll().unwrap(), 6);
+    }
+
+    #[test]
+    fn query_typed() {
+        let mut io = Loopback::default();
+        io.respond(|p, rx| {
+            assert!(p.response());
+            assert_eq!(p.data_length(), 0);
+            let id = p.msg_id().unwrap();
+            if id == b"lit_time" {
+                rx.extend(encode_packet(
+                    b"led_state",
+                    MessageType::U8,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    &[1],
+                ));
+                rx.extend(encode_packet(
+                    b"lit_time",
+                    MessageType::U16,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    &[70, 0],
+                ));
+            } else if id == MessageId::BOARD_NAME {
+                rx.extend(encode_packet(
+                    b"name",
+                    MessageType::Char,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    b"my-board",
+                ));
+            } else if id == b"speed" {
+                rx.extend(encode_packet(
+                    b"speed",
+                    MessageType::I32,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    &[0; 4],
+                ));
+            }
+        });
+        let mut c = Connection::new(io);
+        let timeout = Duration::from_millis(10);
+
+        let lit_time = MessageId::new(b"lit_time").unwrap();
+        assert_eq!(c.query_as::<u16>(lit_time, timeout).unwrap(), 70);
+        let name = c
+            .query_as::<String>(MessageId::BOARD_NAME, timeout)
+            .unwrap();
+        assert_eq!(name, "my-board");
+        let speed = MessageId::new(b"speed").unwrap();
+        assert!(matches!(
+            c.query_as::<f32>(speed, timeout),
+            Err(Error::UnexpectedType(MessageType::I32))
+        ));
+        let missing = MessageId::new(b"missing").unwrap();
+        assert!(matches!(
+            c.query_as::<u8>(missing, timeout),
+            Err(Error::Timeout)
+        ));
+
+        // Unrelated update is left for the application
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"led_state");
+        assert_eq!(
+            c.get_ref().tx[..encode_packet(
+                b"lit_time",
+                MessageType::U16,
+                false,
+                true,
+                AckNum::NONE,
+                &[]
+            )
+            .len()],
+            encode_packet(
+                b"lit_time",
+                MessageType::U16,
+                false,
+                true,
+                AckNum::NONE,
+                &[]
+            )
+        );
+    }
+
+    #[test]
+    fn reassemble_large_variable() {
+        let data: Vec<u16> = (0..300_u16).collect();
+        let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut io = Loopback::default();
+        for p in offset_transfer(b"samples", MessageType::U16, &bytes, 256) {
+            let mut enc = vec![0; Framing::max_encoded_len(p.len())];
+            let size = Framing::encode_buf(&p, &mut enc);
+            io.push_rx(&enc[..size]);
+        }
+        io.push_rx(&encode_packet(
+            b"abc",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        ));
+        let mut c = Connection::new(io);
+
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let p = progress.clone();
+        c.on_progress(move |id, received, total| {
+            assert_eq!(id, b"samples");
+            p.borrow_mut().push((received, total));
+        });
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let u = updates.clone();
+        c.subscribe("samples", move |_id, v: &Value| {
+            u.borrow_mut().push(v.clone())
+        });
+
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert!(c.recv().unwrap().is_none());
+        assert_eq!(
+            *progress.borrow(),
+            vec![(0, 600), (256, 600), (512, 600), (600, 600)]
+        );
+        assert_eq!(*updates.borrow(), vec![Value::U16(data.clone())]);
+        assert_eq!(
+            c.recv_reassembled(),
+            Some((b"samples".to_vec(), Value::U16(data)))
+        );
+        assert_eq!(c.recv_reassembled(), None);
+        assert_eq!(c.invalid_count(), 0);
+    }
+
+    /// Replies like the target in the protocol diagram
+    fn handshake_responder(p: &Packet<&[u8]>, rx: &mut VecDeque<u8>) {
+        use MessageType::*;
+        let id = p.msg_id().unwrap();
+        if id == MessageId::INTERNAL_BOARD_ID {
+            rx.extend(encode_packet(
+                b"i",
+                U16,
+                true,
+                false,
+                AckNum::NONE,
+                &[0xEF, 0xBE],
+            ));
+        } else if id == MessageId::INTERNAL_AM {
+            rx.extend(encode_packet(
+                b"u",
+                Custom,
+                true,
+                false,
+                AckNum::NONE,
+                b"led_blink\0led_state\0",
+            ));
+            rx.extend(encode_packet(
+                b"u",
+                Custom,
+                true,
+                false,
+                AckNum::NONE,
+                b"lit_time\0name\0",
+            ));
+            rx.extend(encode_packet(b"v", U8, true, false, AckNum::NONE, &[4]));
+        } else if id == MessageId::INTERNAL_AV {
+            rx.extend(encode_packet(
+                b"led_blink",
+                U8,
+                false,
+                false,
+                AckNum::NONE,
+                &[1],
+            ));
+            rx.extend(encode_packet(
+                b"led_state",
+                U8,
+                false,
+                false,
+                AckNum::NONE,
+                &[0],
+            ));
+            rx.extend(encode_packet(
+                b"name",
+                Char,
+                false,
+                false,
+                AckNum::NONE,
+                b"my-board",
+            ));
+            rx.extend(encode_packet(
+                b"lit_time",
+                U16,
+                false,
+                false,
+                AckNum::NONE,
+                &[0x46, 0x00],
+            ));
+        } else if p.acknum().is_requested() {
+            rx.extend(encode_packet(
+                id.as_bytes(),
+                p.typ(),
+                false,
+                false,
+                p.acknum(),
+                &[],
+            ));
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let mut io = Loopback::default();
+        io.respond(handshake_responder);
+        let mut c = Connection::new(io);
+        let d = c.handshake(Duration::from_millis(10)).unwrap();
+        assert_eq!(d.board_id, Some(0xBEEF));
+        assert_eq!(d.variables.len(), 4);
+        assert_eq!(d.get("led_blink"), Some(&Value::U8(vec![1])));
+        assert_eq!(d.get("led_state"), Some(&Value::U8(vec![0])));
+        assert_eq!(d.get("lit_time"), Some(&Value::U16(vec![70])));
+        assert_eq!(d.get("name"), Some(&Value::Char(b"my-board".to_vec())));
+    }
+
+    #[test]
+    fn handshake_count_mismatch() {
+        let mut io = Loopback::default();
+        io.respond(|p, rx| {
+            let id = p.msg_id().unwrap();
+            if id == MessageId::INTERNAL_AM {
+                rx.extend(encode_packet(
+                    b"u",
+                    MessageType::Custom,
+                    true,
+                    false,
+                    AckNum::NONE,
+                    b"a\0",
+                ));
+                rx.extend(encode_packet(
+                    b"v",
+                    MessageType::U8,
+                    true,
+                    false,
+                    AckNum::NONE,
+                    &[2],
+                ));
+            } else {
+                handshake_responder(p, rx);
+            }
+        });
+        let mut c = Connection::new(io);
+        assert!(matches!(
+            c.handshake(Duration::from_millis(10)),
+            Err(Error::AnnouncementCount)
+        ));
+    }
+
+    #[test]
+    fn restore_snapshot() {
+        let mut io = Loopback::default();
+        io.respond(handshake_responder);
+        let mut c = Connection::new(io);
+        let mut d = Device::new();
+        d.update(MessageId::new(b"led_blink").unwrap(), Value::U8(vec![0]));
+        d.update(MessageId::new(b"lit_time").unwrap(), Value::U16(vec![22]));
+        d.update(MessageId::new(b"reset").unwrap(), Value::Callback);
+        c.restore(&d, 0, Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            c.get_ref().tx,
+            [
+                encode_packet(
+                    b"led_blink",
+                    MessageType::U8,
+                    false,
+                    true,
+                    AckNum::new(1).unwrap(),
+                    &[0]
+                ),
+                encode_packet(
+                    b"lit_time",
+                    MessageType::U16,
+                    false,
+                    true,
+                    AckNum::new(2).unwrap(),
+                    &[22, 0]
+                ),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn acknum_sequence() {
+        let mut c = Connection::new(Loopback::default());
+        let id = MessageId::new(b"a").unwrap();
+        let timeout = Duration::from_secs(1);
+        let nums: Vec<u8> = (0..7)
+            .map(|_| c.send_acked(id, 1_u8, 0, timeout).unwrap().get())
+            .collect();
+        assert_eq!(nums, vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(c.pending_acks(), 7);
+        assert!(matches!(
+            c.send_acked(id, 1_u8, 0, timeout),
+            Err(Error::AckWindowFull)
+        ));
+    }
+
+    #[test]
+    fn send_acked_outcomes() {
+        let mut io = Loopback::default();
+        let mut writes = 0;
+        io.respond(move |p, rx| {
+            writes += 1;
+            // Only ever ack the first write
+            if writes == 1 {
+                rx.extend(encode_packet(
+                    b"a",
+                    MessageType::U8,
+                    false,
+                    false,
+                    p.acknum(),
+                    &[],
+                ));
+            }
+        });
+        let mut c = Connection::new(io);
+        let id = MessageId::new(b"a").unwrap();
+        let timeout = Duration::from_millis(2);
+        assert_eq!(c.send_acked(id, 1_u8, 0, timeout).unwrap().get(), 1);
+        assert_eq!(c.send_acked(id, 2_u8, 1, timeout).unwrap().get(), 2);
+        assert_eq!(c.poll().unwrap(), 0);
+        assert_eq!(
+            c.take_ack_outcome(),
+            Some(AckOutcome::Acked(AckNum::new(1).unwrap()))
+        );
+        assert_eq!(c.take_ack_outcome(), None);
+
+        thread::sleep(timeout);
+        c.poll().unwrap();
+        assert_eq!(c.take_ack_outcome(), None);
+        thread::sleep(timeout);
+        c.poll().unwrap();
+        assert_eq!(
+            c.take_ack_outcome(),
+            Some(AckOutcome::Failed(AckNum::new(2).unwrap()))
+        );
+        assert_eq!(c.pending_acks(), 0);
+        let attempt = encode_packet(
+            b"a",
+            MessageType::U8,
+            false,
+            true,
+            AckNum::new(2).unwrap(),
+            &[2],
+        );
+        assert_eq!(
+            c.get_ref().tx,
+            [
+                encode_packet(
+                    b"a",
+                    MessageType::U8,
+                    false,
+                    true,
+                    AckNum::new(1).unwrap(),
+                    &[1]
+                ),
+                attempt.clone(),
+                attempt
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn error_codes_from_crate_errors() {
+        let e: Error = crate::Error::from(decoder::Error::InsufficientBufferSize).into();
+        assert_eq!(e.code(), 0x30);
+        let e: Error = crate::Error::Capacity.into();
+        assert_eq!(e.code(), 0x02);
+        let e: Error = crate::Error::FrameTooLarge.into();
+        assert_eq!(e.code(), 0x01);
+        let e: Error = crate::Error::from(packet::Error::InvalidDataLength).into();
+        assert_eq!(e.code(), packet::Error::InvalidDataLength.code());
+    }
+
+    /// Answers every read with the same frame
+    struct Streaming(Vec<u8>);
+
+    impl io::Read for Streaming {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let size = self.0.len().min(buf.len());
+            buf[..size].copy_from_slice(&self.0[..size]);
+            Ok(size)
+        }
+    }
+
+    impl io::Write for Streaming {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn query_times_out_while_streaming() {
+        let tele = encode_packet(b"tele", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        let mut c = Connection::new(Streaming(tele));
+        let id = MessageId::new(b"speed").unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            c.query(id, MessageType::U16, Duration::from_millis(1)),
+            Err(Error::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(c.rx_queue.len() <= RX_QUEUE_SIZE);
+        assert_eq!(c.recv().unwrap().unwrap().msg_id().unwrap(), b"tele");
+    }
+}
@@ -0,0 +1,97 @@
+//! A host-side view of a device's variables
+
+use crate::host::value::Value;
+use crate::message::MessageId;
+use std::collections::BTreeMap;
+use std::string::String;
+
+/// The board ID and tracked variables of a device, as discovered by
+/// [`Connection::handshake`](crate::host::Connection::handshake).
+///
+/// Variables are keyed by their message ID, IDs that aren't valid UTF-8
+/// are not tracked.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Device {
+    pub board_id: Option<u16>,
+    pub variables: BTreeMap<String, Value>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Value> {
+        self.variables.get(id)
+    }
+
+    /// Inserts or replaces a variable, returns false if the ID isn't valid UTF-8
+    pub fn update(&mut self, id: MessageId<'_>, value: Value) -> bool {
+        match id.as_str() {
+            Ok(id) => {
+                self.variables.insert(id.into(), value);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Serializes the device state to a JSON snapshot
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Deserializes a JSON snapshot produced by [`Device::to_json`]
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::vec;
+
+    #[test]
+    fn update_variables() {
+        let mut d = Device::new();
+        assert!(d.update(MessageId::BOARD_NAME, Value::Char(b"my-board".to_vec())));
+        assert!(d.update(MessageId::new(b"led_blink").unwrap(), Value::U8(vec![0])));
+        assert!(d.update(MessageId::new(b"led_blink").unwrap(), Value::U8(vec![1])));
+        assert!(!d.update(MessageId::new(&[0xFF]).unwrap(), Value::U8(vec![1])));
+        assert_eq!(d.variables.len(), 2);
+        assert_eq!(d.get("led_blink"), Some(&Value::U8(vec![1])));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        let mut d = Device::new();
+        d.board_id = Some(0xBEEF);
+        d.update(MessageId::new(b"led_blink").unwrap(), Value::U8(vec![1]));
+        d.update(MessageId::new(b"lit_time").unwrap(), Value::U16(vec![70]));
+        d.update(
+            MessageId::new(b"gains").unwrap(),
+            Value::F32(vec![0.5, -1.25]),
+        );
+
+        let json = d.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "board_id": 48879,
+                "variables": {
+                    "gains": { "F32": [0.5, -1.25] },
+                    "led_blink": { "U8": [1] },
+                    "lit_time": { "U16": [70] },
+                }
+            })
+        );
+        assert_eq!(Device::from_json(&json).unwrap(), d);
+    }
+}
@@ -0,0 +1,164 @@
+//! Splitting a byte stream into packets
+
+use crate::collections;
+use crate::host::connection::Error;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::Packet;
+use std::io::{self, Read};
+use std::vec::Vec;
+
+pub(crate) use crate::collections::encode_frame;
+pub use crate::collections::MAX_FRAME_SIZE;
+
+pub(crate) const READ_CHUNK_SIZE: usize = 256;
+
+/// A decoded frame
+pub type FrameResult = Result<Packet<Vec<u8>>, Error>;
+
+/// Accumulates bytes up to each frame delimiter, then decodes the
+/// framed packet, see [`collections::Deframer`]
+#[derive(Debug)]
+pub struct Deframer(collections::Deframer);
+
+impl Default for Deframer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deframer {
+    pub fn new() -> Self {
+        Self(collections::Deframer::new())
+    }
+
+    /// Resets the frame in progress
+    pub fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    /// Feeds a byte, returns the result of decoding a frame once its
+    /// delimiter arrives.
+    /// Empty frames (consecutive delimiters) are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<FrameResult> {
+        self.0.push(byte).map(|res| res.map_err(Error::from))
+    }
+
+    /// Feeds bytes up to and including the next delimiter, returns the
+    /// number of bytes consumed along with the result of decoding a frame
+    /// if one ended
+    pub fn push_slice(&mut self, bytes: &[u8]) -> (usize, Option<FrameResult>) {
+        let (used, res) = self.0.push_slice(bytes);
+        (used, res.map(|res| res.map_err(Error::from)))
+    }
+}
+
+/// Builds a non-offset packet
+pub(crate) fn build_packet(
+    id: MessageId<'_>,
+    typ: MessageType,
+    internal: bool,
+    response: bool,
+    acknum: AckNum,
+    payload: &[u8],
+) -> Result<Packet<Vec<u8>>, Error> {
+    Ok(crate::collections::packet_buf(
+        id, typ, internal, response, acknum, payload,
+    )?)
+}
+
+/// Reads whatever is available, treating timeouts and interruptions
+/// as no data
+pub(crate) fn read_available<R: Read>(io: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    match io.read(buf) {
+        Ok(size) => Ok(size),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ) =>
+        {
+            Ok(0)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::test_io::encode_packet;
+    use crate::wire::packet;
+    use pretty_assertions::assert_eq;
+    use std::{format, vec};
+
+    fn push_all(d: &mut Deframer, bytes: &[u8]) -> Vec<Result<Packet<Vec<u8>>, Error>> {
+        bytes.iter().filter_map(|b| d.push(*b)).collect()
+    }
+
+    fn push_slices(d: &mut Deframer, mut bytes: &[u8]) -> Vec<Result<Packet<Vec<u8>>, Error>> {
+        let mut res = Vec::new();
+        while !bytes.is_empty() {
+            let (used, r) = d.push_slice(bytes);
+            bytes = &bytes[used..];
+            res.extend(r);
+        }
+        res
+    }
+
+    #[test]
+    fn frames() {
+        let mut d = Deframer::new();
+        let frame = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        let res = push_all(&mut d, &[&[0, 0], &frame[..], &[0], &frame[..]].concat());
+        assert_eq!(res.len(), 2);
+        for r in res.into_iter() {
+            let p = r.unwrap();
+            assert_eq!(p.msg_id().unwrap(), b"abc");
+            assert_eq!(p.payload().unwrap(), &[1]);
+        }
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let mut d = Deframer::new();
+        let mut bad = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        bad[6] ^= 0x01;
+        let res = push_all(&mut d, &bad);
+        assert!(matches!(
+            res[..],
+            [Err(Error::Packet(packet::Error::InvalidChecksum { .. }))]
+        ));
+
+        let res = push_all(&mut d, &[vec![0xFF; MAX_FRAME_SIZE + 1], vec![0]].concat());
+        assert!(matches!(res[..], [Err(Error::FrameTooLarge)]));
+
+        let frame = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        let res = push_all(&mut d, &frame);
+        assert!(matches!(res[..], [Ok(_)]));
+    }
+
+    #[test]
+    fn slices_match_bytes() {
+        let frame = encode_packet(b"abc", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        let mut bad = frame.clone();
+        bad[6] ^= 0x01;
+        let input = [
+            &frame[..],
+            &[0, 0],
+            &bad[..],
+            &vec![0xFF; MAX_FRAME_SIZE + 1],
+            &[0],
+            &frame[..],
+            &frame[..3],
+        ]
+        .concat();
+
+        let bytes = push_all(&mut Deframer::new(), &input);
+        let mut d = Deframer::new();
+        let slices = push_slices(&mut d, &input);
+        assert_eq!(slices.len(), 4);
+        assert_eq!(format!("{:?}", slices), format!("{:?}", bytes));
+        // The partial frame is kept
+        assert!(matches!(push_slices(&mut d, &frame[3..])[..], [Ok(_)]));
+    }
+}
@@ -0,0 +1,278 @@
+//! Periodic heartbeats and link statistics
+
+use crate::host::connection::{Connection, Error};
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use crate::wire::Packet;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Whether the device is answering heartbeats
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Liveness {
+    /// No heartbeat has been answered yet
+    Unknown,
+    Alive,
+    /// Too many consecutive heartbeats went unanswered
+    Lost,
+}
+
+/// Heartbeat round-trip and loss statistics
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub sent: usize,
+    pub received: usize,
+    pub missed: usize,
+    pub consecutive_missed: usize,
+    pub last_rtt: Option<Duration>,
+    pub min_rtt: Option<Duration>,
+    pub max_rtt: Option<Duration>,
+    /// Mean of all the round-trip times measured
+    pub mean_rtt: Option<Duration>,
+}
+
+impl Stats {
+    pub(crate) fn record_rtt(&mut self, rtt: Duration) {
+        self.received += 1;
+        self.consecutive_missed = 0;
+        self.last_rtt = Some(rtt);
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min| min.min(rtt)));
+        self.max_rtt = Some(self.max_rtt.map_or(rtt, |max| max.max(rtt)));
+        let n = self.received as u32;
+        self.mean_rtt = Some(match self.mean_rtt {
+            // Running mean
+            Some(mean) => mean + (rtt / n) - (mean / n),
+            None => rtt,
+        });
+    }
+}
+
+/// Sends a heartbeat every `interval` and waits up to `timeout` for the
+/// device to echo its value back.
+///
+/// Drive it by calling [`Keepalive::tick`] from the application loop.
+#[derive(Debug)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    max_missed: usize,
+    value: u8,
+    outstanding: Option<(u8, Instant)>,
+    last_sent: Option<Instant>,
+    stats: Stats,
+}
+
+impl Keepalive {
+    /// Consecutive misses after which the device is considered lost
+    pub const DEFAULT_MAX_MISSED: usize = 3;
+
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self {
+            interval,
+            timeout,
+            max_missed: Self::DEFAULT_MAX_MISSED,
+            value: 0,
+            outstanding: None,
+            last_sent: None,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_max_missed(mut self, max_missed: usize) -> Self {
+        self.max_missed = max_missed;
+        self
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn liveness(&self) -> Liveness {
+        if self.stats.consecutive_missed >= self.max_missed {
+            Liveness::Lost
+        } else if self.stats.received == 0 {
+            Liveness::Unknown
+        } else {
+            Liveness::Alive
+        }
+    }
+
+    /// Collects the outstanding heartbeat response, accounts for a
+    /// timed out heartbeat and sends the next one when it is due.
+    ///
+    /// Heartbeat responses are taken out of the connection's receive queue.
+    pub fn tick<T: Transport<Error = io::Error>>(
+        &mut self,
+        connection: &mut Connection<T>,
+    ) -> Result<(), Error> {
+        if let Some((value, _)) = self.outstanding {
+            if let Some(p) = connection.recv_matching(Duration::ZERO, |p| {
+                Self::is_response(p) && p.payload().ok() == Some(&[value][..])
+            })? {
+                self.handle(&p);
+            }
+        }
+
+        let now = Instant::now();
+        if let Some((_, sent)) = self.outstanding {
+            if now.duration_since(sent) >= self.timeout {
+                self.outstanding = None;
+                self.stats.missed += 1;
+                self.stats.consecutive_missed += 1;
+                log!(
+                    debug,
+                    "Heartbeat missed, {} in a row",
+                    self.stats.consecutive_missed
+                );
+            }
+        }
+
+        let due = self
+            .last_sent
+            .is_none_or(|sent| now.duration_since(sent) >= self.interval);
+        if due && self.outstanding.is_none() {
+            self.value = self.value.wrapping_add(1);
+            connection.send_message(
+                MessageId::INTERNAL_HEARTBEAT,
+                MessageType::U8,
+                true,
+                true,
+                AckNum::NONE,
+                &[self.value],
+            )?;
+            self.outstanding = Some((self.value, now));
+            self.last_sent = Some(now);
+            self.stats.sent += 1;
+        }
+        Ok(())
+    }
+
+    /// Accounts for a heartbeat response the application received itself,
+    /// returns true if it answered the outstanding heartbeat
+    pub fn handle<B: AsRef<[u8]>>(&mut self, packet: &Packet<B>) -> bool {
+        match self.outstanding {
+            Some((value, sent))
+                if Self::is_response(packet) && packet.payload().ok() == Some(&[value][..]) =>
+            {
+                self.outstanding = None;
+                self.stats.record_rtt(sent.elapsed());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn is_response<B: AsRef<[u8]>>(packet: &Packet<B>) -> bool {
+        packet.internal()
+            && !packet.response()
+            && packet.msg_id() == Ok(MessageId::INTERNAL_HEARTBEAT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use pretty_assertions::assert_eq;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::thread;
+
+    fn echo(p: &Packet<&[u8]>, rx: &mut std::collections::VecDeque<u8>) {
+        if p.msg_id() == Ok(MessageId::INTERNAL_HEARTBEAT) {
+            rx.extend(encode_packet(
+                b"h",
+                MessageType::U8,
+                true,
+                false,
+                AckNum::NONE,
+                p.payload().unwrap(),
+            ));
+        }
+    }
+
+    #[test]
+    fn alive() {
+        let mut io = Loopback::default();
+        io.respond(echo);
+        let mut c = Connection::new(io);
+        let mut k = Keepalive::new(Duration::ZERO, Duration::from_secs(1));
+        assert_eq!(k.liveness(), Liveness::Unknown);
+
+        for _ in 0..4 {
+            k.tick(&mut c).unwrap();
+        }
+        assert_eq!(k.liveness(), Liveness::Alive);
+        let stats = k.stats();
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.missed, 0);
+        assert!(stats.min_rtt.unwrap() <= stats.mean_rtt.unwrap());
+        assert!(stats.mean_rtt.unwrap() <= stats.max_rtt.unwrap());
+        assert_eq!(
+            c.get_ref().tx,
+            (1..=4)
+                .flat_map(|v| encode_packet(b"h", MessageType::U8, true, true, AckNum::NONE, &[v]))
+                .collect::<std::vec::Vec<u8>>()
+        );
+        // Collected responses are consumed, the last one is still queued
+        assert_eq!(c.poll().unwrap(), 1);
+    }
+
+    #[test]
+    fn lost() {
+        let answer = Rc::new(Cell::new(true));
+        let a = answer.clone();
+        let mut io = Loopback::default();
+        io.respond(move |p, rx| {
+            if a.get() {
+                echo(p, rx)
+            }
+        });
+        let mut c = Connection::new(io);
+        let mut k = Keepalive::new(Duration::ZERO, Duration::from_millis(1)).with_max_missed(2);
+
+        k.tick(&mut c).unwrap();
+        k.tick(&mut c).unwrap();
+        assert_eq!(k.liveness(), Liveness::Alive);
+
+        answer.set(false);
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(2));
+            k.tick(&mut c).unwrap();
+        }
+        assert_eq!(k.liveness(), Liveness::Lost);
+        assert_eq!(k.stats().missed, 2);
+        assert_eq!(k.stats().consecutive_missed, 2);
+
+        answer.set(true);
+        thread::sleep(Duration::from_millis(2));
+        k.tick(&mut c).unwrap();
+        k.tick(&mut c).unwrap();
+        assert_eq!(k.liveness(), Liveness::Alive);
+        assert_eq!(k.stats().consecutive_missed, 0);
+    }
+
+    #[test]
+    fn interval() {
+        let mut c = Connection::new(Loopback::default());
+        let mut k = Keepalive::new(Duration::from_secs(60), Duration::from_secs(60));
+        k.tick(&mut c).unwrap();
+        k.tick(&mut c).unwrap();
+        assert_eq!(k.stats().sent, 1);
+    }
+
+    #[test]
+    fn handle_application_received() {
+        let mut c = Connection::new(Loopback::default());
+        let mut k = Keepalive::new(Duration::ZERO, Duration::from_secs(1));
+        k.tick(&mut c).unwrap();
+        let mut bytes = encode_packet(b"h", MessageType::U8, true, false, AckNum::NONE, &[2]);
+        let size = crate::wire::Framing::decode_in_place(&mut bytes).unwrap();
+        assert!(!k.handle(&Packet::new(&bytes[..size]).unwrap()));
+        let mut bytes = encode_packet(b"h", MessageType::U8, true, false, AckNum::NONE, &[1]);
+        let size = crate::wire::Framing::decode_in_place(&mut bytes).unwrap();
+        assert!(k.handle(&Packet::new(&bytes[..size]).unwrap()));
+        assert_eq!(k.stats().received, 1);
+    }
+}
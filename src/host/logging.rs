@@ -0,0 +1,13 @@
+//! Log records for host tools, forwarded to the log crate when the `log`
+//! feature is enabled
+
+/// `log!(level, "format", args...)`, where level is one of the log
+/// crate's macros, e.g. `debug`
+macro_rules! log {
+    ($lvl:ident, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "log")]
+        ::log::$lvl!($fmt $(, $arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = ($(&$arg),*);
+    }};
+}
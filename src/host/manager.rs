@@ -0,0 +1,291 @@
+//! Several connections managed together
+
+use crate::host::connection::{Connection, Error};
+use crate::host::subscription::{IdPattern, SubscriptionId};
+use crate::host::value::{FromValue, Value};
+use crate::message::{MessageId, MessageType};
+use crate::transport::Transport;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+use std::vec::Vec;
+
+/// Identifies a connection owned by a [`Manager`]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct DeviceHandle(usize);
+
+type SharedCallback = Rc<RefCell<dyn FnMut(DeviceHandle, MessageId<'_>, &Value)>>;
+
+struct ManagedSubscription {
+    id: SubscriptionId,
+    pattern: IdPattern,
+    callback: SharedCallback,
+    /// The subscription registered with each connection
+    per_device: BTreeMap<DeviceHandle, SubscriptionId>,
+}
+
+/// Owns multiple connections, routing writes and queries to a device
+/// by its handle and tagging variable updates with the handle of the
+/// device they came from
+pub struct Manager<T> {
+    devices: BTreeMap<DeviceHandle, Connection<T>>,
+    next_handle: usize,
+    subscriptions: Vec<ManagedSubscription>,
+    next_subscription_id: usize,
+}
+
+impl<T: Transport<Error = io::Error>> Default for Manager<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Transport<Error = io::Error>> Manager<T> {
+    pub fn new() -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            next_handle: 0,
+            subscriptions: Vec::new(),
+            next_subscription_id: 0,
+        }
+    }
+
+    /// Takes ownership of a connection, existing subscriptions apply to it too
+    pub fn add(&mut self, mut connection: Connection<T>) -> DeviceHandle {
+        let handle = DeviceHandle(self.next_handle);
+        self.next_handle = self.next_handle.wrapping_add(1);
+        for s in self.subscriptions.iter_mut() {
+            let id = subscribe_device(&mut connection, handle, s.pattern.clone(), &s.callback);
+            s.per_device.insert(handle, id);
+        }
+        self.devices.insert(handle, connection);
+        handle
+    }
+
+    /// Gives back a connection, without the manager's subscriptions
+    pub fn remove(&mut self, handle: DeviceHandle) -> Option<Connection<T>> {
+        let mut connection = self.devices.remove(&handle)?;
+        for s in self.subscriptions.iter_mut() {
+            if let Some(id) = s.per_device.remove(&handle) {
+                connection.unsubscribe(id);
+            }
+        }
+        Some(connection)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = DeviceHandle> + '_ {
+        self.devices.keys().copied()
+    }
+
+    pub fn get(&self, handle: DeviceHandle) -> Option<&Connection<T>> {
+        self.devices.get(&handle)
+    }
+
+    pub fn get_mut(&mut self, handle: DeviceHandle) -> Option<&mut Connection<T>> {
+        self.devices.get_mut(&handle)
+    }
+
+    /// Registers a callback for variable updates, from any device, whose
+    /// message ID matches `pattern`. See [`Connection::subscribe`].
+    pub fn subscribe<P, F>(&mut self, pattern: P, callback: F) -> SubscriptionId
+    where
+        P: Into<IdPattern>,
+        F: FnMut(DeviceHandle, MessageId<'_>, &Value) + 'static,
+    {
+        let id = SubscriptionId(self.next_subscription_id);
+        self.next_subscription_id = self.next_subscription_id.wrapping_add(1);
+        let pattern = pattern.into();
+        let callback: SharedCallback = Rc::new(RefCell::new(callback));
+        let per_device = self
+            .devices
+            .iter_mut()
+            .map(|(handle, c)| {
+                let id = subscribe_device(c, *handle, pattern.clone(), &callback);
+                (*handle, id)
+            })
+            .collect();
+        self.subscriptions.push(ManagedSubscription {
+            id,
+            pattern,
+            callback,
+            per_device,
+        });
+        id
+    }
+
+    /// Removes a subscription from every device, returns false if it didn't exist
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let idx = match self.subscriptions.iter().position(|s| s.id == id) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let s = self.subscriptions.remove(idx);
+        for (handle, id) in s.per_device.into_iter() {
+            if let Some(c) = self.devices.get_mut(&handle) {
+                c.unsubscribe(id);
+            }
+        }
+        true
+    }
+
+    /// See [`Connection::write`]
+    pub fn write<V: Into<Value>>(
+        &mut self,
+        handle: DeviceHandle,
+        id: MessageId<'_>,
+        value: V,
+    ) -> Result<(), Error> {
+        self.device(handle)?.write(id, value)
+    }
+
+    /// See [`Connection::write_acked`]
+    pub fn write_acked<V: Into<Value>>(
+        &mut self,
+        handle: DeviceHandle,
+        id: MessageId<'_>,
+        value: V,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        self.device(handle)?
+            .write_acked(id, value, retries, timeout)
+    }
+
+    /// See [`Connection::query`]
+    pub fn query(
+        &mut self,
+        handle: DeviceHandle,
+        id: MessageId<'_>,
+        typ: MessageType,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        self.device(handle)?.query(id, typ, timeout)
+    }
+
+    /// See [`Connection::query_as`]
+    pub fn query_as<V: FromValue>(
+        &mut self,
+        handle: DeviceHandle,
+        id: MessageId<'_>,
+        timeout: Duration,
+    ) -> Result<V, Error> {
+        self.device(handle)?.query_as(id, timeout)
+    }
+
+    /// Polls every connection, see [`Connection::poll`].
+    /// Stops at the first connection that fails, returning its handle
+    /// along with the error.
+    pub fn poll(&mut self) -> Result<usize, (DeviceHandle, Error)> {
+        let mut cnt = 0;
+        for (handle, c) in self.devices.iter_mut() {
+            cnt += c.poll().map_err(|e| (*handle, e))?;
+        }
+        Ok(cnt)
+    }
+
+    fn device(&mut self, handle: DeviceHandle) -> Result<&mut Connection<T>, Error> {
+        self.devices.get_mut(&handle).ok_or(Error::UnknownDevice)
+    }
+}
+
+fn subscribe_device<T: Transport<Error = io::Error>>(
+    connection: &mut Connection<T>,
+    handle: DeviceHandle,
+    pattern: IdPattern,
+    callback: &SharedCallback,
+) -> SubscriptionId {
+    let callback = callback.clone();
+    connection.subscribe(pattern, move |id, value| {
+        (callback.borrow_mut())(handle, id, value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::device::Device;
+    use crate::host::mock::MockDevice;
+    use pretty_assertions::assert_eq;
+    use std::vec;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn board(board_id: u16, speed: u16) -> Connection<MockDevice> {
+        let mut d = Device::new();
+        d.board_id = Some(board_id);
+        d.variables.insert("speed".into(), Value::U16(vec![speed]));
+        Connection::new(MockDevice::new(d))
+    }
+
+    #[test]
+    fn route_writes_and_queries() {
+        let mut m = Manager::new();
+        let a = m.add(board(1, 10));
+        let b = m.add(board(2, 20));
+        assert_eq!(m.len(), 2);
+        let speed = MessageId::new(b"speed").unwrap();
+
+        assert_eq!(m.query_as::<u16>(a, speed, TIMEOUT).unwrap(), 10);
+        assert_eq!(m.query_as::<u16>(b, speed, TIMEOUT).unwrap(), 20);
+        m.write_acked(b, speed, 25_u16, 0, TIMEOUT).unwrap();
+        assert_eq!(m.query_as::<u16>(a, speed, TIMEOUT).unwrap(), 10);
+        assert_eq!(m.query_as::<u16>(b, speed, TIMEOUT).unwrap(), 25);
+
+        let c = m.remove(a).unwrap();
+        assert_eq!(c.get_ref().state().board_id, Some(1));
+        assert!(matches!(
+            m.query(a, speed, MessageType::U16, TIMEOUT),
+            Err(Error::UnknownDevice)
+        ));
+    }
+
+    #[test]
+    fn tagged_updates() {
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let mut m = Manager::new();
+        let a = m.add(board(1, 10));
+
+        let u = updates.clone();
+        let sub = m.subscribe("speed", move |handle, _id, value| {
+            u.borrow_mut().push((handle, value.clone()))
+        });
+        // Subscriptions apply to devices added later too
+        let b = m.add(board(2, 20));
+
+        m.get_mut(a)
+            .unwrap()
+            .get_mut()
+            .set("speed", 11_u16)
+            .unwrap();
+        m.get_mut(b)
+            .unwrap()
+            .get_mut()
+            .set("speed", 21_u16)
+            .unwrap();
+        assert_eq!(m.poll().unwrap(), 2);
+        assert_eq!(
+            *updates.borrow(),
+            [(a, Value::U16(vec![11])), (b, Value::U16(vec![21]))]
+        );
+
+        assert!(m.unsubscribe(sub));
+        assert!(!m.unsubscribe(sub));
+        m.get_mut(a)
+            .unwrap()
+            .get_mut()
+            .set("speed", 12_u16)
+            .unwrap();
+        assert_eq!(m.poll().unwrap(), 1);
+        assert_eq!(updates.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,262 @@
+//! Local cache of device variables
+
+use crate::host::connection::{Connection, Error};
+use crate::host::subscription::{IdPattern, SubscriptionId};
+use crate::host::value::{FromValue, Value};
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use crate::wire::packet;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::rc::Rc;
+use std::string::String;
+use std::time::{Duration, Instant};
+
+/// How a [`Mirror`] keeps a variable up to date.
+///
+/// Updates the device sends on its own are always taken, polled variables
+/// are additionally queried every interval.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tracking {
+    pub typ: MessageType,
+    pub poll_interval: Option<Duration>,
+    /// Age after which the cached value is considered stale
+    pub stale_after: Option<Duration>,
+}
+
+impl Tracking {
+    /// Relies on the device sending updates, never stale
+    pub fn passive(typ: MessageType) -> Self {
+        Self {
+            typ,
+            poll_interval: None,
+            stale_after: None,
+        }
+    }
+
+    /// Queries the variable every `interval`, stale once two polls have
+    /// gone unanswered
+    pub fn polled(typ: MessageType, interval: Duration) -> Self {
+        Self {
+            typ,
+            poll_interval: Some(interval),
+            stale_after: interval.checked_mul(2),
+        }
+    }
+
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = Some(stale_after);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    tracking: Tracking,
+    value: Option<Value>,
+    updated: Option<Instant>,
+    queried: Option<Instant>,
+}
+
+/// Keeps a set of variables up to date so the application can read
+/// them locally.
+///
+/// Attach it to a connection with [`Mirror::attach`] and call
+/// [`Mirror::tick`] from the application loop to send the due queries.
+/// Replies arrive, like any other update, while the connection is polled.
+#[derive(Debug, Default)]
+pub struct Mirror {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Mirror {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking a variable, replacing its previous tracking
+    pub fn track(&mut self, id: &str, tracking: Tracking) -> Result<(), Error> {
+        MessageId::from_utf8(id).ok_or(packet::Error::InvalidMessageId)?;
+        self.entries.insert(
+            String::from(id),
+            Entry {
+                tracking,
+                value: None,
+                updated: None,
+                queried: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stops tracking a variable, returns its last value
+    pub fn untrack(&mut self, id: &str) -> Option<Value> {
+        self.entries.remove(id).and_then(|e| e.value)
+    }
+
+    /// Subscribes a shared mirror to updates from the connection
+    pub fn attach<T: Transport<Error = io::Error>>(
+        mirror: &Rc<RefCell<Self>>,
+        connection: &mut Connection<T>,
+    ) -> SubscriptionId {
+        let mirror = mirror.clone();
+        connection.subscribe(IdPattern::Any, move |id, value: &Value| {
+            mirror.borrow_mut().update(id, value);
+        })
+    }
+
+    /// Sends a query for each polled variable that is due,
+    /// returns the number of queries sent
+    pub fn tick<T: Transport<Error = io::Error>>(
+        &mut self,
+        connection: &mut Connection<T>,
+    ) -> Result<usize, Error> {
+        let now = Instant::now();
+        let mut sent = 0;
+        for (id, entry) in self.entries.iter_mut() {
+            let interval = match entry.tracking.poll_interval {
+                Some(interval) => interval,
+                None => continue,
+            };
+            let last = entry.queried.max(entry.updated);
+            if last.is_none_or(|t| now.duration_since(t) >= interval) {
+                // Checked when tracked
+                let id = MessageId::from_utf8(id).ok_or(packet::Error::InvalidMessageId)?;
+                connection.send_message(id, entry.tracking.typ, false, true, AckNum::NONE, &[])?;
+                entry.queried = Some(now);
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Caches the value if the variable is tracked and of the tracked type,
+    /// returns false otherwise
+    pub fn update(&mut self, id: MessageId<'_>, value: &Value) -> bool {
+        let entry = match id.as_str().ok().and_then(|id| self.entries.get_mut(id)) {
+            Some(e) if e.tracking.typ == value.typ() => e,
+            _ => return false,
+        };
+        entry.value = Some(value.clone());
+        entry.updated = Some(Instant::now());
+        true
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Value> {
+        self.entries.get(id)?.value.as_ref()
+    }
+
+    pub fn get_as<V: FromValue>(&self, id: &str) -> Option<V> {
+        V::from_value(self.get(id)?)
+    }
+
+    /// Time since the variable was last updated
+    pub fn age(&self, id: &str) -> Option<Duration> {
+        Some(self.entries.get(id)?.updated?.elapsed())
+    }
+
+    /// A tracked variable is stale until its first update, and once it
+    /// is older than its `stale_after` age
+    pub fn is_stale(&self, id: &str) -> bool {
+        match self.entries.get(id) {
+            Some(e) => match (e.updated, e.tracking.stale_after) {
+                (None, _) => true,
+                (Some(t), Some(max)) => t.elapsed() > max,
+                (Some(_), None) => false,
+            },
+            None => false,
+        }
+    }
+
+    /// The IDs of all the stale variables
+    pub fn stale(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries
+            .keys()
+            .map(String::as_str)
+            .filter(|id| self.is_stale(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::device::Device;
+    use crate::host::mock::MockDevice;
+    use pretty_assertions::assert_eq;
+    use std::thread;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn connection() -> Connection<MockDevice> {
+        let mut d = Device::new();
+        d.variables.insert("speed".into(), Value::U16(vec![10]));
+        d.variables.insert("state".into(), Value::U8(vec![1]));
+        Connection::new(MockDevice::new(d))
+    }
+
+    #[test]
+    fn polled_variables() {
+        let mut c = connection();
+        let mirror = Rc::new(RefCell::new(Mirror::new()));
+        Mirror::attach(&mirror, &mut c);
+        let interval = Duration::from_millis(20);
+        mirror
+            .borrow_mut()
+            .track("speed", Tracking::polled(MessageType::U16, interval))
+            .unwrap();
+        assert!(mirror.borrow().is_stale("speed"));
+
+        assert_eq!(mirror.borrow_mut().tick(&mut c).unwrap(), 1);
+        assert_eq!(mirror.borrow_mut().tick(&mut c).unwrap(), 0);
+        c.poll().unwrap();
+        assert_eq!(mirror.borrow().get_as::<u16>("speed"), Some(10));
+        assert!(!mirror.borrow().is_stale("speed"));
+
+        thread::sleep(interval);
+        assert_eq!(mirror.borrow_mut().tick(&mut c).unwrap(), 1);
+        c.get_mut().unplug();
+        thread::sleep(interval * 2);
+        assert!(mirror.borrow().is_stale("speed"));
+        assert_eq!(mirror.borrow().stale().collect::<Vec<_>>(), ["speed"]);
+    }
+
+    #[test]
+    fn passive_variables() {
+        let mut c = connection();
+        let mirror = Rc::new(RefCell::new(Mirror::new()));
+        Mirror::attach(&mirror, &mut c);
+        mirror
+            .borrow_mut()
+            .track("state", Tracking::passive(MessageType::U8))
+            .unwrap();
+        assert_eq!(mirror.borrow_mut().tick(&mut c).unwrap(), 0);
+
+        c.get_mut().set("state", 2_u8).unwrap();
+        c.get_mut().set("speed", 11_u16).unwrap();
+        c.poll().unwrap();
+        let m = mirror.borrow();
+        assert_eq!(m.get("state"), Some(&Value::U8(vec![2])));
+        assert!(m.age("state").is_some());
+        assert!(!m.is_stale("state"));
+        // Untracked
+        assert_eq!(m.get("speed"), None);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let mut m = Mirror::new();
+        m.track("state", Tracking::passive(MessageType::U8))
+            .unwrap();
+        assert_eq!(
+            m.track("", Tracking::passive(MessageType::U8))
+                .unwrap_err()
+                .code(),
+            packet::Error::InvalidMessageId.code()
+        );
+        assert_eq!(m.get(""), None);
+        let id = MessageId::new(b"state").unwrap();
+        assert!(!m.update(id, &Value::U16(vec![1])));
+        assert!(m.update(id, &Value::U8(vec![1])));
+    }
+}
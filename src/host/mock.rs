@@ -0,0 +1,447 @@
+//! In-memory device simulator
+
+use crate::host::connection::Error;
+use crate::host::device::Device;
+use crate::host::framer::{build_packet, encode_frame, Deframer};
+use crate::host::value::Value;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::nack::{Nack, Reason};
+use crate::wire::{packet, Packet};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::string::String;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// Simulates the device side of the protocol, usable anywhere a serial
+/// port is, e.g. `Connection<MockDevice>`.
+///
+/// Handles the board ID, announcement, tracked variable and heartbeat
+/// internal messages, as well as variable writes, queries and acks.
+/// Requests for unknown variables, writes of the wrong type and writes
+/// to read-only variables are refused with a [`Nack`].
+pub struct MockDevice {
+    state: Device,
+    read_only: BTreeSet<String>,
+    latency: Duration,
+    drop_rate: f64,
+    rng: u64,
+    deframer: Deframer,
+    /// Framed replies along with when they become readable
+    tx: VecDeque<(Instant, Vec<u8>)>,
+    rx: VecDeque<u8>,
+    received: usize,
+    dropped: usize,
+    unplugged: bool,
+}
+
+impl MockDevice {
+    /// Creates a device exposing the variables in `state`
+    pub fn new(state: Device) -> Self {
+        Self {
+            state,
+            read_only: BTreeSet::new(),
+            latency: Duration::ZERO,
+            drop_rate: 0.0,
+            rng: 0x2545_F491_4F6C_DD1D,
+            deframer: Deframer::new(),
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            received: 0,
+            dropped: 0,
+            unplugged: false,
+        }
+    }
+
+    /// Delays every reply by `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Drops frames, in either direction, with probability `rate` (0.0 to 1.0)
+    pub fn with_drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Refuses writes to the variable `id`
+    pub fn with_read_only(mut self, id: &str) -> Self {
+        self.read_only.insert(String::from(id));
+        self
+    }
+
+    /// Seeds the generator used for dropping frames
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // xorshift has a fixed point at zero
+        self.rng = seed.max(1);
+        self
+    }
+
+    /// The device's current variables
+    pub fn state(&self) -> &Device {
+        &self.state
+    }
+
+    /// Number of valid packets received from the host, including dropped ones
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Number of frames dropped, in either direction
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Simulates the device being unplugged, all further reads and
+    /// writes fail
+    pub fn unplug(&mut self) {
+        self.unplugged = true;
+    }
+
+    /// Updates a variable on the device side and sends it to the host,
+    /// as a device does when a tracked variable changes
+    pub fn set<V: Into<Value>>(&mut self, id: &str, value: V) -> Result<(), Error> {
+        let msg_id = MessageId::from_utf8(id).ok_or(packet::Error::InvalidMessageId)?;
+        let value = value.into();
+        let bytes = value.to_bytes();
+        self.reply(msg_id, value.typ(), false, AckNum::NONE, &bytes);
+        self.state.variables.insert(String::from(id), value);
+        Ok(())
+    }
+
+    fn chance(&mut self) -> f64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    fn drop_frame(&mut self) -> bool {
+        let drop = self.drop_rate > 0.0 && self.chance() < self.drop_rate;
+        if drop {
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        drop
+    }
+
+    fn reply(
+        &mut self,
+        id: MessageId<'_>,
+        typ: MessageType,
+        internal: bool,
+        acknum: AckNum,
+        payload: &[u8],
+    ) {
+        if self.drop_frame() {
+            return;
+        }
+        if let Ok(p) = build_packet(id, typ, internal, false, acknum, payload) {
+            let mut frame = Vec::new();
+            encode_frame(p.as_ref(), &mut frame);
+            self.tx.push_back((Instant::now() + self.latency, frame));
+        }
+    }
+
+    fn handle(&mut self, packet: &Packet<Vec<u8>>) -> Result<(), Error> {
+        let id = packet.msg_id()?;
+        let payload = packet.payload()?;
+        if packet.internal() {
+            self.handle_internal(id, packet.typ(), payload);
+            return Ok(());
+        }
+
+        let name = match id.as_str() {
+            Ok(name) if self.state.variables.contains_key(name) => String::from(name),
+            _ => {
+                self.refuse(id, Reason::UnknownId, packet.acknum());
+                return Ok(());
+            }
+        };
+        if !payload.is_empty() {
+            let refused = if self.read_only.contains(&name) {
+                Some(Reason::ReadOnly)
+            } else if Some(packet.typ()) != self.state.get(&name).map(Value::typ) {
+                Some(Reason::BadType)
+            } else {
+                match Value::parse(packet.typ(), payload) {
+                    Some(value) => {
+                        self.state.variables.insert(name.clone(), value);
+                        None
+                    }
+                    None => Some(Reason::BadLength),
+                }
+            };
+            if let Some(reason) = refused {
+                self.refuse(id, reason, packet.acknum());
+                return Ok(());
+            }
+        }
+        if packet.response() {
+            if packet.acknum().is_requested() {
+                self.reply(id, packet.typ(), false, packet.acknum(), &[]);
+            } else if let Some(value) = self.state.get(&name).cloned() {
+                self.reply(id, value.typ(), false, AckNum::NONE, &value.to_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    fn refuse(&mut self, id: MessageId<'_>, reason: Reason, acknum: AckNum) {
+        let mut buf = [0_u8; Nack::MAX_PAYLOAD_SIZE];
+        let payload = Nack::new(reason, id).write_payload(&mut buf).to_vec();
+        self.reply(MessageId::INTERNAL_NACK, Nack::TYPE, true, acknum, &payload);
+    }
+
+    fn handle_internal(&mut self, id: MessageId<'_>, typ: MessageType, payload: &[u8]) {
+        match id {
+            MessageId::INTERNAL_BOARD_ID => {
+                let board_id = self.state.board_id.unwrap_or_default();
+                self.reply(
+                    id,
+                    MessageType::U16,
+                    true,
+                    AckNum::NONE,
+                    &board_id.to_le_bytes(),
+                );
+            }
+            MessageId::INTERNAL_HEARTBEAT => self.reply(id, typ, true, AckNum::NONE, payload),
+            MessageId::INTERNAL_AM => {
+                let names: Vec<String> = self.state.variables.keys().cloned().collect();
+                let mut list = Vec::new();
+                for name in names.iter() {
+                    if list.len() + name.len() + 1 > Packet::<&[u8]>::MAX_PAYLOAD_SIZE {
+                        self.reply(
+                            MessageId::INTERNAL_AM_LIST,
+                            MessageType::Custom,
+                            true,
+                            AckNum::NONE,
+                            &list,
+                        );
+                        list.clear();
+                    }
+                    list.extend_from_slice(name.as_bytes());
+                    list.push(b'\0');
+                }
+                if !list.is_empty() {
+                    self.reply(
+                        MessageId::INTERNAL_AM_LIST,
+                        MessageType::Custom,
+                        true,
+                        AckNum::NONE,
+                        &list,
+                    );
+                }
+                let count = names.len() as u16;
+                if let Ok(count) = u8::try_from(count) {
+                    self.reply(
+                        MessageId::INTERNAL_AM_END,
+                        MessageType::U8,
+                        true,
+                        AckNum::NONE,
+                        &[count],
+                    );
+                } else {
+                    self.reply(
+                        MessageId::INTERNAL_AM_END,
+                        MessageType::U16,
+                        true,
+                        AckNum::NONE,
+                        &count.to_le_bytes(),
+                    );
+                }
+            }
+            MessageId::INTERNAL_AV => {
+                let variables: Vec<(String, Value)> = self
+                    .state
+                    .variables
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                for (name, value) in variables.iter() {
+                    // A device can't announce a name that isn't a message ID
+                    let Some(id) = MessageId::from_utf8(name) else {
+                        continue;
+                    };
+                    let bytes = value.to_bytes();
+                    self.reply(id, value.typ(), false, AckNum::NONE, &bytes);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Read for MockDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unplugged {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let now = Instant::now();
+        while let Some((at, _)) = self.tx.front() {
+            if *at > now {
+                break;
+            }
+            if let Some((_, frame)) = self.tx.pop_front() {
+                self.rx.extend(frame);
+            }
+        }
+        if self.rx.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let size = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..size)) {
+            *dst = src;
+        }
+        Ok(size)
+    }
+}
+
+impl Write for MockDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.unplugged {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        for &byte in buf {
+            if let Some(Ok(packet)) = self.deframer.push(byte) {
+                self.received = self.received.saturating_add(1);
+                if !self.drop_frame() {
+                    // Malformed IDs and payloads are ignored, like a device would
+                    let _ = self.handle(&packet);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::connection::{AckOutcome, Connection};
+    use pretty_assertions::assert_eq;
+    use std::vec;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn device() -> Device {
+        let mut d = Device::new();
+        d.board_id = Some(0xBEEF);
+        d.variables.insert("led_blink".into(), Value::U8(vec![1]));
+        d.variables.insert("lit_time".into(), Value::U16(vec![70]));
+        d.variables
+            .insert("name".into(), Value::Char(b"my-board".to_vec()));
+        d
+    }
+
+    #[test]
+    fn handshake() {
+        let mut c = Connection::new(MockDevice::new(device()));
+        assert_eq!(c.handshake(TIMEOUT).unwrap(), device());
+    }
+
+    #[test]
+    fn write_and_query() {
+        let mut c = Connection::new(MockDevice::new(device()));
+        let id = MessageId::new(b"lit_time").unwrap();
+        c.write_acked(id, 200_u16, 0, TIMEOUT).unwrap();
+        assert_eq!(c.query_as::<u16>(id, TIMEOUT).unwrap(), 200);
+        assert_eq!(
+            c.get_ref().state().get("lit_time"),
+            Some(&Value::U16(vec![200]))
+        );
+
+        // Unknown variables are refused
+        let id = MessageId::new(b"missing").unwrap();
+        assert!(matches!(
+            c.query(id, MessageType::U8, TIMEOUT),
+            Err(Error::Nack(Reason::UnknownId))
+        ));
+    }
+
+    #[test]
+    fn refused_writes() {
+        let mock = MockDevice::new(device()).with_read_only("name");
+        let mut c = Connection::new(mock);
+        let name = MessageId::BOARD_NAME;
+        assert!(matches!(
+            c.write_acked(name, Value::Char(b"other".to_vec()), 0, TIMEOUT),
+            Err(Error::Nack(Reason::ReadOnly))
+        ));
+        let lit_time = MessageId::new(b"lit_time").unwrap();
+        assert!(matches!(
+            c.write_acked(lit_time, 1_u8, 0, TIMEOUT),
+            Err(Error::Nack(Reason::BadType))
+        ));
+        let acknum = c.send_acked(lit_time, 1_u8, 0, TIMEOUT).unwrap();
+        while c.pending_acks() != 0 {
+            c.poll().unwrap();
+        }
+        assert_eq!(
+            c.take_ack_outcome(),
+            Some(AckOutcome::Rejected(acknum, Reason::BadType))
+        );
+        assert_eq!(c.get_ref().state(), &device());
+
+        // Unacked writes are refused too, the NACK is left for recv
+        c.write(lit_time, 1_u8).unwrap();
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(
+            Nack::from_packet(&p),
+            Some(Nack::new(Reason::BadType, lit_time))
+        );
+    }
+
+    #[test]
+    fn device_updates() {
+        let mut c = Connection::new(MockDevice::new(device()));
+        c.get_mut().set("led_blink", 0_u8).unwrap();
+        let p = c.recv().unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"led_blink");
+        assert_eq!(p.payload().unwrap(), &[0]);
+    }
+
+    #[test]
+    fn latency() {
+        let latency = Duration::from_millis(20);
+        let mut c = Connection::new(MockDevice::new(device()).with_latency(latency));
+        let id = MessageId::new(b"lit_time").unwrap();
+        assert!(matches!(
+            c.query(id, MessageType::U16, Duration::from_millis(1)),
+            Err(Error::Timeout)
+        ));
+
+        let mut c = Connection::new(MockDevice::new(device()).with_latency(latency));
+        let start = Instant::now();
+        assert_eq!(c.query_as::<u16>(id, TIMEOUT).unwrap(), 70);
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[test]
+    fn drop_rate() {
+        let mut c = Connection::new(MockDevice::new(device()).with_drop_rate(1.0));
+        let id = MessageId::new(b"lit_time").unwrap();
+        assert!(matches!(
+            c.write_acked(id, 1_u16, 2, Duration::from_millis(1)),
+            Err(Error::Timeout)
+        ));
+        assert_eq!(c.get_ref().received(), 3);
+        assert_eq!(c.get_ref().dropped(), 3);
+
+        let mut c = Connection::new(MockDevice::new(device()).with_drop_rate(0.5).with_seed(7));
+        for i in 0..10 {
+            c.write_acked(id, i as u16, 20, Duration::from_millis(1))
+                .unwrap();
+        }
+        assert!(c.get_ref().dropped() > 0);
+        assert_eq!(
+            c.get_ref().state().get("lit_time"),
+            Some(&Value::U16(vec![9]))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Host-side client support, enabled by the `host` feature
+
+pub use benchmark::Benchmark;
+pub use bridge::Bridge;
+pub use capture::decode_capture;
+pub use connection::{AckOutcome, Connection, Error};
+pub use device::Device;
+pub use keepalive::Keepalive;
+pub use manager::{DeviceHandle, Manager};
+pub use mirror::{Mirror, Tracking};
+pub use mock::MockDevice;
+pub use reassembly::Reassembler;
+pub use reconnect::{ReconnectPolicy, Reconnecting};
+pub use session::{Recorder, Replay};
+pub use sniff::{Direction, Output, Sniffer};
+#[cfg(all(feature = "socketcan", target_os = "linux"))]
+pub use socketcan::SocketCanTransport;
+pub use subscription::{IdPattern, SubscriptionId};
+pub use tcp::{TcpServer, TcpTransport};
+pub use telemetry::TelemetryLogger;
+pub use udp::UdpTransport;
+pub use value::{FromValue, Value};
+#[cfg(feature = "websocket")]
+pub use websocket::WebSocketClient;
+
+#[macro_use]
+mod logging;
+
+pub mod benchmark;
+pub mod bridge;
+pub mod capture;
+pub mod connection;
+pub mod device;
+pub mod framer;
+pub mod keepalive;
+pub mod manager;
+pub mod mirror;
+pub mod mock;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod reassembly;
+pub mod reconnect;
+pub mod session;
+pub mod sniff;
+#[cfg(all(feature = "socketcan", target_os = "linux"))]
+pub mod socketcan;
+pub mod subscription;
+pub mod tcp;
+pub mod telemetry;
+pub mod udp;
+pub mod value;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+#[cfg(test)]
+pub(crate) mod test_io {
+    use crate::message::{AckNum, MessageId, MessageType};
+    use crate::wire::packet::Repr;
+    use crate::wire::{Framing, Packet};
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::vec;
+    use std::vec::Vec;
+
+    type Responder = Box<dyn FnMut(&Packet<&[u8]>, &mut VecDeque<u8>)>;
+
+    /// In-memory stand-in for a serial port
+    #[derive(Default)]
+    pub struct Loopback {
+        pub rx: VecDeque<u8>,
+        pub tx: Vec<u8>,
+        responder: Option<Responder>,
+    }
+
+    impl Loopback {
+        pub fn push_rx(&mut self, bytes: &[u8]) {
+            self.rx.extend(bytes.iter().copied());
+        }
+
+        /// Invokes `f` with every complete packet written, allowing it
+        /// to queue up replies
+        pub fn respond<F>(&mut self, f: F)
+        where
+            F: FnMut(&Packet<&[u8]>, &mut VecDeque<u8>) + 'static,
+        {
+            self.responder = Some(Box::new(f));
+        }
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.rx.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let size = buf.len().min(self.rx.len());
+            for (dst, src) in buf.iter_mut().zip(self.rx.drain(..size)) {
+                *dst = src;
+            }
+            Ok(size)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.tx.extend_from_slice(buf);
+            if let Some(f) = self.responder.as_mut() {
+                // Writes are always whole frames
+                let mut frame = buf.to_vec();
+                let size = Framing::decode_in_place(&mut frame).unwrap();
+                let p = Packet::new(&frame[..size]).unwrap();
+                f(&p, &mut self.rx);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns a framed packet
+    pub fn encode_packet(
+        msg_id: &[u8],
+        typ: MessageType,
+        internal: bool,
+        response: bool,
+        acknum: AckNum,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let repr = Repr {
+            internal,
+            response,
+            acknum,
+            ..Repr::new(MessageId::new(msg_id).unwrap(), typ, payload)
+        };
+        let mut bytes = vec![0; repr.buffer_len()];
+        repr.emit(&mut Packet::new_unchecked(&mut bytes[..]))
+            .unwrap();
+        let mut enc = vec![0; Framing::max_encoded_len(bytes.len())];
+        let size = Framing::encode_buf(&bytes, &mut enc);
+        enc.truncate(size);
+        enc
+    }
+}
@@ -0,0 +1,341 @@
+//! Python bindings for scripting devices, e.g. from pytest, enabled by the
+//! `python` feature
+//!
+//! Build the `electricui_embedded` extension module with
+//! [maturin](https://www.maturin.rs/), `maturin develop` picks up the
+//! settings in `pyproject.toml`.
+//!
+//! ```python
+//! from electricui_embedded import Connection
+//!
+//! c = Connection.open_serial("/dev/ttyACM0", 115200)
+//! device = c.handshake()
+//! c.subscribe("mot.*", lambda id, value: print(id, value))
+//! c.write("speed", "u16", 20)
+//! assert c.query("speed", "u16") == 20
+//! c.poll()
+//! ```
+
+use crate::host::connection::{Connection, Error};
+use crate::host::subscription::SubscriptionId;
+use crate::host::tcp::TcpTransport;
+use crate::host::value::Value;
+use crate::message::{MessageId, MessageType};
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyString};
+use pyo3::IntoPyObjectExt;
+use serial::prelude::*;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::string::{String, ToString};
+use std::time::Duration;
+use std::vec::Vec;
+use std::{format, vec};
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Timeout => PyTimeoutError::new_err(e.to_string()),
+            Error::Io(_) | Error::Disconnected => PyIOError::new_err(e.to_string()),
+            _ => PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
+
+enum Port {
+    Serial(serial::SystemPort),
+    Tcp(TcpTransport),
+}
+
+impl Read for Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Port::Serial(p) => p.read(buf),
+            Port::Tcp(p) => p.read(buf),
+        }
+    }
+}
+
+impl Write for Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Port::Serial(p) => p.write(buf),
+            Port::Tcp(p) => p.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Port::Serial(p) => p.flush(),
+            Port::Tcp(p) => p.flush(),
+        }
+    }
+}
+
+/// Error raised by a subscription callback, re-raised from `poll`
+type CallbackError = Rc<RefCell<Option<PyErr>>>;
+
+/// A connection to a device
+#[pyclass(unsendable, name = "Connection")]
+pub struct PyConnection {
+    connection: Connection<Port>,
+    callback_error: CallbackError,
+}
+
+#[pymethods]
+impl PyConnection {
+    /// Opens a serial port, 8N1 without flow control
+    #[staticmethod]
+    #[pyo3(signature = (path, baud = 115200))]
+    fn open_serial(path: &str, baud: usize) -> PyResult<Self> {
+        let serial_err = |e: serial::Error| PyIOError::new_err(e.to_string());
+        let mut port = serial::open(path).map_err(serial_err)?;
+        port.reconfigure(&|settings| {
+            settings.set_baud_rate(serial::BaudRate::from_speed(baud))?;
+            settings.set_char_size(serial::Bits8);
+            settings.set_parity(serial::ParityNone);
+            settings.set_stop_bits(serial::Stop1);
+            settings.set_flow_control(serial::FlowNone);
+            Ok(())
+        })
+        .map_err(serial_err)?;
+        port.set_timeout(Duration::from_millis(10))
+            .map_err(serial_err)?;
+        Ok(Self::new(Port::Serial(port)))
+    }
+
+    /// Connects to a device or bridge over TCP, e.g. `"127.0.0.1:9000"`
+    #[staticmethod]
+    fn connect_tcp(addr: &str) -> PyResult<Self> {
+        let t = TcpTransport::connect(addr).map_err(Error::from)?;
+        Ok(Self::new(Port::Tcp(t)))
+    }
+
+    /// Runs the handshake, returns a dict with the `board_id` and the
+    /// `variables` by message ID
+    #[pyo3(signature = (timeout = 1.0))]
+    fn handshake<'py>(&mut self, py: Python<'py>, timeout: f64) -> PyResult<Bound<'py, PyDict>> {
+        let device = self.connection.handshake(duration(timeout)?)?;
+        let variables = PyDict::new(py);
+        for (id, value) in device.variables.iter() {
+            variables.set_item(id, to_py(py, value)?)?;
+        }
+        let dict = PyDict::new(py);
+        dict.set_item("board_id", device.board_id)?;
+        dict.set_item("variables", variables)?;
+        Ok(dict)
+    }
+
+    /// Requests the current value of a variable of type `typ`, e.g. `"u16"`
+    #[pyo3(signature = (msg_id, typ, timeout = 1.0))]
+    fn query<'py>(
+        &mut self,
+        py: Python<'py>,
+        msg_id: &str,
+        typ: &str,
+        timeout: f64,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let value =
+            self.connection
+                .query(message_id(msg_id)?, parse_type(typ)?, duration(timeout)?)?;
+        to_py(py, &value)
+    }
+
+    /// Writes a variable of type `typ`, waiting for the device to
+    /// acknowledge it unless `acked` is false
+    #[pyo3(signature = (msg_id, typ, value, acked = true, retries = 2, timeout = 1.0))]
+    fn write(
+        &mut self,
+        msg_id: &str,
+        typ: &str,
+        value: &Bound<'_, PyAny>,
+        acked: bool,
+        retries: usize,
+        timeout: f64,
+    ) -> PyResult<()> {
+        let id = message_id(msg_id)?;
+        let value = from_py(parse_type(typ)?, value)?;
+        if acked {
+            self.connection
+                .write_acked(id, value, retries, duration(timeout)?)?;
+        } else {
+            self.connection.write(id, value)?;
+        }
+        Ok(())
+    }
+
+    /// Calls `callback(msg_id, value)` from `poll` for each variable
+    /// received matching `pattern`, e.g. `"speed"`, `"mot.*"` or `"*"`.
+    /// Returns an ID for `unsubscribe`.
+    fn subscribe(&mut self, pattern: &str, callback: Py<PyAny>) -> usize {
+        let callback_error = self.callback_error.clone();
+        let id = self.connection.subscribe(pattern, move |id, value| {
+            Python::attach(|py| {
+                let res =
+                    to_py(py, value).and_then(|value| callback.call1(py, (id.to_string(), value)));
+                if let Err(e) = res {
+                    callback_error.borrow_mut().get_or_insert(e);
+                }
+            })
+        });
+        id.0
+    }
+
+    /// Returns false if the subscription didn't exist
+    fn unsubscribe(&mut self, id: usize) -> bool {
+        self.connection.unsubscribe(SubscriptionId(id))
+    }
+
+    /// Receives and dispatches the available packets, returns how many
+    /// there were
+    fn poll(&mut self) -> PyResult<usize> {
+        let cnt = self.connection.poll()?;
+        match self.callback_error.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(cnt),
+        }
+    }
+}
+
+impl PyConnection {
+    fn new(port: Port) -> Self {
+        Self {
+            connection: Connection::new(port),
+            callback_error: Rc::default(),
+        }
+    }
+}
+
+#[pymodule]
+fn electricui_embedded(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyConnection>()
+}
+
+fn duration(secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn message_id(id: &str) -> PyResult<MessageId<'_>> {
+    MessageId::new(id.as_bytes())
+        .ok_or_else(|| PyValueError::new_err(format!("Invalid message ID '{}'", id)))
+}
+
+/// Parses a message type name, e.g. `"u16"` or `"F32"`
+fn parse_type(typ: &str) -> PyResult<MessageType> {
+    use MessageType::*;
+    Ok(match typ.to_ascii_lowercase().as_str() {
+        "callback" => Callback,
+        "custom" => Custom,
+        "offset_metadata" => OffsetMetadata,
+        "byte" => Byte,
+        "char" => Char,
+        "i8" => I8,
+        "u8" => U8,
+        "i16" => I16,
+        "u16" => U16,
+        "i32" => I32,
+        "u32" => U32,
+        "f32" => F32,
+        "f64" => F64,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "Unknown message type '{}'",
+                typ
+            )))
+        }
+    })
+}
+
+/// Numeric values become a number, or a list when there's more than one
+/// element, `Char` values a str and the opaque types bytes
+fn to_py<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    fn elements<'py, T>(py: Python<'py>, v: &[T]) -> PyResult<Bound<'py, PyAny>>
+    where
+        T: Copy + IntoPyObject<'py>,
+    {
+        if v.len() == 1 {
+            v[0].into_bound_py_any(py)
+        } else {
+            v.to_vec().into_bound_py_any(py)
+        }
+    }
+
+    match value {
+        Value::Callback => Ok(py.None().into_bound(py)),
+        Value::Char(v) => Ok(PyString::new(py, &String::from_utf8_lossy(v)).into_any()),
+        Value::Custom(v) | Value::OffsetMetadata(v) | Value::Byte(v) | Value::Unknown(_, v) => {
+            Ok(PyBytes::new(py, v).into_any())
+        }
+        Value::I8(v) => elements(py, v),
+        Value::U8(v) => elements(py, v),
+        Value::I16(v) => elements(py, v),
+        Value::U16(v) => elements(py, v),
+        Value::I32(v) => elements(py, v),
+        Value::U32(v) => elements(py, v),
+        Value::F32(v) => elements(py, v),
+        Value::F64(v) => elements(py, v),
+    }
+}
+
+/// The inverse of [`to_py`], numeric types take a number or a list
+fn from_py(typ: MessageType, obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    macro_rules! elements {
+        ($t:ty) => {
+            match obj.extract::<$t>() {
+                Ok(v) => vec![v],
+                Err(_) => obj.extract::<Vec<$t>>()?,
+            }
+        };
+    }
+
+    use MessageType::*;
+    Ok(match typ {
+        Callback => Value::Callback,
+        Char => Value::Char(obj.extract::<String>()?.into_bytes()),
+        Custom => Value::Custom(obj.extract()?),
+        OffsetMetadata => Value::OffsetMetadata(obj.extract()?),
+        Byte => Value::Byte(obj.extract()?),
+        I8 => Value::I8(elements!(i8)),
+        U8 => Value::U8(elements!(u8)),
+        I16 => Value::I16(elements!(i16)),
+        U16 => Value::U16(elements!(u16)),
+        I32 => Value::I32(elements!(i32)),
+        U32 => Value::U32(elements!(u32)),
+        F32 => Value::F32(elements!(f32)),
+        F64 => Value::F64(elements!(f64)),
+        Unknown(t) => Value::Unknown(t, obj.extract()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn value_conversions() {
+        Python::initialize();
+        Python::attach(|py| {
+            let values = [
+                (MessageType::U16, Value::U16(vec![20])),
+                (MessageType::F32, Value::F32(vec![1.5, -2.0])),
+                (MessageType::Char, Value::Char(b"name".to_vec())),
+                (MessageType::Custom, Value::Custom(vec![1, 2, 3])),
+                (MessageType::Callback, Value::Callback),
+            ];
+            for (typ, value) in values.iter() {
+                let obj = to_py(py, value).unwrap();
+                assert_eq!(&from_py(*typ, &obj).unwrap(), value);
+            }
+
+            let obj = to_py(py, &Value::U16(vec![300])).unwrap();
+            assert_eq!(obj.extract::<u16>().unwrap(), 300);
+            assert!(from_py(MessageType::U8, &obj).is_err());
+        });
+
+        assert_eq!(parse_type("U16").unwrap(), MessageType::U16);
+        assert!(parse_type("u64").is_err());
+    }
+}
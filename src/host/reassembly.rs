@@ -0,0 +1,296 @@
+//! Reassembly of variables too large for a single packet
+//!
+//! The device announces a large variable with an [`OffsetMetadata`] packet
+//! carrying the little-endian `[base, end]` address range, followed by
+//! offset packets each holding a chunk of the range at its offset address.
+
+use crate::message::MessageType;
+use crate::offset::OffsetMetadata;
+use crate::wire::{packet, Packet};
+use std::vec;
+use std::vec::Vec;
+
+/// Outcome of feeding a packet to a [`Reassembler`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Number of bytes received so far for the transfer
+    Progress { received: usize, total: usize },
+
+    /// The transfer is complete
+    Complete { typ: MessageType, data: Vec<u8> },
+
+    /// An offset packet that doesn't belong to a known transfer,
+    /// or falls outside of its address range
+    Orphaned,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    id: Vec<u8>,
+    typ: Option<MessageType>,
+    base: u16,
+    data: Vec<u8>,
+    filled: Vec<bool>,
+    received: usize,
+}
+
+/// Collects offset packets into whole variables, keyed by message ID
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    transfers: Vec<Transfer>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the packet is part of an offset transfer
+    pub fn accepts<B: AsRef<[u8]>>(packet: &Packet<B>) -> bool {
+        packet.offset() || packet.typ() == MessageType::OffsetMetadata
+    }
+
+    /// Number of transfers in progress
+    pub fn pending(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Feeds a packet, returns `Ok(None)` if it isn't part of an offset transfer
+    pub fn push<B: AsRef<[u8]>>(
+        &mut self,
+        packet: &Packet<B>,
+    ) -> Result<Option<Status>, packet::Error> {
+        if !Self::accepts(packet) {
+            return Ok(None);
+        }
+        let id = packet.msg_id()?;
+        let id = id.as_bytes();
+        let payload = packet.payload()?;
+
+        if packet.typ() == MessageType::OffsetMetadata && !packet.offset() {
+            let meta = OffsetMetadata::parse(payload).ok_or(packet::Error::InvalidDataLength)?;
+            let base = meta.base();
+            let total = meta.len();
+            // No chunk could ever complete an empty range
+            if total == 0 {
+                return Err(packet::Error::InvalidDataLength);
+            }
+            // A new announcement restarts any transfer of the same variable
+            self.transfers.retain(|t| t.id != id);
+            self.transfers.push(Transfer {
+                id: id.to_vec(),
+                typ: None,
+                base,
+                data: vec![0; total],
+                filled: vec![false; total],
+                received: 0,
+            });
+            return Ok(Some(Status::Progress { received: 0, total }));
+        }
+
+        let address = match packet.offset_address()? {
+            Some(a) => a,
+            None => return Ok(Some(Status::Orphaned)),
+        };
+        let idx = match self.transfers.iter().position(|t| t.id == id) {
+            Some(idx) => idx,
+            None => return Ok(Some(Status::Orphaned)),
+        };
+        let t = &mut self.transfers[idx];
+        let start = match address.checked_sub(t.base) {
+            Some(start) => usize::from(start),
+            None => return Ok(Some(Status::Orphaned)),
+        };
+        let end = start + payload.len();
+        if end > t.data.len() {
+            return Ok(Some(Status::Orphaned));
+        }
+        t.typ = Some(packet.typ());
+        t.data[start..end].copy_from_slice(payload);
+        for f in t.filled[start..end].iter_mut().filter(|f| !**f) {
+            *f = true;
+            t.received += 1;
+        }
+
+        if t.received == t.data.len() {
+            let t = self.transfers.swap_remove(idx);
+            Ok(Some(Status::Complete {
+                typ: t.typ.unwrap_or(MessageType::Custom),
+                data: t.data,
+            }))
+        } else {
+            Ok(Some(Status::Progress {
+                received: t.received,
+                total: t.data.len(),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::message::{MessageId, MessageType};
+    use crate::offset::OffsetMetadata;
+    use crate::wire::Packet;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Returns an unframed packet, with an offset address when `offset` is set
+    pub fn offset_packet(
+        msg_id: &[u8],
+        typ: MessageType,
+        offset: Option<u16>,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let id = MessageId::new(msg_id).unwrap();
+        let offset_size = offset.map_or(0, |_| Packet::<&[u8]>::OFFSET_SIZE);
+        let mut bytes = vec![0; Packet::<&[u8]>::buffer_len(id.len(), payload.len()) + offset_size];
+        if let Some(addr) = offset {
+            let start = Packet::<&[u8]>::HEADER_SIZE + id.len();
+            bytes[start..start + 2].copy_from_slice(&addr.to_le_bytes());
+        }
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        p.set_typ(typ);
+        p.set_offset(offset.is_some());
+        p.set_msg_id(id).unwrap();
+        p.set_payload(payload).unwrap();
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        bytes
+    }
+
+    /// Returns the metadata packet followed by the chunks, sent from the end first
+    pub fn offset_transfer(
+        msg_id: &[u8],
+        typ: MessageType,
+        data: &[u8],
+        chunk: usize,
+    ) -> Vec<Vec<u8>> {
+        let meta = OffsetMetadata::new(0, data.len() as u16).unwrap();
+        let mut pkts = vec![offset_packet(
+            msg_id,
+            MessageType::OffsetMetadata,
+            None,
+            &meta.to_bytes(),
+        )];
+        let mut end = data.len();
+        while end > 0 {
+            let start = end.saturating_sub(chunk);
+            pkts.push(offset_packet(
+                msg_id,
+                typ,
+                Some(start as u16),
+                &data[start..end],
+            ));
+            end = start;
+        }
+        pkts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::*;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reassemble() {
+        let data: Vec<u8> = (0..250_u8).collect();
+        let pkts = offset_transfer(b"blob", MessageType::U8, &data, 100);
+        assert_eq!(pkts.len(), 4);
+
+        let mut r = Reassembler::new();
+        let mut statuses = Vec::new();
+        for p in pkts.iter() {
+            statuses.push(r.push(&Packet::new(&p[..]).unwrap()).unwrap().unwrap());
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                Status::Progress {
+                    received: 0,
+                    total: 250
+                },
+                Status::Progress {
+                    received: 100,
+                    total: 250
+                },
+                Status::Progress {
+                    received: 200,
+                    total: 250
+                },
+                Status::Complete {
+                    typ: MessageType::U8,
+                    data
+                },
+            ]
+        );
+        assert_eq!(r.pending(), 0);
+    }
+
+    #[test]
+    fn duplicate_chunks() {
+        let data: Vec<u8> = (0..20_u8).collect();
+        let pkts = offset_transfer(b"blob", MessageType::Custom, &data, 10);
+        let mut r = Reassembler::new();
+        r.push(&Packet::new(&pkts[0][..]).unwrap()).unwrap();
+        let s = r.push(&Packet::new(&pkts[1][..]).unwrap()).unwrap();
+        assert_eq!(
+            s,
+            Some(Status::Progress {
+                received: 10,
+                total: 20
+            })
+        );
+        let s = r.push(&Packet::new(&pkts[1][..]).unwrap()).unwrap();
+        assert_eq!(
+            s,
+            Some(Status::Progress {
+                received: 10,
+                total: 20
+            })
+        );
+        let s = r.push(&Packet::new(&pkts[2][..]).unwrap()).unwrap();
+        assert_eq!(
+            s,
+            Some(Status::Complete {
+                typ: MessageType::Custom,
+                data
+            })
+        );
+    }
+
+    #[test]
+    fn orphaned_chunks() {
+        let mut r = Reassembler::new();
+        let chunk = offset_packet(b"blob", MessageType::U8, Some(0), &[1, 2]);
+        let s = r.push(&Packet::new(&chunk[..]).unwrap()).unwrap();
+        assert_eq!(s, Some(Status::Orphaned));
+
+        let pkts = offset_transfer(b"blob", MessageType::U8, &[0; 4], 4);
+        r.push(&Packet::new(&pkts[0][..]).unwrap()).unwrap();
+        let chunk = offset_packet(b"blob", MessageType::U8, Some(3), &[1, 2]);
+        let s = r.push(&Packet::new(&chunk[..]).unwrap()).unwrap();
+        assert_eq!(s, Some(Status::Orphaned));
+        assert_eq!(r.pending(), 1);
+    }
+
+    #[test]
+    fn empty_range() {
+        let mut r = Reassembler::new();
+        let pkts = offset_transfer(b"blob", MessageType::U8, &[], 4);
+        assert_eq!(pkts.len(), 1);
+        assert_eq!(
+            r.push(&Packet::new(&pkts[0][..]).unwrap()),
+            Err(packet::Error::InvalidDataLength)
+        );
+        assert_eq!(r.pending(), 0);
+    }
+
+    #[test]
+    fn unrelated_packets() {
+        let mut r = Reassembler::new();
+        let p = offset_packet(b"abc", MessageType::U8, None, &[1]);
+        assert_eq!(r.push(&Packet::new(&p[..]).unwrap()).unwrap(), None);
+    }
+}
@@ -0,0 +1,373 @@
+//! Automatic reconnection
+
+use crate::host::connection::{Connection, Error};
+use crate::host::device::Device;
+use crate::transport::Transport;
+use std::boxed::Box;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// When and how often a [`Reconnecting`] connection tries to reopen its port
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt after the connection is lost
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: u32,
+    /// Consecutive failed attempts after which to give up, `None` to retry forever
+    pub max_attempts: Option<usize>,
+    /// Timeout for each response of the handshake
+    pub handshake_timeout: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            max_attempts: None,
+            handshake_timeout: Duration::from_millis(250),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before the given attempt, starting at 1
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let exp = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.multiplier
+            .checked_pow(exp)
+            .and_then(|m| self.initial_backoff.checked_mul(m))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Connection state changes reported by [`Reconnecting`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The port was opened and the handshake completed
+    Connected,
+    /// The port failed, reconnection will be attempted
+    Disconnected,
+    /// An attempt failed, the next one is in `delay`
+    AttemptFailed { attempt: usize, delay: Duration },
+    /// The policy's maximum number of attempts was reached
+    GaveUp,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Connected,
+    Reconnecting { attempt: usize, next: Instant },
+    GaveUp,
+}
+
+type Opener<T> = Box<dyn FnMut() -> io::Result<T>>;
+type Setup<T> = Box<dyn FnMut(&mut Connection<T>)>;
+
+/// A connection that reopens its port and re-runs the handshake whenever
+/// the port fails, e.g. when a USB serial adapter is unplugged.
+///
+/// Drive it by calling [`Reconnecting::poll`]. Reconnection happens from
+/// within `poll`, one attempt per call once its backoff has elapsed.
+pub struct Reconnecting<T> {
+    open: Opener<T>,
+    policy: ReconnectPolicy,
+    connection: Option<Connection<T>>,
+    device: Option<Device>,
+    state: State,
+    setup: Option<Setup<T>>,
+    events: Option<Box<dyn FnMut(Event)>>,
+}
+
+impl<T: Transport<Error = io::Error>> Reconnecting<T> {
+    /// Creates a disconnected instance, the first connection attempt is
+    /// made on the first call to [`Reconnecting::poll`]
+    pub fn new<F>(policy: ReconnectPolicy, open: F) -> Self
+    where
+        F: FnMut() -> io::Result<T> + 'static,
+    {
+        Self {
+            open: Box::new(open),
+            policy,
+            connection: None,
+            device: None,
+            state: State::Reconnecting {
+                attempt: 1,
+                next: Instant::now(),
+            },
+            setup: None,
+            events: None,
+        }
+    }
+
+    /// Registers a callback run on every new connection before the
+    /// handshake, e.g. to (re)register subscriptions
+    pub fn on_connect<F>(&mut self, setup: F)
+    where
+        F: FnMut(&mut Connection<T>) + 'static,
+    {
+        self.setup = Some(Box::new(setup));
+    }
+
+    /// Registers a callback invoked on every state change
+    pub fn on_event<F>(&mut self, callback: F)
+    where
+        F: FnMut(Event) + 'static,
+    {
+        self.events = Some(Box::new(callback));
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// The result of the most recent handshake
+    pub fn device(&self) -> Option<&Device> {
+        self.device.as_ref()
+    }
+
+    pub fn connection(&mut self) -> Option<&mut Connection<T>> {
+        self.connection.as_mut()
+    }
+
+    /// Runs `f` with the connection, marking it lost if `f` fails because
+    /// of the port. Returns [`Error::Disconnected`] when not connected.
+    pub fn with<R, F>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Connection<T>) -> Result<R, Error>,
+    {
+        let c = self.connection.as_mut().ok_or(Error::Disconnected)?;
+        let res = f(c);
+        if let Err(e) = &res {
+            if is_port_failure(e) {
+                self.disconnected();
+            }
+        }
+        res
+    }
+
+    /// Polls the connection, see [`Connection::poll`], or makes a
+    /// reconnection attempt when one is due.
+    ///
+    /// Port failures are handled rather than returned. Returns
+    /// [`Error::Disconnected`] once the policy gives up.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        match self.state {
+            State::Connected => {
+                self.with(|c| c.poll()).or_else(
+                    |e| {
+                        if is_port_failure(&e) {
+                            Ok(0)
+                        } else {
+                            Err(e)
+                        }
+                    },
+                )
+            }
+            State::Reconnecting { attempt, next } => {
+                if Instant::now() >= next {
+                    self.attempt(attempt);
+                }
+                Ok(0)
+            }
+            State::GaveUp => Err(Error::Disconnected),
+        }
+    }
+
+    fn attempt(&mut self, attempt: usize) {
+        let res = (self.open)().map_err(Error::from).and_then(|io| {
+            let mut c = Connection::new(io);
+            if let Some(setup) = self.setup.as_mut() {
+                setup(&mut c);
+            }
+            let device = c.handshake(self.policy.handshake_timeout)?;
+            Ok((c, device))
+        });
+        match res {
+            Ok((c, device)) => {
+                self.connection = Some(c);
+                self.device = Some(device);
+                self.state = State::Connected;
+                self.emit(Event::Connected);
+            }
+            Err(_) if self.policy.max_attempts.is_some_and(|max| attempt >= max) => {
+                self.state = State::GaveUp;
+                self.emit(Event::GaveUp);
+            }
+            Err(_) => {
+                let delay = self.policy.backoff(attempt + 1);
+                self.state = State::Reconnecting {
+                    attempt: attempt + 1,
+                    next: Instant::now() + delay,
+                };
+                self.emit(Event::AttemptFailed { attempt, delay });
+            }
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.connection = None;
+        self.state = State::Reconnecting {
+            attempt: 1,
+            next: Instant::now() + self.policy.backoff(1),
+        };
+        self.emit(Event::Disconnected);
+    }
+
+    fn emit(&mut self, event: Event) {
+        match event {
+            Event::Connected => log!(info, "Connected"),
+            Event::Disconnected => log!(warn, "Disconnected, reconnecting"),
+            Event::AttemptFailed { attempt, delay } => log!(
+                warn,
+                "Connection attempt {} failed, retrying in {:?}",
+                attempt,
+                delay
+            ),
+            Event::GaveUp => log!(error, "Giving up reconnecting"),
+        }
+        if let Some(f) = self.events.as_mut() {
+            f(event);
+        }
+    }
+}
+
+/// Errors that mean the port itself is gone, rather than a bad packet
+/// or an unresponsive device
+fn is_port_failure(e: &Error) -> bool {
+    matches!(e, Error::Io(_) | Error::Disconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::mock::MockDevice;
+    use crate::host::value::Value;
+    use crate::message::MessageId;
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            multiplier: 2,
+            max_attempts: Some(3),
+            handshake_timeout: Duration::from_millis(10),
+        }
+    }
+
+    fn device() -> Device {
+        let mut d = Device::new();
+        d.board_id = Some(7);
+        d.variables.insert("speed".into(), Value::U16(vec![1]));
+        d
+    }
+
+    fn events(r: &mut Reconnecting<MockDevice>) -> Rc<RefCell<Vec<Event>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let e = events.clone();
+        r.on_event(move |ev| e.borrow_mut().push(ev));
+        events
+    }
+
+    #[test]
+    fn backoff() {
+        let p = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(p.backoff(1), Duration::from_millis(100));
+        assert_eq!(p.backoff(2), Duration::from_millis(200));
+        assert_eq!(p.backoff(4), Duration::from_millis(800));
+        assert_eq!(p.backoff(5), Duration::from_secs(1));
+        assert_eq!(p.backoff(1000), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reconnect_after_unplug() {
+        let mut r = Reconnecting::new(policy(), || Ok(MockDevice::new(device())));
+        let events = events(&mut r);
+        let setups = Rc::new(RefCell::new(0));
+        let s = setups.clone();
+        r.on_connect(move |_| *s.borrow_mut() += 1);
+
+        r.poll().unwrap();
+        assert!(r.is_connected());
+        assert_eq!(r.device(), Some(&device()));
+
+        r.connection().unwrap().get_mut().unplug();
+        let speed = MessageId::new(b"speed").unwrap();
+        assert!(matches!(
+            r.with(|c| c.write(speed, 2_u16)),
+            Err(Error::Io(_))
+        ));
+        assert!(!r.is_connected());
+        assert!(matches!(
+            r.with(|c| c.write(speed, 2_u16)),
+            Err(Error::Disconnected)
+        ));
+
+        r.poll().unwrap();
+        assert!(r.is_connected());
+        assert_eq!(*setups.borrow(), 2);
+        assert_eq!(
+            *events.borrow(),
+            [Event::Connected, Event::Disconnected, Event::Connected]
+        );
+    }
+
+    #[test]
+    fn give_up() {
+        let mut r: Reconnecting<MockDevice> =
+            Reconnecting::new(policy(), || Err(io::ErrorKind::NotFound.into()));
+        let events = events(&mut r);
+        for _ in 0..3 {
+            r.poll().unwrap();
+        }
+        assert_eq!(r.state(), State::GaveUp);
+        assert!(matches!(r.poll(), Err(Error::Disconnected)));
+        assert_eq!(
+            *events.borrow(),
+            [
+                Event::AttemptFailed {
+                    attempt: 1,
+                    delay: Duration::ZERO
+                },
+                Event::AttemptFailed {
+                    attempt: 2,
+                    delay: Duration::ZERO
+                },
+                Event::GaveUp,
+            ]
+        );
+    }
+
+    #[test]
+    fn handshake_failure_retries() {
+        let mut opened = 0;
+        let mut r = Reconnecting::new(policy(), move || {
+            opened += 1;
+            // The first device never answers
+            let drop_rate = if opened == 1 { 1.0 } else { 0.0 };
+            Ok(MockDevice::new(device()).with_drop_rate(drop_rate))
+        });
+        r.poll().unwrap();
+        assert!(!r.is_connected());
+        r.poll().unwrap();
+        assert!(r.is_connected());
+    }
+}
@@ -0,0 +1,327 @@
+//! Session recording and replay
+//!
+//! A session log starts with the [`MAGIC`] bytes followed by one record
+//! per read or write on the recorded stream:
+//!
+//! | Field     | Size | Notes                                       |
+//! |-----------|------|---------------------------------------------|
+//! | direction | 1    | 0 device to host, 1 host to device          |
+//! | delta     | 4    | microseconds since the previous record (LE) |
+//! | length    | 2    | number of data bytes (LE)                   |
+//! | data      | N    | raw bytes, frames may span records          |
+
+use crate::host::connection::Error;
+use crate::host::sniff::Direction;
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// Identifies a session log, includes the format version
+pub const MAGIC: [u8; 4] = *b"EUS1";
+
+const RECORD_HEADER_SIZE: usize = 7;
+
+/// A chunk of bytes recorded in a session
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the start of the session
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// Wraps a stream (e.g. a serial port), logging everything read from and
+/// written to it
+pub struct Recorder<T, W> {
+    io: T,
+    log: W,
+    last: Instant,
+}
+
+impl<T: Read + Write, W: Write> Recorder<T, W> {
+    /// Writes the session header to `log`
+    pub fn new(io: T, mut log: W) -> io::Result<Self> {
+        log.write_all(&MAGIC)?;
+        Ok(Self {
+            io,
+            log,
+            last: Instant::now(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> (T, W) {
+        (self.io, self.log)
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        let delta = u32::try_from(now.duration_since(self.last).as_micros()).unwrap_or(u32::MAX);
+        self.last = now;
+        // Split chunks that don't fit a record, only the first carries the delta
+        for (i, chunk) in data.chunks(usize::from(u16::MAX)).enumerate() {
+            let mut header = [0; RECORD_HEADER_SIZE];
+            header[0] = match direction {
+                Direction::DeviceToHost => 0,
+                Direction::HostToDevice => 1,
+            };
+            LittleEndian::write_u32(&mut header[1..5], if i == 0 { delta } else { 0 });
+            LittleEndian::write_u16(&mut header[5..7], chunk.len() as u16);
+            self.log.write_all(&header)?;
+            self.log.write_all(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Write, W: Write> Read for Recorder<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.io.read(buf)?;
+        if size != 0 {
+            self.record(Direction::DeviceToHost, &buf[..size])?;
+        }
+        Ok(size)
+    }
+}
+
+impl<T: Read + Write, W: Write> Write for Recorder<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.io.write(buf)?;
+        self.record(Direction::HostToDevice, &buf[..size])?;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()?;
+        self.log.flush()
+    }
+}
+
+/// Returns true if `bytes` starts with a session header
+pub fn is_session(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Parses a whole session log
+pub fn parse(session: &[u8]) -> Result<Vec<Record>, Error> {
+    let mut rest = session
+        .strip_prefix(&MAGIC[..])
+        .ok_or(Error::InvalidCapture)?;
+    let mut timestamp = Duration::ZERO;
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < RECORD_HEADER_SIZE {
+            return Err(Error::InvalidCapture);
+        }
+        let direction = match rest[0] {
+            0 => Direction::DeviceToHost,
+            1 => Direction::HostToDevice,
+            _ => return Err(Error::InvalidCapture),
+        };
+        timestamp += Duration::from_micros(LittleEndian::read_u32(&rest[1..5]).into());
+        let len = usize::from(LittleEndian::read_u16(&rest[5..7]));
+        let data = rest
+            .get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len)
+            .ok_or(Error::InvalidCapture)?;
+        records.push(Record {
+            timestamp,
+            direction,
+            data: data.to_vec(),
+        });
+        rest = &rest[RECORD_HEADER_SIZE + len..];
+    }
+    Ok(records)
+}
+
+/// Plays the device side of a recorded session back, usable anywhere
+/// the recorded stream was, e.g. `Connection<Replay>`.
+///
+/// Device data recorded after a host write only becomes readable once
+/// the host has written as many bytes, so replies follow their requests.
+/// Written bytes are compared against the recording, see [`Replay::diverged`].
+pub struct Replay {
+    records: VecDeque<Record>,
+    rx: VecDeque<u8>,
+    /// Bytes of the current host record still expected
+    expected: VecDeque<u8>,
+    diverged: bool,
+    paced: Option<Instant>,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Self {
+        Self {
+            records: records.into(),
+            rx: VecDeque::new(),
+            expected: VecDeque::new(),
+            diverged: false,
+            paced: None,
+        }
+    }
+
+    /// Parses a session log, see [`parse`]
+    pub fn from_session(session: &[u8]) -> Result<Self, Error> {
+        Ok(Self::new(parse(session)?))
+    }
+
+    /// Releases device data no earlier than its original timestamp,
+    /// relative to now
+    pub fn paced(mut self) -> Self {
+        self.paced = Some(Instant::now());
+        self
+    }
+
+    /// True once the host wrote something other than what was recorded
+    pub fn diverged(&self) -> bool {
+        self.diverged
+    }
+
+    /// True once all the recorded data has been read and written
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty() && self.rx.is_empty() && self.expected.is_empty()
+    }
+
+    /// Moves the device records up to the next host record into the
+    /// readable data
+    fn advance(&mut self) {
+        while self.expected.is_empty() {
+            let due = match (self.records.front(), self.paced) {
+                (None, _) => return,
+                (Some(r), Some(start)) if r.direction == Direction::DeviceToHost => {
+                    start.elapsed() >= r.timestamp
+                }
+                (Some(_), _) => true,
+            };
+            if !due {
+                return;
+            }
+            if let Some(r) = self.records.pop_front() {
+                match r.direction {
+                    Direction::DeviceToHost => self.rx.extend(r.data),
+                    Direction::HostToDevice => self.expected.extend(r.data),
+                }
+            }
+        }
+    }
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.advance();
+        if self.rx.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let size = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..size)) {
+            *dst = src;
+        }
+        Ok(size)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.advance();
+            match self.expected.pop_front() {
+                Some(expected) if expected == byte => (),
+                _ => self.diverged = true,
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::capture::decode_capture;
+    use crate::host::connection::Connection;
+    use crate::host::device::Device;
+    use crate::host::mock::MockDevice;
+    use crate::host::sniff::Output;
+    use crate::host::value::Value;
+    use crate::message::MessageId;
+    use pretty_assertions::assert_eq;
+    use std::string::String;
+    use std::vec;
+
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    fn record_session() -> Vec<u8> {
+        let mut d = Device::new();
+        d.board_id = Some(1);
+        d.variables.insert("speed".into(), Value::U16(vec![10]));
+        let mut c = Connection::new(Recorder::new(MockDevice::new(d), Vec::new()).unwrap());
+        let speed = MessageId::new(b"speed").unwrap();
+        c.handshake(TIMEOUT).unwrap();
+        c.write_acked(speed, 20_u16, 0, TIMEOUT).unwrap();
+        assert_eq!(c.query_as::<u16>(speed, TIMEOUT).unwrap(), 20);
+        c.into_inner().into_inner().1
+    }
+
+    #[test]
+    fn record_and_parse() {
+        let session = record_session();
+        assert!(is_session(&session));
+        let records = parse(&session).unwrap();
+        assert_eq!(records[0].direction, Direction::HostToDevice);
+        assert!(records.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert!(matches!(
+            parse(&session[..session.len() - 1]),
+            Err(Error::InvalidCapture)
+        ));
+        assert!(matches!(parse(b"EUS0"), Err(Error::InvalidCapture)));
+    }
+
+    #[test]
+    fn replay_through_connection() {
+        let session = record_session();
+        let mut c = Connection::new(Replay::from_session(&session).unwrap());
+        let speed = MessageId::new(b"speed").unwrap();
+        let device = c.handshake(TIMEOUT).unwrap();
+        assert_eq!(device.board_id, Some(1));
+        c.write_acked(speed, 20_u16, 0, TIMEOUT).unwrap();
+        assert_eq!(c.query_as::<u16>(speed, TIMEOUT).unwrap(), 20);
+        assert!(c.get_ref().is_finished());
+        assert!(!c.get_ref().diverged());
+    }
+
+    #[test]
+    fn replay_divergence() {
+        let session = record_session();
+        let mut c = Connection::new(Replay::from_session(&session).unwrap());
+        let speed = MessageId::new(b"speed").unwrap();
+        c.write(speed, 1_u16).unwrap();
+        assert!(c.get_ref().diverged());
+    }
+
+    #[test]
+    fn decode_session() {
+        let session = record_session();
+        let mut out = Vec::new();
+        let frames =
+            decode_capture(&session, Direction::DeviceToHost, Output::Text, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), frames);
+        let first = out.lines().next().unwrap();
+        assert!(
+            first.ends_with(">> i (board_id) U16 internal response"),
+            "{}",
+            first
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Passive protocol sniffing
+
+use crate::host::connection::Error;
+use crate::host::framer::{read_available, Deframer, READ_CHUNK_SIZE};
+use crate::host::value::Value;
+use crate::message::MessageId;
+use crate::wire::Packet;
+use core::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use std::vec::Vec;
+
+/// Which way a tapped packet was travelling
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Direction {
+    HostToDevice,
+    DeviceToHost,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::HostToDevice => f.write_str(">>"),
+            Direction::DeviceToHost => f.write_str("<<"),
+        }
+    }
+}
+
+/// Returns the symbolic name of an internal message ID
+pub fn internal_name(id: MessageId<'_>) -> Option<&'static str> {
+    Some(match id {
+        MessageId::INTERNAL_LIB_VER => "lib_ver",
+        MessageId::INTERNAL_BOARD_ID => "board_id",
+        MessageId::INTERNAL_HEARTBEAT => "heartbeat",
+        MessageId::INTERNAL_AM => "am",
+        MessageId::INTERNAL_AM_LIST => "am_list",
+        MessageId::INTERNAL_AM_END => "am_end",
+        MessageId::INTERNAL_AV => "av",
+        _ => return None,
+    })
+}
+
+/// Human-readable, single line rendering of a packet, e.g.
+/// `i (board_id) U16 internal = 1234`
+pub struct PacketDump<'a, T: AsRef<[u8]>>(pub &'a Packet<T>);
+
+impl<'a, T: AsRef<[u8]>> fmt::Display for PacketDump<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p = self.0;
+        let id = p.msg_id().map_err(|_| fmt::Error)?;
+        write!(f, "{}", id)?;
+        if p.internal() {
+            if let Some(name) = internal_name(id) {
+                write!(f, " ({})", name)?;
+            }
+        }
+        write!(f, " {}", p.typ())?;
+        if p.internal() {
+            f.write_str(" internal")?;
+        }
+        if p.response() {
+            f.write_str(" response")?;
+        }
+        if p.acknum().is_requested() {
+            write!(f, " ack={}", p.acknum())?;
+        }
+        if let Ok(Some(addr)) = p.offset_address() {
+            write!(f, " offset=0x{:04X}", addr)?;
+        }
+        let payload = p.payload().map_err(|_| fmt::Error)?;
+        if payload.is_empty() {
+            return Ok(());
+        }
+        match Value::parse(p.typ(), payload) {
+            Some(v) if !p.offset() => write!(f, " = {}", v),
+            // Offset chunks don't necessarily hold whole elements
+            _ => write!(f, " = {:02X?}", payload),
+        }
+    }
+}
+
+/// How sniffed packets are rendered
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum Output {
+    /// One [`PacketDump`] line per frame
+    #[default]
+    Text,
+    /// One JSON object per line
+    #[cfg(feature = "json")]
+    Json,
+}
+
+struct Tap<R> {
+    io: R,
+    direction: Direction,
+    deframer: Deframer,
+}
+
+/// Read-only observer of one or both directions of a link, printing
+/// every packet seen
+pub struct Sniffer<R> {
+    taps: Vec<Tap<R>>,
+    output: Output,
+}
+
+impl<R: Read> Sniffer<R> {
+    /// Sniffs the device-to-host traffic read from `rx`
+    pub fn new(rx: R) -> Self {
+        let mut s = Self {
+            taps: Vec::new(),
+            output: Output::default(),
+        };
+        s.tap(rx, Direction::DeviceToHost);
+        s
+    }
+
+    /// Adds another tapped line, typically the host's transmit line
+    pub fn tap(&mut self, io: R, direction: Direction) -> &mut Self {
+        self.taps.push(Tap {
+            io,
+            direction,
+            deframer: Deframer::new(),
+        });
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Reads from each tap and writes a line per frame to `out`,
+    /// returns the number of frames seen
+    pub fn poll<W: Write>(&mut self, out: &mut W) -> Result<usize, Error> {
+        let mut chunk = [0_u8; READ_CHUNK_SIZE];
+        let mut count = 0;
+        for tap in self.taps.iter_mut() {
+            let size = read_available(&mut tap.io, &mut chunk)?;
+            for &byte in &chunk[..size] {
+                if let Some(res) = tap.deframer.push(byte) {
+                    write_line(out, self.output, None, tap.direction, &res)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Writes a single sniffed frame, prefixed with the capture timestamp
+/// if there is one
+pub(crate) fn write_line<W: Write>(
+    out: &mut W,
+    output: Output,
+    timestamp: Option<Duration>,
+    dir: Direction,
+    res: &Result<Packet<Vec<u8>>, Error>,
+) -> io::Result<()> {
+    match output {
+        Output::Text => {
+            if let Some(ts) = timestamp {
+                write!(out, "{:.6} ", ts.as_secs_f64())?;
+            }
+            match res {
+                Ok(p) => writeln!(out, "{} {}", dir, PacketDump(p)),
+                Err(e) => writeln!(out, "{} invalid frame: {}", dir, e),
+            }
+        }
+        #[cfg(feature = "json")]
+        Output::Json => {
+            let mut obj = json_record(dir, res);
+            if let Some(ts) = timestamp {
+                obj["timestamp"] = ts.as_secs_f64().into();
+            }
+            serde_json::to_writer(&mut *out, &obj)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_record(dir: Direction, res: &Result<Packet<Vec<u8>>, Error>) -> serde_json::Value {
+    use std::string::ToString;
+
+    let p = match res {
+        Ok(p) => p,
+        Err(e) => {
+            return serde_json::json!({
+                "direction": dir.to_string(),
+                "error": e.to_string(),
+            })
+        }
+    };
+    let id = p.msg_id().ok();
+    let payload = p.payload().unwrap_or_default();
+    let value = if p.offset() {
+        None
+    } else {
+        Value::parse(p.typ(), payload)
+    };
+    serde_json::json!({
+        "direction": dir.to_string(),
+        "id": id.map(|id| id.to_string()),
+        "name": id.filter(|_| p.internal()).and_then(internal_name),
+        "type": p.typ().to_string(),
+        "internal": p.internal(),
+        "response": p.response(),
+        "acknum": p.acknum().get(),
+        "offset": p.offset_address().ok().flatten(),
+        "value": value,
+        "payload": payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::reassembly::test_util::offset_packet;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use crate::message::{AckNum, MessageType};
+    use pretty_assertions::assert_eq;
+    use std::string::{String, ToString};
+
+    #[test]
+    fn dump_packets() {
+        let p = Packet::new(offset_packet(b"i", MessageType::U16, None, &[0xD2, 0x04])).unwrap();
+        assert_eq!(PacketDump(&p).to_string(), "i U16 = 1234");
+
+        let p = Packet::new(offset_packet(b"big", MessageType::U8, Some(0x10), &[1, 2])).unwrap();
+        assert_eq!(
+            PacketDump(&p).to_string(),
+            "big U8 offset=0x0010 = [01, 02]"
+        );
+    }
+
+    #[test]
+    fn sniff_both_directions() {
+        let mut rx = Loopback::default();
+        rx.push_rx(&encode_packet(
+            b"i",
+            MessageType::U16,
+            true,
+            false,
+            AckNum::NONE,
+            &[0xD2, 0x04],
+        ));
+        rx.push_rx(&[0xFF, 0x01, 0x00]);
+        let mut tx = Loopback::default();
+        tx.push_rx(&encode_packet(
+            b"i",
+            MessageType::Callback,
+            true,
+            true,
+            AckNum::NONE,
+            &[],
+        ));
+        tx.push_rx(&encode_packet(
+            b"speed",
+            MessageType::F32,
+            false,
+            true,
+            AckNum::new(2).unwrap(),
+            &1.5_f32.to_le_bytes(),
+        ));
+
+        let mut s = Sniffer::new(rx);
+        s.tap(tx, Direction::HostToDevice);
+        let mut out = Vec::new();
+        assert_eq!(s.poll(&mut out).unwrap(), 4);
+        assert_eq!(s.poll(&mut out).unwrap(), 0);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<< i (board_id) U16 internal = 1234\n\
+             << invalid frame: Framing error. input truncated\n\
+             >> i (board_id) Callback internal response\n\
+             >> speed F32 response ack=2 = 1.5\n"
+        );
+    }
+}
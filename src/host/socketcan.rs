@@ -0,0 +1,89 @@
+//! SocketCAN transport, for talking to devices on a CAN bus through a
+//! Linux CAN interface, e.g. a USB-CAN adapter
+//!
+//! Frames are segmented and reassembled exactly as on the device side,
+//! see [`transport::can`](crate::transport::can).
+
+use crate::collections::MAX_FRAME_SIZE;
+use crate::transport::can::{self, CanTransport};
+use crate::transport::Transport;
+use crate::wire::Framing;
+use embedded_can::Id;
+use socketcan::{CanSocket, Socket};
+use std::io::{self, Read, Write};
+use std::string::ToString;
+use std::vec::Vec;
+
+/// Byte stream view of a CAN interface, usable anywhere a serial port
+/// is, e.g. `Connection<SocketCanTransport>`.
+///
+/// Frames are sent with `tx_id` and only frames with `rx_id` are
+/// received, the reverse of the device's IDs.
+pub struct SocketCanTransport {
+    can: CanTransport<CanSocket, MAX_FRAME_SIZE>,
+    tx: Vec<u8>,
+}
+
+impl SocketCanTransport {
+    /// Opens an interface such as `can0` or `vcan0`
+    pub fn open(ifname: &str, tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> io::Result<Self> {
+        Self::new(CanSocket::open(ifname)?, tx_id, rx_id)
+    }
+
+    /// Makes the socket non-blocking
+    pub fn new(socket: CanSocket, tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            can: CanTransport::new(socket, tx_id, rx_id),
+            tx: Vec::with_capacity(MAX_FRAME_SIZE),
+        })
+    }
+
+    /// Number of incomplete frames dropped
+    pub fn dropped(&self) -> usize {
+        self.can.dropped()
+    }
+
+    pub fn get_ref(&self) -> &CanSocket {
+        self.can.get_ref()
+    }
+
+    pub fn into_inner(self) -> CanSocket {
+        self.can.into_inner()
+    }
+}
+
+fn io_error(e: can::Error<socketcan::Error>) -> io::Error {
+    match e {
+        can::Error::Can(socketcan::Error::Io(e)) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+impl Read for SocketCanTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match Transport::read(&mut self.can, buf).map_err(io_error)? {
+            0 if !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+            size => Ok(size),
+        }
+    }
+}
+
+impl Write for SocketCanTransport {
+    /// Segments and sends each frame once its delimiter is written
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.tx.push(byte);
+            if byte == Framing::ZERO {
+                let res = Transport::write(&mut self.can, &self.tx).map_err(io_error);
+                self.tx.clear();
+                res?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Variable subscriptions
+
+use crate::host::value::Value;
+use crate::message::MessageId;
+use std::boxed::Box;
+use std::vec::Vec;
+
+/// Handle returned by [`Connection::subscribe`](crate::host::Connection::subscribe)
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SubscriptionId(pub(crate) usize);
+
+/// Selects which message IDs a subscription receives.
+///
+/// Converting from a `&str` treats `"*"` as any ID and a trailing `*`
+/// as a prefix match, e.g. `"mot.*"`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum IdPattern {
+    Any,
+    Exact(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+impl IdPattern {
+    pub fn matches(&self, id: MessageId<'_>) -> bool {
+        match self {
+            IdPattern::Any => true,
+            IdPattern::Exact(p) => id.as_bytes() == p.as_slice(),
+            IdPattern::Prefix(p) => id.as_bytes().starts_with(p),
+        }
+    }
+}
+
+impl From<&str> for IdPattern {
+    fn from(pattern: &str) -> Self {
+        match pattern.strip_suffix('*') {
+            Some("") => IdPattern::Any,
+            Some(prefix) => IdPattern::Prefix(prefix.as_bytes().to_vec()),
+            None => IdPattern::Exact(pattern.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl<'a> From<MessageId<'a>> for IdPattern {
+    fn from(id: MessageId<'a>) -> Self {
+        IdPattern::Exact(id.as_bytes().to_vec())
+    }
+}
+
+pub(crate) type Callback = Box<dyn FnMut(MessageId<'_>, &Value)>;
+
+pub(crate) struct Subscription {
+    pub(crate) id: SubscriptionId,
+    pub(crate) pattern: IdPattern,
+    pub(crate) callback: Callback,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pattern_from_str() {
+        assert_eq!(IdPattern::from("*"), IdPattern::Any);
+        assert_eq!(
+            IdPattern::from("mot.*"),
+            IdPattern::Prefix(b"mot.".to_vec())
+        );
+        assert_eq!(
+            IdPattern::from("led_state"),
+            IdPattern::Exact(b"led_state".to_vec())
+        );
+    }
+
+    #[test]
+    fn pattern_matches() {
+        let speed = MessageId::new(b"mot.speed").unwrap();
+        assert!(IdPattern::Any.matches(speed));
+        assert!(IdPattern::from("mot.*").matches(speed));
+        assert!(IdPattern::from("mot.speed").matches(speed));
+        assert!(!IdPattern::from("mot.current").matches(speed));
+        assert!(!IdPattern::from("led*").matches(speed));
+        assert!(IdPattern::from(MessageId::BOARD_NAME).matches(MessageId::BOARD_NAME));
+    }
+}
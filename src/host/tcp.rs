@@ -0,0 +1,165 @@
+//! TCP transport, for devices behind an Ethernet or Wi-Fi bridge and for
+//! host tools talking to a [`Bridge`](crate::host::Bridge)
+//!
+//! The socket carries the same COBS framed byte stream as a serial port.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a read waits for bytes before reporting none are available
+pub const POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// A connected socket configured for the protocol, with Nagle's algorithm
+/// disabled and short read timeouts so reads don't block.
+///
+/// Usable anywhere a serial port is, e.g. `Connection<TcpTransport>`.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    /// Connects to a device or bridge listening on `addr`
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> io::Result<Self> {
+        Self::new(TcpStream::connect_timeout(addr, timeout)?)
+    }
+
+    /// Configures an already connected stream
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(POLL_TIMEOUT))?;
+        Ok(Self { stream })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Accepts [`TcpTransport`] connections
+#[derive(Debug)]
+pub struct TcpServer {
+    listener: TcpListener,
+}
+
+impl TcpServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Blocks until a peer connects
+    pub fn accept(&self) -> io::Result<(TcpTransport, SocketAddr)> {
+        let (stream, addr) = self.listener.accept()?;
+        Ok((TcpTransport::new(stream)?, addr))
+    }
+
+    /// Returns `None` instead of blocking when no peer is waiting
+    pub fn try_accept(&self) -> io::Result<Option<(TcpTransport, SocketAddr)>> {
+        self.listener.set_nonblocking(true)?;
+        let res = self.listener.accept();
+        self.listener.set_nonblocking(false)?;
+        match res {
+            Ok((stream, addr)) => {
+                stream.set_nonblocking(false)?;
+                Ok(Some((TcpTransport::new(stream)?, addr)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    pub fn into_inner(self) -> TcpListener {
+        self.listener
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::bridge::Bridge;
+    use crate::host::connection::{Connection, Error};
+    use crate::host::device::Device;
+    use crate::host::mock::MockDevice;
+    use crate::host::value::Value;
+    use crate::message::MessageId;
+    use pretty_assertions::assert_eq;
+    use std::thread;
+    use std::vec;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn connection_over_tcp() {
+        let server = TcpServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        assert!(server.try_accept().unwrap().is_none());
+
+        let device = thread::spawn(move || {
+            let (client, _) = server.accept().unwrap();
+            let mut d = Device::new();
+            d.board_id = Some(7);
+            d.variables.insert("speed".into(), Value::U16(vec![10]));
+            let mut bridge = Bridge::new(MockDevice::new(d), client);
+            loop {
+                match bridge.poll() {
+                    Ok(_) => (),
+                    Err(Error::Disconnected) => break,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        });
+
+        let mut c = Connection::new(TcpTransport::connect(addr).unwrap());
+        let device_info = c.handshake(TIMEOUT).unwrap();
+        assert_eq!(device_info.board_id, Some(7));
+        let speed = MessageId::new(b"speed").unwrap();
+        c.write_acked(speed, 20_u16, 2, TIMEOUT).unwrap();
+        assert_eq!(c.query_as::<u16>(speed, TIMEOUT).unwrap(), 20);
+
+        c.into_inner().shutdown().unwrap();
+        device.join().unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Timestamped logging of variable updates
+
+use crate::host::connection::Connection;
+use crate::host::subscription::{IdPattern, SubscriptionId};
+use crate::host::value::Value;
+use crate::message::MessageId;
+use crate::transport::Transport;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::string::ToString;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// A single variable update
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Row<'a> {
+    /// Time since the logger was created
+    pub timestamp: Duration,
+    pub id: MessageId<'a>,
+    pub value: &'a Value,
+}
+
+/// Destination for telemetry rows
+pub trait Sink {
+    fn write_row(&mut self, row: &Row<'_>) -> io::Result<()>;
+}
+
+impl<F> Sink for F
+where
+    F: FnMut(&Row<'_>) -> io::Result<()>,
+{
+    fn write_row(&mut self, row: &Row<'_>) -> io::Result<()> {
+        self(row)
+    }
+}
+
+/// Writes rows as CSV with the columns `timestamp,id,type,value`,
+/// the timestamp is in seconds
+#[derive(Debug)]
+pub struct CsvSink<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn write_row(&mut self, row: &Row<'_>) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(self.writer, "timestamp,id,type,value")?;
+            self.header_written = true;
+        }
+        writeln!(
+            self.writer,
+            "{:.6},{},{},{}",
+            row.timestamp.as_secs_f64(),
+            CsvField(&row.id.to_string()),
+            row.value.typ(),
+            CsvField(&row.value.to_string()),
+        )
+    }
+}
+
+/// Quotes a field if it contains a delimiter, quote or line break
+struct CsvField<'a>(&'a str);
+
+impl core::fmt::Display for CsvField<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0.contains([',', '"', '\n', '\r']) {
+            write!(f, "\"{}\"", self.0.replace('"', "\"\""))
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+/// Logs the updates of a set of variables to a [`Sink`] as they arrive
+/// on a [`Connection`].
+///
+/// Sink errors can't propagate out of the subscription callbacks,
+/// the first one is retained and available from [`TelemetryLogger::take_error`].
+pub struct TelemetryLogger<S> {
+    start: Instant,
+    sink: S,
+    rows: usize,
+    error: Option<io::Error>,
+}
+
+impl<S: Sink + 'static> TelemetryLogger<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            start: Instant::now(),
+            sink,
+            rows: 0,
+            error: None,
+        }
+    }
+
+    /// Subscribes a shared logger to each of the patterns
+    pub fn attach<T, P, I>(
+        logger: &Rc<RefCell<Self>>,
+        connection: &mut Connection<T>,
+        patterns: I,
+    ) -> Vec<SubscriptionId>
+    where
+        T: Transport<Error = io::Error>,
+        P: Into<IdPattern>,
+        I: IntoIterator<Item = P>,
+    {
+        patterns
+            .into_iter()
+            .map(|pattern| {
+                let logger = logger.clone();
+                connection.subscribe(pattern, move |id, value: &Value| {
+                    logger.borrow_mut().log(id, value)
+                })
+            })
+            .collect()
+    }
+
+    /// Writes a row timestamped with the current time
+    pub fn log(&mut self, id: MessageId<'_>, value: &Value) {
+        let row = Row {
+            timestamp: self.start.elapsed(),
+            id,
+            value,
+        };
+        match self.sink.write_row(&row) {
+            Ok(()) => self.rows += 1,
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Number of rows written
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use crate::message::{AckNum, MessageType};
+    use pretty_assertions::assert_eq;
+    use std::string::String;
+    use std::vec;
+
+    #[test]
+    fn csv_rows() {
+        let mut sink = CsvSink::new(Vec::new());
+        let id = MessageId::new(b"gains").unwrap();
+        let value = Value::F32(vec![0.5, -1.25]);
+        let row = Row {
+            timestamp: Duration::from_millis(1500),
+            id,
+            value: &value,
+        };
+        sink.write_row(&row).unwrap();
+        let value = Value::Char(b"a \"b\", c".to_vec());
+        let row = Row {
+            timestamp: Duration::from_micros(2),
+            id: MessageId::BOARD_NAME,
+            value: &value,
+        };
+        sink.write_row(&row).unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "timestamp,id,type,value\n\
+             1.500000,gains,F32,\"[0.5, -1.25]\"\n\
+             0.000002,name,Char,\"a \"\"b\"\", c\"\n"
+        );
+    }
+
+    #[test]
+    fn log_subscribed_variables() {
+        let mut io = Loopback::default();
+        io.push_rx(&encode_packet(
+            b"mot.speed",
+            MessageType::U16,
+            false,
+            false,
+            AckNum::NONE,
+            &[1, 0],
+        ));
+        io.push_rx(&encode_packet(
+            b"led_state",
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[1],
+        ));
+        io.push_rx(&encode_packet(
+            b"mot.current",
+            MessageType::I8,
+            false,
+            false,
+            AckNum::NONE,
+            &[0xFF],
+        ));
+        let mut c = Connection::new(io);
+
+        let rows = Rc::new(RefCell::new(Vec::new()));
+        let r = rows.clone();
+        let sink = move |row: &Row<'_>| {
+            r.borrow_mut().push((row.id.to_string(), row.value.clone()));
+            Ok(())
+        };
+        let logger = Rc::new(RefCell::new(TelemetryLogger::new(sink)));
+        let subs = TelemetryLogger::attach(&logger, &mut c, ["mot.*"]);
+        assert_eq!(subs.len(), 1);
+
+        assert_eq!(c.poll().unwrap(), 3);
+        assert_eq!(logger.borrow().rows(), 2);
+        assert!(logger.borrow_mut().take_error().is_none());
+        assert_eq!(
+            *rows.borrow(),
+            vec![
+                (String::from("mot.speed"), Value::U16(vec![1])),
+                (String::from("mot.current"), Value::I8(vec![-1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn sink_errors_are_retained() {
+        let sink = |_row: &Row<'_>| Err(io::Error::other("full"));
+        let mut logger = TelemetryLogger::new(sink);
+        logger.log(MessageId::BOARD_NAME, &Value::Callback);
+        logger.log(MessageId::BOARD_NAME, &Value::Callback);
+        assert_eq!(logger.rows(), 0);
+        assert!(logger.take_error().is_some());
+        assert!(logger.take_error().is_none());
+    }
+}
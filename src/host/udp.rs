@@ -0,0 +1,241 @@
+//! UDP transport, one packet per datagram
+//!
+//! Suited to Wi-Fi devices (e.g. ESP32) sending telemetry on a LAN.
+//! Datagrams carry either the usual COBS frame or, with
+//! [`UdpTransport::unframed`], the bare packet, since the datagram
+//! boundary already delimits it. Devices decode the latter with
+//! [`Decoder::decode_unframed`](crate::decoder::Decoder::decode_unframed).
+
+use crate::host::framer::{encode_frame, MAX_FRAME_SIZE};
+use crate::wire::Framing;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::vec;
+use std::vec::Vec;
+
+pub use crate::host::tcp::POLL_TIMEOUT;
+
+/// Byte stream view of a UDP socket, usable anywhere a serial port is,
+/// e.g. `Connection<UdpTransport>`.
+///
+/// Each written frame is sent as its own datagram to the peer, which is
+/// either set with [`UdpTransport::connect`] or the sender of the last
+/// datagram received.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+    connected: bool,
+    framed: bool,
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+    datagram: Vec<u8>,
+}
+
+impl UdpTransport {
+    /// Binds a socket with short read timeouts so reads don't block
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::new(UdpSocket::bind(addr)?)
+    }
+
+    pub fn new(socket: UdpSocket) -> io::Result<Self> {
+        socket.set_read_timeout(Some(POLL_TIMEOUT))?;
+        Ok(Self {
+            socket,
+            peer: None,
+            connected: false,
+            framed: true,
+            rx: VecDeque::new(),
+            tx: Vec::with_capacity(MAX_FRAME_SIZE),
+            datagram: vec![0; MAX_FRAME_SIZE],
+        })
+    }
+
+    /// Datagrams carry bare packets instead of COBS frames
+    pub fn unframed(mut self) -> Self {
+        self.framed = false;
+        self
+    }
+
+    /// Only exchanges datagrams with `addr`
+    pub fn connect<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        self.socket.connect(addr)?;
+        self.peer = Some(self.socket.peer_addr()?);
+        self.connected = true;
+        Ok(())
+    }
+
+    /// Where written frames are sent
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+
+    fn recv(&mut self) -> io::Result<()> {
+        let (size, from) = self.socket.recv_from(&mut self.datagram)?;
+        if !self.connected {
+            self.peer = Some(from);
+        }
+        let datagram = &self.datagram[..size];
+        if self.framed {
+            self.rx.extend(datagram);
+            // The datagram ends the frame even without a delimiter
+            if datagram.last() != Some(&Framing::ZERO) {
+                self.rx.push_back(Framing::ZERO);
+            }
+        } else if !datagram.is_empty() {
+            let mut frame = Vec::new();
+            encode_frame(datagram, &mut frame);
+            self.rx.extend(frame);
+        }
+        Ok(())
+    }
+
+    fn send_frame(&mut self) -> io::Result<()> {
+        let peer = self.peer.ok_or(io::ErrorKind::NotConnected)?;
+        if self.framed {
+            self.socket.send_to(&self.tx, peer)?;
+        } else {
+            let size = Framing::decode_in_place(&mut self.tx)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            self.socket.send_to(&self.tx[..size], peer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for UdpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rx.is_empty() {
+            self.recv()?;
+        }
+        let size = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..size)) {
+            *dst = src;
+        }
+        Ok(size)
+    }
+}
+
+impl Write for UdpTransport {
+    /// Sends a datagram for each frame delimiter written
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == Framing::ZERO && self.tx.is_empty() {
+                continue;
+            }
+            self.tx.push(byte);
+            if byte == Framing::ZERO {
+                let res = self.send_frame();
+                self.tx.clear();
+                res?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::host::connection::Connection;
+    use crate::host::test_io::encode_packet;
+    use crate::message::{AckNum, MessageId, MessageType};
+    use crate::wire::Packet;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn recv(c: &mut Connection<UdpTransport>) -> Packet<Vec<u8>> {
+        c.recv_matching(TIMEOUT, |_| true).unwrap().unwrap()
+    }
+
+    fn unframe(mut frame: Vec<u8>) -> Vec<u8> {
+        let size = Framing::decode_in_place(&mut frame).unwrap();
+        frame.truncate(size);
+        frame
+    }
+
+    #[test]
+    fn unframed_datagrams() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        device.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let host = UdpTransport::bind("127.0.0.1:0").unwrap().unframed();
+        let host_addr = host.local_addr().unwrap();
+        let mut c = Connection::new(host);
+        let speed = MessageId::new(b"speed").unwrap();
+
+        // Telemetry, the host learns the device address from it
+        let telemetry = unframe(encode_packet(
+            b"speed",
+            MessageType::U16,
+            false,
+            false,
+            AckNum::NONE,
+            &[3, 0],
+        ));
+        device.send_to(&telemetry, host_addr).unwrap();
+        let p = recv(&mut c);
+        assert_eq!(p.msg_id(), Ok(speed));
+        assert_eq!(c.get_ref().peer_addr(), Some(device.local_addr().unwrap()));
+
+        c.query(speed, MessageType::U16, Duration::ZERO).ok();
+        let mut datagram = [0_u8; 64];
+        let (size, _) = device.recv_from(&mut datagram).unwrap();
+        let mut storage = [0_u8; 64];
+        let mut dec = Decoder::new(&mut storage);
+        let query = dec.decode_unframed(&datagram[..size]).unwrap();
+        assert_eq!(query.msg_id(), Ok(speed));
+        assert!(query.response());
+    }
+
+    #[test]
+    fn framed_datagrams() {
+        let mut device = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut host = UdpTransport::bind("127.0.0.1:0").unwrap();
+        device.connect(host.local_addr().unwrap()).unwrap();
+        host.connect(device.local_addr().unwrap()).unwrap();
+
+        // Frames without a trailing delimiter are still delimited
+        let frame = encode_packet(b"a", MessageType::U8, false, false, AckNum::NONE, &[1]);
+        device.get_ref().send(&frame[..frame.len() - 1]).unwrap();
+        let mut c = Connection::new(host);
+        let p = recv(&mut c);
+        assert_eq!(p.payload(), Ok(&[1][..]));
+
+        c.send_message(
+            MessageId::new(b"b").unwrap(),
+            MessageType::U8,
+            false,
+            false,
+            AckNum::NONE,
+            &[2],
+        )
+        .unwrap();
+        let mut datagram = [0_u8; 64];
+        device.get_ref().set_read_timeout(Some(TIMEOUT)).unwrap();
+        let size = device.get_ref().recv(&mut datagram).unwrap();
+        assert_eq!(
+            &datagram[..size],
+            &encode_packet(b"b", MessageType::U8, false, false, AckNum::NONE, &[2])[..]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Owned, typed variable values
+
+use crate::message::{MessageType, UnknownType};
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+use std::string::String;
+use std::vec::Vec;
+
+/// A tracked variable value, decoded from a packet payload according
+/// to its [`MessageType`].
+///
+/// ElectricUI variables are arrays of elements, a scalar is represented
+/// as a single element array.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
+    Callback,
+    Custom(Vec<u8>),
+    OffsetMetadata(Vec<u8>),
+    Byte(Vec<u8>),
+    Char(Vec<u8>),
+    I8(Vec<i8>),
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+    Unknown(UnknownType, Vec<u8>),
+}
+
+impl Value {
+    /// Decodes a payload of the given type.
+    /// Returns `None` if the payload is not a whole number of elements.
+    pub fn parse(typ: MessageType, payload: &[u8]) -> Option<Self> {
+        use MessageType::*;
+        let wire_size = typ.wire_size_hint();
+        if wire_size != 0 && !payload.len().is_multiple_of(wire_size) {
+            return None;
+        }
+        Some(match typ {
+            Callback => Value::Callback,
+            Custom => Value::Custom(payload.to_vec()),
+            OffsetMetadata => Value::OffsetMetadata(payload.to_vec()),
+            Byte => Value::Byte(payload.to_vec()),
+            Char => Value::Char(payload.to_vec()),
+            I8 => Value::I8(payload.iter().map(|b| *b as i8).collect()),
+            U8 => Value::U8(payload.to_vec()),
+            I16 => Value::I16(
+                payload
+                    .chunks_exact(2)
+                    .map(LittleEndian::read_i16)
+                    .collect(),
+            ),
+            U16 => Value::U16(
+                payload
+                    .chunks_exact(2)
+                    .map(LittleEndian::read_u16)
+                    .collect(),
+            ),
+            I32 => Value::I32(
+                payload
+                    .chunks_exact(4)
+                    .map(LittleEndian::read_i32)
+                    .collect(),
+            ),
+            U32 => Value::U32(
+                payload
+                    .chunks_exact(4)
+                    .map(LittleEndian::read_u32)
+                    .collect(),
+            ),
+            F32 => Value::F32(
+                payload
+                    .chunks_exact(4)
+                    .map(LittleEndian::read_f32)
+                    .collect(),
+            ),
+            F64 => Value::F64(
+                payload
+                    .chunks_exact(8)
+                    .map(LittleEndian::read_f64)
+                    .collect(),
+            ),
+            Unknown(typ) => Value::Unknown(typ, payload.to_vec()),
+        })
+    }
+
+    pub fn typ(&self) -> MessageType {
+        use MessageType::*;
+        match self {
+            Value::Callback => Callback,
+            Value::Custom(_) => Custom,
+            Value::OffsetMetadata(_) => OffsetMetadata,
+            Value::Byte(_) => Byte,
+            Value::Char(_) => Char,
+            Value::I8(_) => I8,
+            Value::U8(_) => U8,
+            Value::I16(_) => I16,
+            Value::U16(_) => U16,
+            Value::I32(_) => I32,
+            Value::U32(_) => U32,
+            Value::F32(_) => F32,
+            Value::F64(_) => F64,
+            Value::Unknown(typ, _) => Unknown(*typ),
+        }
+    }
+
+    /// Returns the number of elements, or the number of bytes for
+    /// the opaque types
+    pub fn len(&self) -> usize {
+        match self {
+            Value::Callback => 0,
+            Value::Custom(v)
+            | Value::OffsetMetadata(v)
+            | Value::Byte(v)
+            | Value::Char(v)
+            | Value::U8(v)
+            | Value::Unknown(_, v) => v.len(),
+            Value::I8(v) => v.len(),
+            Value::I16(v) => v.len(),
+            Value::U16(v) => v.len(),
+            Value::I32(v) => v.len(),
+            Value::U32(v) => v.len(),
+            Value::F32(v) => v.len(),
+            Value::F64(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the little-endian wire encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.typ().array_wire_size_hint(self.len()));
+        match self {
+            Value::Callback => (),
+            Value::Custom(v)
+            | Value::OffsetMetadata(v)
+            | Value::Byte(v)
+            | Value::Char(v)
+            | Value::U8(v)
+            | Value::Unknown(_, v) => bytes.extend_from_slice(v),
+            Value::I8(v) => bytes.extend(v.iter().map(|e| *e as u8)),
+            Value::I16(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+            Value::U16(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+            Value::I32(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+            Value::U32(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+            Value::F32(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+            Value::F64(v) => bytes.extend(v.iter().flat_map(|e| e.to_le_bytes())),
+        }
+        bytes
+    }
+}
+
+/// Numeric values display as a single element or a `[a, b, ..]` list,
+/// `Char` values as (lossy) UTF-8 text and the opaque types as hex bytes
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn elements<T: fmt::Display>(f: &mut fmt::Formatter<'_>, v: &[T]) -> fmt::Result {
+            if v.len() == 1 {
+                return write!(f, "{}", v[0]);
+            }
+            f.write_str("[")?;
+            for (idx, e) in v.iter().enumerate() {
+                if idx != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", e)?;
+            }
+            f.write_str("]")
+        }
+
+        match self {
+            Value::Callback => Ok(()),
+            Value::Char(v) => f.write_str(&String::from_utf8_lossy(v)),
+            Value::Custom(v) | Value::OffsetMetadata(v) | Value::Byte(v) | Value::Unknown(_, v) => {
+                write!(f, "{:02X?}", v)
+            }
+            Value::I8(v) => elements(f, v),
+            Value::U8(v) => elements(f, v),
+            Value::I16(v) => elements(f, v),
+            Value::U16(v) => elements(f, v),
+            Value::I32(v) => elements(f, v),
+            Value::U32(v) => elements(f, v),
+            Value::F32(v) => elements(f, v),
+            Value::F64(v) => elements(f, v),
+        }
+    }
+}
+
+/// Types that can be extracted from a [`Value`] of a specific [`MessageType`]
+pub trait FromValue: Sized {
+    /// The message type a value must have
+    const TYPE: MessageType;
+
+    /// Returns `None` if the value is of a different type or shape
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! impl_from_scalar {
+    ($t:ty, $variant:ident) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self {
+                Value::$variant(std::vec![v])
+            }
+        }
+
+        impl From<Vec<$t>> for Value {
+            fn from(v: Vec<$t>) -> Self {
+                Value::$variant(v)
+            }
+        }
+
+        impl FromValue for $t {
+            const TYPE: MessageType = MessageType::$variant;
+
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    Value::$variant(v) if v.len() == 1 => Some(v[0]),
+                    _ => None,
+                }
+            }
+        }
+
+        impl FromValue for Vec<$t> {
+            const TYPE: MessageType = MessageType::$variant;
+
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    Value::$variant(v) => Some(v.clone()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_from_scalar!(i8, I8);
+impl_from_scalar!(u8, U8);
+impl_from_scalar!(i16, I16);
+impl_from_scalar!(u16, U16);
+impl_from_scalar!(i32, I32);
+impl_from_scalar!(u32, U32);
+impl_from_scalar!(f32, F32);
+impl_from_scalar!(f64, F64);
+
+impl FromValue for String {
+    const TYPE: MessageType = MessageType::Char;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Char(v) => String::from_utf8(v.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::vec;
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(
+            Value::parse(MessageType::U8, &[0x2A]),
+            Some(Value::U8(vec![42]))
+        );
+        assert_eq!(
+            Value::parse(MessageType::I8, &[0xFF]),
+            Some(Value::I8(vec![-1]))
+        );
+        assert_eq!(
+            Value::parse(MessageType::U16, &[0x46, 0x00]),
+            Some(Value::U16(vec![70]))
+        );
+        assert_eq!(
+            Value::parse(MessageType::F32, &[0x14, 0xAE, 0x29, 0x42]),
+            Some(Value::F32(vec![42.42]))
+        );
+        assert_eq!(
+            Value::parse(MessageType::Callback, &[]),
+            Some(Value::Callback)
+        );
+    }
+
+    #[test]
+    fn parse_arrays() {
+        let v = Value::parse(MessageType::I16, &[0x01, 0x00, 0xFE, 0xFF]).unwrap();
+        assert_eq!(v, Value::I16(vec![1, -2]));
+        assert_eq!(v.typ(), MessageType::I16);
+        assert_eq!(v.len(), 2);
+        let v = Value::parse(MessageType::Char, b"my-board").unwrap();
+        assert_eq!(v, Value::Char(b"my-board".to_vec()));
+        assert_eq!(v.len(), 8);
+    }
+
+    #[test]
+    fn round_trip_bytes() {
+        let values = [
+            Value::Callback,
+            Value::from(-3_i8),
+            Value::from(vec![1_u16, 0xBEEF]),
+            Value::from(-70_000_i32),
+            Value::from(42.42_f32),
+            Value::from(vec![1.5_f64, -2.25]),
+            Value::Char(b"my-board".to_vec()),
+        ];
+        for v in values.iter() {
+            let bytes = v.to_bytes();
+            assert_eq!(Value::parse(v.typ(), &bytes).as_ref(), Some(v));
+        }
+    }
+
+    #[test]
+    fn from_value() {
+        assert_eq!(u16::from_value(&Value::U16(vec![70])), Some(70));
+        assert_eq!(u16::from_value(&Value::U16(vec![70, 71])), None);
+        assert_eq!(u16::from_value(&Value::I16(vec![70])), None);
+        assert_eq!(
+            Vec::<u16>::from_value(&Value::U16(vec![70, 71])),
+            Some(vec![70, 71])
+        );
+        assert_eq!(
+            String::from_value(&Value::Char(b"my-board".to_vec())),
+            Some(String::from("my-board"))
+        );
+        assert_eq!(String::from_value(&Value::Char(vec![0xFF])), None);
+        assert_eq!(<f32 as FromValue>::TYPE, MessageType::F32);
+    }
+
+    #[test]
+    fn display() {
+        use std::string::ToString;
+        assert_eq!(Value::U8(vec![1]).to_string(), "1");
+        assert_eq!(Value::I16(vec![1, -2]).to_string(), "[1, -2]");
+        assert_eq!(Value::F32(vec![0.5]).to_string(), "0.5");
+        assert_eq!(Value::Char(b"my-board".to_vec()).to_string(), "my-board");
+        assert_eq!(Value::Custom(vec![0x0A, 0xFF]).to_string(), "[0A, FF]");
+        assert_eq!(Value::Callback.to_string(), "");
+    }
+
+    #[test]
+    fn partial_elements() {
+        assert_eq!(Value::parse(MessageType::U16, &[0x01]), None);
+        assert_eq!(Value::parse(MessageType::F64, &[0; 12]), None);
+    }
+}
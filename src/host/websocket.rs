@@ -0,0 +1,142 @@
+//! WebSocket client transport for the [`Bridge`](crate::host::Bridge),
+//! enabled by the `websocket` feature
+
+use crate::host::connection::Error;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::vec::Vec;
+use tungstenite::handshake::HandshakeError;
+use tungstenite::{Message, WebSocket};
+
+/// Byte stream view of a WebSocket, where each binary message carries
+/// one or more whole frames.
+///
+/// Written bytes are sent as a single binary message on flush.
+pub struct WebSocketClient<S> {
+    ws: WebSocket<S>,
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+}
+
+impl<S: Read + Write> WebSocketClient<S> {
+    /// Performs the server side of the WebSocket handshake on `stream`.
+    /// The stream should be blocking until this returns.
+    pub fn accept(stream: S) -> Result<Self, Error> {
+        match tungstenite::accept(stream) {
+            Ok(ws) => Ok(Self::new(ws)),
+            Err(HandshakeError::Failure(e)) => Err(io_error(e).into()),
+            Err(HandshakeError::Interrupted(_)) => Err(Error::Io(io::ErrorKind::WouldBlock.into())),
+        }
+    }
+
+    pub fn new(ws: WebSocket<S>) -> Self {
+        Self {
+            ws,
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &WebSocket<S> {
+        &self.ws
+    }
+
+    pub fn get_mut(&mut self) -> &mut WebSocket<S> {
+        &mut self.ws
+    }
+
+    pub fn into_inner(self) -> WebSocket<S> {
+        self.ws
+    }
+}
+
+fn io_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl<S: Read + Write> Read for WebSocketClient<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.rx.is_empty() {
+            match self.ws.read() {
+                Ok(Message::Binary(data)) => self.rx.extend(data.iter().copied()),
+                // Control messages are handled by tungstenite
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => {
+                    return Err(io::ErrorKind::WouldBlock.into())
+                }
+                Ok(Message::Text(_)) => return Err(io::ErrorKind::InvalidData.into()),
+                Ok(Message::Close(_))
+                | Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(0)
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        let size = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..size)) {
+            *dst = src;
+        }
+        Ok(size)
+    }
+}
+
+impl<S: Read + Write> Write for WebSocketClient<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.tx.is_empty() {
+            let msg = Message::binary(mem::take(&mut self.tx));
+            self.ws.send(msg).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::bridge::Bridge;
+    use crate::host::test_io::{encode_packet, Loopback};
+    use crate::message::{AckNum, MessageType};
+    use pretty_assertions::assert_eq;
+    use tungstenite::protocol::Role;
+
+    #[test]
+    fn bridge_over_websocket() {
+        let req = encode_packet(b"i", MessageType::Callback, true, true, AckNum::NONE, &[]);
+        let resp = encode_packet(
+            b"i",
+            MessageType::U16,
+            true,
+            false,
+            AckNum::NONE,
+            &[0xD2, 0x04],
+        );
+
+        // The browser side
+        let mut browser = WebSocket::from_raw_socket(Loopback::default(), Role::Client, None);
+        browser.send(Message::binary(req.clone())).unwrap();
+
+        let mut server_io = Loopback::default();
+        server_io.push_rx(&browser.get_mut().tx.split_off(0));
+        let client =
+            WebSocketClient::new(WebSocket::from_raw_socket(server_io, Role::Server, None));
+
+        let mut device = Loopback::default();
+        device.push_rx(&resp);
+        let mut b = Bridge::new(device, client);
+        assert_eq!(b.poll().unwrap(), 2);
+        assert_eq!(b.device().tx, req);
+
+        let (_, client) = b.into_inner();
+        let sent = client.into_inner().get_mut().tx.split_off(0);
+        browser.get_mut().push_rx(&sent);
+        assert_eq!(browser.read().unwrap(), Message::binary(resp));
+    }
+}
@@ -4,16 +4,41 @@
 // TODO
 // - support offset / split packets
 // - static assertions
-// - error types
 // - support partial payloads/metadata
 // - add the send APIs and others
 // - tests
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+mod fmt;
+
 pub use crate::error::Error;
 
+pub mod ack;
+#[cfg(any(feature = "alloc", feature = "heapless"))]
+pub mod collections;
+pub mod conformance;
+#[cfg(feature = "postcard")]
+pub mod custom;
 pub mod decoder;
+pub mod dispatch;
+pub mod encoder;
 pub mod error;
+#[cfg(feature = "host")]
+pub mod host;
 pub mod message;
+pub mod nack;
+pub mod offset;
+#[cfg(feature = "zerocopy")]
+pub mod pod;
 pub mod prelude;
-mod sealed;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod transport;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub mod wire;
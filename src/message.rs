@@ -1,4 +1,7 @@
+use core::borrow::Borrow;
+use core::cmp::Ordering;
 use core::convert::TryFrom;
+use core::hash::{Hash, Hasher};
 use core::{fmt, mem, str};
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -21,10 +24,45 @@ impl<'a> MessageId<'a> {
     pub const INTERNAL_AM_END: Self = MessageId(b"v");
     /// Send writable variables
     pub const INTERNAL_AV: Self = MessageId(b"w");
+    /// A request was refused, see [`crate::nack`]
+    pub const INTERNAL_NACK: Self = MessageId(b"e");
 
     pub const BOARD_NAME: Self = MessageId(b"name");
 
+    /// The message ID's sent with the internal flag.
+    ///
+    /// This is the whole set the [reference device library] uses, plus
+    /// [`MessageId::INTERNAL_NACK`]. Refused requests and errors are
+    /// reported with NACKs, the protocol has no separate IDs for read-only
+    /// announcements or link statistics.
+    ///
+    /// [reference device library]: https://github.com/electricui/electricui-embedded
+    pub const INTERNAL: [MessageId<'static>; 8] = [
+        MessageId::INTERNAL_LIB_VER,
+        MessageId::INTERNAL_BOARD_ID,
+        MessageId::INTERNAL_HEARTBEAT,
+        MessageId::INTERNAL_AM,
+        MessageId::INTERNAL_AM_LIST,
+        MessageId::INTERNAL_AM_END,
+        MessageId::INTERNAL_AV,
+        MessageId::INTERNAL_NACK,
+    ];
+
+    /// Returns `None` if the ID is empty, longer than [`Self::MAX_SIZE`] or
+    /// has a NUL byte, which delimits the ID's in the
+    /// [`Self::INTERNAL_AM_LIST`] announcements
     pub const fn new(id: &'a [u8]) -> Option<Self> {
+        if has_nul(id) {
+            None
+        } else {
+            Self::new_binary(id)
+        }
+    }
+
+    /// Like [`Self::new`] but allows NUL bytes, other than a lone `[0]`,
+    /// for raw binary ID's that are never announced. Received packets are
+    /// parsed with this.
+    pub const fn new_binary(id: &'a [u8]) -> Option<Self> {
         if id.is_empty() || id.len() > Self::MAX_SIZE || (id.len() == 1 && id[0] == 0) {
             None
         } else {
@@ -32,12 +70,28 @@ impl<'a> MessageId<'a> {
         }
     }
 
+    /// Like [`MessageId::new`] for string literals, panicking if the ID is
+    /// invalid or has a NUL byte, a compile error in const context.
+    /// See [`msg_id!`](crate::msg_id).
+    pub const fn from_literal(id: &'a str) -> Self {
+        let bytes = id.as_bytes();
+        assert!(!bytes.is_empty(), "Empty message ID");
+        assert!(bytes.len() <= Self::MAX_SIZE, "Message ID too long");
+        assert!(!has_nul(bytes), "NUL byte in message ID");
+        MessageId(bytes)
+    }
+
     /// # Safety
     /// Must follow the rules
     pub const unsafe fn new_unchecked(id: &'a [u8]) -> Self {
         Self(id)
     }
 
+    /// Wraps bytes that may not be a valid ID, for comparisons
+    pub(crate) const fn from_bytes_unchecked(id: &'a [u8]) -> Self {
+        MessageId(id)
+    }
+
     pub const fn as_bytes(&self) -> &[u8] {
         self.0
     }
@@ -46,14 +100,96 @@ impl<'a> MessageId<'a> {
         str::from_utf8(self.0)
     }
 
-    pub fn from_utf8(s: &'a str) -> Self {
-        Self(s.as_bytes())
+    /// As [`MessageId::new`] for a string
+    pub fn from_utf8(s: &'a str) -> Option<Self> {
+        Self::new(s.as_bytes())
     }
 
     #[allow(clippy::len_without_is_empty)]
-    pub fn len(&self) -> usize {
+    pub const fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn starts_with(&self, prefix: &[u8]) -> bool {
+        self.0.starts_with(prefix)
+    }
+
+    /// The rest of the ID after `prefix`, `None` if it doesn't start
+    /// with `prefix` or nothing is left
+    pub fn strip_prefix(&self, prefix: &[u8]) -> Option<MessageId<'a>> {
+        MessageId::new_binary(self.0.strip_prefix(prefix)?)
+    }
+
+    /// Returns true if this is one of the [`MessageId::INTERNAL`] ID's
+    pub const fn is_internal(&self) -> bool {
+        let mut i = 0;
+        while i < MessageId::INTERNAL.len() {
+            if bytes_eq(MessageId::INTERNAL[i].0, self.0) {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+}
+
+/// A `MessageId<'static>` from a string literal, checked at compile time
+///
+/// ```
+/// use electricui_embedded::msg_id;
+/// use electricui_embedded::message::MessageId;
+///
+/// const SPEED: MessageId<'static> = msg_id!("speed");
+/// assert_eq!(SPEED, MessageId::new(b"speed").unwrap());
+/// assert_eq!(msg_id!("name"), MessageId::BOARD_NAME);
+/// ```
+///
+/// Empty, over-long or NUL containing IDs fail to compile
+///
+/// ```compile_fail
+/// use electricui_embedded::msg_id;
+///
+/// let id = msg_id!("this_is_sixteen!");
+/// ```
+///
+/// ```compile_fail
+/// use electricui_embedded::msg_id;
+///
+/// let id = msg_id!("a\0b");
+/// ```
+#[macro_export]
+macro_rules! msg_id {
+    ($id:literal) => {{
+        const ID: $crate::message::MessageId<'static> =
+            $crate::message::MessageId::from_literal($id);
+        ID
+    }};
+}
+
+const fn has_nul(id: &[u8]) -> bool {
+    let mut i = 0;
+    while i < id.len() {
+        if id[i] == 0 {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Slice equality usable in const fns
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
 }
 
 impl<'a> From<MessageId<'a>> for &'a [u8] {
@@ -108,7 +244,304 @@ impl<'a> fmt::Display for MessageId<'a> {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl<'a> defmt::Format for MessageId<'a> {
+    fn format(&self, f: defmt::Formatter) {
+        if let Ok(s) = self.as_str() {
+            defmt::write!(f, "{=str}", s)
+        } else {
+            defmt::write!(f, "{=[u8]:X}", self.0)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'a> serde::Serialize for MessageId<'a> {
+    /// As a string when it's valid UTF-8, bytes otherwise
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => serializer.serialize_bytes(self.0),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de: 'a, 'a> serde::Deserialize<'de> for MessageId<'a> {
+    /// Borrows from the input, from either a string or bytes
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = MessageId<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a borrowed message ID string or byte array")
+            }
+
+            fn visit_borrowed_str<E: serde::de::Error>(
+                self,
+                v: &'de str,
+            ) -> Result<Self::Value, E> {
+                self.visit_borrowed_bytes(v.as_bytes())
+            }
+
+            fn visit_borrowed_bytes<E: serde::de::Error>(
+                self,
+                v: &'de [u8],
+            ) -> Result<Self::Value, E> {
+                MessageId::new(v).ok_or_else(|| {
+                    E::invalid_value(serde::de::Unexpected::Bytes(v), &"a valid message ID")
+                })
+            }
+        }
+
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
+
+/// A group of message ID's sharing a prefix, like `mot.speed` and
+/// `mot.current`
+///
+/// ```
+/// use electricui_embedded::message::{MessageId, Namespace};
+///
+/// const MOTOR: Namespace = Namespace::new(b"mot.");
+///
+/// let id = MessageId::new(b"mot.speed").unwrap();
+/// assert_eq!(MOTOR.strip(id), MessageId::new(b"speed"));
+///
+/// let mut buf = [0; MessageId::MAX_SIZE];
+/// assert_eq!(MOTOR.join(b"current", &mut buf), MessageId::new(b"mot.current"));
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Namespace<'p> {
+    prefix: &'p [u8],
+}
+
+impl<'p> Namespace<'p> {
+    /// `prefix` includes any separator, e.g. `b"mot."`
+    pub const fn new(prefix: &'p [u8]) -> Self {
+        Namespace { prefix }
+    }
+
+    pub const fn prefix(&self) -> &'p [u8] {
+        self.prefix
+    }
+
+    pub fn contains(&self, id: MessageId<'_>) -> bool {
+        id.starts_with(self.prefix)
+    }
+
+    /// The name of `id` within the namespace, see [`MessageId::strip_prefix`]
+    pub fn strip<'a>(&self, id: MessageId<'a>) -> Option<MessageId<'a>> {
+        id.strip_prefix(self.prefix)
+    }
+
+    /// Writes the prefix and `name` into `buf`, `None` if the result isn't
+    /// a valid ID
+    pub fn join<'b>(
+        &self,
+        name: &[u8],
+        buf: &'b mut [u8; MessageId::MAX_SIZE],
+    ) -> Option<MessageId<'b>> {
+        let len = self.prefix.len() + name.len();
+        if len > buf.len() {
+            return None;
+        }
+        buf[..self.prefix.len()].copy_from_slice(self.prefix);
+        buf[self.prefix.len()..len].copy_from_slice(name);
+        MessageId::new(&buf[..len])
+    }
+}
+
+/// An owned [`MessageId`], stored inline so it isn't tied to a packet
+/// buffer's lifetime
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct MessageIdBuf {
+    // Unused bytes are kept zeroed so the derived equality holds
+    bytes: [u8; MessageId::MAX_SIZE],
+    len: u8,
+}
+
+impl MessageIdBuf {
+    /// Copies `id`, `None` if [`MessageId::new`] rejects it
+    pub const fn new(id: &[u8]) -> Option<Self> {
+        match MessageId::new(id) {
+            Some(id) => Some(Self::from_id(id)),
+            None => None,
+        }
+    }
+
+    pub const fn from_id(id: MessageId<'_>) -> Self {
+        let mut bytes = [0_u8; MessageId::MAX_SIZE];
+        let mut i = 0;
+        while i < id.0.len() {
+            bytes[i] = id.0[i];
+            i += 1;
+        }
+        MessageIdBuf {
+            bytes,
+            len: id.0.len() as u8,
+        }
+    }
+
+    pub fn as_id(&self) -> MessageId<'_> {
+        MessageId(self.as_bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+
+    pub fn as_str(&self) -> Result<&str, str::Utf8Error> {
+        str::from_utf8(self.as_bytes())
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> usize {
+        self.len as usize
+    }
+}
+
+impl MessageId<'_> {
+    /// Copies the ID into a [`MessageIdBuf`]
+    pub const fn to_buf(&self) -> MessageIdBuf {
+        MessageIdBuf::from_id(*self)
+    }
+}
+
+impl<'a> From<MessageId<'a>> for MessageIdBuf {
+    fn from(id: MessageId<'a>) -> Self {
+        Self::from_id(id)
+    }
+}
+
+impl<'a> From<&'a MessageIdBuf> for MessageId<'a> {
+    fn from(id: &'a MessageIdBuf) -> Self {
+        id.as_id()
+    }
+}
+
+impl AsRef<[u8]> for MessageIdBuf {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+// Hashes and orders like the bytes, so lookups by `&[u8]` work
+impl Borrow<[u8]> for MessageIdBuf {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Hash for MessageIdBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state)
+    }
+}
+
+impl Ord for MessageIdBuf {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl PartialOrd for MessageIdBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// MessageIdBuf == MessageId
+impl<'a> PartialEq<MessageId<'a>> for MessageIdBuf {
+    fn eq(&self, other: &MessageId<'a>) -> bool {
+        self.as_bytes() == other.0
+    }
+}
+
+// MessageId == MessageIdBuf
+impl<'a> PartialEq<MessageIdBuf> for MessageId<'a> {
+    fn eq(&self, other: &MessageIdBuf) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+// MessageIdBuf == [u8]
+impl PartialEq<[u8]> for MessageIdBuf {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+// MessageIdBuf == &[u8; N]
+impl<const N: usize> PartialEq<&[u8; N]> for MessageIdBuf {
+    fn eq(&self, other: &&[u8; N]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+impl fmt::Debug for MessageIdBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MessageIdBuf")
+            .field(&self.as_bytes())
+            .finish()
+    }
+}
+
+impl fmt::Display for MessageIdBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.as_id(), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for MessageIdBuf {
+    fn format(&self, f: defmt::Formatter) {
+        self.as_id().format(f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MessageIdBuf {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_id().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MessageIdBuf {
+    /// Copies from the input, from either a string or bytes
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = MessageIdBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a message ID string or byte array")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                self.visit_bytes(v.as_bytes())
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                MessageIdBuf::new(v).ok_or_else(|| {
+                    E::invalid_value(serde::de::Unexpected::Bytes(v), &"a valid message ID")
+                })
+            }
+        }
+
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
     Callback,
     Custom,
@@ -123,48 +556,26 @@ pub enum MessageType {
     U32,
     F32,
     F64,
-    Unknown(u8),
+    Unknown(UnknownType),
 }
 
 impl MessageType {
-    /// Returns the wire size for this MessageType variant.
-    /// Only applicable to data carrying types.
-    pub fn wire_size_hint(self) -> usize {
-        use MessageType::*;
-        match self {
-            Callback | Custom | Unknown(_) => 0, // Up to the user
-            OffsetMetadata => 0,                 // TODO - add offset support
-            Byte | Char | I8 | U8 => mem::size_of::<u8>(),
-            I16 | U16 => mem::size_of::<u16>(),
-            I32 | U32 => mem::size_of::<u32>(),
-            F32 => mem::size_of::<f32>(),
-            F64 => mem::size_of::<f64>(),
-        }
-    }
-
-    /// Returns the wire size for an array of this MessageType variant.
-    /// Only applicable to data carrying types.
-    pub fn array_wire_size_hint(self, num_elements: usize) -> usize {
-        num_elements * self.wire_size_hint()
-    }
+    /// Largest value the 4 bit type field holds
+    pub const MAX_WIRE_VALUE: u8 = 0x0F;
 
-    /// Returns the number of elements for this MessageType
-    /// and data payload size.
-    /// Only applicable to data carrying types.
-    pub fn array_wire_length_hint(self, data_size: usize) -> usize {
-        let wire_size = self.wire_size_hint();
-        if wire_size == 0 {
-            0
+    /// Returns `None` if `value` doesn't fit the 4 bit field
+    pub const fn try_from_wire(value: u8) -> Option<Self> {
+        if value <= Self::MAX_WIRE_VALUE {
+            Some(Self::from_bits(value))
         } else {
-            data_size / wire_size
+            None
         }
     }
-}
 
-impl From<u8> for MessageType {
-    fn from(value: u8) -> Self {
+    /// The low 4 bits of `bits`, as they're read from the header
+    pub const fn from_bits(bits: u8) -> Self {
         use MessageType::*;
-        match value {
+        match bits & Self::MAX_WIRE_VALUE {
             0 => Callback,
             1 => Custom,
             2 => OffsetMetadata,
@@ -178,15 +589,29 @@ impl From<u8> for MessageType {
             10 => U32,
             11 => F32,
             12 => F64,
-            _ => Unknown(value),
+            value => Unknown(UnknownType(value)),
         }
     }
-}
 
-impl From<MessageType> for u8 {
-    fn from(value: MessageType) -> Self {
+    /// Returns the wire size for this MessageType variant.
+    /// Only applicable to data carrying types.
+    pub fn wire_size_hint(self) -> usize {
+        use MessageType::*;
+        match self {
+            Callback | Custom | Unknown(_) => 0, // Up to the user
+            OffsetMetadata => 0,                 // TODO - add offset support
+            Byte | Char | I8 | U8 => mem::size_of::<u8>(),
+            I16 | U16 => mem::size_of::<u16>(),
+            I32 | U32 => mem::size_of::<u32>(),
+            F32 => mem::size_of::<f32>(),
+            F64 => mem::size_of::<f64>(),
+        }
+    }
+
+    /// The wire value, as `u8::from` but usable in const fns
+    pub const fn to_u8(self) -> u8 {
         use MessageType::*;
-        match value {
+        match self {
             Callback => 0,
             Custom => 1,
             OffsetMetadata => 2,
@@ -200,64 +625,219 @@ impl From<MessageType> for u8 {
             U32 => 10,
             F32 => 11,
             F64 => 12,
-            Unknown(typ) => typ,
+            Unknown(typ) => typ.0,
+        }
+    }
+
+    /// Returns true if packets of this MessageType can carry an offset
+    /// address, only data carrying types can.
+    pub const fn supports_offset(self) -> bool {
+        use MessageType::*;
+        !matches!(self, Callback | OffsetMetadata | Unknown(_))
+    }
+
+    /// Returns true for the `I8` to `U32` types, `Byte` and `Char` are
+    /// raw data and text
+    pub const fn is_integer(self) -> bool {
+        use MessageType::*;
+        matches!(self, I8 | U8 | I16 | U16 | I32 | U32)
+    }
+
+    pub const fn is_float(self) -> bool {
+        matches!(self, MessageType::F32 | MessageType::F64)
+    }
+
+    /// Returns true for the signed integer and float types
+    pub const fn is_signed(self) -> bool {
+        use MessageType::*;
+        matches!(self, I8 | I16 | I32 | F32 | F64)
+    }
+
+    pub const fn is_callback(self) -> bool {
+        matches!(self, MessageType::Callback)
+    }
+
+    /// Alignment of the element type on the target, 1 for types without
+    /// a fixed size element
+    pub const fn alignment(self) -> usize {
+        use MessageType::*;
+        match self {
+            Callback | Custom | Unknown(_) | OffsetMetadata => 1,
+            Byte | Char | I8 | U8 => mem::align_of::<u8>(),
+            I16 | U16 => mem::align_of::<u16>(),
+            I32 | U32 => mem::align_of::<u32>(),
+            F32 => mem::align_of::<f32>(),
+            F64 => mem::align_of::<f64>(),
+        }
+    }
+
+    /// Returns the wire size for an array of this MessageType variant.
+    /// Only applicable to data carrying types.
+    pub fn array_wire_size_hint(self, num_elements: usize) -> usize {
+        num_elements * self.wire_size_hint()
+    }
+
+    /// Returns the number of elements for this MessageType
+    /// and data payload size.
+    /// Only applicable to data carrying types.
+    pub fn array_wire_length_hint(self, data_size: usize) -> usize {
+        let wire_size = self.wire_size_hint();
+        if wire_size == 0 {
+            0
+        } else {
+            data_size / wire_size
         }
     }
 }
 
+impl From<MessageType> for u8 {
+    fn from(value: MessageType) -> Self {
+        value.to_u8()
+    }
+}
+
 impl fmt::Display for MessageType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-#[cfg(test)]
-pub(crate) mod propt {
-    use super::*;
-    use proptest::{
-        collection, num,
-        prelude::*,
-        prop_oneof,
-        std_facade::{vec, Vec},
-    };
-
-    pub fn gen_message_type() -> impl Strategy<Value = MessageType> {
-        prop_oneof![
-            Just(MessageType::Callback),
-            Just(MessageType::Custom),
-            Just(MessageType::OffsetMetadata),
-            Just(MessageType::Byte),
-            Just(MessageType::Char),
-            Just(MessageType::I8),
-            Just(MessageType::U8),
-            Just(MessageType::I16),
-            Just(MessageType::U16),
-            Just(MessageType::I32),
-            Just(MessageType::U32),
-            Just(MessageType::F32),
-            Just(MessageType::F64),
-            gen_unknown_msg_typ(),
-        ]
-    }
-
-    prop_compose! {
-        fn gen_unknown_msg_typ()(value in 13_u8..=0x0F_u8) -> MessageType {
-            MessageType::Unknown(value)
+/// A type field value without a [`MessageType`] variant, 13 to 15, so
+/// every [`MessageType::Unknown`] fits the 4 bit field
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+#[repr(transparent)]
+pub struct UnknownType(u8);
+
+impl UnknownType {
+    pub const MIN: Self = UnknownType(13);
+    pub const MAX: Self = UnknownType(MessageType::MAX_WIRE_VALUE);
+
+    /// Returns `None` if `value` is a known type or doesn't fit the field
+    pub const fn new(value: u8) -> Option<Self> {
+        if value >= Self::MIN.0 && value <= Self::MAX.0 {
+            Some(Self(value))
+        } else {
+            None
         }
     }
 
-    prop_compose! {
-        pub fn gen_msg_id_bytes()(bytes in collection::vec(num::u8::ANY, 1..=MessageId::MAX_SIZE)) -> Vec<u8> {
-            bytes
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<UnknownType> for u8 {
+    fn from(typ: UnknownType) -> Self {
+        typ.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for UnknownType {
+    /// Rejects known and out of range values
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = u8::deserialize(deserializer)?;
+        UnknownType::new(value).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(value.into()),
+                &"an unknown message type of 13 to 15",
+            )
+        })
+    }
+}
+
+/// A packet's 3 bit acknum, [`AckNum::NONE`] when no ack is requested.
+///
+/// Requested acknums cycle through 1..=7, see [`AckNum::next`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+#[repr(transparent)]
+pub struct AckNum(u8);
+
+impl AckNum {
+    /// No ack requested
+    pub const NONE: Self = AckNum(0);
+    /// First requested acknum
+    pub const MIN: Self = AckNum(1);
+    /// Last requested acknum
+    pub const MAX: Self = AckNum(7);
+
+    /// Returns `None` if `value` doesn't fit the 3 bit field
+    pub const fn new(value: u8) -> Option<Self> {
+        if value <= Self::MAX.0 {
+            Some(Self(value))
+        } else {
+            None
+        }
+    }
+
+    /// The low 3 bits of `bits`, as they're read from the header
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::MAX.0)
+    }
+
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true unless this is [`AckNum::NONE`]
+    pub const fn is_requested(self) -> bool {
+        self.0 != 0
+    }
+
+    /// The requested acknum after this one, wrapping from
+    /// [`AckNum::MAX`] back to [`AckNum::MIN`]
+    pub const fn next(self) -> Self {
+        Self((self.0 % Self::MAX.0) + 1)
+    }
+
+    /// Number of [`AckNum::next`] steps from `earlier` to this acknum,
+    /// `None` if either isn't requested
+    pub const fn steps_since(self, earlier: Self) -> Option<u8> {
+        if self.is_requested() && earlier.is_requested() {
+            Some((self.0 + Self::MAX.0 - earlier.0) % Self::MAX.0)
+        } else {
+            None
         }
     }
 }
 
+impl From<AckNum> for u8 {
+    fn from(acknum: AckNum) -> Self {
+        acknum.0
+    }
+}
+
+impl fmt::Display for AckNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AckNum {
+    /// Rejects values that don't fit the 3 bit field
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = u8::deserialize(deserializer)?;
+        AckNum::new(value).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(value.into()),
+                &"an acknum of 0 to 7",
+            )
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    extern crate std;
+
     use super::*;
+    use crate::test_support::*;
     use pretty_assertions::assert_eq;
-    use propt::*;
     use proptest::prelude::*;
 
     #[test]
@@ -269,35 +849,238 @@ mod tests {
         assert_eq!(MessageId::INTERNAL_AM_LIST, b"u");
         assert_eq!(MessageId::INTERNAL_AM_END, b"v");
         assert_eq!(MessageId::INTERNAL_AV, b"w");
+        assert_eq!(MessageId::INTERNAL_NACK, b"e");
 
         assert_eq!(MessageId::new(b"name"), Some(MessageId::BOARD_NAME));
+
+        assert!(MessageId::INTERNAL.iter().all(|id| id.is_internal()));
+        assert!(!MessageId::BOARD_NAME.is_internal());
+        assert!(!MessageId::new(b"hh").unwrap().is_internal());
+    }
+
+    #[test]
+    fn acknums() {
+        assert_eq!(AckNum::new(8), None);
+        assert_eq!(AckNum::new(7), Some(AckNum::MAX));
+        assert_eq!(AckNum::from_bits(0xFA), AckNum::new(2).unwrap());
+        assert!(!AckNum::NONE.is_requested());
+        assert_eq!(AckNum::NONE.next(), AckNum::MIN);
+        assert_eq!(AckNum::MAX.next(), AckNum::MIN);
+        assert_eq!(AckNum::MIN.next().get(), 2);
+
+        let (three, six) = (AckNum::new(3).unwrap(), AckNum::new(6).unwrap());
+        assert_eq!(six.steps_since(three), Some(3));
+        assert_eq!(three.steps_since(six), Some(4));
+        assert_eq!(three.steps_since(three), Some(0));
+        assert_eq!(AckNum::MIN.steps_since(AckNum::MAX), Some(1));
+        assert_eq!(three.steps_since(AckNum::NONE), None);
+    }
+
+    #[test]
+    fn offset_support() {
+        assert!(MessageType::U8.supports_offset());
+        assert!(MessageType::Custom.supports_offset());
+        assert!(!MessageType::Callback.supports_offset());
+        assert!(!MessageType::OffsetMetadata.supports_offset());
+        assert!(!MessageType::Unknown(UnknownType::MAX).supports_offset());
+    }
+
+    #[test]
+    fn owned_ids() {
+        let id = MessageId::new(b"speed").unwrap();
+        let buf = MessageIdBuf::from(id);
+        assert_eq!(buf, id);
+        assert_eq!(id, buf);
+        assert_eq!(buf, b"speed");
+        assert_eq!(buf.len(), 5);
+        assert_eq!(buf.as_id(), id);
+        assert_eq!(id.to_buf(), buf);
+        assert_eq!(MessageIdBuf::new(b"speed"), Some(buf));
+        assert_eq!(MessageIdBuf::new(&[]), None);
+        assert_eq!(MessageIdBuf::new(&[0]), None);
+        assert_eq!(MessageIdBuf::new(&[1; MessageId::MAX_SIZE + 1]), None);
+
+        let max = MessageIdBuf::new(b"abcdefghijklmno").unwrap();
+        assert_eq!(max.as_str(), Ok("abcdefghijklmno"));
+        assert_eq!(std::format!("{}", buf), "speed");
+        assert_eq!(
+            std::format!("{:?}", buf),
+            std::format!("MessageIdBuf({:?})", b"speed")
+        );
+
+        let a = MessageIdBuf::new(b"a").unwrap();
+        let a0 = MessageIdBuf::from(MessageId::new_binary(b"a\0").unwrap());
+        assert!(a < a0 && a0 < max);
+        assert_ne!(a, a0);
+        let borrowed: &[u8] = a0.borrow();
+        assert_eq!(borrowed, b"a\0");
+    }
+
+    #[test]
+    fn prefixes() {
+        let id = MessageId::new(b"mot.speed").unwrap();
+        assert!(id.starts_with(b"mot."));
+        assert!(id.starts_with(b""));
+        assert!(!id.starts_with(b"led."));
+        assert_eq!(id.strip_prefix(b"mot."), MessageId::new(b"speed"));
+        assert_eq!(id.strip_prefix(b"mot.speed"), None);
+        assert_eq!(id.strip_prefix(b"led."), None);
+
+        let ns = Namespace::new(b"mot.");
+        assert!(ns.contains(id));
+        assert!(!ns.contains(MessageId::BOARD_NAME));
+        assert_eq!(ns.strip(id), MessageId::new(b"speed"));
+
+        let mut buf = [0; MessageId::MAX_SIZE];
+        assert_eq!(ns.join(b"speed", &mut buf), Some(id));
+        assert_eq!(
+            ns.join(b"abcdefghijk", &mut buf).map(|id| id.len()),
+            Some(15)
+        );
+        assert_eq!(ns.join(b"abcdefghijkl", &mut buf), None);
+        assert_eq!(ns.join(b"a\0", &mut buf), None);
+        assert_eq!(Namespace::new(b"").join(b"", &mut buf), None);
+    }
+
+    #[test]
+    fn literal_ids() {
+        assert_eq!(msg_id!("o"), MessageId::INTERNAL_LIB_VER);
+        assert_eq!(msg_id!("abcdefghijklmno").len(), MessageId::MAX_SIZE);
+        assert!(std::panic::catch_unwind(|| MessageId::from_literal("")).is_err());
+        assert!(std::panic::catch_unwind(|| MessageId::from_literal("ab\0")).is_err());
+    }
+
+    #[test]
+    fn type_classes() {
+        use MessageType::*;
+        for typ in (0..=MessageType::MAX_WIRE_VALUE).map(MessageType::from_bits) {
+            assert!(!(typ.is_integer() && typ.is_float()));
+            assert_eq!(typ.is_callback(), typ == Callback);
+            if typ.is_signed() {
+                assert!(typ.is_integer() || typ.is_float());
+            }
+            if typ.is_integer() || typ.is_float() {
+                assert!(typ.alignment() <= typ.wire_size_hint());
+            } else {
+                assert!(typ.alignment() >= 1);
+            }
+        }
+        assert!(U16.is_integer() && !U16.is_signed());
+        assert!(I32.is_integer() && I32.is_signed());
+        assert!(F64.is_float() && F64.is_signed());
+        assert!(!Byte.is_integer() && !Char.is_integer());
+        assert_eq!(Custom.alignment(), 1);
+        assert_eq!(U32.alignment(), mem::align_of::<u32>());
+    }
+
+    #[test]
+    fn unknown_types() {
+        assert_eq!(MessageType::try_from_wire(12), Some(MessageType::F64));
+        assert_eq!(
+            MessageType::try_from_wire(13),
+            Some(MessageType::Unknown(UnknownType::MIN))
+        );
+        assert_eq!(MessageType::try_from_wire(0x10), None);
+        assert_eq!(MessageType::from_bits(0x30), MessageType::Callback);
+        assert_eq!(UnknownType::new(12), None);
+        assert_eq!(UnknownType::new(0x10), None);
+        assert_eq!(UnknownType::new(0x0F), Some(UnknownType::MAX));
     }
 
     #[test]
     fn invalid_ids() {
         assert_eq!(MessageId::new(&[]), None);
         assert_eq!(MessageId::new(&[0]), None);
+        assert_eq!(MessageId::new_binary(&[0]), None);
+        assert_eq!(MessageId::new(b"a\0b"), None);
+        assert_eq!(MessageId::new(b"\0a"), None);
+        assert_eq!(MessageId::new_binary(b"a\0b").unwrap().len(), 3);
         let id_bytes: [u8; 16] = [1; 16];
         assert_eq!(id_bytes.len(), MessageId::MAX_SIZE + 1);
         assert_eq!(MessageId::new(&id_bytes), None);
+        assert_eq!(MessageId::from_utf8(""), None);
+        assert_eq!(MessageId::from_utf8("a\0b"), None);
+        assert_eq!(MessageId::from_utf8("sixteen_chars_id"), None);
+        assert_eq!(MessageId::from_utf8("led").unwrap(), b"led");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_fixtures() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Fixture<'a> {
+            #[serde(borrow)]
+            id: MessageId<'a>,
+            typ: MessageType,
+        }
+
+        let json = r#"{"id":"speed","typ":"U16"}"#;
+        let f: Fixture = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            f,
+            Fixture {
+                id: MessageId::new(b"speed").unwrap(),
+                typ: MessageType::U16,
+            }
+        );
+        assert_eq!(serde_json::to_string(&f).unwrap(), json);
+
+        let f: Result<Fixture, _> = serde_json::from_str(r#"{"id":"","typ":"U8"}"#);
+        assert!(f.is_err());
+
+        let id: MessageIdBuf = serde_json::from_str(r#""speed""#).unwrap();
+        assert_eq!(id, b"speed");
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""speed""#);
+        assert!(serde_json::from_str::<MessageIdBuf>(r#""abcdefghijklmnop""#).is_err());
+
+        assert_eq!(serde_json::to_string(&AckNum::MAX).unwrap(), "7");
+        assert_eq!(serde_json::from_str::<AckNum>("3").ok(), AckNum::new(3));
+        assert!(serde_json::from_str::<AckNum>("8").is_err());
+        assert_eq!(
+            serde_json::to_string(&MessageType::Unknown(UnknownType::MAX)).unwrap(),
+            r#"{"Unknown":15}"#
+        );
+        assert!(serde_json::from_str::<MessageType>(r#"{"Unknown":48}"#).is_err());
+
+        let e = crate::Error::Packet(crate::wire::packet::Error::InvalidChecksum {
+            expected: 0x1234,
+            computed: 0x5678,
+        });
+        assert_eq!(
+            serde_json::to_string(&e).unwrap(),
+            r#"{"Packet":{"InvalidChecksum":{"expected":4660,"computed":22136}}}"#
+        );
     }
 
     proptest! {
         #[test]
         fn round_trip_message_type(v_in in gen_message_type()) {
             let wire = u8::from(v_in);
-            let v_out = MessageType::from(wire);
-            assert_eq!(v_in, v_out);
+            let v_out = MessageType::try_from_wire(wire);
+            assert_eq!(Some(v_in), v_out);
+        }
+
+        #[test]
+        fn wire_message_types(value in any::<u8>()) {
+            let typ = MessageType::try_from_wire(value);
+            assert_eq!(typ.is_some(), value <= MessageType::MAX_WIRE_VALUE);
+            assert_eq!(MessageType::from_bits(value).to_u8(), value & 0x0F);
+            if let Some(typ) = typ {
+                assert_eq!(typ.to_u8(), value);
+                assert_eq!(typ, MessageType::from_bits(value));
+            }
+            assert_eq!(UnknownType::new(value).is_some(), (13..=0x0F).contains(&value));
         }
 
         #[test]
         fn round_trip_message_id(id_bytes in gen_msg_id_bytes()) {
             if id_bytes.len() == 1 && id_bytes[0] == 0 {
-                assert_eq!(MessageId::new(id_bytes.as_ref()), None);
+                assert_eq!(MessageId::new_binary(id_bytes.as_ref()), None);
             } else {
+                assert_eq!(MessageId::new(id_bytes.as_ref()).is_some(), !id_bytes.contains(&0));
                 let len = id_bytes.len();
                 let s = str::from_utf8(id_bytes.as_ref());
-                let id = MessageId::new(id_bytes.as_ref()).unwrap();
+                let id = MessageId::new_binary(id_bytes.as_ref()).unwrap();
                 assert_eq!(len, id.len());
                 assert_eq!(s, id.as_str());
             }
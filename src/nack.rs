@@ -0,0 +1,220 @@
+//! Error replies for requests a device can't honor
+//!
+//! Instead of staying silent, a device replies to a write or query it
+//! refuses with an internal [`MessageId::INTERNAL_NACK`] packet. Its
+//! `Custom` payload is a [`Reason`] code byte followed by the offending
+//! message ID, and it carries the acknum of the request so an acked
+//! write is resolved rather than retransmitted until it times out.
+//!
+//! ```
+//! use electricui_embedded::message::MessageId;
+//! use electricui_embedded::nack::{Nack, Reason};
+//!
+//! let nack = Nack::new(Reason::ReadOnly, MessageId::new(b"speed").unwrap());
+//! let mut buf = [0_u8; Nack::MAX_PAYLOAD_SIZE];
+//! let payload = nack.write_payload(&mut buf);
+//! assert_eq!(payload, b"\x03speed");
+//! assert_eq!(Nack::parse(payload), Some(nack));
+//! ```
+
+use crate::encoder;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use crate::wire::{Framing, Packet};
+use core::fmt;
+
+const MAX_PAYLOAD_SIZE: usize = 1 + MessageId::MAX_SIZE;
+
+/// Largest framed NACK
+const FRAME_SIZE: usize = Framing::max_encoded_len(Packet::<&[u8]>::buffer_len(
+    MessageId::INTERNAL_NACK.len(),
+    MAX_PAYLOAD_SIZE,
+));
+
+/// Why a request was refused
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Reason {
+    /// No variable has the message ID
+    UnknownId,
+    /// The message type doesn't match the variable's
+    BadType,
+    /// The variable can't be written
+    ReadOnly,
+    /// The value is outside the range the device accepts
+    OutOfRange,
+    /// The payload size doesn't match the variable's
+    BadLength,
+    /// A device specific reason
+    Other(u8),
+}
+
+impl From<u8> for Reason {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Reason::UnknownId,
+            2 => Reason::BadType,
+            3 => Reason::ReadOnly,
+            4 => Reason::OutOfRange,
+            5 => Reason::BadLength,
+            _ => Reason::Other(value),
+        }
+    }
+}
+
+impl From<Reason> for u8 {
+    fn from(value: Reason) -> Self {
+        match value {
+            Reason::UnknownId => 1,
+            Reason::BadType => 2,
+            Reason::ReadOnly => 3,
+            Reason::OutOfRange => 4,
+            Reason::BadLength => 5,
+            Reason::Other(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::UnknownId => f.write_str("Unknown message ID"),
+            Reason::BadType => f.write_str("Wrong message type"),
+            Reason::ReadOnly => f.write_str("Read-only variable"),
+            Reason::OutOfRange => f.write_str("Value out of range"),
+            Reason::BadLength => f.write_str("Wrong payload length"),
+            Reason::Other(v) => write!(f, "Device error 0x{:02X}", v),
+        }
+    }
+}
+
+/// A refused request, the payload of a [`MessageId::INTERNAL_NACK`] packet
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Nack<'a> {
+    pub reason: Reason,
+    /// Message ID of the refused request
+    pub id: MessageId<'a>,
+}
+
+impl<'a> Nack<'a> {
+    pub const TYPE: MessageType = MessageType::Custom;
+
+    /// Largest payload, a reason byte and the longest message ID
+    pub const MAX_PAYLOAD_SIZE: usize = MAX_PAYLOAD_SIZE;
+
+    pub const fn new(reason: Reason, id: MessageId<'a>) -> Self {
+        Nack { reason, id }
+    }
+
+    /// Parses a NACK payload, `None` if it has no valid message ID
+    pub fn parse(payload: &'a [u8]) -> Option<Self> {
+        let (&reason, id) = payload.split_first()?;
+        Some(Nack {
+            reason: reason.into(),
+            id: MessageId::new_binary(id)?,
+        })
+    }
+
+    /// Parses a packet if it's a NACK
+    pub fn from_packet<B: AsRef<[u8]>>(packet: &'a Packet<B>) -> Option<Self> {
+        if !packet.internal() || packet.msg_id().ok()? != MessageId::INTERNAL_NACK {
+            return None;
+        }
+        Self::parse(packet.payload().ok()?)
+    }
+
+    /// Writes the payload into the start of `buf`, returning it
+    pub fn write_payload<'b>(&self, buf: &'b mut [u8; MAX_PAYLOAD_SIZE]) -> &'b [u8] {
+        let len = 1 + self.id.len();
+        buf[0] = self.reason.into();
+        buf[1..len].copy_from_slice(self.id.as_bytes());
+        &buf[..len]
+    }
+
+    /// Frames the NACK straight into `transport`, `acknum` is the
+    /// acknum of the refused request
+    pub fn send<T: Transport>(
+        &self,
+        transport: &mut T,
+        acknum: AckNum,
+    ) -> Result<usize, encoder::Error<T::Error>> {
+        let mut buf = [0_u8; Self::MAX_PAYLOAD_SIZE];
+        let payload = self.write_payload(&mut buf);
+        encoder::send::<FRAME_SIZE, _>(
+            transport,
+            MessageId::INTERNAL_NACK,
+            Self::TYPE,
+            true,
+            false,
+            acknum,
+            payload,
+        )
+    }
+}
+
+impl fmt::Display for Nack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.reason, self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::transport::mailbox::{Full, Mailbox};
+    use crate::transport::test_util::Buffers;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reason_codes() {
+        for code in 0..=u8::MAX {
+            assert_eq!(u8::from(Reason::from(code)), code);
+        }
+        assert_eq!(Reason::from(3), Reason::ReadOnly);
+        assert_eq!(Reason::from(0x80), Reason::Other(0x80));
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        assert_eq!(Nack::parse(&[]), None);
+        assert_eq!(Nack::parse(&[1]), None);
+        assert_eq!(Nack::parse(&[1, 0]), None);
+        assert_eq!(Nack::parse(&[1; Nack::MAX_PAYLOAD_SIZE + 1]), None);
+    }
+
+    #[test]
+    fn send_and_decode() {
+        let id = MessageId::new(b"abcdefghijklmno").unwrap();
+        let nack = Nack::new(Reason::OutOfRange, id);
+        let mut t = Buffers::new(&[]);
+        let size = nack.send(&mut t, AckNum::MAX).unwrap();
+        assert_eq!(size, t.written().len());
+
+        let mut storage = [0_u8; 64];
+        let mut dec = Decoder::new(&mut storage);
+        let (_, p) = dec.decode_slice(t.written());
+        let p = p.unwrap().unwrap();
+        assert!(p.internal());
+        assert_eq!(p.acknum(), AckNum::MAX);
+        assert_eq!(p.msg_id().unwrap(), MessageId::INTERNAL_NACK);
+        assert_eq!(Nack::parse(p.payload()), Some(nack));
+    }
+
+    #[test]
+    fn send_whole_frame_or_nothing() {
+        let nack = Nack::new(Reason::OutOfRange, MessageId::new(b"abc").unwrap());
+        let mut mailbox = Mailbox::<16, 8>::new();
+        assert_eq!(
+            nack.send(&mut mailbox, AckNum::MAX),
+            Err(encoder::Error::Write(Full))
+        );
+        assert_eq!(mailbox.tx_len(), 0);
+
+        let mut mailbox = Mailbox::<16, 16>::new();
+        let size = nack.send(&mut mailbox, AckNum::MAX).unwrap();
+        assert_eq!(mailbox.tx_len(), size);
+    }
+}
@@ -0,0 +1,213 @@
+//! Announcements of variables sent in chunks
+//!
+//! A variable too large for a single packet is sent as an
+//! [`MessageType::OffsetMetadata`] packet followed by offset packets, each
+//! holding a chunk of the variable at its offset address. The metadata
+//! payload is the little-endian `[base, end]` address range the chunks
+//! cover, its length is the size of the whole variable.
+//!
+//! ```
+//! use electricui_embedded::offset::OffsetMetadata;
+//!
+//! let meta = OffsetMetadata::new(0, 300).unwrap();
+//! assert_eq!(meta.len(), 300);
+//! assert_eq!(meta.to_bytes(), [0x00, 0x00, 0x2C, 0x01]);
+//! assert_eq!(OffsetMetadata::parse(&meta.to_bytes()), Some(meta));
+//! ```
+
+use crate::encoder;
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::transport::Transport;
+use crate::wire::packet::Error;
+use crate::wire::payload::Codec;
+use crate::wire::{Framing, Packet};
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+
+/// Largest framed announcement
+const FRAME_SIZE: usize = Framing::max_encoded_len(Packet::<&[u8]>::buffer_len(
+    MessageId::MAX_SIZE,
+    OffsetMetadata::SIZE,
+));
+
+/// The address range of a chunked variable, the payload of a
+/// [`MessageType::OffsetMetadata`] packet
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OffsetMetadata {
+    base: u16,
+    end: u16,
+}
+
+impl OffsetMetadata {
+    pub const TYPE: MessageType = MessageType::OffsetMetadata;
+
+    /// Payload size, the base and end addresses
+    pub const SIZE: usize = 4;
+
+    /// The range `base..end`, `None` if `end` is before `base`
+    pub const fn new(base: u16, end: u16) -> Option<Self> {
+        if end < base {
+            None
+        } else {
+            Some(OffsetMetadata { base, end })
+        }
+    }
+
+    /// Address of the variable's first byte
+    pub const fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// Address one past the variable's last byte
+    pub const fn end(&self) -> u16 {
+        self.end
+    }
+
+    /// Total size of the variable in bytes
+    pub const fn len(&self) -> usize {
+        (self.end - self.base) as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.base == self.end
+    }
+
+    /// Returns true if a chunk of `size` bytes at `address` is within
+    /// the range
+    pub fn contains(&self, address: u16, size: usize) -> bool {
+        address >= self.base && usize::from(address) + size <= usize::from(self.end)
+    }
+
+    /// Parses a metadata payload, `None` if it isn't [`Self::SIZE`] bytes
+    /// or the range is reversed
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() != Self::SIZE {
+            return None;
+        }
+        Self::new(
+            LittleEndian::read_u16(&payload[0..2]),
+            LittleEndian::read_u16(&payload[2..4]),
+        )
+    }
+
+    /// Parses a packet if it's an offset metadata announcement
+    pub fn from_packet<B: AsRef<[u8]>>(packet: &Packet<B>) -> Option<Self> {
+        if packet.typ() != Self::TYPE || packet.offset() {
+            return None;
+        }
+        Self::parse(packet.payload().ok()?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0_u8; Self::SIZE];
+        LittleEndian::write_u16(&mut buf[0..2], self.base);
+        LittleEndian::write_u16(&mut buf[2..4], self.end);
+        buf
+    }
+
+    /// Frames the announcement for the variable `id` straight into
+    /// `transport`
+    pub fn send<T: Transport>(
+        &self,
+        transport: &mut T,
+        id: MessageId<'_>,
+    ) -> Result<usize, encoder::Error<T::Error>> {
+        encoder::send::<FRAME_SIZE, _>(
+            transport,
+            id,
+            Self::TYPE,
+            false,
+            false,
+            AckNum::NONE,
+            &self.to_bytes(),
+        )
+    }
+}
+
+impl Codec for OffsetMetadata {
+    const WIRE_TYPE: MessageType = Self::TYPE;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        buf.get_mut(..Self::SIZE)
+            .ok_or(Error::InvalidDataLength)?
+            .copy_from_slice(&self.to_bytes());
+        Ok(Self::SIZE)
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, Error> {
+        Self::parse(payload).ok_or(Error::InvalidDataLength)
+    }
+}
+
+impl fmt::Display for OffsetMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06X}..{:#06X}", self.base, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::transport::test_util::Buffers;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ranges() {
+        let meta = OffsetMetadata::new(0x10, 0x20).unwrap();
+        assert_eq!(meta.len(), 16);
+        assert!(!meta.is_empty());
+        assert!(meta.contains(0x10, 16));
+        assert!(meta.contains(0x18, 8));
+        assert!(!meta.contains(0x18, 9));
+        assert!(!meta.contains(0x0F, 1));
+        assert!(OffsetMetadata::new(5, 5).unwrap().is_empty());
+        assert_eq!(OffsetMetadata::new(6, 5), None);
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        assert_eq!(OffsetMetadata::parse(&[]), None);
+        assert_eq!(OffsetMetadata::parse(&[0, 0, 1]), None);
+        assert_eq!(OffsetMetadata::parse(&[0, 0, 1, 0, 0]), None);
+        assert_eq!(OffsetMetadata::parse(&[2, 0, 1, 0]), None);
+        assert_eq!(
+            OffsetMetadata::parse(&[1, 0, 2, 0]),
+            OffsetMetadata::new(1, 2)
+        );
+    }
+
+    #[test]
+    fn send_and_decode() {
+        let meta = OffsetMetadata::new(0, 1000).unwrap();
+        let id = MessageId::new(b"blob").unwrap();
+        let mut t = Buffers::new(&[]);
+        let size = meta.send(&mut t, id).unwrap();
+        assert_eq!(size, t.written().len());
+
+        let mut storage = [0_u8; 64];
+        let mut dec = Decoder::new(&mut storage);
+        let (_, p) = dec.decode_slice(t.written());
+        let p = p.unwrap().unwrap();
+        assert_eq!(p.typ(), MessageType::OffsetMetadata);
+        assert_eq!(p.msg_id().unwrap(), id);
+        assert_eq!(OffsetMetadata::parse(p.payload()), Some(meta));
+
+        let mut buf = [0_u8; 16];
+        let size = Packet::builder(id, MessageType::OffsetMetadata)
+            .payload(&meta.to_bytes())
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(OffsetMetadata::from_packet(&p), Some(meta));
+        assert_eq!(p.decode_payload(), Ok(meta));
+        let size = Packet::builder(id, MessageType::Custom)
+            .payload(&meta.to_bytes())
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(OffsetMetadata::from_packet(&p), None);
+    }
+}
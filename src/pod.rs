@@ -0,0 +1,134 @@
+//! Plain structs as `MessageType::Custom` payloads, enabled by the
+//! `zerocopy` feature
+//!
+//! Any type that is [`IntoBytes`], [`FromBytes`] and [`Immutable`], e.g. a
+//! `#[repr(C)]` struct deriving them, is carried as its in-memory bytes by
+//! wrapping it in a [`Pod`]. Both ends must agree on the layout and
+//! endianness, the [`zerocopy::little_endian`] field types keep it
+//! portable.
+//!
+//! ```
+//! use electricui_embedded::message::{MessageId, MessageType};
+//! use electricui_embedded::pod::Pod;
+//! use electricui_embedded::wire::Packet;
+//! use zerocopy::little_endian::{I16, U32};
+//! use zerocopy::{FromBytes, Immutable, IntoBytes};
+//!
+//! #[derive(FromBytes, IntoBytes, Immutable, Copy, Clone, Debug, PartialEq)]
+//! #[repr(C)]
+//! struct Telemetry {
+//!     uptime: U32,
+//!     temp: I16,
+//!     current: I16,
+//! }
+//!
+//! let t = Telemetry {
+//!     uptime: 1000.into(),
+//!     temp: (-12).into(),
+//!     current: 300.into(),
+//! };
+//! let mut buf = [0_u8; 32];
+//! let mut p = Packet::new_unchecked(&mut buf[..]);
+//! p.set_msg_id(MessageId::new(b"tlm").unwrap()).unwrap();
+//! assert_eq!(p.encode_payload(&Pod(t)), Ok(8));
+//! assert_eq!(p.typ(), MessageType::Custom);
+//!
+//! let Pod(decoded) = p.decode_payload::<Pod<Telemetry>>().unwrap();
+//! assert_eq!(decoded, t);
+//! ```
+
+use crate::message::MessageType;
+use crate::wire::packet::Error;
+use crate::wire::payload::Codec;
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+/// Carries a `T` as its bytes, a [`Codec`] for
+/// [`MessageType::Custom`] payloads
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pod<T>(pub T);
+
+impl<T: IntoBytes + FromBytes + Immutable> Codec for Pod<T> {
+    const WIRE_TYPE: MessageType = MessageType::Custom;
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let bytes = self.0.as_bytes();
+        buf.get_mut(..bytes.len())
+            .ok_or(Error::InvalidDataLength)?
+            .copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    /// The payload must be exactly the size of `T`, it needn't be aligned
+    fn decode(payload: &[u8]) -> Result<Self, Error> {
+        T::read_from_bytes(payload)
+            .map(Pod)
+            .map_err(|_| Error::InvalidDataLength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageId;
+    use crate::wire::Packet;
+    use pretty_assertions::assert_eq;
+    use zerocopy::little_endian::{F32, U16};
+
+    #[derive(FromBytes, IntoBytes, Immutable, Copy, Clone, Debug, PartialEq)]
+    #[repr(C)]
+    struct Sample {
+        id: U16,
+        flags: [u8; 2],
+        value: F32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = Sample {
+            id: 0x0102.into(),
+            flags: [3, 4],
+            value: 1.5.into(),
+        };
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"s").unwrap()).unwrap();
+        assert_eq!(p.encode_payload(&Pod(s)), Ok(8));
+        assert_eq!(
+            p.payload(),
+            Ok(&[0x02, 0x01, 3, 4, 0x00, 0x00, 0xC0, 0x3F][..])
+        );
+        assert_eq!(p.decode_payload(), Ok(Pod(s)));
+
+        // Payload starts at an odd offset
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"ab").unwrap()).unwrap();
+        p.encode_payload(&Pod(s)).unwrap();
+        assert_eq!(p.decode_payload(), Ok(Pod(s)));
+    }
+
+    #[test]
+    fn size_mismatches() {
+        let mut buf = [0_u8; 10];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"s").unwrap()).unwrap();
+        p.set_data_length(0).unwrap();
+        assert_eq!(
+            p.encode_payload(&Pod([0_u32; 2])),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(p.encode_payload(&Pod(7_u32)), Ok(4));
+        assert_eq!(
+            p.decode_payload::<Pod<u16>>(),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(p.decode_payload(), Ok(Pod(7_u32)));
+
+        p.set_typ(MessageType::U32);
+        assert_eq!(
+            p.decode_payload::<Pod<u32>>(),
+            Err(Error::UnexpectedType(MessageType::U32))
+        );
+    }
+}
@@ -1,4 +1,4 @@
 pub use crate::decoder::Decoder;
 pub use crate::error::Error;
-pub use crate::message::{MessageId, MessageType};
+pub use crate::message::{AckNum, MessageId, MessageIdBuf, MessageType};
 pub use crate::wire::{Framing, Packet};
@@ -0,0 +1,530 @@
+//! [proptest] strategies for protocol entities and a noisy channel
+//! simulation, enabled by the `test-support` feature
+//!
+//! [proptest]: https://crates.io/crates/proptest
+
+use crate::message::{AckNum, MessageId, MessageType, UnknownType};
+use crate::wire::{packet, Framing, Packet};
+use proptest::{
+    collection, num,
+    prelude::*,
+    prop_oneof,
+    std_facade::{vec, Vec},
+};
+
+/// Largest payload of the packets generated by [`gen_packet`]
+pub const MAX_GEN_PAYLOAD_SIZE: usize = 128;
+
+pub fn gen_message_type() -> impl Strategy<Value = MessageType> {
+    prop_oneof![
+        Just(MessageType::Callback),
+        Just(MessageType::Custom),
+        Just(MessageType::OffsetMetadata),
+        Just(MessageType::Byte),
+        Just(MessageType::Char),
+        Just(MessageType::I8),
+        Just(MessageType::U8),
+        Just(MessageType::I16),
+        Just(MessageType::U16),
+        Just(MessageType::I32),
+        Just(MessageType::U32),
+        Just(MessageType::F32),
+        Just(MessageType::F64),
+        gen_unknown_msg_typ(),
+    ]
+}
+
+prop_compose! {
+    fn gen_unknown_msg_typ()(value in UnknownType::MIN.get()..=UnknownType::MAX.get()) -> MessageType {
+        MessageType::Unknown(UnknownType::new(value).unwrap())
+    }
+}
+
+/// Any acknum, including [`AckNum::NONE`]
+pub fn gen_acknum() -> impl Strategy<Value = AckNum> {
+    (0_u8..=AckNum::MAX.get()).prop_map(AckNum::from_bits)
+}
+
+prop_compose! {
+    /// Message ID bytes of any length, including the invalid `[0]`
+    pub fn gen_msg_id_bytes()(bytes in collection::vec(num::u8::ANY, 1..=MessageId::MAX_SIZE)) -> Vec<u8> {
+        bytes
+    }
+}
+
+/// Bytes of a valid message ID
+pub fn gen_valid_msg_id_bytes() -> impl Strategy<Value = Vec<u8>> {
+    gen_msg_id_bytes().prop_filter("valid message ID", |id| MessageId::new(id).is_some())
+}
+
+/// Bytes of one of the [`MessageId::INTERNAL`] ID's
+pub fn gen_internal_msg_id_bytes() -> impl Strategy<Value = Vec<u8>> {
+    proptest::sample::select(&MessageId::INTERNAL[..]).prop_map(|id| id.as_bytes().to_vec())
+}
+
+prop_compose! {
+    /// A valid non-offset packet with a payload of up to
+    /// [`MAX_GEN_PAYLOAD_SIZE`] bytes, only internal ID's are
+    /// internal flagged and queries don't request an ack
+    pub fn gen_packet()(
+        id in prop_oneof![3 => gen_valid_msg_id_bytes(), 1 => gen_internal_msg_id_bytes()],
+        typ in gen_message_type(),
+        internal in any::<bool>(),
+        response in any::<bool>(),
+        acknum in gen_acknum(),
+        payload in collection::vec(num::u8::ANY, 0..=MAX_GEN_PAYLOAD_SIZE),
+    ) -> Packet<Vec<u8>> {
+        let id = MessageId::new(&id).unwrap();
+        let internal = internal && id.is_internal();
+        let query = response && payload.is_empty() && typ != MessageType::Callback;
+        let acknum = if query { AckNum::NONE } else { acknum };
+        let mut bytes = vec![0; Packet::<&[u8]>::buffer_len(id.len(), payload.len())];
+        packet::emit(&mut bytes, id, typ, internal, response, acknum, &payload).unwrap();
+        Packet::new_unchecked(bytes)
+    }
+}
+
+prop_compose! {
+    /// A byte stream of up to `max_packets` framed packets, each
+    /// terminated by a delimiter, along with the packets
+    pub fn gen_framed_stream(max_packets: usize)(
+        packets in collection::vec(gen_packet(), 0..=max_packets),
+    ) -> (Vec<u8>, Vec<Packet<Vec<u8>>>) {
+        let mut stream = Vec::new();
+        for p in packets.iter() {
+            let start = stream.len();
+            stream.resize(start + Framing::max_encoded_len(p.as_ref().len()), 0);
+            let size = Framing::encode_buf(p.as_ref(), &mut stream[start..]);
+            stream.truncate(start + size);
+        }
+        (stream, packets)
+    }
+}
+
+/// One part of a [`gen_noisy_stream`]
+#[derive(Clone, Debug)]
+enum Part {
+    Frame(Packet<Vec<u8>>),
+    /// A framed packet with one byte changed
+    Corrupt(Packet<Vec<u8>>, usize, u8),
+    Noise(Vec<u8>),
+    Delimiter,
+}
+
+fn gen_part() -> impl Strategy<Value = Part> {
+    prop_oneof![
+        3 => gen_packet().prop_map(Part::Frame),
+        1 => (gen_packet(), any::<usize>(), 1_u8..=0xFF)
+            .prop_map(|(p, idx, flip)| Part::Corrupt(p, idx, flip)),
+        1 => collection::vec(num::u8::ANY, 0..=32).prop_map(Part::Noise),
+        1 => Just(Part::Delimiter),
+    ]
+}
+
+fn push_frame(stream: &mut Vec<u8>, packet: &[u8]) -> usize {
+    let start = stream.len();
+    stream.resize(start + Framing::max_encoded_len(packet.len()) + 1, 0);
+    let size = Framing::encode_buf(packet, &mut stream[start..]);
+    stream.truncate(start + size);
+    start
+}
+
+prop_compose! {
+    /// A byte stream of up to `max_parts` framed packets, corrupted
+    /// frames, random bytes and stray delimiters
+    pub fn gen_noisy_stream(max_parts: usize)(
+        parts in collection::vec(gen_part(), 0..=max_parts),
+    ) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for part in parts.into_iter() {
+            match part {
+                Part::Frame(p) => {
+                    push_frame(&mut stream, p.as_ref());
+                }
+                Part::Corrupt(p, idx, flip) => {
+                    let start = push_frame(&mut stream, p.as_ref());
+                    let idx = start + idx % (stream.len() - start);
+                    stream[idx] ^= flip;
+                }
+                Part::Noise(bytes) => stream.extend_from_slice(&bytes),
+                Part::Delimiter => stream.push(Framing::ZERO),
+            }
+        }
+        stream
+    }
+}
+
+/// A slice based model of the streaming
+/// [`Decoder`](crate::decoder::Decoder), returns the wire bytes of every
+/// packet it would decode from `stream`.
+///
+/// The stream is split on delimiters, each frame's COBS blocks are decoded
+/// keeping whatever a truncated last block holds, and the result is parsed
+/// with [`Packet::new`], ignoring any bytes past the checksum.
+pub fn reference_decode(stream: &[u8]) -> Vec<Vec<u8>> {
+    stream
+        .split(|&b| b == Framing::ZERO)
+        .filter_map(|frame| {
+            let raw = cobs_decode_prefix(frame);
+            let size = Packet::new(&raw[..]).ok()?.wire_size().ok()?;
+            Some(raw[..size].to_vec())
+        })
+        .collect()
+}
+
+/// COBS decodes as much of `frame` as is there
+fn cobs_decode_prefix(frame: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    let mut rest = frame;
+    let mut zero = false;
+    while let Some((&code, tail)) = rest.split_first() {
+        if zero {
+            raw.push(0);
+        }
+        let len = (usize::from(code) - 1).min(tail.len());
+        raw.extend_from_slice(&tail[..len]);
+        rest = &tail[len..];
+        // A full block isn't followed by a zero
+        zero = code != 0xFF;
+    }
+    raw
+}
+
+/// Corrupts framed packets on their way from an encoder to a decoder,
+/// for checking that the decoder recovers from a noisy link.
+///
+/// Every byte can have a bit flipped, be dropped or be duplicated, and
+/// every frame can lose its tail before the delimiter. The same seed
+/// and rates always corrupt a sequence of packets the same way.
+#[derive(Clone, Debug)]
+pub struct NoisyChannel {
+    bit_flip_rate: f64,
+    drop_rate: f64,
+    duplicate_rate: f64,
+    truncate_rate: f64,
+    rng: u64,
+    stats: NoiseStats,
+}
+
+/// What a [`NoisyChannel`] has done so far
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct NoiseStats {
+    pub frames: usize,
+    pub bit_flips: usize,
+    pub dropped: usize,
+    pub duplicated: usize,
+    pub truncated: usize,
+}
+
+impl NoisyChannel {
+    /// A channel that passes everything through untouched until
+    /// rates are set
+    pub fn new(seed: u64) -> Self {
+        Self {
+            bit_flip_rate: 0.0,
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            truncate_rate: 0.0,
+            // xorshift has a fixed point at zero
+            rng: seed.max(1),
+            stats: NoiseStats::default(),
+        }
+    }
+
+    /// Flips one bit of a byte with probability `rate` (0.0 to 1.0)
+    pub fn with_bit_flip_rate(mut self, rate: f64) -> Self {
+        self.bit_flip_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Drops a byte with probability `rate` (0.0 to 1.0)
+    pub fn with_drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sends a byte twice with probability `rate` (0.0 to 1.0)
+    pub fn with_duplicate_rate(mut self, rate: f64) -> Self {
+        self.duplicate_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Cuts a frame short, keeping its delimiter, with probability
+    /// `rate` (0.0 to 1.0)
+    pub fn with_truncate_rate(mut self, rate: f64) -> Self {
+        self.truncate_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn stats(&self) -> NoiseStats {
+        self.stats
+    }
+
+    /// Frames each packet and passes the frames through the channel
+    pub fn transmit<'a, I>(&mut self, packets: I) -> Transmission
+    where
+        I: IntoIterator<Item = &'a Packet<Vec<u8>>>,
+    {
+        let mut t = Transmission {
+            stream: Vec::new(),
+            sent: Vec::new(),
+            intact: Vec::new(),
+        };
+        // Start of stream counts as a delimiter
+        let mut delimited = true;
+        for p in packets.into_iter() {
+            let mut frame = Vec::new();
+            push_frame(&mut frame, p.as_ref());
+            let (clean, ends_delimited) = self.corrupt(&frame, &mut t.stream);
+            t.sent.push(p.as_ref().to_vec());
+            t.intact.push(clean && delimited);
+            delimited = ends_delimited;
+        }
+        t
+    }
+
+    /// Pushes the corrupted `frame` onto `out`, returns whether it went
+    /// through untouched and whether its delimiter arrived
+    fn corrupt(&mut self, frame: &[u8], out: &mut Vec<u8>) -> (bool, bool) {
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        let (body, delimiter) = frame.split_at(frame.len() - 1);
+        let mut clean = true;
+        let body = if self.happens(self.truncate_rate) {
+            self.stats.truncated = self.stats.truncated.saturating_add(1);
+            clean = false;
+            &body[..self.below(body.len())]
+        } else {
+            body
+        };
+        let mut delimited = false;
+        for (i, &b) in body.iter().chain(delimiter).enumerate() {
+            let last = i == body.len();
+            if self.happens(self.drop_rate) {
+                self.stats.dropped = self.stats.dropped.saturating_add(1);
+                clean = false;
+                continue;
+            }
+            let mut b = b;
+            if self.happens(self.bit_flip_rate) {
+                self.stats.bit_flips = self.stats.bit_flips.saturating_add(1);
+                clean = false;
+                b ^= 1 << self.below(8);
+            }
+            out.push(b);
+            if self.happens(self.duplicate_rate) {
+                self.stats.duplicated = self.stats.duplicated.saturating_add(1);
+                clean = false;
+                out.push(b);
+            }
+            delimited = last && b == Framing::ZERO;
+        }
+        (clean, delimited)
+    }
+
+    fn happens(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.chance() < rate
+    }
+
+    /// Uniform in `0..n`, `n` must be non-zero
+    fn below(&mut self, n: usize) -> usize {
+        ((self.chance() * n as f64) as usize).min(n - 1)
+    }
+
+    fn chance(&mut self) -> f64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+/// The output of [`NoisyChannel::transmit`]
+#[derive(Clone, Debug)]
+pub struct Transmission {
+    stream: Vec<u8>,
+    sent: Vec<Vec<u8>>,
+    intact: Vec<bool>,
+}
+
+impl Transmission {
+    /// The corrupted byte stream to feed a decoder
+    pub fn stream(&self) -> &[u8] {
+        &self.stream
+    }
+
+    /// Wire bytes of every packet sent
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    /// Wire bytes of the packets whose frame arrived untouched and
+    /// right after a delimiter, any decoder must recover these
+    pub fn intact(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.sent
+            .iter()
+            .zip(self.intact.iter())
+            .filter_map(|(p, intact)| intact.then_some(&p[..]))
+    }
+
+    /// Panics unless every [intact](Transmission::intact) packet is
+    /// among the `decoded` wire bytes, in the order they were sent
+    pub fn assert_recovered(&self, decoded: &[Vec<u8>]) {
+        let mut rest = decoded.iter();
+        for (n, p) in self.intact().enumerate() {
+            assert!(
+                rest.any(|d| &d[..] == p),
+                "intact packet {} of {} wasn't decoded: {:02X?}",
+                n,
+                self.intact().count(),
+                p
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::wire::limits::ProtocolLimits;
+    use pretty_assertions::assert_eq;
+
+    fn decode_all(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut storage = [0_u8; ProtocolLimits::DECODER_STORAGE_SIZE];
+        let mut dec = Decoder::new(&mut storage);
+        let mut decoded = Vec::new();
+        for &b in stream.iter() {
+            if let Ok(Some(p)) = dec.decode(b) {
+                let mut bytes = vec![0_u8; p.wire_size()];
+                p.write_to(&mut bytes);
+                decoded.push(bytes);
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn quiet_channel() {
+        let packets = [b"abc".as_slice(), b"speed"]
+            .iter()
+            .map(|id| {
+                let id = MessageId::new(id).unwrap();
+                let mut bytes = vec![0; Packet::<&[u8]>::buffer_len(id.len(), 2)];
+                packet::emit(
+                    &mut bytes,
+                    id,
+                    MessageType::U16,
+                    false,
+                    false,
+                    AckNum::NONE,
+                    &[1, 0],
+                )
+                .unwrap();
+                Packet::new_unchecked(bytes)
+            })
+            .collect::<Vec<_>>();
+        let mut ch = NoisyChannel::new(1);
+        let t = ch.transmit(&packets);
+        assert_eq!(t.intact().count(), 2);
+        assert_eq!(decode_all(t.stream()), t.sent());
+        t.assert_recovered(&decode_all(t.stream()));
+        assert_eq!(
+            ch.stats(),
+            NoiseStats {
+                frames: 2,
+                ..Default::default()
+            }
+        );
+
+        let mut ch = NoisyChannel::new(1).with_truncate_rate(1.0);
+        let t = ch.transmit(&packets);
+        assert_eq!(t.intact().count(), 0);
+        assert!(decode_all(t.stream()).is_empty());
+        assert_eq!(ch.stats().truncated, 2);
+    }
+
+    proptest! {
+        #[test]
+        fn noisy_channel_recovery(
+            packets in collection::vec(gen_packet(), 1..=16),
+            seed in any::<u64>(),
+            bit_flip_rate in 0.0..0.01,
+            drop_rate in 0.0..0.01,
+            duplicate_rate in 0.0..0.01,
+            truncate_rate in 0.0..0.2,
+        ) {
+            let channel = NoisyChannel::new(seed)
+                .with_bit_flip_rate(bit_flip_rate)
+                .with_drop_rate(drop_rate)
+                .with_duplicate_rate(duplicate_rate)
+                .with_truncate_rate(truncate_rate);
+            let t = channel.clone().transmit(&packets);
+            assert_eq!(channel.clone().transmit(&packets).stream(), t.stream());
+            t.assert_recovered(&decode_all(t.stream()));
+        }
+
+        #[test]
+        fn generated_packets_are_valid(p in gen_packet()) {
+            assert_eq!(Packet::new(p.as_ref()).map(|_| ()), Ok(()));
+        }
+
+        #[test]
+        fn generated_streams_decode(stream in gen_framed_stream(4)) {
+            let (bytes, packets) = stream;
+            let mut storage = [0_u8; 512];
+            let mut dec = Decoder::new(&mut storage);
+            let mut decoded = Vec::new();
+            for &b in bytes.iter() {
+                if let Some(p) = dec.decode(b).unwrap() {
+                    let mut bytes = vec![0_u8; p.wire_size()];
+                    p.write_to(&mut bytes);
+                    decoded.push(bytes);
+                }
+            }
+            let expected: Vec<Vec<u8>> = packets.into_iter().map(Packet::into_inner).collect();
+            assert_eq!(decoded, expected);
+        }
+
+        #[test]
+        fn reference_decodes_generated_streams(stream in gen_framed_stream(4)) {
+            let (bytes, packets) = stream;
+            let expected: Vec<Vec<u8>> = packets.into_iter().map(Packet::into_inner).collect();
+            assert_eq!(reference_decode(&bytes), expected);
+        }
+
+        #[test]
+        fn decoder_matches_reference(
+            stream in prop_oneof![
+                gen_noisy_stream(8),
+                collection::vec(num::u8::ANY, 0..=512),
+            ],
+            chunk_size in 1_usize..=64,
+        ) {
+            let expected = reference_decode(&stream);
+            let mut storage = [0_u8; ProtocolLimits::DECODER_STORAGE_SIZE];
+
+            let mut dec = Decoder::new(&mut storage);
+            let mut decoded = Vec::new();
+            for &b in stream.iter() {
+                if let Ok(Some(p)) = dec.decode(b) {
+                    let mut bytes = vec![0_u8; p.wire_size()];
+                    p.write_to(&mut bytes);
+                    decoded.push(bytes);
+                }
+            }
+            assert_eq!(decoded, expected);
+
+            let mut dec = Decoder::new(&mut storage);
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(chunk_size) {
+                dec.feed_chunk(chunk, |p| {
+                    let mut bytes = vec![0_u8; p.wire_size()];
+                    p.write_to(&mut bytes);
+                    decoded.push(bytes);
+                });
+            }
+            assert_eq!(decoded, expected);
+        }
+    }
+}
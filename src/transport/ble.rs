@@ -0,0 +1,176 @@
+//! BLE transport over a Nordic UART Service (NUS) style pair of
+//! characteristics
+//!
+//! Frames are split into chunks fitting the negotiated ATT MTU on send.
+//! Received chunks are handed to the decoder in order, the COBS
+//! delimiters stitch them back into frames.
+
+use crate::transport::Transport;
+
+/// ATT MTU every connection starts with
+pub const DEFAULT_MTU: u16 = 23;
+
+/// Bytes of each ATT PDU taken by the opcode and attribute handle
+pub const ATT_HEADER_SIZE: usize = 3;
+
+/// Longest attribute value
+pub const MAX_CHUNK_SIZE: usize = 512;
+
+/// Hooks into a BLE stack, e.g. nrf-softdevice or bleps.
+///
+/// On a peripheral `send` notifies the TX characteristic and `receive`
+/// takes writes to the RX characteristic, a central does the opposite.
+pub trait BleLink {
+    type Error;
+
+    /// Sends one chunk, never longer than the MTU allows
+    fn send(&mut self, chunk: &[u8]) -> Result<(), Self::Error>;
+
+    /// Copies the next received chunk into `buf` without blocking,
+    /// returns 0 if there are none
+    fn receive(&mut self, buf: &mut [u8; MAX_CHUNK_SIZE]) -> Result<usize, Self::Error>;
+}
+
+/// A [`Transport`] over a [`BleLink`], usually one per connection
+#[derive(Debug)]
+pub struct Nus<L> {
+    link: L,
+    mtu: u16,
+    chunk: [u8; MAX_CHUNK_SIZE],
+    chunk_start: usize,
+    chunk_end: usize,
+}
+
+impl<L: BleLink> Nus<L> {
+    pub fn new(link: L) -> Self {
+        Self {
+            link,
+            mtu: DEFAULT_MTU,
+            chunk: [0; MAX_CHUNK_SIZE],
+            chunk_start: 0,
+            chunk_end: 0,
+        }
+    }
+
+    /// Call once the stack has negotiated a new ATT MTU
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu.max(DEFAULT_MTU);
+    }
+
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Largest chunk sent
+    pub fn chunk_size(&self) -> usize {
+        (usize::from(self.mtu) - ATT_HEADER_SIZE).min(MAX_CHUNK_SIZE)
+    }
+
+    pub fn get_ref(&self) -> &L {
+        &self.link
+    }
+
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.link
+    }
+
+    pub fn into_inner(self) -> L {
+        self.link
+    }
+}
+
+impl<L: BleLink> Transport for Nus<L> {
+    type Error = L::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.chunk_start == self.chunk_end {
+            self.chunk_start = 0;
+            self.chunk_end = self.link.receive(&mut self.chunk)?;
+        }
+        let size = buf.len().min(self.chunk_end - self.chunk_start);
+        buf[..size].copy_from_slice(&self.chunk[self.chunk_start..self.chunk_start + size]);
+        self.chunk_start += size;
+        Ok(size)
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        for chunk in frame.chunks(self.chunk_size()) {
+            self.link.send(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Default)]
+    struct Link {
+        /// Lengths of the chunks sent
+        sent: [usize; 8],
+        num_sent: usize,
+        /// Chunks to receive, as ranges of `rx`
+        rx: &'static [u8],
+        rx_chunks: &'static [usize],
+    }
+
+    impl BleLink for Link {
+        type Error = ();
+
+        fn send(&mut self, chunk: &[u8]) -> Result<(), ()> {
+            self.sent[self.num_sent] = chunk.len();
+            self.num_sent += 1;
+            Ok(())
+        }
+
+        fn receive(&mut self, buf: &mut [u8; MAX_CHUNK_SIZE]) -> Result<usize, ()> {
+            match self.rx_chunks.split_first() {
+                Some((&len, rest)) => {
+                    buf[..len].copy_from_slice(&self.rx[..len]);
+                    self.rx = &self.rx[len..];
+                    self.rx_chunks = rest;
+                    Ok(len)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    static MSG_F32: [u8; 14] = [
+        0x00, 0x0D, 0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+    ];
+
+    #[test]
+    fn segments_to_mtu() {
+        let mut t = Nus::new(Link::default());
+        assert_eq!(t.chunk_size(), 20);
+        t.write(&[0; 45]).unwrap();
+        assert_eq!(&t.get_ref().sent[..3], &[20, 20, 5]);
+
+        t.set_mtu(247);
+        assert_eq!(t.chunk_size(), 244);
+        t.write(&[0; 45]).unwrap();
+        assert_eq!(t.get_ref().num_sent, 4);
+        assert_eq!(t.get_ref().sent[3], 45);
+
+        t.set_mtu(0);
+        assert_eq!(t.mtu(), DEFAULT_MTU);
+    }
+
+    #[test]
+    fn stitches_chunks() {
+        let mut t = Nus::new(Link {
+            rx: &MSG_F32,
+            rx_chunks: &[5, 1, 8],
+            ..Default::default()
+        });
+        let mut buffer = [0_u8; 64];
+        let mut dec = Decoder::new(&mut buffer);
+        let p = dec.decode_from(&mut t).unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert!(dec.decode_from(&mut t).unwrap().is_none());
+    }
+}
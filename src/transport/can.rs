@@ -0,0 +1,515 @@
+//! Carrying frames over CAN and CAN-FD
+//!
+//! Each CAN frame carries a one byte segmentation header followed by
+//! up to 7 (CAN) or 63 (CAN-FD) bytes of the framed packet:
+//!
+//! | Bits | Field                                   |
+//! |------|-----------------------------------------|
+//! | 7    | first segment of a frame                |
+//! | 6    | last segment of a frame                 |
+//! | 0..5 | sequence number, wraps after 63         |
+//!
+//! Frames with a missing or out of order segment are dropped.
+
+use crate::wire::Framing;
+use core::fmt;
+
+/// Size of the segmentation header
+pub const HEADER_SIZE: usize = 1;
+
+/// Data length of a classic CAN frame
+pub const CAN_DATA_LEN: usize = 8;
+
+/// Largest data length of a CAN-FD frame
+pub const CAN_FD_DATA_LEN: usize = 64;
+
+const FIRST: u8 = 0x80;
+const LAST: u8 = 0x40;
+const SEQ_MASK: u8 = 0x3F;
+
+/// The data of one CAN frame
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Segment {
+    data: [u8; CAN_FD_DATA_LEN],
+    len: usize,
+}
+
+impl Segment {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Segment {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Segment({=[u8]:02X})", self.as_bytes())
+    }
+}
+
+/// Splits a frame into [`Segment`]s of at most `data_len` bytes
+#[derive(Clone, Debug)]
+pub struct Segments<'a> {
+    rest: &'a [u8],
+    chunk_size: usize,
+    seq: u8,
+}
+
+impl<'a> Segments<'a> {
+    /// `data_len` is clamped to 2..=[`CAN_FD_DATA_LEN`]
+    pub fn new(frame: &'a [u8], data_len: usize) -> Self {
+        Self {
+            rest: frame,
+            chunk_size: data_len.clamp(HEADER_SIZE + 1, CAN_FD_DATA_LEN) - HEADER_SIZE,
+            seq: 0,
+        }
+    }
+}
+
+impl Iterator for Segments<'_> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let size = self.rest.len().min(self.chunk_size);
+        let (chunk, rest) = self.rest.split_at(size);
+        let mut header = self.seq & SEQ_MASK;
+        if self.seq == 0 {
+            header |= FIRST;
+        }
+        if rest.is_empty() {
+            header |= LAST;
+        }
+        let mut segment = Segment {
+            data: [0; CAN_FD_DATA_LEN],
+            len: HEADER_SIZE + size,
+        };
+        segment.data[0] = header;
+        segment.data[HEADER_SIZE..segment.len].copy_from_slice(chunk);
+        self.rest = rest;
+        self.seq = self.seq.wrapping_add(1);
+        Some(segment)
+    }
+}
+
+/// Puts frames of up to `N` bytes back together from their segments
+#[derive(Debug)]
+pub struct FrameReassembler<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    next_seq: Option<u8>,
+    complete: bool,
+    #[cfg(feature = "stats")]
+    dropped: usize,
+}
+
+impl<const N: usize> Default for FrameReassembler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameReassembler<N> {
+    const VALID: () = assert!(
+        N >= Framing::MIN_FRAME_SIZE,
+        "FrameReassembler must hold at least Framing::MIN_FRAME_SIZE bytes"
+    );
+
+    pub const fn new() -> Self {
+        let () = Self::VALID;
+        Self {
+            buf: [0; N],
+            len: 0,
+            next_seq: None,
+            complete: false,
+            #[cfg(feature = "stats")]
+            dropped: 0,
+        }
+    }
+
+    /// Adds the data of a CAN frame, returns true once it completes a
+    /// frame, see [`FrameReassembler::frame`]
+    pub fn push(&mut self, segment: &[u8]) -> bool {
+        let Some((&header, chunk)) = segment.split_first() else {
+            return false;
+        };
+        if self.complete || header & FIRST != 0 {
+            if self.next_seq.is_some() {
+                self.count_dropped();
+            }
+            self.complete = false;
+            self.len = 0;
+            self.next_seq = (header & FIRST != 0).then_some(0);
+        }
+        let in_order = self.next_seq == Some(header & SEQ_MASK);
+        let fits = chunk.len() <= N - self.len;
+        if !in_order || !fits {
+            if self.next_seq.take().is_some() {
+                self.count_dropped();
+            }
+            return false;
+        }
+        self.buf[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+        self.len += chunk.len();
+        if header & LAST != 0 {
+            self.next_seq = None;
+            self.complete = true;
+        } else {
+            self.next_seq = Some((header + 1) & SEQ_MASK);
+        }
+        self.complete
+    }
+
+    /// The last completed frame, empty until there is one
+    pub fn frame(&self) -> &[u8] {
+        if self.complete {
+            &self.buf[..self.len]
+        } else {
+            &[]
+        }
+    }
+
+    /// Number of incomplete frames dropped
+    #[cfg(feature = "stats")]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    #[inline(always)]
+    fn count_dropped(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.dropped = self.dropped.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for FrameReassembler<N> {
+    fn format(&self, f: defmt::Formatter) {
+        #[cfg(feature = "stats")]
+        defmt::write!(
+            f,
+            "FrameReassembler {{ len: {}, next_seq: {}, complete: {}, dropped: {} }}",
+            self.len,
+            self.next_seq,
+            self.complete,
+            self.dropped
+        );
+        #[cfg(not(feature = "stats"))]
+        defmt::write!(
+            f,
+            "FrameReassembler {{ len: {}, next_seq: {}, complete: {} }}",
+            self.len,
+            self.next_seq,
+            self.complete
+        );
+    }
+}
+
+/// Error returned by `CanTransport`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error<E> {
+    Can(E),
+    /// The driver's frame type can't hold the configured data length
+    DataLength,
+}
+
+impl<E> Error<E> {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Can(_) => 0x63,
+            Error::DataLength => 0x64,
+        }
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Can(e) => write!(f, "CAN error. {:?}", e),
+            Error::DataLength => f.write_str("Unsupported CAN frame data length"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> core::error::Error for Error<E> {}
+
+#[cfg(feature = "embedded-can")]
+pub use self::driver::CanTransport;
+
+#[cfg(feature = "embedded-can")]
+mod driver {
+    use super::{Error, FrameReassembler, Segments, CAN_DATA_LEN};
+    use crate::transport::Transport;
+    use embedded_can::nb::Can;
+    use embedded_can::{Frame, Id};
+
+    /// A [`Transport`] over an `embedded_can` driver, sending frames
+    /// with one ID and receiving them with another
+    #[derive(Debug)]
+    pub struct CanTransport<C, const N: usize> {
+        can: C,
+        tx_id: Id,
+        rx_id: Id,
+        data_len: usize,
+        rx: FrameReassembler<N>,
+        rx_pos: usize,
+    }
+
+    impl<C: Can, const N: usize> CanTransport<C, N> {
+        /// Uses classic CAN frames, received frames of up to `N` bytes are kept
+        pub fn new(can: C, tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> Self {
+            Self {
+                can,
+                tx_id: tx_id.into(),
+                rx_id: rx_id.into(),
+                data_len: CAN_DATA_LEN,
+                rx: FrameReassembler::new(),
+                rx_pos: 0,
+            }
+        }
+
+        /// Sends up to `data_len` bytes per frame, for CAN-FD drivers
+        pub fn with_data_len(mut self, data_len: usize) -> Self {
+            self.data_len = data_len;
+            self
+        }
+
+        /// Number of incomplete frames dropped
+        #[cfg(feature = "stats")]
+        pub fn dropped(&self) -> usize {
+            self.rx.dropped()
+        }
+
+        pub fn get_ref(&self) -> &C {
+            &self.can
+        }
+
+        pub fn get_mut(&mut self) -> &mut C {
+            &mut self.can
+        }
+
+        pub fn into_inner(self) -> C {
+            self.can
+        }
+    }
+
+    impl<C: Can, const N: usize> Transport for CanTransport<C, N> {
+        type Error = Error<C::Error>;
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            while self.rx_pos >= self.rx.frame().len() {
+                match self.can.receive() {
+                    Ok(frame) if frame.is_data_frame() && frame.id() == self.rx_id => {
+                        if self.rx.push(frame.data()) {
+                            self.rx_pos = 0;
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(nb::Error::WouldBlock) => return Ok(0),
+                    Err(nb::Error::Other(e)) => return Err(Error::Can(e)),
+                }
+            }
+            let frame = &self.rx.frame()[self.rx_pos..];
+            let size = buf.len().min(frame.len());
+            buf[..size].copy_from_slice(&frame[..size]);
+            self.rx_pos += size;
+            Ok(size)
+        }
+
+        fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+            for segment in Segments::new(frame, self.data_len) {
+                let mut frame =
+                    C::Frame::new(self.tx_id, segment.as_bytes()).ok_or(Error::DataLength)?;
+                // Frames displaced from the mailbox are sent again
+                while let Some(displaced) =
+                    nb::block!(self.can.transmit(&frame)).map_err(Error::Can)?
+                {
+                    frame = displaced;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn segments(frame: &[u8], data_len: usize) -> ([Segment; 8], usize) {
+        let mut out = [Segment {
+            data: [0; CAN_FD_DATA_LEN],
+            len: 0,
+        }; 8];
+        let mut n = 0;
+        for (dst, s) in out.iter_mut().zip(Segments::new(frame, data_len)) {
+            *dst = s;
+            n += 1;
+        }
+        (out, n)
+    }
+
+    #[test]
+    fn segmentation() {
+        let frame: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let (s, n) = segments(&frame, CAN_DATA_LEN);
+        assert_eq!(n, 3);
+        assert_eq!(s[0].as_bytes(), &[0x80, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(s[1].as_bytes(), &[0x01, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(s[2].as_bytes(), &[0x42, 14, 15]);
+
+        let (s, n) = segments(&frame, CAN_FD_DATA_LEN);
+        assert_eq!(n, 1);
+        assert_eq!(s[0].as_bytes()[0], 0xC0);
+        assert_eq!(&s[0].as_bytes()[1..], &frame);
+    }
+
+    #[test]
+    fn reassembly() {
+        let frame: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let (s, _) = segments(&frame, CAN_DATA_LEN);
+        let mut r = FrameReassembler::<32>::new();
+
+        assert!(!r.push(s[0].as_bytes()));
+        assert!(!r.push(s[1].as_bytes()));
+        assert!(r.push(s[2].as_bytes()));
+        assert_eq!(r.frame(), &frame);
+
+        // Missing middle segment
+        assert!(!r.push(s[0].as_bytes()));
+        assert!(r.frame().is_empty());
+        assert!(!r.push(s[2].as_bytes()));
+        #[cfg(feature = "stats")]
+        assert_eq!(r.dropped(), 1);
+
+        // Restarted frame
+        assert!(!r.push(s[0].as_bytes()));
+        assert!(!r.push(s[0].as_bytes()));
+        assert!(!r.push(s[1].as_bytes()));
+        assert!(r.push(s[2].as_bytes()));
+        #[cfg(feature = "stats")]
+        assert_eq!(r.dropped(), 2);
+
+        // Too large
+        let mut r = FrameReassembler::<8>::new();
+        assert!(!r.push(s[0].as_bytes()));
+        assert!(!r.push(s[1].as_bytes()));
+        #[cfg(feature = "stats")]
+        assert_eq!(r.dropped(), 1);
+    }
+
+    #[cfg(feature = "embedded-can")]
+    mod driver {
+        use super::super::*;
+        use crate::decoder::Decoder;
+        use crate::transport::Transport;
+        use embedded_can::{Frame, Id, StandardId};
+        use pretty_assertions::assert_eq;
+
+        #[derive(Copy, Clone, Debug)]
+        struct TestFrame {
+            id: Id,
+            data: [u8; CAN_DATA_LEN],
+            dlc: usize,
+        }
+
+        impl Frame for TestFrame {
+            fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+                let mut frame = TestFrame {
+                    id: id.into(),
+                    data: [0; CAN_DATA_LEN],
+                    dlc: data.len(),
+                };
+                frame.data.get_mut(..data.len())?.copy_from_slice(data);
+                Some(frame)
+            }
+
+            fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+                None
+            }
+
+            fn is_extended(&self) -> bool {
+                matches!(self.id, Id::Extended(_))
+            }
+
+            fn is_remote_frame(&self) -> bool {
+                false
+            }
+
+            fn id(&self) -> Id {
+                self.id
+            }
+
+            fn dlc(&self) -> usize {
+                self.dlc
+            }
+
+            fn data(&self) -> &[u8] {
+                &self.data[..self.dlc]
+            }
+        }
+
+        /// Receives the frames it transmits
+        #[derive(Default)]
+        struct Loopback {
+            frames: [Option<TestFrame>; 8],
+            head: usize,
+            tail: usize,
+        }
+
+        impl embedded_can::nb::Can for Loopback {
+            type Frame = TestFrame;
+            type Error = embedded_can::ErrorKind;
+
+            fn transmit(
+                &mut self,
+                frame: &TestFrame,
+            ) -> nb::Result<Option<TestFrame>, Self::Error> {
+                self.frames[self.tail % 8] = Some(*frame);
+                self.tail += 1;
+                Ok(None)
+            }
+
+            fn receive(&mut self) -> nb::Result<TestFrame, Self::Error> {
+                if self.head == self.tail {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.head += 1;
+                Ok(self.frames[(self.head - 1) % 8].take().unwrap())
+            }
+        }
+
+        static MSG_F32: [u8; 14] = [
+            0x00, 0x0D, 0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+        ];
+
+        #[test]
+        fn loopback() {
+            let id = StandardId::new(0x123).unwrap();
+            let mut t = CanTransport::<_, 64>::new(Loopback::default(), id, id);
+            let mut frame = [0_u8; 15];
+            frame[..14].copy_from_slice(&MSG_F32);
+            t.write(&frame).unwrap();
+            assert_eq!(t.get_ref().tail, 3);
+
+            let mut buffer = [0_u8; 64];
+            let mut dec = Decoder::new(&mut buffer);
+            let p = dec.decode_from(&mut t).unwrap().unwrap();
+            assert_eq!(p.msg_id().unwrap(), b"abc");
+            assert!(dec.decode_from(&mut t).unwrap().is_none());
+            #[cfg(feature = "stats")]
+            assert_eq!(t.dropped(), 0);
+
+            let mut t = t.with_data_len(CAN_FD_DATA_LEN);
+            assert_eq!(t.write(&frame), Err(Error::DataLength));
+        }
+    }
+}
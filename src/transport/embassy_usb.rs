@@ -0,0 +1,422 @@
+//! USB CDC-ACM transport for [embassy-usb](https://crates.io/crates/embassy-usb)
+//!
+//! An example firmware, echoing back every packet it receives. The USB
+//! driver comes from the HAL, e.g. `embassy_stm32::usb::Driver`, and the
+//! buffers are usually statics.
+//!
+//! ```no_run
+//! use electricui_embedded::prelude::*;
+//! use electricui_embedded::transport::{AsyncTransport, EmbassyUsb, ReadError};
+//! use electricui_embedded::wire::Framing;
+//! use embassy_futures::join::join;
+//! use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+//! use embassy_usb::driver::Driver;
+//! use embassy_usb::{Builder, Config};
+//!
+//! async fn run<'d, D: Driver<'d>>(
+//!     driver: D,
+//!     state: &'d mut State<'d>,
+//!     config_descriptor: &'d mut [u8; 256],
+//!     bos_descriptor: &'d mut [u8; 256],
+//!     control_buf: &'d mut [u8; 64],
+//! ) {
+//!     let mut config = Config::new(0x16c0, 0x27dd);
+//!     config.product = Some("ElectricUI device");
+//!
+//!     let mut builder = Builder::new(
+//!         driver,
+//!         config,
+//!         config_descriptor,
+//!         bos_descriptor,
+//!         &mut [],
+//!         control_buf,
+//!     );
+//!     let class = CdcAcmClass::new(&mut builder, state, 64);
+//!     let mut usb = builder.build();
+//!
+//!     let mut serial = EmbassyUsb::<_>::new(class);
+//!     let protocol = async {
+//!         let mut buffer = [0_u8; 512];
+//!         let mut raw = [0_u8; 512];
+//!         let mut frame = [0_u8; 520];
+//!         let mut dec = Decoder::new(&mut buffer);
+//!         loop {
+//!             serial.wait_connection().await;
+//!             dec.reset();
+//!             loop {
+//!                 match dec.decode_from_async(&mut serial).await {
+//!                     Ok(packet) => {
+//!                         let size = packet.write_to(&mut raw);
+//!                         let size = Framing::encode_buf(&raw[..size], &mut frame);
+//!                         if serial.write(&frame[..size]).await.is_err() {
+//!                             break;
+//!                         }
+//!                     }
+//!                     Err(ReadError::Decoder(_)) => continue,
+//!                     // Unplugged, wait for the host to come back
+//!                     Err(_) => break,
+//!                 }
+//!             }
+//!         }
+//!     };
+//!     join(usb.run(), protocol).await;
+//! }
+//! ```
+
+use crate::transport::AsyncTransport;
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_usb::driver::{Driver, EndpointError};
+
+/// Wraps a [`CdcAcmClass`], buffering one received USB packet of up to
+/// `N` bytes.
+///
+/// The class only moves whole USB packets, reads smaller than the
+/// endpoint's max packet size are served from the buffer and writes are
+/// split into max packet size chunks, ending with a zero length packet
+/// when the last chunk is full so the host sees the end of the transfer.
+/// `N` has to be at least the class's max packet size, 64 at full speed.
+///
+/// Reads return 0 once the host disables the interface, see
+/// [`EmbassyUsb::wait_connection`].
+pub struct EmbassyUsb<'d, D: Driver<'d>, const N: usize = 64> {
+    class: CdcAcmClass<'d, D>,
+    rx: [u8; N],
+    rx_start: usize,
+    rx_end: usize,
+}
+
+impl<'d, D: Driver<'d>, const N: usize> EmbassyUsb<'d, D, N> {
+    /// Panics if the class's max packet size is larger than `N`
+    pub fn new(class: CdcAcmClass<'d, D>) -> Self {
+        assert!(usize::from(class.max_packet_size()) <= N);
+        Self {
+            class,
+            rx: [0; N],
+            rx_start: 0,
+            rx_end: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &CdcAcmClass<'d, D> {
+        &self.class
+    }
+
+    pub fn get_mut(&mut self) -> &mut CdcAcmClass<'d, D> {
+        &mut self.class
+    }
+
+    /// Drops any buffered bytes
+    pub fn into_inner(self) -> CdcAcmClass<'d, D> {
+        self.class
+    }
+
+    /// Waits for the host to enable the interface, dropping any bytes
+    /// buffered from a previous connection
+    pub async fn wait_connection(&mut self) {
+        self.class.wait_connection().await;
+        self.rx_start = 0;
+        self.rx_end = 0;
+    }
+
+    fn max_packet_size(&self) -> usize {
+        usize::from(self.class.max_packet_size())
+    }
+}
+
+impl<'d, D: Driver<'d>, const N: usize> AsyncTransport for EmbassyUsb<'d, D, N> {
+    type Error = EndpointError;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.rx_start == self.rx_end {
+            let mps = self.max_packet_size();
+            // Reads into a whole packet sized buffer skip the copy
+            let res = if buf.len() >= mps {
+                self.class.read_packet(buf).await
+            } else {
+                self.class
+                    .read_packet(&mut self.rx[..mps])
+                    .await
+                    .map(|size| {
+                        self.rx_start = 0;
+                        self.rx_end = size;
+                        0
+                    })
+            };
+            match res {
+                // Zero length packets end transfers, they carry no bytes
+                Ok(0) => (),
+                Ok(size) => return Ok(size),
+                Err(EndpointError::Disabled) => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+        let size = buf.len().min(self.rx_end - self.rx_start);
+        buf[..size].copy_from_slice(&self.rx[self.rx_start..self.rx_start + size]);
+        self.rx_start += size;
+        Ok(size)
+    }
+
+    async fn write(&mut self, frame: &[u8]) -> Result<(), EndpointError> {
+        let mps = self.max_packet_size();
+        for chunk in frame.chunks(mps) {
+            self.class.write_packet(chunk).await?;
+        }
+        if !frame.is_empty() && frame.len().is_multiple_of(mps) {
+            self.class.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::message::{AckNum, MessageId, MessageType};
+    use crate::transport::test_util::block_on;
+    use crate::transport::ReadError;
+    use crate::wire::{Framing, Packet};
+    use embassy_usb::class::cdc_acm::State;
+    use embassy_usb::driver::{
+        Bus, ControlPipe, Direction, Endpoint, EndpointAddress, EndpointAllocError, EndpointIn,
+        EndpointInfo, EndpointOut, EndpointType, Event, Unsupported,
+    };
+    use embassy_usb::{Builder, Config};
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Host {
+        next: usize,
+        /// Packets sent by the host, `None` once it disables the interface
+        rx: VecDeque<Option<Vec<u8>>>,
+        /// Packets received by the host
+        tx: Vec<Vec<u8>>,
+    }
+
+    type Shared = Rc<RefCell<Host>>;
+
+    /// A full speed driver talking to a scripted host
+    struct TestDriver(Shared);
+
+    struct Ep(Shared, EndpointInfo);
+
+    /// Never started, only the class's endpoints are used
+    struct Unused;
+
+    impl<'a> Driver<'a> for TestDriver {
+        type EndpointOut = Ep;
+        type EndpointIn = Ep;
+        type ControlPipe = Unused;
+        type Bus = Unused;
+
+        fn alloc_endpoint_out(
+            &mut self,
+            ep_type: EndpointType,
+            _ep_addr: Option<EndpointAddress>,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> Result<Ep, EndpointAllocError> {
+            Ok(self.alloc(Direction::Out, ep_type, max_packet_size, interval_ms))
+        }
+
+        fn alloc_endpoint_in(
+            &mut self,
+            ep_type: EndpointType,
+            _ep_addr: Option<EndpointAddress>,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> Result<Ep, EndpointAllocError> {
+            Ok(self.alloc(Direction::In, ep_type, max_packet_size, interval_ms))
+        }
+
+        fn start(self, _control_max_packet_size: u16) -> (Unused, Unused) {
+            unreachable!()
+        }
+    }
+
+    impl TestDriver {
+        fn alloc(&mut self, dir: Direction, ep_type: EndpointType, mps: u16, interval: u8) -> Ep {
+            let mut host = self.0.borrow_mut();
+            host.next += 1;
+            let info = EndpointInfo {
+                addr: EndpointAddress::from_parts(host.next, dir),
+                ep_type,
+                max_packet_size: mps,
+                interval_ms: interval,
+            };
+            Ep(self.0.clone(), info)
+        }
+    }
+
+    impl Endpoint for Ep {
+        fn info(&self) -> &EndpointInfo {
+            &self.1
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointOut for Ep {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+            let packet = self.0.borrow_mut().rx.pop_front().flatten();
+            let packet = packet.ok_or(EndpointError::Disabled)?;
+            assert!(packet.len() <= usize::from(self.1.max_packet_size));
+            let dst = buf
+                .get_mut(..packet.len())
+                .ok_or(EndpointError::BufferOverflow)?;
+            dst.copy_from_slice(&packet);
+            Ok(packet.len())
+        }
+    }
+
+    impl EndpointIn for Ep {
+        async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+            if buf.len() > usize::from(self.1.max_packet_size) {
+                return Err(EndpointError::BufferOverflow);
+            }
+            self.0.borrow_mut().tx.push(buf.to_vec());
+            Ok(())
+        }
+    }
+
+    impl Bus for Unused {
+        async fn enable(&mut self) {}
+
+        async fn disable(&mut self) {}
+
+        async fn poll(&mut self) -> Event {
+            unreachable!()
+        }
+
+        fn endpoint_set_enabled(&mut self, _ep_addr: EndpointAddress, _enabled: bool) {}
+
+        fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {}
+
+        fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+
+        async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+            Err(Unsupported)
+        }
+    }
+
+    impl ControlPipe for Unused {
+        fn max_packet_size(&self) -> usize {
+            64
+        }
+
+        async fn setup(&mut self) -> [u8; 8] {
+            unreachable!()
+        }
+
+        async fn data_out(
+            &mut self,
+            _buf: &mut [u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<usize, EndpointError> {
+            unreachable!()
+        }
+
+        async fn data_in(
+            &mut self,
+            _data: &[u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<(), EndpointError> {
+            unreachable!()
+        }
+
+        async fn accept(&mut self) {}
+
+        async fn reject(&mut self) {}
+
+        async fn accept_set_address(&mut self, _addr: u8) {}
+    }
+
+    /// A single U8 `abc` packet
+    fn frame(frame: &mut [u8; 34]) -> usize {
+        let mut packet = [0_u8; 32];
+        let mut p = Packet::new_unchecked(&mut packet[..]);
+        p.set_data_length(1).unwrap();
+        p.set_typ(MessageType::U8);
+        p.set_internal(false);
+        p.set_offset(false);
+        p.set_id_length(3).unwrap();
+        p.set_response(false);
+        p.set_acknum(AckNum::NONE);
+        p.msg_id_mut().unwrap().copy_from_slice(b"abc");
+        p.payload_mut().unwrap()[0] = 7;
+        let size = p.wire_size().unwrap();
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        Framing::encode_buf(&packet[..size], frame)
+    }
+
+    #[test]
+    fn packets_and_frames() {
+        let host = Shared::default();
+        let mut config_descriptor = [0; 256];
+        let mut bos_descriptor = [0; 256];
+        let mut control_buf = [0; 64];
+        let mut state = State::new();
+        let mut builder = Builder::new(
+            TestDriver(host.clone()),
+            Config::new(0x16c0, 0x27dd),
+            &mut config_descriptor,
+            &mut bos_descriptor,
+            &mut [],
+            &mut control_buf,
+        );
+        let class = CdcAcmClass::new(&mut builder, &mut state, 64);
+        let mut t = EmbassyUsb::<_>::new(class);
+
+        // Writes are chunked, full sized last chunks get a ZLP
+        let frame: Vec<u8> = (1..=200).collect();
+        block_on(t.write(&frame)).unwrap();
+        block_on(t.write(&frame[..128])).unwrap();
+        let tx = host.borrow().tx.clone();
+        let sizes: Vec<usize> = tx.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 64, 64, 8, 64, 64, 0]);
+        assert_eq!(tx.concat(), [&frame[..], &frame[..128]].concat());
+
+        // Byte at a time decoding is served from the buffered packets,
+        // ZLPs are skipped
+        let mut buf = [0_u8; 34];
+        let size = self::frame(&mut buf);
+        host.borrow_mut().rx.extend([
+            Some(buf[..5].to_vec()),
+            Some(Vec::new()),
+            Some(buf[5..size].to_vec()),
+            Some(frame[..64].to_vec()),
+            None,
+        ]);
+        let mut storage = [0_u8; 64];
+        let mut dec = Decoder::new(&mut storage);
+        let p = block_on(dec.decode_from_async(&mut t)).unwrap();
+        assert_eq!(p.msg_id().unwrap(), MessageId::new(b"abc").unwrap());
+
+        // The delimiter is still buffered, then packet sized reads go
+        // straight to the endpoint
+        let mut buf = [0_u8; 64];
+        assert_eq!(block_on(t.read(&mut buf)), Ok(1));
+        assert_eq!(buf[0], 0);
+        assert_eq!(block_on(t.read(&mut buf)), Ok(64));
+        assert_eq!(buf, frame[..64]);
+
+        // The host disabled the interface
+        assert_eq!(
+            block_on(dec.decode_from_async(&mut t)).unwrap_err(),
+            ReadError::Closed
+        );
+    }
+}
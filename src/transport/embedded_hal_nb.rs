@@ -0,0 +1,136 @@
+//! Adapter for [embedded-hal-nb](https://crates.io/crates/embedded-hal-nb)
+//! serial drivers
+
+use crate::transport::Transport;
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial::{ErrorType, Read, Write};
+
+/// Runs the protocol over an nb-based serial port in a superloop.
+///
+/// Reads drain the bytes the port has ready and stop at `WouldBlock`,
+/// writes spin on `WouldBlock` until every byte is accepted.
+#[derive(Debug)]
+pub struct NbSerial<T>(pub T);
+
+impl<T> NbSerial<T> {
+    pub fn new(serial: T) -> Self {
+        NbSerial(serial)
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Read + Write> Transport for NbSerial<T> {
+    type Error = <T as ErrorType>::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        for (size, dst) in buf.iter_mut().enumerate() {
+            match self.0.read() {
+                Ok(byte) => *dst = byte,
+                Err(nb::Error::WouldBlock) => return Ok(size),
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        for &byte in frame {
+            nb::block!(self.0.write(byte))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(self.0.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_nb::serial::ErrorKind;
+    use pretty_assertions::assert_eq;
+
+    /// Receives `rx` one byte per poll, accepts a byte every other poll
+    struct Uart<'a> {
+        rx: &'a [u8],
+        ready: bool,
+        tx: [u8; 16],
+        tx_len: usize,
+    }
+
+    impl ErrorType for Uart<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Uart<'_> {
+        fn read(&mut self) -> nb::Result<u8, ErrorKind> {
+            match self.rx.split_first() {
+                Some((0xFF, _)) => Err(nb::Error::Other(ErrorKind::Overrun)),
+                Some((&byte, rest)) => {
+                    self.rx = rest;
+                    Ok(byte)
+                }
+                None => Err(nb::Error::WouldBlock),
+            }
+        }
+    }
+
+    impl Write for Uart<'_> {
+        fn write(&mut self, byte: u8) -> nb::Result<(), ErrorKind> {
+            self.ready = !self.ready;
+            if !self.ready {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.tx[self.tx_len] = byte;
+            self.tx_len += 1;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ErrorKind> {
+            Ok(())
+        }
+    }
+
+    fn uart(rx: &[u8]) -> NbSerial<Uart<'_>> {
+        NbSerial::new(Uart {
+            rx,
+            ready: false,
+            tx: [0; 16],
+            tx_len: 0,
+        })
+    }
+
+    #[test]
+    fn read_until_would_block() {
+        let mut t = uart(&[1, 2, 3]);
+        let mut buf = [0; 2];
+        assert_eq!(t.read(&mut buf), Ok(2));
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(t.read(&mut buf), Ok(1));
+        assert_eq!(t.read(&mut buf), Ok(0));
+
+        let mut t = uart(&[1, 0xFF]);
+        assert_eq!(t.read(&mut buf), Err(ErrorKind::Overrun));
+    }
+
+    #[test]
+    fn write_blocks() {
+        let mut t = uart(&[]);
+        t.write(&[1, 2, 3]).unwrap();
+        t.flush().unwrap();
+        let uart = t.into_inner();
+        assert_eq!(&uart.tx[..uart.tx_len], &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Adapter for [embedded-io](https://crates.io/crates/embedded-io) and
+//! [embedded-io-async](https://crates.io/crates/embedded-io-async) drivers
+
+#[cfg(feature = "embedded-io-async")]
+use crate::transport::AsyncTransport;
+use crate::transport::Transport;
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+
+/// Runs the protocol over any blocking `embedded_io` driver, such as a
+/// HAL serial port, or with the `embedded-io-async` feature any
+/// `embedded_io_async` driver, such as an Embassy UART or embassy-net socket.
+///
+/// Blocking reads only happen once the driver reports data is ready so
+/// they don't block.
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+impl<T> EmbeddedIo<T> {
+    pub fn new(io: T) -> Self {
+        EmbeddedIo(io)
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Read + ReadReady + Write> Transport for EmbeddedIo<T> {
+    type Error = <T as ErrorType>::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() || !self.0.read_ready()? {
+            return Ok(0);
+        }
+        self.0.read(buf)
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(frame)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<T> AsyncTransport for EmbeddedIo<T>
+where
+    T: embedded_io_async::Read + embedded_io_async::Write,
+{
+    type Error = <T as ErrorType>::Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Read::read(&mut self.0, buf).await
+    }
+
+    async fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        embedded_io_async::Write::write_all(&mut self.0, frame).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        embedded_io_async::Write::flush(&mut self.0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use crate::message::{AckNum, MessageId, MessageType};
+    use crate::wire::{Framing, Packet};
+    use core::convert::Infallible;
+    use pretty_assertions::assert_eq;
+
+    /// A serial port with some received bytes that accepts 3 bytes per write
+    struct Serial<'a> {
+        rx: &'a [u8],
+        tx: [u8; 64],
+        tx_len: usize,
+    }
+
+    impl ErrorType for Serial<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Serial<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            // Blocks forever when there's nothing to read
+            assert!(!self.rx.is_empty());
+            let size = buf.len().min(self.rx.len());
+            buf[..size].copy_from_slice(&self.rx[..size]);
+            self.rx = &self.rx[size..];
+            Ok(size)
+        }
+    }
+
+    impl ReadReady for Serial<'_> {
+        fn read_ready(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.rx.is_empty())
+        }
+    }
+
+    impl Write for Serial<'_> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            let size = buf.len().min(3);
+            self.tx[self.tx_len..self.tx_len + size].copy_from_slice(&buf[..size]);
+            self.tx_len += size;
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    impl embedded_io_async::Read for Serial<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let size = buf.len().min(self.rx.len());
+            buf[..size].copy_from_slice(&self.rx[..size]);
+            self.rx = &self.rx[size..];
+            Ok(size)
+        }
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    impl embedded_io_async::Write for Serial<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            Write::write(self, buf)
+        }
+    }
+
+    /// A single U8 `abc` packet
+    fn frame(frame: &mut [u8; 34]) -> usize {
+        let mut packet = [0_u8; 32];
+        let mut p = Packet::new_unchecked(&mut packet[..]);
+        p.set_data_length(1).unwrap();
+        p.set_typ(MessageType::U8);
+        p.set_internal(false);
+        p.set_offset(false);
+        p.set_id_length(3).unwrap();
+        p.set_response(false);
+        p.set_acknum(AckNum::NONE);
+        p.msg_id_mut().unwrap().copy_from_slice(b"abc");
+        p.payload_mut().unwrap()[0] = 7;
+        let size = p.wire_size().unwrap();
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        Framing::encode_buf(&packet[..size], frame)
+    }
+
+    #[test]
+    fn serial_transport() {
+        let mut frame = [0_u8; 34];
+        let frame_size = self::frame(&mut frame);
+
+        let mut t = EmbeddedIo::new(Serial {
+            rx: &frame[..frame_size],
+            tx: [0; 64],
+            tx_len: 0,
+        });
+        let mut buffer = [0_u8; 64];
+        let mut dec = Decoder::new(&mut buffer);
+        let p = dec.decode_from(&mut t).unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), MessageId::new(b"abc").unwrap());
+        assert!(dec.decode_from(&mut t).unwrap().is_none());
+
+        Transport::write(&mut t, &frame[..frame_size]).unwrap();
+        Transport::flush(&mut t).unwrap();
+        let serial = t.into_inner();
+        assert_eq!(&serial.tx[..serial.tx_len], &frame[..frame_size]);
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    #[test]
+    fn async_serial_transport() {
+        use crate::transport::test_util::block_on;
+        use crate::transport::ReadError;
+
+        let mut frame = [0_u8; 34];
+        let frame_size = self::frame(&mut frame);
+        let mut t = EmbeddedIo::new(Serial {
+            rx: &frame[..frame_size],
+            tx: [0; 64],
+            tx_len: 0,
+        });
+        let mut buffer = [0_u8; 64];
+        let mut dec = Decoder::new(&mut buffer);
+        let p = block_on(dec.decode_from_async(&mut t)).unwrap();
+        assert_eq!(p.msg_id().unwrap(), MessageId::new(b"abc").unwrap());
+        assert_eq!(
+            block_on(dec.decode_from_async(&mut t)).unwrap_err(),
+            ReadError::Closed
+        );
+
+        block_on(AsyncTransport::write(&mut t, &frame[..frame_size])).unwrap();
+        block_on(AsyncTransport::flush(&mut t)).unwrap();
+        let serial = t.into_inner();
+        assert_eq!(&serial.tx[..serial.tx_len], &frame[..frame_size]);
+    }
+}
@@ -0,0 +1,220 @@
+//! Mailbox transport for devices that are an I2C or SPI slave
+//!
+//! The bus master writes frames into the inbound mailbox and reads
+//! frames out of the outbound one. When there's nothing to send, reads
+//! are padded with frame delimiters, which the master's decoder skips,
+//! so the master can simply poll fixed size reads.
+//!
+//! The bus side is usually driven from the peripheral's interrupt
+//! handler, share the [`Mailbox`] with the application through a
+//! critical section mutex.
+
+use crate::transport::Transport;
+use crate::wire::Framing;
+use core::fmt;
+
+/// The outbound mailbox can't hold the frame yet
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Full;
+
+impl fmt::Display for Full {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Mailbox full")
+    }
+}
+
+impl core::error::Error for Full {}
+
+#[derive(Debug)]
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const VALID: () = assert!(N >= 1, "Mailbox buffers must hold at least 1 byte");
+
+    const fn new() -> Self {
+        let () = Self::VALID;
+        Self {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn free(&self) -> usize {
+        N - self.len
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let size = bytes.len().min(self.free());
+        for &b in &bytes[..size] {
+            self.buf[(self.start + self.len) % N] = b;
+            self.len += 1;
+        }
+        size
+    }
+
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let size = buf.len().min(self.len);
+        for dst in &mut buf[..size] {
+            *dst = self.buf[self.start];
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+        }
+        size
+    }
+}
+
+/// An `RX` byte inbound and `TX` byte outbound mailbox
+#[derive(Debug)]
+pub struct Mailbox<const RX: usize, const TX: usize> {
+    rx: Ring<RX>,
+    tx: Ring<TX>,
+    #[cfg(feature = "stats")]
+    overruns: usize,
+}
+
+impl<const RX: usize, const TX: usize> Default for Mailbox<RX, TX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const RX: usize, const TX: usize> Mailbox<RX, TX> {
+    pub const fn new() -> Self {
+        Self {
+            rx: Ring::new(),
+            tx: Ring::new(),
+            #[cfg(feature = "stats")]
+            overruns: 0,
+        }
+    }
+
+    /// Stores bytes written by the master, bytes that don't fit are
+    /// dropped and counted as overruns
+    pub fn bus_write(&mut self, bytes: &[u8]) {
+        let size = self.rx.push(bytes);
+        self.count_overruns(bytes.len() - size);
+    }
+
+    /// Fills `buf` for a read by the master, padding it with delimiters
+    /// past the end of the outbound data.
+    /// Returns the number of data bytes.
+    pub fn bus_read(&mut self, buf: &mut [u8]) -> usize {
+        let size = self.tx.pop(buf);
+        buf[size..].fill(Framing::ZERO);
+        size
+    }
+
+    /// Number of outbound bytes waiting for the master, for a status
+    /// register or data ready line
+    pub fn tx_len(&self) -> usize {
+        self.tx.len
+    }
+
+    /// Number of inbound bytes dropped because the mailbox was full
+    #[cfg(feature = "stats")]
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    #[inline(always)]
+    fn count_overruns(&mut self, _dropped: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.overruns = self.overruns.saturating_add(_dropped);
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const RX: usize, const TX: usize> defmt::Format for Mailbox<RX, TX> {
+    fn format(&self, f: defmt::Formatter) {
+        #[cfg(feature = "stats")]
+        defmt::write!(
+            f,
+            "Mailbox {{ rx_len: {}, tx_len: {}, overruns: {} }}",
+            self.rx.len,
+            self.tx.len,
+            self.overruns
+        );
+        #[cfg(not(feature = "stats"))]
+        defmt::write!(
+            f,
+            "Mailbox {{ rx_len: {}, tx_len: {} }}",
+            self.rx.len,
+            self.tx.len
+        );
+    }
+}
+
+impl<const RX: usize, const TX: usize> Transport for Mailbox<RX, TX> {
+    type Error = Full;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Full> {
+        Ok(self.rx.pop(buf))
+    }
+
+    /// Queues the whole frame or none of it
+    fn write(&mut self, frame: &[u8]) -> Result<(), Full> {
+        if frame.len() > self.tx.free() {
+            return Err(Full);
+        }
+        self.tx.push(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoder;
+    use pretty_assertions::assert_eq;
+
+    static MSG_F32: [u8; 14] = [
+        0x00, 0x0D, 0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+    ];
+
+    #[test]
+    fn master_writes() {
+        let mut m = Mailbox::<16, 16>::new();
+        let mut buffer = [0_u8; 64];
+        let mut dec = Decoder::new(&mut buffer);
+
+        // Split over two bus transactions
+        m.bus_write(&MSG_F32[..6]);
+        assert!(dec.decode_from(&mut m).unwrap().is_none());
+        m.bus_write(&MSG_F32[6..]);
+        let p = dec.decode_from(&mut m).unwrap().unwrap();
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+
+        m.bus_write(&[1; 20]);
+        #[cfg(feature = "stats")]
+        assert_eq!(m.overruns(), 4);
+    }
+
+    #[test]
+    fn master_reads() {
+        let mut m = Mailbox::<16, 20>::new();
+        m.write(&MSG_F32).unwrap();
+        assert_eq!(m.write(&MSG_F32), Err(Full));
+        assert_eq!(m.tx_len(), 14);
+
+        let mut buf = [0xFF; 8];
+        assert_eq!(m.bus_read(&mut buf), 8);
+        assert_eq!(buf, MSG_F32[..8]);
+        assert_eq!(m.bus_read(&mut buf), 6);
+        assert_eq!(buf[..6], MSG_F32[8..]);
+        assert_eq!(buf[6..], [0, 0]);
+
+        // Wraps around
+        m.write(&MSG_F32).unwrap();
+        let mut buf = [0xFF; 16];
+        assert_eq!(m.bus_read(&mut buf), 14);
+        assert_eq!(buf[..14], MSG_F32);
+    }
+}
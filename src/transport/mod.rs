@@ -0,0 +1,308 @@
+//! The link between a device and a host
+
+use crate::decoder;
+use core::fmt;
+
+pub use self::ble::{BleLink, Nus};
+#[cfg(feature = "embedded-can")]
+pub use self::can::CanTransport;
+#[cfg(feature = "embassy-usb")]
+pub use self::embassy_usb::EmbassyUsb;
+#[cfg(feature = "embedded-hal-nb")]
+pub use self::embedded_hal_nb::NbSerial;
+#[cfg(feature = "embedded-io")]
+pub use self::embedded_io::EmbeddedIo;
+pub use self::mailbox::Mailbox;
+#[cfg(feature = "rtt-target")]
+pub use self::rtt::RttTransport;
+#[cfg(feature = "std")]
+pub use self::std_io::StdIo;
+#[cfg(feature = "usbd-serial")]
+pub use self::usbd_serial::UsbSerial;
+
+pub mod ble;
+pub mod can;
+#[cfg(feature = "embassy-usb")]
+mod embassy_usb;
+#[cfg(feature = "embedded-hal-nb")]
+mod embedded_hal_nb;
+#[cfg(feature = "embedded-io")]
+mod embedded_io;
+pub mod mailbox;
+#[cfg(feature = "rtt-target")]
+mod rtt;
+#[cfg(feature = "std")]
+mod std_io;
+#[cfg(feature = "usbd-serial")]
+mod usbd_serial;
+
+/// A byte link, such as a UART, USB CDC endpoint or socket.
+///
+/// Implementing this is all it takes to run the protocol over a new
+/// link type. With the `std` feature it's implemented for every
+/// `std::io::Read + std::io::Write` and transports can be used as one
+/// through a `StdIo`, with the `embedded-io` feature
+/// drivers can be wrapped in an `EmbeddedIo` and with the
+/// `embedded-hal-nb` feature serial ports in an `NbSerial`.
+/// USB CDC-ACM ports are covered by `UsbSerial` with the `usbd-serial` feature.
+pub trait Transport {
+    type Error;
+
+    /// Reads the bytes currently available into `buf` without blocking,
+    /// returns 0 if there are none
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Writes a whole framed buffer
+    fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Pushes out any buffered bytes, for links that buffer writes
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// The async counterpart of [`Transport`], for async drivers such as
+/// Embassy's UART, USB CDC or embassy-net sockets.
+///
+/// With the `embedded-io-async` feature drivers can be wrapped in an
+/// `EmbeddedIo`, with the `embassy-usb` feature CDC-ACM classes in an
+/// `EmbassyUsb`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    type Error;
+
+    /// Waits for bytes to be available and reads them into `buf`,
+    /// returns 0 once the link is closed
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Writes a whole framed buffer
+    async fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Pushes out any buffered bytes, for links that buffer writes
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Streams are non-blocking by way of read timeouts, a timed out or
+/// interrupted read has no bytes available.
+/// End of file is reported as `ErrorKind::UnexpectedEof`.
+#[cfg(feature = "std")]
+impl<T: std::io::Read + std::io::Write> Transport for T {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        use std::io::ErrorKind;
+        match std::io::Read::read(self, buf) {
+            Ok(0) if !buf.is_empty() => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(size) => Ok(size),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        std::io::Write::write_all(self, frame)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        std::io::Write::flush(self)
+    }
+}
+
+/// Error returned by [`Decoder::decode_from`](crate::decoder::Decoder::decode_from)
+/// and [`Decoder::decode_from_async`](crate::decoder::Decoder::decode_from_async)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ReadError<E> {
+    Transport(E),
+    Decoder(decoder::Error),
+    /// An async transport was closed
+    Closed,
+}
+
+impl<E> ReadError<E> {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            ReadError::Transport(_) => 0x60,
+            ReadError::Decoder(e) => e.code(),
+            ReadError::Closed => 0x61,
+        }
+    }
+}
+
+impl<E> From<decoder::Error> for ReadError<E> {
+    fn from(e: decoder::Error) -> Self {
+        ReadError::Decoder(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ReadError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Transport(e) => write!(f, "Transport error. {}", e),
+            ReadError::Decoder(e) => write!(f, "Decoder error. {}", e),
+            ReadError::Closed => f.write_str("Transport closed"),
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for ReadError<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            ReadError::Transport(e) => Some(e),
+            ReadError::Decoder(e) => Some(e),
+            ReadError::Closed => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ReadError<std::io::Error> {
+    fn from(e: std::io::Error) -> Self {
+        ReadError::Transport(e)
+    }
+}
+
+/// Decoder errors become `ErrorKind::InvalidData` and a closed transport
+/// `ErrorKind::UnexpectedEof`
+#[cfg(feature = "std")]
+impl From<ReadError<std::io::Error>> for std::io::Error {
+    fn from(e: ReadError<std::io::Error>) -> Self {
+        use std::io::ErrorKind;
+        match e {
+            ReadError::Transport(e) => e,
+            ReadError::Decoder(e) => std::io::Error::new(ErrorKind::InvalidData, e),
+            ReadError::Closed => ErrorKind::UnexpectedEof.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// Polls a future that never waits on anything to completion
+    pub fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = pin!(f);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    /// Fixed input, records writes into a fixed buffer
+    pub struct Buffers<'a> {
+        pub rx: &'a [u8],
+        pub tx: [u8; 64],
+        pub tx_len: usize,
+        /// Number of `write` calls
+        pub writes: usize,
+    }
+
+    impl<'a> Buffers<'a> {
+        pub fn new(rx: &'a [u8]) -> Self {
+            Self {
+                rx,
+                tx: [0; 64],
+                tx_len: 0,
+                writes: 0,
+            }
+        }
+
+        pub fn written(&self) -> &[u8] {
+            &self.tx[..self.tx_len]
+        }
+    }
+
+    impl<'a> Transport for Buffers<'a> {
+        type Error = ();
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let size = buf.len().min(self.rx.len());
+            buf[..size].copy_from_slice(&self.rx[..size]);
+            self.rx = &self.rx[size..];
+            Ok(size)
+        }
+
+        fn write(&mut self, frame: &[u8]) -> Result<(), ()> {
+            self.writes += 1;
+            let dst = self
+                .tx
+                .get_mut(self.tx_len..self.tx_len + frame.len())
+                .ok_or(())?;
+            dst.copy_from_slice(frame);
+            self.tx_len += frame.len();
+            Ok(())
+        }
+    }
+
+    impl<'a> AsyncTransport for Buffers<'a> {
+        type Error = ();
+
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            Transport::read(self, buf)
+        }
+
+        async fn write(&mut self, frame: &[u8]) -> Result<(), ()> {
+            Transport::write(self, frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::Buffers;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn send<T: Transport>(t: &mut T, frame: &[u8]) -> Result<(), T::Error> {
+        t.write(frame)?;
+        t.flush()
+    }
+
+    #[test]
+    fn buffers_transport() {
+        let mut t = Buffers::new(&[1, 2, 3]);
+        send(&mut t, &[4, 5]).unwrap();
+        send(&mut t, &[6]).unwrap();
+        assert_eq!(t.written(), &[4, 5, 6]);
+        assert_eq!(send(&mut t, &[0; 64]), Err(()));
+
+        let mut buf = [0; 2];
+        assert_eq!(Transport::read(&mut t, &mut buf), Ok(2));
+        assert_eq!(Transport::read(&mut t, &mut buf), Ok(1));
+        assert_eq!(Transport::read(&mut t, &mut buf), Ok(0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_errors() {
+        use std::error::Error;
+        use std::io::{self, ErrorKind};
+
+        let e: ReadError<io::Error> = io::Error::from(ErrorKind::BrokenPipe).into();
+        assert!(matches!(&e, ReadError::Transport(e) if e.kind() == ErrorKind::BrokenPipe));
+        assert!(e.source().is_some());
+
+        let e = io::Error::from(ReadError::Decoder(decoder::Error::InsufficientBufferSize));
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.get_ref().unwrap().is::<decoder::Error>());
+        let e = io::Error::from(ReadError::<io::Error>::Closed);
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+}
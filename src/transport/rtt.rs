@@ -0,0 +1,64 @@
+//! Debug probe transport over [rtt-target](https://crates.io/crates/rtt-target)
+//! channels, handy during development when no UART is wired up
+
+use crate::transport::Transport;
+use core::convert::Infallible;
+use rtt_target::{ChannelMode, DownChannel, UpChannel};
+
+/// Sends frames on an RTT up channel and receives them on a down channel.
+///
+/// Frames that don't fit in the up channel's buffer are dropped whole
+/// rather than blocking, so the device keeps running with no probe
+/// attached.
+pub struct RttTransport {
+    up: UpChannel,
+    down: DownChannel,
+    #[cfg(feature = "stats")]
+    dropped: usize,
+}
+
+impl RttTransport {
+    /// Sets the up channel to [`ChannelMode::NoBlockSkip`]
+    pub fn new(mut up: UpChannel, down: DownChannel) -> Self {
+        up.set_mode(ChannelMode::NoBlockSkip);
+        Self {
+            up,
+            down,
+            #[cfg(feature = "stats")]
+            dropped: 0,
+        }
+    }
+
+    /// Number of frames dropped because the up channel was full
+    #[cfg(feature = "stats")]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn into_inner(self) -> (UpChannel, DownChannel) {
+        (self.up, self.down)
+    }
+
+    #[inline(always)]
+    fn count_dropped(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.dropped = self.dropped.saturating_add(1);
+        }
+    }
+}
+
+impl Transport for RttTransport {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        Ok(self.down.read(buf))
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), Infallible> {
+        if !frame.is_empty() && self.up.write(frame) == 0 {
+            self.count_dropped();
+        }
+        Ok(())
+    }
+}
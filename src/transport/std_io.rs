@@ -0,0 +1,87 @@
+//! [`std::io`] adapter for transports
+
+use crate::transport::Transport;
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Exposes any [`Transport`] as a `std::io::Read + std::io::Write`
+/// stream, e.g. to drive a [`Mailbox`](crate::transport::Mailbox) from a
+/// test or simulator with `std::io` code.
+///
+/// Reads with no bytes available return `ErrorKind::WouldBlock`, the
+/// inverse of how `std::io` streams are used as transports.
+#[derive(Debug)]
+pub struct StdIo<T>(pub T);
+
+impl<T> StdIo<T> {
+    pub fn new(transport: T) -> Self {
+        StdIo(transport)
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Read for StdIo<T>
+where
+    T: Transport,
+    T::Error: Error + Send + Sync + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf).map_err(io::Error::other)? {
+            0 if !buf.is_empty() => Err(ErrorKind::WouldBlock.into()),
+            size => Ok(size),
+        }
+    }
+}
+
+/// Each write is passed on as one frame
+impl<T> Write for StdIo<T>
+where
+    T: Transport,
+    T::Error: Error + Send + Sync + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StdIo;
+    use crate::transport::mailbox::{Full, Mailbox};
+    use pretty_assertions::assert_eq;
+    use std::io::{ErrorKind, Read, Write};
+
+    #[test]
+    fn mailbox_stream() {
+        let mut io = StdIo::new(Mailbox::<8, 4>::new());
+        let mut buf = [0_u8; 4];
+        assert_eq!(io.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        io.get_mut().bus_write(&[1, 2, 3]);
+        assert_eq!(io.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [1, 2, 3]);
+
+        io.write_all(&[4, 5]).unwrap();
+        io.flush().unwrap();
+        assert_eq!(io.get_ref().tx_len(), 2);
+        let err = io.write_all(&[6, 7, 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(err.into_inner().unwrap().is::<Full>());
+    }
+}
@@ -0,0 +1,282 @@
+//! USB CDC-ACM transport for [usbd-serial](https://crates.io/crates/usbd-serial)
+//!
+//! The bus allocator comes from the HAL, e.g.
+//! `UsbBusAllocator::new(hal_usb_bus)`.
+//!
+//! ```no_run
+//! use electricui_embedded::prelude::*;
+//! use electricui_embedded::transport::UsbSerial;
+//! use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! use usb_device::prelude::*;
+//! use usbd_serial::SerialPort;
+//!
+//! fn run<B: UsbBus>(bus: &UsbBusAllocator<B>) -> ! {
+//!     let mut serial = UsbSerial::<_, 256>::new(SerialPort::new(bus));
+//!     let mut usb_dev = UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27dd))
+//!         .device_class(usbd_serial::USB_CLASS_CDC)
+//!         .build();
+//!
+//!     let mut buffer = [0_u8; 512];
+//!     let mut dec = Decoder::new(&mut buffer);
+//!     loop {
+//!         usb_dev.poll(&mut [serial.get_mut()]);
+//!         serial.poll().ok();
+//!         while let Ok(Some(_packet)) = dec.decode_from(&mut serial) {
+//!             // Handle the packet, replies go out with serial.write(frame)
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::transport::Transport;
+use core::borrow::BorrowMut;
+use usb_device::bus::UsbBus;
+use usb_device::UsbError;
+use usbd_serial::{DefaultBufferStore, SerialPort};
+
+/// Wraps a [`SerialPort`], queueing up to `N` bytes of outgoing frames.
+///
+/// USB packets are at most 64 bytes and the port only moves them when
+/// the endpoint is free, so frames go out over several polls.
+/// Call [`UsbSerial::poll`] after every `UsbDevice::poll` to keep them moving.
+pub struct UsbSerial<'a, B, const N: usize, RS = DefaultBufferStore, WS = DefaultBufferStore>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    serial: SerialPort<'a, B, RS, WS>,
+    tx: [u8; N],
+    tx_start: usize,
+    tx_end: usize,
+}
+
+impl<'a, B, const N: usize, RS, WS> UsbSerial<'a, B, N, RS, WS>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    pub fn new(serial: SerialPort<'a, B, RS, WS>) -> Self {
+        Self {
+            serial,
+            tx: [0; N],
+            tx_start: 0,
+            tx_end: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &SerialPort<'a, B, RS, WS> {
+        &self.serial
+    }
+
+    /// The port also has to be passed to `UsbDevice::poll`
+    pub fn get_mut(&mut self) -> &mut SerialPort<'a, B, RS, WS> {
+        &mut self.serial
+    }
+
+    /// Drops any queued bytes
+    pub fn into_inner(self) -> SerialPort<'a, B, RS, WS> {
+        self.serial
+    }
+
+    /// Number of queued bytes not yet handed to the port
+    pub fn pending(&self) -> usize {
+        self.tx_end - self.tx_start
+    }
+
+    /// Moves queued bytes into the port and the port's buffer onto the
+    /// endpoint, as far as they'll go
+    pub fn poll(&mut self) -> Result<(), UsbError> {
+        while self.tx_start != self.tx_end {
+            match self.serial.write(&self.tx[self.tx_start..self.tx_end]) {
+                Ok(size) => self.tx_start += size,
+                Err(UsbError::WouldBlock) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.tx_start == self.tx_end {
+            self.tx_start = 0;
+            self.tx_end = 0;
+        }
+        // Sends at most one endpoint packet per call
+        match self.serial.flush() {
+            Ok(()) | Err(UsbError::WouldBlock) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<B, const N: usize, RS, WS> Transport for UsbSerial<'_, B, N, RS, WS>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    type Error = UsbError;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, UsbError> {
+        self.poll()?;
+        match self.serial.read(buf) {
+            Ok(size) => Ok(size),
+            Err(UsbError::WouldBlock) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Queues the whole frame or none of it, returns `BufferOverflow` if
+    /// it's larger than `N` and `WouldBlock` if there isn't room yet
+    fn write(&mut self, frame: &[u8]) -> Result<(), UsbError> {
+        self.poll()?;
+        if frame.len() > N {
+            return Err(UsbError::BufferOverflow);
+        }
+        if frame.len() > N - self.pending() {
+            return Err(UsbError::WouldBlock);
+        }
+        if frame.len() > N - self.tx_end {
+            self.tx.copy_within(self.tx_start..self.tx_end, 0);
+            self.tx_end -= self.tx_start;
+            self.tx_start = 0;
+        }
+        self.tx[self.tx_end..self.tx_end + frame.len()].copy_from_slice(frame);
+        self.tx_end += frame.len();
+        self.poll()
+    }
+
+    /// Doesn't wait for the queue to drain, see [`UsbSerial::poll`]
+    fn flush(&mut self) -> Result<(), UsbError> {
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::vec::Vec;
+    use usb_device::bus::{PollResult, UsbBusAllocator};
+    use usb_device::endpoint::{EndpointAddress, EndpointType};
+    use usb_device::prelude::{UsbDeviceBuilder, UsbVidPid};
+    use usb_device::UsbDirection;
+
+    #[derive(Default)]
+    struct Endpoints {
+        next: usize,
+        bulk_out: Option<EndpointAddress>,
+        bulk_in: Option<EndpointAddress>,
+        /// Bytes sent by the host
+        rx: VecDeque<u8>,
+        /// Packets received by the host
+        tx: Vec<Vec<u8>>,
+        /// Whether the host is taking IN packets
+        draining: bool,
+    }
+
+    /// A full speed bus talking to a scripted host
+    #[derive(Default)]
+    struct Bus(Arc<Mutex<Endpoints>>);
+
+    impl UsbBus for Bus {
+        fn alloc_ep(
+            &mut self,
+            ep_dir: UsbDirection,
+            _ep_addr: Option<EndpointAddress>,
+            ep_type: EndpointType,
+            _max_packet_size: u16,
+            _interval: u8,
+        ) -> usb_device::Result<EndpointAddress> {
+            let mut eps = self.0.lock().unwrap();
+            eps.next += 1;
+            let addr = EndpointAddress::from_parts(eps.next, ep_dir);
+            if ep_type == EndpointType::Bulk {
+                match ep_dir {
+                    UsbDirection::Out => eps.bulk_out = Some(addr),
+                    UsbDirection::In => eps.bulk_in = Some(addr),
+                }
+            }
+            Ok(addr)
+        }
+
+        fn enable(&mut self) {}
+
+        fn reset(&self) {}
+
+        fn set_device_address(&self, _addr: u8) {}
+
+        fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+            let mut eps = self.0.lock().unwrap();
+            if eps.bulk_in != Some(ep_addr) || !eps.draining {
+                return Err(UsbError::WouldBlock);
+            }
+            assert!(buf.len() <= 64);
+            eps.tx.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+            let mut eps = self.0.lock().unwrap();
+            if eps.bulk_out != Some(ep_addr) || eps.rx.is_empty() {
+                return Err(UsbError::WouldBlock);
+            }
+            let size = buf.len().min(64).min(eps.rx.len());
+            for (dst, src) in buf.iter_mut().zip(eps.rx.drain(..size)) {
+                *dst = src;
+            }
+            Ok(size)
+        }
+
+        fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) {}
+
+        fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+
+        fn suspend(&self) {}
+
+        fn resume(&self) {}
+
+        fn poll(&self) -> PollResult {
+            PollResult::None
+        }
+    }
+
+    #[test]
+    fn frames_span_packets() {
+        let bus = Bus::default();
+        let host = bus.0.clone();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut t = UsbSerial::<_, 256>::new(SerialPort::new(&alloc));
+        let _usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27dd)).build();
+
+        let frame: Vec<u8> = (1..=200).collect();
+        t.write(&frame).unwrap();
+        assert_eq!(t.pending(), 72);
+        assert_eq!(t.write(&frame), Err(UsbError::WouldBlock));
+        assert_eq!(t.write(&[0; 257]), Err(UsbError::BufferOverflow));
+
+        host.lock().unwrap().draining = true;
+        while !host.lock().unwrap().tx.concat().ends_with(&frame) {
+            t.poll().unwrap();
+        }
+        assert_eq!(t.pending(), 0);
+        let tx = host.lock().unwrap().tx.clone();
+        assert!(tx.iter().all(|p| p.len() <= 64));
+        assert_eq!(tx.concat(), frame);
+
+        host.lock().unwrap().rx.extend(&frame[..100]);
+        let mut buf = [0; 256];
+        let mut received = Vec::new();
+        loop {
+            match t.read(&mut buf).unwrap() {
+                0 => break,
+                size => received.extend_from_slice(&buf[..size]),
+            }
+        }
+        assert_eq!(received, &frame[..100]);
+    }
+}
@@ -0,0 +1,169 @@
+//! wasm-bindgen wrapper for decoding and encoding frames in the browser,
+//! e.g. bytes from WebSerial or WebUSB, enabled by the `wasm` feature
+
+use crate::collections::{self, encode_frame, packet_buf, PacketBuf};
+use crate::message::{AckNum, MessageId, MessageType};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// A decoded packet
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct DecodedPacket(PacketBuf);
+
+#[wasm_bindgen]
+impl DecodedPacket {
+    /// The message ID, lossily converted to a string
+    #[wasm_bindgen(getter, js_name = msgId)]
+    pub fn msg_id(&self) -> String {
+        String::from_utf8_lossy(self.0.msg_id_raw().unwrap_or_default()).into_owned()
+    }
+
+    #[wasm_bindgen(getter, js_name = msgIdBytes)]
+    pub fn msg_id_bytes(&self) -> Vec<u8> {
+        self.0.msg_id_raw().unwrap_or_default().to_vec()
+    }
+
+    /// The raw message type
+    #[wasm_bindgen(getter)]
+    pub fn typ(&self) -> u8 {
+        self.0.typ_raw()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn internal(&self) -> bool {
+        self.0.internal()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn response(&self) -> bool {
+        self.0.response()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn acknum(&self) -> u8 {
+        self.0.acknum().get()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn offset(&self) -> Option<u16> {
+        self.0.offset_address().ok().flatten()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn payload(&self) -> Vec<u8> {
+        self.0.payload().unwrap_or_default().to_vec()
+    }
+
+    /// The whole packet, without framing
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.as_ref().to_vec()
+    }
+}
+
+/// Splits received bytes into packets, invalid frames are counted and
+/// dropped
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    deframer: collections::Deframer,
+    valid: usize,
+    invalid: usize,
+}
+
+#[wasm_bindgen]
+impl FrameDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk of received bytes, returns the packets it completed
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<DecodedPacket> {
+        let mut packets = Vec::new();
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let (used, res) = self.deframer.push_slice(bytes);
+            bytes = &bytes[used..];
+            match res {
+                Some(Ok(p)) => {
+                    self.valid = self.valid.saturating_add(1);
+                    packets.push(DecodedPacket(p));
+                }
+                Some(Err(_)) => self.invalid = self.invalid.saturating_add(1),
+                None => (),
+            }
+        }
+        packets
+    }
+
+    pub fn reset(&mut self) {
+        self.deframer.reset();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn count(&self) -> usize {
+        self.valid
+    }
+
+    #[wasm_bindgen(getter, js_name = invalidCount)]
+    pub fn invalid_count(&self) -> usize {
+        self.invalid
+    }
+}
+
+/// Builds and frames a packet, ready to be written to the port
+#[wasm_bindgen(js_name = encodeFrame)]
+pub fn encode(
+    msg_id: &str,
+    typ: u8,
+    internal: bool,
+    response: bool,
+    acknum: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    let id = MessageId::new(msg_id.as_bytes()).ok_or_else(|| JsError::new("Invalid message ID"))?;
+    let acknum = AckNum::new(acknum).ok_or_else(|| JsError::new("Invalid acknum"))?;
+    let p = packet_buf(
+        id,
+        MessageType::try_from_wire(typ).ok_or_else(|| JsError::new("Invalid message type"))?,
+        internal,
+        response,
+        acknum,
+        payload,
+    )
+    .map_err(|e| JsError::new(&e.to_string()))?;
+    let mut frame = Vec::new();
+    encode_frame(p.as_ref(), &mut frame);
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn round_trip() {
+        let frame = encode("speed", u8::from(MessageType::U16), false, true, 2, &[3, 0]).unwrap();
+        let mut dec = FrameDecoder::new();
+        assert!(dec.push(&frame[..4]).is_empty());
+        let mut stream = frame[4..].to_vec();
+        stream.extend_from_slice(&[0x01, 0x02, 0x00]);
+        stream.extend_from_slice(&frame);
+        let packets = dec.push(&stream);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(dec.count(), 2);
+        assert_eq!(dec.invalid_count(), 1);
+
+        let p = &packets[0];
+        assert_eq!(p.msg_id(), "speed");
+        assert_eq!(p.typ(), u8::from(MessageType::U16));
+        assert!(!p.internal());
+        assert!(p.response());
+        assert_eq!(p.acknum(), 2);
+        assert_eq!(p.offset(), None);
+        assert_eq!(p.payload(), [3, 0]);
+    }
+}
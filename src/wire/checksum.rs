@@ -0,0 +1,170 @@
+//! The packet checksum, CRC-16/CCITT-FALSE over the header, message ID,
+//! offset and payload
+//!
+//! By default the checksum is table driven, the `small-crc` feature swaps
+//! the 512 byte table for a bitwise implementation that's slower per byte
+//! but takes a fraction of the flash, for bootloader sized targets.
+
+use core::fmt;
+#[cfg(not(feature = "small-crc"))]
+use crc::Digest;
+use crc::{Algorithm, Crc};
+
+pub const CRC16_CCITT_FALSE: Algorithm<u16> = Algorithm {
+    poly: 0x1021,
+    init: 0xFFFF,
+    refin: false,
+    refout: false,
+    xorout: 0,
+    check: 0x29B1,
+    residue: 0,
+};
+
+/// The table driven implementation, the lookup table is built at
+/// compile time and only linked in when used
+pub static CRC: Crc<u16> = Crc::<u16>::new(&CRC16_CCITT_FALSE);
+
+#[inline]
+pub fn checksum(bytes: &[u8]) -> u16 {
+    let mut acc = CrcAccumulator::new();
+    acc.update(bytes);
+    acc.finalize()
+}
+
+/// Checksums a packet from its parts, for senders that don't assemble
+/// it in one buffer first.
+/// For offset packets `payload` starts with the two offset bytes.
+pub fn checksum_of_parts(header: &[u8], msg_id: &[u8], payload: &[u8]) -> u16 {
+    let mut acc = CrcAccumulator::new();
+    acc.update(header);
+    acc.update(msg_id);
+    acc.update(payload);
+    acc.finalize()
+}
+
+/// Computes a checksum over several slices, for senders that don't
+/// assemble the whole packet in one buffer
+#[cfg(not(feature = "small-crc"))]
+pub struct CrcAccumulator(Digest<'static, u16>);
+
+#[cfg(not(feature = "small-crc"))]
+impl CrcAccumulator {
+    pub const fn new() -> Self {
+        CrcAccumulator(CRC.digest())
+    }
+
+    #[inline]
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    pub fn finalize(self) -> u16 {
+        self.0.finalize()
+    }
+}
+
+/// Computes a checksum over several slices, for senders that don't
+/// assemble the whole packet in one buffer
+#[cfg(feature = "small-crc")]
+pub struct CrcAccumulator(u16);
+
+#[cfg(feature = "small-crc")]
+impl CrcAccumulator {
+    pub const fn new() -> Self {
+        CrcAccumulator(CRC16_CCITT_FALSE.init)
+    }
+
+    #[inline]
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0 = bitwise::update(self.0, bytes);
+    }
+
+    pub fn finalize(self) -> u16 {
+        self.0 ^ CRC16_CCITT_FALSE.xorout
+    }
+}
+
+/// The checksum computed at compile time, for packets built in const
+/// contexts, see [`const_packet!`](crate::const_packet)
+pub const fn const_checksum(bytes: &[u8]) -> u16 {
+    bitwise::update(CRC16_CCITT_FALSE.init, bytes) ^ CRC16_CCITT_FALSE.xorout
+}
+
+mod bitwise {
+    use super::CRC16_CCITT_FALSE;
+
+    /// Shifts `bytes` through the CRC a bit at a time, MSB first
+    pub(super) const fn update(mut crc: u16, bytes: &[u8]) -> u16 {
+        let mut i = 0;
+        while i < bytes.len() {
+            crc ^= (bytes[i] as u16) << 8;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ CRC16_CCITT_FALSE.poly
+                } else {
+                    crc << 1
+                };
+                bit += 1;
+            }
+            i += 1;
+        }
+        crc
+    }
+}
+
+impl fmt::Debug for CrcAccumulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CrcAccumulator").finish_non_exhaustive()
+    }
+}
+
+impl Default for CrcAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn accumulated_checksum() {
+        assert_eq!(checksum(b"123456789"), CRC16_CCITT_FALSE.check);
+
+        let mut acc = CrcAccumulator::new();
+        acc.update(b"1234");
+        acc.update(b"");
+        acc.update(b"56789");
+        assert_eq!(acc.finalize(), CRC16_CCITT_FALSE.check);
+        assert_eq!(CrcAccumulator::default().finalize(), checksum(&[]));
+    }
+
+    #[test]
+    fn packet_parts() {
+        // The F32 `abc` packet
+        let packet = [
+            0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+        ];
+        let sum = checksum_of_parts(&packet[..3], &packet[3..6], &packet[6..10]);
+        assert_eq!(sum, u16::from_le_bytes([packet[10], packet[11]]));
+        assert_eq!(sum, checksum(&packet[..10]));
+    }
+
+    #[test]
+    fn bitwise_matches_table() {
+        let init = CRC16_CCITT_FALSE.init;
+        assert_eq!(bitwise::update(init, b"123456789"), CRC16_CCITT_FALSE.check);
+        let bytes: [u8; 256] = core::array::from_fn(|i| (i * 7) as u8);
+        for len in 0..bytes.len() {
+            assert_eq!(
+                bitwise::update(init, &bytes[..len]),
+                CRC.checksum(&bytes[..len])
+            );
+        }
+        const CHECK: u16 = const_checksum(b"123456789");
+        assert_eq!(CHECK, CRC16_CCITT_FALSE.check);
+    }
+}
@@ -1,18 +1,99 @@
 //! A framing wrapper around <https://crates.io/crates/corncobs>
+//!
+//! [`Framing`] is the protocol's COBS framing. Links that already delimit
+//! packets, such as USB packets, CAN frames or BLE characteristics, can
+//! use another [`Framer`] like [`Passthrough`] or [`LengthPrefixed`].
 
-use err_derive::Error;
+use crate::wire::Packet;
+use core::{fmt, mem};
 
-#[derive(Debug, Copy, Clone, Error)]
+/// Longest run of non-zero bytes in one COBS block
+const MAX_RUN: usize = 254;
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Error {
-    #[error(display = "{}", _0)]
-    Cobs(#[source] corncobs::CobsError),
+    Cobs(#[cfg_attr(feature = "serde", serde(with = "CobsErrorDef"))] corncobs::CobsError),
+    /// The frame's length doesn't match its contents, or the output
+    Length,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Cobs(e) => write!(f, "{}", e),
+            Error::Length => f.write_str("Frame length doesn't match its contents"),
+        }
+    }
+}
+
+/// `CobsError` only implements `Error` with corncobs' `std` feature
+impl core::error::Error for Error {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Cobs(e) => Some(e),
+            Error::Length => None,
+        }
+    }
+}
+
+impl From<corncobs::CobsError> for Error {
+    fn from(e: corncobs::CobsError) -> Self {
+        Error::Cobs(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(remote = "corncobs::CobsError")]
+enum CobsErrorDef {
+    Truncated,
+    Corrupt,
+}
+
+impl Error {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::Cobs(corncobs::CobsError::Truncated) => 0x20,
+            Error::Cobs(corncobs::CobsError::Corrupt) => 0x21,
+            Error::Length => 0x22,
+        }
+    }
 }
 
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Framing {}
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Cobs(corncobs::CobsError::Truncated) => defmt::write!(f, "Cobs(Truncated)"),
+            Error::Cobs(corncobs::CobsError::Corrupt) => defmt::write!(f, "Cobs(Corrupt)"),
+            Error::Length => defmt::write!(f, "Length"),
+        }
+    }
+}
+
+/// Compile time check of an `M` byte output for `N` bytes
+struct OutputLen<const N: usize, const M: usize>;
+
+impl<const N: usize, const M: usize> OutputLen<N, M> {
+    const VALID: () = assert!(
+        M >= Framing::max_encoded_len(N),
+        "Framing output must hold Framing::max_encoded_len(N) bytes"
+    );
+}
+
 impl Framing {
     pub const ZERO: u8 = corncobs::ZERO;
 
+    /// Smallest frame, of a packet with a 1 byte message ID and no payload
+    pub const MIN_FRAME_SIZE: usize = Self::max_encoded_len(Packet::<&[u8]>::BASE_PACKET_SIZE + 1);
+
     pub const fn max_encoded_len(raw_len: usize) -> usize {
         corncobs::max_encoded_len(raw_len)
     }
@@ -27,11 +108,325 @@ impl Framing {
         Ok(b)
     }
 
+    /// Encodes `bytes` and the trailing delimiter into `output`, which
+    /// must hold at least [`Framing::max_encoded_len`] bytes.
+    /// Returns the encoded size.
+    ///
+    /// Runs between zeros are found a word at a time and copied whole,
+    /// producing the same output as `corncobs::encode_buf`.
     pub fn encode_buf(bytes: &[u8], output: &mut [u8]) -> usize {
-        corncobs::encode_buf(bytes, output)
+        debug_assert!(output.len() >= Self::max_encoded_len(bytes.len()));
+        let mut out = 0;
+        let mut rest = bytes;
+        let mut prev_run_was_maximal = false;
+        loop {
+            let (mut run, next) = match Self::find_delimiter(rest) {
+                Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+                None => (rest, None),
+            };
+            if prev_run_was_maximal {
+                // Explicit zero after a maximal chunk of the previous run
+                output[out] = 1;
+                out += 1;
+            }
+            loop {
+                let (chunk, tail) = run.split_at(run.len().min(MAX_RUN));
+                output[out] = chunk.len() as u8 + 1;
+                output[out + 1..out + 1 + chunk.len()].copy_from_slice(chunk);
+                out += 1 + chunk.len();
+                prev_run_was_maximal = chunk.len() == MAX_RUN;
+                run = tail;
+                if run.is_empty() {
+                    break;
+                }
+            }
+            match next {
+                Some(next) => rest = next,
+                None => break,
+            }
+        }
+        output[out] = Self::ZERO;
+        out + 1
+    }
+
+    /// [`Framing::encode_buf`] between arrays, an `output` shorter than
+    /// [`Framing::max_encoded_len`] fails to compile
+    ///
+    /// ```compile_fail
+    /// use electricui_embedded::wire::Framing;
+    ///
+    /// let mut output = [0_u8; 4];
+    /// Framing::encode_array(&[1, 2, 3, 4], &mut output);
+    /// ```
+    pub fn encode_array<const N: usize, const M: usize>(
+        bytes: &[u8; N],
+        output: &mut [u8; M],
+    ) -> usize {
+        let () = OutputLen::<N, M>::VALID;
+        Self::encode_buf(bytes, output)
     }
 
     pub fn encode_iter(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
         corncobs::encode_iter(bytes)
     }
+
+    /// Returns the index of the first delimiter in `bytes`, checking a
+    /// word at a time
+    pub fn find_delimiter(bytes: &[u8]) -> Option<usize> {
+        const WORD: usize = mem::size_of::<usize>();
+        const LO: usize = usize::MAX / 0xFF;
+        const HI: usize = LO << 7;
+
+        let mut words = bytes.chunks_exact(WORD);
+        for (idx, word) in (&mut words).enumerate() {
+            let w = usize::from_ne_bytes(word.try_into().unwrap());
+            // Non-zero if and only if some byte is zero
+            if w.wrapping_sub(LO) & !w & HI != 0 {
+                return word
+                    .iter()
+                    .position(|b| *b == Self::ZERO)
+                    .map(|pos| idx * WORD + pos);
+            }
+        }
+        let start = bytes.len() - words.remainder().len();
+        words
+            .remainder()
+            .iter()
+            .position(|b| *b == Self::ZERO)
+            .map(|pos| start + pos)
+    }
+}
+
+/// How packets are delimited on a link
+///
+/// Frames are decoded a whole frame at a time with
+/// [`Decoder::decode_frame`](crate::decoder::Decoder::decode_frame), the
+/// byte at a time decoder only handles [`Framing`].
+pub trait Framer {
+    /// Byte ending each frame, `None` when frames aren't delimited in-band
+    const DELIMITER: Option<u8>;
+
+    fn max_encoded_len(raw_len: usize) -> usize;
+
+    /// Encodes `bytes` into `output`, which must hold at least
+    /// [`Framer::max_encoded_len`] bytes. Returns the encoded size.
+    fn encode_buf(bytes: &[u8], output: &mut [u8]) -> usize;
+
+    fn decode_buf(bytes: &[u8], output: &mut [u8]) -> Result<usize, Error>;
+
+    /// Decodes a frame, leaving the packet at the start of `bytes`.
+    /// Returns the packet size.
+    fn decode_in_place(bytes: &mut [u8]) -> Result<usize, Error>;
+}
+
+impl Framer for Framing {
+    const DELIMITER: Option<u8> = Some(Self::ZERO);
+
+    fn max_encoded_len(raw_len: usize) -> usize {
+        Framing::max_encoded_len(raw_len)
+    }
+
+    fn encode_buf(bytes: &[u8], output: &mut [u8]) -> usize {
+        Framing::encode_buf(bytes, output)
+    }
+
+    fn decode_buf(bytes: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        Framing::decode_buf(bytes, output)
+    }
+
+    fn decode_in_place(bytes: &mut [u8]) -> Result<usize, Error> {
+        Framing::decode_in_place(bytes)
+    }
+}
+
+/// Frames that are the packets as they are, for links that keep packet
+/// boundaries
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Passthrough;
+
+impl Framer for Passthrough {
+    const DELIMITER: Option<u8> = None;
+
+    fn max_encoded_len(raw_len: usize) -> usize {
+        raw_len
+    }
+
+    fn encode_buf(bytes: &[u8], output: &mut [u8]) -> usize {
+        output[..bytes.len()].copy_from_slice(bytes);
+        bytes.len()
+    }
+
+    fn decode_buf(bytes: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        output
+            .get_mut(..bytes.len())
+            .ok_or(Error::Length)?
+            .copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn decode_in_place(bytes: &mut [u8]) -> Result<usize, Error> {
+        Ok(bytes.len())
+    }
+}
+
+/// Frames that start with the packet size as a little-endian `u16`, for
+/// links that split or merge writes but carry zeros unchanged
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LengthPrefixed;
+
+impl LengthPrefixed {
+    pub const PREFIX_SIZE: usize = mem::size_of::<u16>();
+
+    /// Size of the packet in a frame starting with `prefix`, to read the
+    /// rest of a frame from a stream
+    pub fn packet_len(prefix: [u8; Self::PREFIX_SIZE]) -> usize {
+        usize::from(u16::from_le_bytes(prefix))
+    }
+
+    /// The packet in a whole frame
+    fn packet(bytes: &[u8]) -> Result<&[u8], Error> {
+        let prefix = bytes.first_chunk().ok_or(Error::Length)?;
+        let packet = &bytes[Self::PREFIX_SIZE..];
+        if Self::packet_len(*prefix) == packet.len() {
+            Ok(packet)
+        } else {
+            Err(Error::Length)
+        }
+    }
+}
+
+impl Framer for LengthPrefixed {
+    const DELIMITER: Option<u8> = None;
+
+    fn max_encoded_len(raw_len: usize) -> usize {
+        Self::PREFIX_SIZE + raw_len
+    }
+
+    fn encode_buf(bytes: &[u8], output: &mut [u8]) -> usize {
+        debug_assert!(bytes.len() <= usize::from(u16::MAX));
+        let size = Self::max_encoded_len(bytes.len());
+        output[..Self::PREFIX_SIZE].copy_from_slice(&(bytes.len() as u16).to_le_bytes());
+        output[Self::PREFIX_SIZE..size].copy_from_slice(bytes);
+        size
+    }
+
+    fn decode_buf(bytes: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        Passthrough::decode_buf(Self::packet(bytes)?, output)
+    }
+
+    fn decode_in_place(bytes: &mut [u8]) -> Result<usize, Error> {
+        let len = Self::packet(bytes)?.len();
+        bytes.copy_within(Self::PREFIX_SIZE.., 0);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn array_encoding() {
+        assert_eq!(Framing::MIN_FRAME_SIZE, 8);
+        let mut output = [0xFF; Framing::max_encoded_len(4)];
+        let size = Framing::encode_array(&[1, 0, 2, 3], &mut output);
+        assert_eq!(&output[..size], &[2, 1, 3, 2, 3, 0]);
+    }
+
+    /// Round trips a packet through framer `F`
+    fn round_trip<F: Framer>(packet: &[u8]) -> usize {
+        let mut frame = [0_u8; 64];
+        let size = F::encode_buf(packet, &mut frame);
+        assert!(size <= F::max_encoded_len(packet.len()));
+        if let Some(delimiter) = F::DELIMITER {
+            assert_eq!(Framing::find_delimiter(&frame[..size]), Some(size - 1));
+            assert_eq!(frame[size - 1], delimiter);
+        }
+        let mut decoded = [0_u8; 64];
+        let len = F::decode_buf(&frame[..size], &mut decoded).unwrap();
+        assert_eq!(&decoded[..len], packet);
+        let len = F::decode_in_place(&mut frame[..size]).unwrap();
+        assert_eq!(&frame[..len], packet);
+        size
+    }
+
+    #[test]
+    fn framers() {
+        let packet = [0x01, 0x14, 0x63, 0x61, 0x62, 0x63, 0x2A, 0xB8, 0xA3];
+        assert_eq!(round_trip::<Framing>(&packet), 11);
+        assert_eq!(round_trip::<Passthrough>(&packet), 9);
+        assert_eq!(round_trip::<LengthPrefixed>(&packet), 11);
+        assert_eq!(round_trip::<LengthPrefixed>(&[]), 2);
+
+        let mut frame = [0_u8; 16];
+        let size = LengthPrefixed::encode_buf(&packet, &mut frame);
+        assert_eq!(&frame[..2], &[9, 0]);
+        assert_eq!(LengthPrefixed::packet_len([9, 0]), 9);
+        let mut out = [0_u8; 16];
+        assert!(matches!(
+            LengthPrefixed::decode_buf(&frame[..size - 1], &mut out),
+            Err(Error::Length)
+        ));
+        assert!(matches!(
+            LengthPrefixed::decode_in_place(&mut frame[..1]),
+            Err(Error::Length)
+        ));
+        assert!(matches!(
+            Passthrough::decode_buf(&packet, &mut out[..8]),
+            Err(Error::Length)
+        ));
+        assert_eq!(Error::Length.code(), 0x22);
+    }
+
+    #[test]
+    fn delimiter_search() {
+        assert_eq!(Framing::find_delimiter(&[]), None);
+        assert_eq!(Framing::find_delimiter(&[1; 37]), None);
+        assert_eq!(Framing::find_delimiter(&[0x80; 16]), None);
+        let mut bytes = [0xFF_u8; 37];
+        for idx in (0..bytes.len()).rev() {
+            bytes[idx] = 0;
+            assert_eq!(Framing::find_delimiter(&bytes), Some(idx));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn encode_matches_corncobs(
+            bytes in proptest::collection::vec(any::<u8>(), 0..1200),
+            sparse in any::<bool>(),
+        ) {
+            // Mostly non-zero input for long runs
+            let bytes: Vec<u8> = if sparse {
+                bytes.iter().map(|b| if *b == 7 { 0 } else { b | 1 }).collect()
+            } else {
+                bytes.iter().map(|b| b % 4).collect()
+            };
+            let mut expected = std::vec![0_u8; Framing::max_encoded_len(bytes.len())];
+            let mut actual = expected.clone();
+            let size = corncobs::encode_buf(&bytes, &mut expected);
+            prop_assert_eq!(Framing::encode_buf(&bytes, &mut actual), size);
+            prop_assert_eq!(&actual[..size], &expected[..size]);
+        }
+
+        #[test]
+        fn delimiter_search_matches_position(
+            bytes in proptest::collection::vec(any::<u8>(), 0..64),
+            start in 0_usize..8,
+        ) {
+            let bytes: Vec<u8> = bytes.iter().map(|b| b % 4).collect();
+            let bytes = bytes.get(start..).unwrap_or(&[]);
+            prop_assert_eq!(
+                Framing::find_delimiter(bytes),
+                bytes.iter().position(|b| *b == 0)
+            );
+        }
+    }
 }
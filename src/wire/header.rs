@@ -0,0 +1,142 @@
+//! The 3 byte packet header
+//!
+//! ```text
+//! byte 0: data length bits 0..8
+//! byte 1: data length bits 8..10 | type << 2 | internal << 6 | offset << 7
+//! byte 2: ID length | response << 4 | acknum << 5
+//! ```
+//!
+//! Every bit is a field, so any 3 bytes parse to a [`Header`] and emit
+//! back unchanged. Whether the fields make sense together is checked by
+//! [`Packet::check_header`](crate::wire::Packet::check_header).
+
+use crate::message::{AckNum, MessageType};
+
+const DATA_LEN_MASK: u16 = 0x3FF;
+const TYPE_MASK: u8 = 0x0F;
+const ID_LEN_MASK: u8 = 0x0F;
+
+/// The header fields of a packet
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// Payload size, the low 10 bits are kept
+    pub data_length: u16,
+    /// The low 4 bits of the wire value are kept
+    pub typ: MessageType,
+    pub internal: bool,
+    pub offset: bool,
+    /// Message ID size, the low 4 bits are kept
+    pub id_length: u8,
+    pub response: bool,
+    pub acknum: AckNum,
+}
+
+impl Header {
+    pub const SIZE: usize = 3;
+
+    /// Largest data length the 10 bit field holds
+    pub const MAX_DATA_LENGTH: u16 = DATA_LEN_MASK;
+
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        Header {
+            data_length: u16::from_le_bytes([bytes[0], bytes[1]]) & DATA_LEN_MASK,
+            typ: MessageType::from_bits(bytes[1] >> 2),
+            internal: (bytes[1] >> 6) & 0x01 != 0,
+            offset: (bytes[1] >> 7) & 0x01 != 0,
+            id_length: bytes[2] & ID_LEN_MASK,
+            response: (bytes[2] >> 4) & 0x01 != 0,
+            acknum: AckNum::from_bits(bytes[2] >> 5),
+        }
+    }
+
+    pub const fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let [len0, len1] = (self.data_length & DATA_LEN_MASK).to_le_bytes();
+        [
+            len0,
+            len1 | ((self.typ.to_u8() & TYPE_MASK) << 2)
+                | ((self.internal as u8) << 6)
+                | ((self.offset as u8) << 7),
+            (self.id_length & ID_LEN_MASK)
+                | ((self.response as u8) << 4)
+                | (self.acknum.get() << 5),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::UnknownType;
+    use crate::test_support::{gen_acknum, gen_message_type};
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    #[test]
+    fn fields() {
+        let h = Header::from_bytes([0x01, 0x14, 0x63]);
+        assert_eq!(
+            h,
+            Header {
+                data_length: 1,
+                typ: MessageType::I8,
+                internal: false,
+                offset: false,
+                id_length: 3,
+                response: false,
+                acknum: AckNum::new(3).unwrap(),
+            }
+        );
+        assert_eq!(h.to_bytes(), [0x01, 0x14, 0x63]);
+
+        let h = Header::from_bytes([0xFF; 3]);
+        assert_eq!(h.data_length, 0x3FF);
+        assert_eq!(h.typ, MessageType::Unknown(UnknownType::MAX));
+        assert!(h.internal && h.offset && h.response);
+        assert_eq!(h.id_length, 0x0F);
+        assert_eq!(h.acknum, AckNum::MAX);
+    }
+
+    #[test]
+    fn out_of_range_fields_are_masked() {
+        let h = Header {
+            data_length: 0xFFFF,
+            typ: MessageType::I8,
+            internal: false,
+            offset: false,
+            id_length: 0xF1,
+            response: false,
+            acknum: AckNum::NONE,
+        };
+        assert_eq!(h.to_bytes(), [0xFF, 0x17, 0x01]);
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_bytes(bytes in any::<[u8; 3]>()) {
+            assert_eq!(Header::from_bytes(bytes).to_bytes(), bytes);
+        }
+
+        #[test]
+        fn round_trip_fields(
+            data_length in 0_u16..=DATA_LEN_MASK,
+            typ in gen_message_type(),
+            internal in any::<bool>(),
+            offset in any::<bool>(),
+            id_length in 0_u8..=ID_LEN_MASK,
+            response in any::<bool>(),
+            acknum in gen_acknum(),
+        ) {
+            let h = Header {
+                data_length,
+                typ,
+                internal,
+                offset,
+                id_length,
+                response,
+                acknum,
+            };
+            assert_eq!(Header::from_bytes(h.to_bytes()), h);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Application specific packet size limits
+
+use crate::wire::{packet, Framing, Packet, PacketView};
+
+/// Size limits for an application that only uses payloads of up to
+/// `PAYLOAD` bytes and message IDs of up to `MSG_ID` bytes, for sizing
+/// decoder storage and frame buffers below the protocol's worst case.
+///
+/// ```
+/// use electricui_embedded::prelude::*;
+/// use electricui_embedded::wire::Limits;
+///
+/// type App = Limits<64, 4>;
+/// let mut storage = [0_u8; App::DECODER_STORAGE_SIZE];
+/// let mut frame = [0_u8; App::MAX_FRAME_SIZE];
+/// let dec = Decoder::new(&mut storage);
+/// ```
+///
+/// Limits beyond the protocol's fail to compile.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Limits<const PAYLOAD: usize, const MSG_ID: usize>;
+
+/// The protocol's own limits
+pub type ProtocolLimits =
+    Limits<{ Packet::<&[u8]>::MAX_PAYLOAD_SIZE }, { Packet::<&[u8]>::MAX_MSG_ID_SIZE }>;
+
+impl<const PAYLOAD: usize, const MSG_ID: usize> Limits<PAYLOAD, MSG_ID> {
+    const VALID: () = assert!(
+        PAYLOAD <= Packet::<&[u8]>::MAX_PAYLOAD_SIZE
+            && MSG_ID >= 1
+            && MSG_ID <= Packet::<&[u8]>::MAX_MSG_ID_SIZE,
+        "Limits exceed the protocol's"
+    );
+
+    pub const MAX_PAYLOAD_SIZE: usize = {
+        let () = Self::VALID;
+        PAYLOAD
+    };
+
+    pub const MAX_MSG_ID_SIZE: usize = {
+        let () = Self::VALID;
+        MSG_ID
+    };
+
+    /// Largest packet, including an offset
+    pub const MAX_PACKET_SIZE: usize = Packet::<&[u8]>::BASE_PACKET_SIZE
+        + Packet::<&[u8]>::OFFSET_SIZE
+        + Self::MAX_MSG_ID_SIZE
+        + Self::MAX_PAYLOAD_SIZE;
+
+    /// Decoder storage for the largest message ID and payload
+    pub const DECODER_STORAGE_SIZE: usize = Self::MAX_MSG_ID_SIZE + Self::MAX_PAYLOAD_SIZE;
+
+    /// Largest encoded frame, including the delimiter
+    pub const MAX_FRAME_SIZE: usize = Framing::max_encoded_len(Self::MAX_PACKET_SIZE) + 1;
+
+    /// Checks that a packet's message ID and payload fit the limits
+    pub fn check<T: AsRef<[u8]>>(packet: &Packet<T>) -> Result<(), packet::Error> {
+        if packet.id_length()? > Self::MAX_MSG_ID_SIZE {
+            Err(packet::Error::InvalidMessageIdLength)
+        } else if usize::from(packet.data_length()) > Self::MAX_PAYLOAD_SIZE {
+            Err(packet::Error::InvalidDataLength)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that a decoded packet's message ID and payload fit the limits
+    pub fn check_view(packet: &PacketView<'_>) -> Result<(), packet::Error> {
+        if packet.id_length() > Self::MAX_MSG_ID_SIZE {
+            Err(packet::Error::InvalidMessageIdLength)
+        } else if packet.payload().len() > Self::MAX_PAYLOAD_SIZE {
+            Err(packet::Error::InvalidDataLength)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{Decoder, Error};
+    use pretty_assertions::assert_eq;
+
+    type App = Limits<4, 3>;
+
+    static MSG_F32: [u8; 14] = [
+        0x00, 0x0D, 0x04, 0x2c, 0x03, 0x61, 0x62, 0x63, 0x14, 0xAE, 0x29, 0x42, 0x8B, 0x1D,
+    ];
+
+    #[test]
+    fn app_limits() {
+        assert_eq!(App::MAX_PACKET_SIZE, 14);
+        assert_eq!(App::MAX_FRAME_SIZE, 17);
+        assert_eq!(App::DECODER_STORAGE_SIZE, 7);
+        assert_eq!(ProtocolLimits::MAX_MSG_ID_SIZE, 15);
+
+        let mut storage = [0_u8; App::DECODER_STORAGE_SIZE];
+        let mut dec = Decoder::new(&mut storage);
+        let p = dec.decode_slice(&MSG_F32).1.unwrap().unwrap();
+        assert_eq!(App::check_view(&p), Ok(()));
+        assert_eq!(
+            Limits::<2, 3>::check_view(&p),
+            Err(packet::Error::InvalidDataLength)
+        );
+        assert_eq!(
+            Limits::<4, 2>::check_view(&p),
+            Err(packet::Error::InvalidMessageIdLength)
+        );
+
+        // Rejected once the header shows it won't fit
+        let mut storage = [0_u8; Limits::<1, 3>::DECODER_STORAGE_SIZE];
+        let mut dec = Decoder::new(&mut storage);
+        let res: Result<(), Error> = MSG_F32[..5]
+            .iter()
+            .try_for_each(|b| dec.decode(*b).map(|_| ()));
+        assert_eq!(res, Err(Error::InsufficientBufferSize));
+    }
+}
@@ -1,8 +1,21 @@
-pub use framing::Framing;
-pub use packet::Packet;
+pub use checksum::{checksum_of_parts, CrcAccumulator};
+pub use framing::{Framer, Framing};
+pub use header::Header;
+pub use limits::Limits;
+pub use packet::{ChecksumPolicy, Packet, PacketBuilder};
+pub use validated::ValidatedPacket;
+pub use value::Value;
+pub use view::PacketView;
 
+pub mod checksum;
 pub mod framing;
+pub mod header;
+pub mod limits;
 pub mod packet;
+pub mod payload;
+pub mod validated;
+pub mod value;
+pub mod view;
 
 pub(crate) type Field = ::core::ops::Range<usize>;
 pub(crate) type Rest = ::core::ops::RangeFrom<usize>;
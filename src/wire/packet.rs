@@ -1,33 +1,126 @@
-use crate::message::{MessageId, MessageType};
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::checksum;
+use crate::wire::header::Header;
+use crate::wire::payload::{self, Codec, FromPayload, PayloadIter, ToPayload};
+use crate::wire::{ValidatedPacket, Value};
 use byteorder::{ByteOrder, LittleEndian};
 use core::fmt;
-use crc::{Algorithm, Crc};
-use err_derive::Error;
+use core::slice::ChunksExact;
+use crc::Algorithm;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Error {
-    #[error(display = "Not enough bytes for a valid header")]
     MissingHeader,
-
-    #[error(display = "Not enough bytes for a valid header and checksum")]
     MissingChecksum,
-
-    #[error(display = "Not enough bytes for a valid payload according to the data length")]
-    IncompletePayload,
-
-    #[error(display = "Invalid checksum")]
-    InvalidChecksum,
-
-    #[error(display = "Invalid message ID length")]
+    /// The buffer is `got` bytes long, the header needs `needed`
+    IncompletePayload {
+        needed: usize,
+        got: usize,
+    },
+    /// The packet's checksum is `expected`, its bytes checksum to `computed`
+    InvalidChecksum {
+        expected: u16,
+        computed: u16,
+    },
     InvalidMessageIdLength,
-
-    #[error(display = "Invalid message ID")]
     InvalidMessageId,
-
-    #[error(display = "Invalid data length")]
     InvalidDataLength,
+    /// Offset flag set on a type that doesn't carry data
+    InvalidOffset,
+    /// Internal flag set on a message ID that isn't an internal one
+    UnknownInternalId,
+    /// The payload isn't of the requested type
+    UnexpectedType(MessageType),
+    /// Element `index` of an array payload holding `len` elements
+    ElementOutOfRange {
+        index: usize,
+        len: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::MissingHeader => "Not enough bytes for a valid header",
+            Error::MissingChecksum => "Not enough bytes for a valid header and checksum",
+            Error::IncompletePayload { needed, got } => {
+                return write!(
+                    f,
+                    "Not enough bytes for a valid payload according to the data length, \
+                     needed {} got {}",
+                    needed, got
+                )
+            }
+            Error::InvalidChecksum { expected, computed } => {
+                return write!(
+                    f,
+                    "Invalid checksum, expected {:#06X} computed {:#06X}",
+                    expected, computed
+                )
+            }
+            Error::InvalidMessageIdLength => "Invalid message ID length",
+            Error::InvalidMessageId => "Invalid message ID",
+            Error::InvalidDataLength => "Invalid data length",
+            Error::InvalidOffset => "Offset flag set on a message type without data",
+            Error::UnknownInternalId => "Internal flag set on a non-internal message ID",
+            Error::UnexpectedType(t) => return write!(f, "Unexpected message type {}", t),
+            Error::ElementOutOfRange { index, len } => {
+                return write!(
+                    f,
+                    "Element {} out of range for a payload of {} elements",
+                    index, len
+                )
+            }
+        })
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl Error {
+    /// Stable code, see [`crate::error`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Error::MissingHeader => 0x10,
+            Error::MissingChecksum => 0x11,
+            Error::IncompletePayload { .. } => 0x12,
+            Error::InvalidChecksum { .. } => 0x13,
+            Error::InvalidMessageIdLength => 0x14,
+            Error::InvalidMessageId => 0x15,
+            Error::InvalidDataLength => 0x16,
+            Error::InvalidOffset => 0x17,
+            // 0x18 was the acknum check, see `check_flags`, and isn't reused
+            Error::UnknownInternalId => 0x19,
+            Error::UnexpectedType(_) => 0x1A,
+            Error::ElementOutOfRange { .. } => 0x1B,
+        }
+    }
+}
+
+/// Whether [`Packet::new_with`] verifies the checksum
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumPolicy {
+    /// Reject packets whose checksum doesn't match, as [`Packet::new`] does
+    #[default]
+    Verify,
+    /// Accept any checksum, for transports with their own integrity
+    /// checks such as USB, or for diagnostics that call
+    /// [`Packet::check_checksum`] afterwards to get the mismatch
+    Skip,
 }
 
+/// A packet over a buffer.
+///
+/// Accessors never panic, whatever the buffer's length. The fallible ones
+/// return an error when the buffer is too short for the field, header
+/// fields of a buffer shorter than the header read as zero and writes to
+/// them are dropped, see [`Packet::new`] for checking the buffer up front.
+///
+/// [`Packet::builder`] writes a whole packet in one go.
 #[derive(Debug, Clone)]
 pub struct Packet<T: AsRef<[u8]>> {
     buffer: T,
@@ -36,14 +129,8 @@ pub struct Packet<T: AsRef<[u8]>> {
 mod field {
     use crate::wire::{Field, Rest};
 
-    // Header field byte indices and ranges
+    // Header bytes, the fields are read and written through `Header`
     pub const DATA_LEN: Field = 0..2;
-    pub const TYPE: usize = 1;
-    pub const INTERNAL: usize = 1;
-    pub const OFFSET: usize = 1;
-    pub const ID_LEN: usize = 2;
-    pub const RESPONSE: usize = 2;
-    pub const ACKNUM: usize = 2;
 
     // Message ID bytes followed by maybe offset and maybe packet payload
     pub const REST: Rest = 3..;
@@ -54,7 +141,8 @@ impl<T: AsRef<[u8]>> Packet<T> {
     pub const HEADER_SIZE: usize = 3;
     pub const CHECKSUM_SIZE: usize = 2;
     pub const OFFSET_SIZE: usize = 2;
-    pub const MAX_PAYLOAD_SIZE: usize = 1024;
+    /// Largest data length the 10 bit field holds
+    pub const MAX_PAYLOAD_SIZE: usize = Header::MAX_DATA_LENGTH as usize;
     pub const MAX_MSG_ID_SIZE: usize = 15;
 
     pub const BASE_PACKET_SIZE: usize = Self::HEADER_SIZE + Self::CHECKSUM_SIZE;
@@ -62,28 +150,49 @@ impl<T: AsRef<[u8]>> Packet<T> {
     pub const MAX_PACKET_SIZE: usize =
         Self::BASE_PACKET_SIZE + Self::MAX_MSG_ID_SIZE + Self::MAX_PAYLOAD_SIZE;
 
-    pub const CRC16_CCITT_FALSE: Algorithm<u16> = Algorithm {
-        poly: 0x1021,
-        init: 0xFFFF,
-        refin: false,
-        refout: false,
-        xorout: 0,
-        check: 0x29B1,
-        residue: 0,
-    };
+    pub const CRC16_CCITT_FALSE: Algorithm<u16> = checksum::CRC16_CCITT_FALSE;
 
     pub fn new_unchecked(buffer: T) -> Packet<T> {
         Packet { buffer }
     }
 
     pub fn new(buffer: T) -> Result<Packet<T>, Error> {
+        Self::new_with(buffer, ChecksumPolicy::Verify)
+    }
+
+    /// Runs the checks of [`Packet::new`], the checksum only if `policy`
+    /// is [`ChecksumPolicy::Verify`]
+    ///
+    /// ```
+    /// use electricui_embedded::prelude::*;
+    /// use electricui_embedded::wire::packet::{ChecksumPolicy, Error};
+    ///
+    /// let mut buf = [0_u8; 16];
+    /// let size = Packet::query_into(&mut buf, MessageId::INTERNAL_BOARD_ID, MessageType::U16)
+    ///     .unwrap();
+    /// buf[size - 1] ^= 0xFF;
+    /// assert!(Packet::new(&buf[..size]).is_err());
+    ///
+    /// let p = Packet::new_with(&buf[..size], ChecksumPolicy::Skip).unwrap();
+    /// assert!(matches!(p.check_checksum(), Err(Error::InvalidChecksum { .. })));
+    /// ```
+    pub fn new_with(buffer: T, policy: ChecksumPolicy) -> Result<Packet<T>, Error> {
         let p = Self::new_unchecked(buffer);
         p.check_len()?;
         p.check_payload_length()?;
-        p.check_checksum()?;
+        if policy == ChecksumPolicy::Verify {
+            p.check_checksum()?;
+        }
+        p.check_header()?;
         Ok(p)
     }
 
+    /// Runs the checks of [`Packet::new`] and caches the field
+    /// positions, see [`ValidatedPacket`]
+    pub fn validate(self) -> Result<ValidatedPacket<T>, Error> {
+        ValidatedPacket::try_from(self)
+    }
+
     pub fn check_len(&self) -> Result<(), Error> {
         let len = self.buffer.as_ref().len();
         if len < field::REST.start {
@@ -101,28 +210,43 @@ impl<T: AsRef<[u8]>> Packet<T> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
         let len = self.buffer.as_ref().len();
-        if len < Self::buffer_len(id_len, data_len) {
-            Err(Error::IncompletePayload)
+        let needed = Self::buffer_len(id_len, data_len) + self.offset_size();
+        if len < needed {
+            Err(Error::IncompletePayload { needed, got: len })
         } else {
             Ok(())
         }
     }
 
     pub fn check_checksum(&self) -> Result<(), Error> {
-        let provided = self.checksum()?;
+        let expected = self.checksum()?;
         let computed = self.compute_checksum()?;
-        if computed != provided {
-            Err(Error::InvalidChecksum)
+        if computed != expected {
+            Err(Error::InvalidChecksum { expected, computed })
         } else {
             Ok(())
         }
     }
 
+    /// Checks that the header flags make sense together, see
+    /// [`check_flags`] and [`check_internal`]
+    pub fn check_header(&self) -> Result<(), Error> {
+        check_flags(self.typ(), self.offset())?;
+        check_internal(self.internal(), self.msg_id_raw()?)
+    }
+
+    /// Checks that the data length is a whole number of values of the
+    /// packet's type, see [`check_type_length`]. It's opt-in, not run by
+    /// [`Packet::new`].
+    pub fn check_type_length(&self) -> Result<(), Error> {
+        check_type_length(self.typ(), self.offset(), self.data_length())
+    }
+
     #[inline]
     pub fn wire_size(&self) -> Result<usize, Error> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        Ok(Self::buffer_len(id_len, data_len))
+        Ok(Self::buffer_len(id_len, data_len) + self.offset_size())
     }
 
     pub fn into_inner(self) -> T {
@@ -132,50 +256,70 @@ impl<T: AsRef<[u8]>> Packet<T> {
     /// Return the length of a buffer required to hold a message
     /// with a payload length of `n_msg_id_bytes` + `n_payload_bytes`.
     #[inline]
-    pub fn buffer_len(n_msg_id_bytes: usize, n_payload_bytes: usize) -> usize {
+    pub const fn buffer_len(n_msg_id_bytes: usize, n_payload_bytes: usize) -> usize {
         Self::BASE_PACKET_SIZE + n_msg_id_bytes + n_payload_bytes
     }
 
+    /// The header, bytes past the end of a buffer shorter than the
+    /// header read as zero
     #[inline]
-    pub fn data_length(&self) -> u16 {
+    pub fn header(&self) -> Header {
+        let mut bytes = [0_u8; Header::SIZE];
+        for (b, v) in bytes.iter_mut().zip(self.buffer.as_ref()) {
+            *b = *v;
+        }
+        Header::from_bytes(bytes)
+    }
+
+    /// Bytes `start..end`, `err` is given the bytes needed and the
+    /// buffer's length if it's too short
+    #[inline]
+    fn get(
+        &self,
+        start: usize,
+        end: usize,
+        err: fn(usize, usize) -> Error,
+    ) -> Result<&[u8], Error> {
         let data = self.buffer.as_ref();
-        LittleEndian::read_u16(&data[field::DATA_LEN]) & 0x3FF
+        data.get(start..end).ok_or_else(|| err(end, data.len()))
+    }
+
+    #[inline]
+    pub fn data_length(&self) -> u16 {
+        self.header().data_length
     }
 
     #[inline]
     pub fn typ_raw(&self) -> u8 {
-        let data = self.buffer.as_ref();
-        (data[field::TYPE] >> 2) & 0x0F
+        self.header().typ.into()
     }
 
     #[inline]
     pub fn typ(&self) -> MessageType {
-        let typ = self.typ_raw();
-        MessageType::from(typ)
+        self.header().typ
     }
 
     #[inline]
     pub fn internal(&self) -> bool {
-        let data = self.buffer.as_ref();
-        ((data[field::INTERNAL] >> 6) & 0x01) != 0
+        self.header().internal
     }
 
     #[inline]
     pub fn offset(&self) -> bool {
-        let data = self.buffer.as_ref();
-        ((data[field::OFFSET] >> 7) & 0x01) != 0
+        self.header().offset
     }
 
     #[inline]
     pub fn id_length_raw(&self) -> u8 {
-        let data = self.buffer.as_ref();
-        data[field::ID_LEN] & 0x0F
+        self.header().id_length
     }
 
     #[inline]
     pub fn id_length(&self) -> Result<usize, Error> {
         let id = self.id_length_raw();
-        if id == 0 {
+        if self.buffer.as_ref().len() < Self::HEADER_SIZE {
+            Err(Error::MissingHeader)
+        } else if id == 0 {
             Err(Error::InvalidMessageIdLength)
         } else {
             Ok(id.into())
@@ -184,36 +328,42 @@ impl<T: AsRef<[u8]>> Packet<T> {
 
     #[inline]
     pub fn response(&self) -> bool {
-        let data = self.buffer.as_ref();
-        ((data[field::RESPONSE] >> 4) & 0x01) != 0
+        self.header().response
     }
 
     #[inline]
-    pub fn acknum(&self) -> u8 {
-        let data = self.buffer.as_ref();
-        (data[field::ACKNUM] >> 5) & 0x07
+    pub fn acknum(&self) -> AckNum {
+        self.header().acknum
+    }
+
+    /// Number of offset address bytes following the message ID,
+    /// zero unless the offset flag is set
+    #[inline]
+    fn offset_size(&self) -> usize {
+        if self.offset() {
+            Self::OFFSET_SIZE
+        } else {
+            0
+        }
     }
 
     #[inline]
     pub fn checksum(&self) -> Result<u16, Error> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        let start = field::REST.start + id_len + data_len;
+        let start = field::REST.start + id_len + self.offset_size() + data_len;
         let end = start + Self::CHECKSUM_SIZE;
-        let data = self.buffer.as_ref();
-        debug_assert!(end <= data.len());
-        Ok(LittleEndian::read_u16(&data[start..end]))
+        let bytes = self.get(start, end, missing_checksum)?;
+        Ok(LittleEndian::read_u16(bytes))
     }
 
     #[inline]
     pub fn compute_checksum(&self) -> Result<u16, Error> {
-        let crc = Crc::<u16>::new(&Self::CRC16_CCITT_FALSE);
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        let end = Self::HEADER_SIZE + id_len + data_len;
-        let data = self.buffer.as_ref();
-        debug_assert!(end <= data.len());
-        Ok(crc.checksum(&data[..end]))
+        let end = Self::HEADER_SIZE + id_len + self.offset_size() + data_len;
+        let bytes = self.get(0, end, incomplete)?;
+        Ok(checksum::checksum(bytes))
     }
 }
 
@@ -222,127 +372,782 @@ impl<T: AsRef<[u8]>> Packet<T> {
     pub fn msg_id_raw(&self) -> Result<&[u8], Error> {
         let id_len = self.id_length()?;
         let end = field::REST.start + id_len;
-        let data = self.buffer.as_ref();
-        debug_assert!(end <= data.len());
-        Ok(&data[field::REST.start..end])
+        self.get(field::REST.start, end, incomplete)
     }
 
     #[inline]
     pub fn msg_id(&self) -> Result<MessageId<'_>, Error> {
         let msg_id = self.msg_id_raw()?;
-        MessageId::new(msg_id).ok_or(Error::InvalidMessageId)
+        MessageId::new_binary(msg_id).ok_or(Error::InvalidMessageId)
+    }
+
+    /// Returns the offset address of an offset packet,
+    /// or `None` when the offset flag is not set
+    #[inline]
+    pub fn offset_address(&self) -> Result<Option<u16>, Error> {
+        if !self.offset() {
+            return Ok(None);
+        }
+        let id_len = self.id_length()?;
+        let start = field::REST.start + id_len;
+        let end = start + Self::OFFSET_SIZE;
+        let bytes = self.get(start, end, incomplete)?;
+        Ok(Some(LittleEndian::read_u16(bytes)))
     }
 
     #[inline]
     pub fn payload(&self) -> Result<&[u8], Error> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        let start = field::REST.start + id_len;
+        let start = field::REST.start + id_len + self.offset_size();
         let end = start + data_len;
-        let data = self.buffer.as_ref();
-        debug_assert!(end <= data.len());
-        Ok(&data[start..end])
+        self.get(start, end, incomplete)
+    }
+
+    /// Decodes the payload as a single little-endian `V`, the packet
+    /// must be of type `V::TYPE` and carry exactly one value
+    pub fn payload_as<V: FromPayload>(&self) -> Result<V, Error> {
+        payload::decode(self.typ(), self.payload()?)
+    }
+
+    /// Decodes the payload with `C`'s [`Codec`], the packet must be of
+    /// type `C::WIRE_TYPE`
+    pub fn decode_payload<C: Codec>(&self) -> Result<C, Error> {
+        payload::decode_with(self.typ(), self.payload()?)
+    }
+
+    /// Iterates the payload as an array of little-endian `V`, the packet
+    /// must be of type `V::TYPE` and carry a whole number of values
+    pub fn payload_iter<V: FromPayload>(&self) -> Result<PayloadIter<'_, V>, Error> {
+        payload::iter(self.typ(), self.payload()?)
+    }
+
+    /// The payload as a [`Value`] of the packet's type
+    pub fn value(&self) -> Result<Value<'_>, Error> {
+        Value::parse(self.typ(), self.payload()?)
+    }
+
+    /// Splits the payload into its elements, the packet must be of type
+    /// `typ`, a fixed size type, and carry a whole number of values
+    pub fn payload_elements(&self, typ: MessageType) -> Result<ChunksExact<'_, u8>, Error> {
+        payload::elements(self.typ(), typ, self.payload()?)
+    }
+
+    /// Decodes element `index` of an array payload of little-endian `V`
+    pub fn payload_element<V: FromPayload>(&self, index: usize) -> Result<V, Error> {
+        payload::element(self.typ(), self.payload()?, index)
+    }
+
+    pub fn payload_u8(&self) -> Result<u8, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_i8(&self) -> Result<i8, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_u16(&self) -> Result<u16, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_i16(&self) -> Result<i16, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_u32(&self) -> Result<u32, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_i32(&self) -> Result<i32, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_f32(&self) -> Result<f32, Error> {
+        self.payload_as()
+    }
+
+    pub fn payload_f64(&self) -> Result<f64, Error> {
+        self.payload_as()
+    }
+
+    /// Writes the reply to this request into `buf`, returning its size.
+    ///
+    /// The reply has the request's message ID, type, internal flag and
+    /// acknum, with the response flag cleared, `payload` and a checksum.
+    ///
+    /// ```
+    /// use electricui_embedded::prelude::*;
+    ///
+    /// let mut req = [0_u8; 16];
+    /// let size = Packet::builder(MessageId::new(b"speed").unwrap(), MessageType::U16)
+    ///     .response(true)
+    ///     .build_into(&mut req)
+    ///     .unwrap();
+    /// let req = Packet::new(&req[..size]).unwrap();
+    ///
+    /// let mut buf = [0_u8; 16];
+    /// let size = req.make_response_into(&mut buf, &[0x2A, 0x00]).unwrap();
+    /// let reply = Packet::new(&buf[..size]).unwrap();
+    /// assert!(!reply.response());
+    /// assert_eq!(reply.payload_u16(), Ok(42));
+    /// ```
+    pub fn make_response_into(&self, buf: &mut [u8], payload: &[u8]) -> Result<usize, Error> {
+        PacketBuilder::new(self.msg_id()?, self.typ())
+            .internal(self.internal())
+            .acknum(self.acknum())
+            .payload(payload)
+            .build_into(buf)
+    }
+
+    /// Writes the ack for this packet into `buf`, a reply without a
+    /// payload, returning its size or `None` if no ack was requested.
+    ///
+    /// Only response flagged packets request an ack, a reply carrying
+    /// an acknum is itself an ack.
+    pub fn make_ack_into(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        if !self.response() || !self.acknum().is_requested() {
+            return Ok(None);
+        }
+        self.make_response_into(buf, &[]).map(Some)
     }
 }
 
 impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
+    /// Writes the header, bytes past the end of a buffer shorter than
+    /// the header are dropped
+    #[inline]
+    pub fn set_header(&mut self, header: &Header) {
+        for (b, v) in self.buffer.as_mut().iter_mut().zip(header.to_bytes()) {
+            *b = v;
+        }
+    }
+
+    #[inline]
+    fn update_header(&mut self, f: impl FnOnce(&mut Header)) {
+        let mut header = self.header();
+        f(&mut header);
+        self.set_header(&header);
+    }
+
+    #[inline]
+    fn get_mut(
+        &mut self,
+        start: usize,
+        end: usize,
+        err: fn(usize, usize) -> Error,
+    ) -> Result<&mut [u8], Error> {
+        let data = self.buffer.as_mut();
+        let got = data.len();
+        data.get_mut(start..end).ok_or_else(|| err(end, got))
+    }
+
     #[inline]
     pub fn set_data_length(&mut self, value: u16) -> Result<(), Error> {
         if usize::from(value) > Self::MAX_PAYLOAD_SIZE {
             Err(Error::InvalidDataLength)
+        } else if self.buffer.as_ref().len() < field::DATA_LEN.end {
+            Err(Error::MissingHeader)
         } else {
-            let data = self.buffer.as_mut();
-            LittleEndian::write_u16(&mut data[field::DATA_LEN], value & 0x3FF);
+            self.update_header(|h| h.data_length = value);
             Ok(())
         }
     }
 
     #[inline]
     pub fn set_typ(&mut self, value: MessageType) {
-        let data = self.buffer.as_mut();
-        data[field::TYPE] = (data[field::TYPE] & !0x3C) | (u8::from(value) << 2);
+        self.update_header(|h| h.typ = value);
     }
 
     #[inline]
     pub fn set_internal(&mut self, value: bool) {
-        let data = self.buffer.as_mut();
-        if value {
-            data[field::INTERNAL] |= 1 << 6;
-        } else {
-            data[field::INTERNAL] &= !(1 << 6);
-        }
+        self.update_header(|h| h.internal = value);
     }
 
     #[inline]
     pub fn set_offset(&mut self, value: bool) {
-        let data = self.buffer.as_mut();
-        if value {
-            data[field::OFFSET] |= 1 << 7;
-        } else {
-            data[field::OFFSET] &= !(1 << 7);
-        }
+        self.update_header(|h| h.offset = value);
     }
 
     #[inline]
     pub fn set_id_length(&mut self, value: u8) -> Result<(), Error> {
         if value == 0 || usize::from(value) > Self::MAX_MSG_ID_SIZE {
             Err(Error::InvalidMessageIdLength)
+        } else if self.buffer.as_ref().len() < Self::HEADER_SIZE {
+            Err(Error::MissingHeader)
         } else {
-            let data = self.buffer.as_mut();
-            data[field::ID_LEN] = (data[field::ID_LEN] & !0x0F) | (value & 0x0F);
+            self.update_header(|h| h.id_length = value);
             Ok(())
         }
     }
 
     #[inline]
     pub fn set_response(&mut self, value: bool) {
-        let data = self.buffer.as_mut();
-        if value {
-            data[field::RESPONSE] |= 1 << 4;
-        } else {
-            data[field::RESPONSE] &= !(1 << 4);
-        }
+        self.update_header(|h| h.response = value);
     }
 
     #[inline]
-    pub fn set_acknum(&mut self, value: u8) {
-        let data = self.buffer.as_mut();
-        data[field::ACKNUM] = (data[field::ACKNUM] & !0xE0) | ((value & 0x07) << 5);
+    pub fn set_acknum(&mut self, value: AckNum) {
+        self.update_header(|h| h.acknum = value);
+    }
+
+    /// Writes the ID length field and the message ID bytes, nothing is
+    /// written if the buffer can't hold the ID.
+    ///
+    /// The offset address and payload follow the ID, so set it first.
+    pub fn set_msg_id(&mut self, id: MessageId<'_>) -> Result<(), Error> {
+        let end = field::REST.start + id.len();
+        let got = self.buffer.as_ref().len();
+        if got < field::REST.start {
+            return Err(Error::MissingHeader);
+        } else if got < end {
+            return Err(Error::IncompletePayload { needed: end, got });
+        }
+        self.set_id_length(id.len() as u8)?;
+        self.msg_id_mut()?.copy_from_slice(id.as_bytes());
+        Ok(())
+    }
+
+    /// Sets the offset flag and writes the offset address, nothing is
+    /// written if the buffer can't hold the address.
+    ///
+    /// The address follows the message ID and the payload follows the
+    /// address, so set it after the ID and before the payload.
+    pub fn set_offset_address(&mut self, address: u16) -> Result<(), Error> {
+        let start = field::REST.start + self.id_length()?;
+        let end = start + Self::OFFSET_SIZE;
+        let got = self.buffer.as_ref().len();
+        if got < end {
+            return Err(Error::IncompletePayload { needed: end, got });
+        }
+        self.set_offset(true);
+        LittleEndian::write_u16(&mut self.buffer.as_mut()[start..end], address);
+        Ok(())
     }
 
     #[inline]
     pub fn msg_id_mut(&mut self) -> Result<&mut [u8], Error> {
         let id_len = self.id_length()?;
         let end = field::REST.start + id_len;
-        let data = self.buffer.as_mut();
-        debug_assert!(end <= data.len());
-        Ok(&mut data[field::REST.start..end])
+        self.get_mut(field::REST.start, end, incomplete)
+    }
+
+    /// Writes the data length field and the payload bytes, nothing is
+    /// written if the payload is too large or the buffer can't hold it.
+    ///
+    /// The payload follows the message ID and offset address, so set
+    /// the ID and offset flag first.
+    pub fn set_payload(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() > Self::MAX_PAYLOAD_SIZE {
+            return Err(Error::InvalidDataLength);
+        }
+        let start = field::REST.start + self.id_length()? + self.offset_size();
+        let needed = start + bytes.len();
+        let got = self.buffer.as_ref().len();
+        if got < needed {
+            return Err(Error::IncompletePayload { needed, got });
+        }
+        self.set_data_length(bytes.len() as u16)?;
+        self.payload_mut()?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Writes `values` little-endian as the payload, setting the type to
+    /// `V::TYPE` and the data length to match, returns the number of
+    /// values written.
+    ///
+    /// Like [`Packet::set_payload`] the message ID and offset flag must
+    /// be set first. The header is left unchanged if the values don't
+    /// fit, though the payload bytes written so far are not restored.
+    pub fn write_payload_from<V, I>(&mut self, values: I) -> Result<usize, Error>
+    where
+        V: ToPayload,
+        I: IntoIterator<Item = V>,
+    {
+        let start = field::REST.start + self.id_length()? + self.offset_size();
+        let size = V::TYPE.wire_size_hint();
+        let buf = self.buffer.as_mut();
+        let mut end = start;
+        let mut count = 0;
+        for value in values {
+            let next = end + size;
+            if next - start > Self::MAX_PAYLOAD_SIZE {
+                return Err(Error::InvalidDataLength);
+            }
+            let got = buf.len();
+            if got < next {
+                return Err(Error::IncompletePayload { needed: next, got });
+            }
+            value.write_le_bytes(&mut buf[end..next]);
+            end = next;
+            count += 1;
+        }
+        self.set_typ(V::TYPE);
+        self.set_data_length((end - start) as u16)?;
+        Ok(count)
+    }
+
+    /// Encodes `value` as the payload, setting the type to
+    /// `C::WIRE_TYPE` and the data length to match, returns the payload
+    /// size.
+    ///
+    /// Like [`Packet::set_payload`] the message ID and offset flag must
+    /// be set first. The header is left unchanged if encoding fails, or
+    /// reports more bytes than it had room for.
+    pub fn encode_payload<C: Codec>(&mut self, value: &C) -> Result<usize, Error> {
+        let start = field::REST.start + self.id_length()? + self.offset_size();
+        let buf = self.buffer.as_mut();
+        let got = buf.len();
+        if got < start {
+            return Err(Error::IncompletePayload { needed: start, got });
+        }
+        let end = got.min(start + Self::MAX_PAYLOAD_SIZE);
+        let size = value.encode(&mut buf[start..end])?;
+        if size > end - start {
+            return Err(Error::InvalidDataLength);
+        }
+        self.set_typ(C::WIRE_TYPE);
+        self.set_data_length(size as u16)?;
+        Ok(size)
+    }
+
+    /// Overwrites element `index` of an array payload of little-endian
+    /// `V`, the type and data length are left unchanged
+    pub fn set_payload_element<V: ToPayload>(
+        &mut self,
+        index: usize,
+        value: V,
+    ) -> Result<(), Error> {
+        let typ = self.typ();
+        let payload = self.payload_mut()?;
+        let range = payload::element_range::<V>(typ, payload.len(), index)?;
+        value.write_le_bytes(&mut payload[range]);
+        Ok(())
     }
 
     #[inline]
     pub fn payload_mut(&mut self) -> Result<&mut [u8], Error> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        let start = field::REST.start + id_len;
+        let start = field::REST.start + id_len + self.offset_size();
         let end = start + data_len;
-        let data = self.buffer.as_mut();
-        debug_assert!(end <= data.len());
-        Ok(&mut data[start..end])
+        self.get_mut(start, end, incomplete)
     }
 
     #[inline]
     pub fn set_checksum(&mut self, value: u16) -> Result<(), Error> {
         let id_len = self.id_length()?;
         let data_len = usize::from(self.data_length());
-        let start = field::REST.start + id_len + data_len;
+        let start = field::REST.start + id_len + self.offset_size() + data_len;
         let end = start + Self::CHECKSUM_SIZE;
-        let data = self.buffer.as_mut();
-        debug_assert!(end <= data.len());
-        LittleEndian::write_u16(&mut data[start..end], value);
+        let bytes = self.get_mut(start, end, missing_checksum)?;
+        LittleEndian::write_u16(bytes, value);
+        Ok(())
+    }
+}
+
+fn incomplete(needed: usize, got: usize) -> Error {
+    Error::IncompletePayload { needed, got }
+}
+
+fn missing_checksum(_needed: usize, _got: usize) -> Error {
+    Error::MissingChecksum
+}
+
+/// Checks the header flags against the type: only data carrying types
+/// have an offset.
+///
+/// The response flag and acknum aren't checked, every combination is
+/// valid on the wire. Response flagged packets with an acknum are acked
+/// writes, or queries, which can't be told apart when the write is an
+/// empty string or array. Packets without the flag carrying an acknum
+/// are acks and replies to acked queries, with or without a payload.
+pub const fn check_flags(typ: MessageType, offset: bool) -> Result<(), Error> {
+    if offset && !typ.supports_offset() {
+        Err(Error::InvalidOffset)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that the payload of a fixed size type is a whole number of
+/// values, other types may carry any number of bytes.
+/// Offset packets carry a chunk of a variable that may split a value,
+/// so they aren't checked.
+pub fn check_type_length(typ: MessageType, offset: bool, data_len: u16) -> Result<(), Error> {
+    let size = typ.wire_size_hint();
+    if !offset && size != 0 && !usize::from(data_len).is_multiple_of(size) {
+        Err(Error::InvalidDataLength)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that an internal flagged packet has one of the
+/// [`MessageId::INTERNAL`] ID's
+pub const fn check_internal(internal: bool, msg_id: &[u8]) -> Result<(), Error> {
+    if internal && !MessageId::from_bytes_unchecked(msg_id).is_internal() {
+        Err(Error::UnknownInternalId)
+    } else {
         Ok(())
     }
 }
 
+/// A high-level representation of a packet, its header fields, message
+/// ID and payload as a plain value
+///
+/// ```
+/// use electricui_embedded::prelude::*;
+/// use electricui_embedded::wire::packet::Repr;
+///
+/// let repr = Repr::new(MessageId::new(b"speed").unwrap(), MessageType::U16, &[0x2A, 0x00]);
+/// let mut buf = [0_u8; 32];
+/// let mut p = Packet::new_unchecked(&mut buf[..repr.buffer_len()]);
+/// repr.emit(&mut p).unwrap();
+///
+/// let p = Packet::new(&buf[..repr.buffer_len()]).unwrap();
+/// assert_eq!(Repr::parse(&p), Ok(repr));
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Repr<'a> {
+    pub typ: MessageType,
+    pub internal: bool,
+    pub response: bool,
+    pub acknum: AckNum,
+    /// Offset address of an offset packet
+    pub offset_address: Option<u16>,
+    pub msg_id: MessageId<'a>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Repr<'a> {
+    /// A plain, non-internal, non-offset packet with no flags set
+    pub const fn new(msg_id: MessageId<'a>, typ: MessageType, payload: &'a [u8]) -> Self {
+        Repr {
+            typ,
+            internal: false,
+            response: false,
+            acknum: AckNum::NONE,
+            offset_address: None,
+            msg_id,
+            payload,
+        }
+    }
+
+    /// Parses a packet, running the checks of [`Packet::new`]
+    pub fn parse<T: AsRef<[u8]> + ?Sized>(packet: &Packet<&'a T>) -> Result<Self, Error> {
+        packet.check_len()?;
+        packet.check_payload_length()?;
+        packet.check_checksum()?;
+        packet.check_header()?;
+        let data: &'a [u8] = packet.buffer.as_ref();
+        let id_end = field::REST.start + packet.id_length()?;
+        let msg_id = MessageId::new_binary(&data[field::REST.start..id_end])
+            .ok_or(Error::InvalidMessageId)?;
+        let start = id_end + packet.offset_size();
+        let end = start + usize::from(packet.data_length());
+        Ok(Repr {
+            typ: packet.typ(),
+            internal: packet.internal(),
+            response: packet.response(),
+            acknum: packet.acknum(),
+            offset_address: packet.offset_address()?,
+            msg_id,
+            payload: &data[start..end],
+        })
+    }
+
+    /// Size of the packet [`Repr::emit`] writes
+    pub const fn buffer_len(&self) -> usize {
+        let offset_size = match self.offset_address {
+            Some(_) => Packet::<&[u8]>::OFFSET_SIZE,
+            None => 0,
+        };
+        Packet::<&[u8]>::buffer_len(self.msg_id.len(), self.payload.len()) + offset_size
+    }
+
+    /// Writes the packet, including its checksum, into the start of
+    /// `packet`, which must hold [`Repr::buffer_len`] bytes.
+    ///
+    /// Nothing is written if the payload is too large or the fields fail
+    /// [`check_flags`] or [`check_internal`].
+    pub fn emit<T>(&self, packet: &mut Packet<T>) -> Result<(), Error>
+    where
+        T: AsRef<[u8]> + AsMut<[u8]>,
+    {
+        if self.payload.len() > Packet::<&[u8]>::MAX_PAYLOAD_SIZE {
+            return Err(Error::InvalidDataLength);
+        }
+        let data_len = self.payload.len() as u16;
+        check_flags(self.typ, self.offset_address.is_some())?;
+        check_internal(self.internal, self.msg_id.as_bytes())?;
+        let needed = self.buffer_len();
+        let got = packet.buffer.as_ref().len();
+        if got < needed {
+            return Err(Error::IncompletePayload { needed, got });
+        }
+
+        packet.set_data_length(data_len)?;
+        packet.set_typ(self.typ);
+        packet.set_internal(self.internal);
+        packet.set_offset(false);
+        packet.set_msg_id(self.msg_id)?;
+        packet.set_response(self.response);
+        packet.set_acknum(self.acknum);
+        if let Some(address) = self.offset_address {
+            packet.set_offset_address(address)?;
+        }
+        packet.set_payload(self.payload)?;
+        let checksum = packet.compute_checksum()?;
+        packet.set_checksum(checksum)
+    }
+}
+
+impl<'a> Packet<&'a [u8]> {
+    /// Starts building a packet, see [`PacketBuilder`]
+    pub const fn builder(msg_id: MessageId<'a>, typ: MessageType) -> PacketBuilder<'a> {
+        PacketBuilder::new(msg_id, typ)
+    }
+
+    /// Writes a query for `msg_id` into `buf`, returning its size.
+    ///
+    /// The query has the response flag set and no payload, `typ` is the
+    /// type the reply is expected to have, and it's an internal packet
+    /// for internal message IDs.
+    ///
+    /// ```
+    /// use electricui_embedded::prelude::*;
+    ///
+    /// let mut buf = [0_u8; 8];
+    /// let size = Packet::query_into(&mut buf, MessageId::INTERNAL_BOARD_ID, MessageType::U16)
+    ///     .unwrap();
+    /// let p = Packet::new(&buf[..size]).unwrap();
+    /// assert!(p.internal() && p.response());
+    /// assert_eq!(p.data_length(), 0);
+    /// ```
+    pub fn query_into(
+        buf: &mut [u8],
+        msg_id: MessageId<'_>,
+        typ: MessageType,
+    ) -> Result<usize, Error> {
+        PacketBuilder::new(msg_id, typ)
+            .internal(msg_id.is_internal())
+            .response(true)
+            .build_into(buf)
+    }
+}
+
+/// Writes a whole packet, header fields, message ID, payload and
+/// checksum, into a buffer
+///
+/// ```
+/// use electricui_embedded::prelude::*;
+///
+/// let mut buf = [0_u8; 32];
+/// let size = Packet::builder(MessageId::new(b"speed").unwrap(), MessageType::U16)
+///     .payload(&[0x2A, 0x00])
+///     .acknum(AckNum::MIN)
+///     .build_into(&mut buf)
+///     .unwrap();
+/// let p = Packet::new(&buf[..size]).unwrap();
+/// assert_eq!(p.payload(), Ok(&[0x2A, 0x00][..]));
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketBuilder<'a> {
+    repr: Repr<'a>,
+}
+
+impl<'a> PacketBuilder<'a> {
+    /// A packet with no payload and no flags set
+    pub const fn new(msg_id: MessageId<'a>, typ: MessageType) -> Self {
+        PacketBuilder {
+            repr: Repr::new(msg_id, typ, &[]),
+        }
+    }
+
+    pub const fn payload(mut self, payload: &'a [u8]) -> Self {
+        self.repr.payload = payload;
+        self
+    }
+
+    pub const fn internal(mut self, internal: bool) -> Self {
+        self.repr.internal = internal;
+        self
+    }
+
+    pub const fn response(mut self, response: bool) -> Self {
+        self.repr.response = response;
+        self
+    }
+
+    pub const fn acknum(mut self, acknum: AckNum) -> Self {
+        self.repr.acknum = acknum;
+        self
+    }
+
+    /// Makes it an offset packet, the payload starts at `address`
+    pub const fn offset_address(mut self, address: u16) -> Self {
+        self.repr.offset_address = Some(address);
+        self
+    }
+
+    /// Size of the packet [`PacketBuilder::build_into`] writes
+    pub const fn buffer_len(&self) -> usize {
+        self.repr.buffer_len()
+    }
+
+    /// Returns the packet as an array at compile time, for packets baked
+    /// into flash, see [`const_packet!`](crate::const_packet).
+    ///
+    /// Fails to compile, or panics outside of a const context, if `N`
+    /// isn't [`PacketBuilder::buffer_len`] or the fields fail the checks
+    /// of [`Repr::emit`].
+    pub const fn to_bytes<const N: usize>(&self) -> [u8; N] {
+        let r = &self.repr;
+        assert!(N == r.buffer_len(), "Array size isn't the packet size");
+        assert!(
+            r.payload.len() <= Packet::<&[u8]>::MAX_PAYLOAD_SIZE,
+            "Payload too large"
+        );
+        let data_len = r.payload.len() as u16;
+        let offset = r.offset_address.is_some();
+        assert!(
+            check_flags(r.typ, offset).is_ok(),
+            "Inconsistent header flags"
+        );
+        assert!(
+            check_internal(r.internal, r.msg_id.as_bytes()).is_ok(),
+            "Internal flag set on a non-internal message ID"
+        );
+
+        let mut buf = [0_u8; N];
+        let header = Header {
+            data_length: data_len,
+            typ: r.typ,
+            internal: r.internal,
+            offset,
+            id_length: r.msg_id.len() as u8,
+            response: r.response,
+            acknum: r.acknum,
+        }
+        .to_bytes();
+        let mut pos = 0;
+        while pos < Header::SIZE {
+            buf[pos] = header[pos];
+            pos += 1;
+        }
+        let id = r.msg_id.as_bytes();
+        let mut i = 0;
+        while i < id.len() {
+            buf[pos] = id[i];
+            pos += 1;
+            i += 1;
+        }
+        if let Some(address) = r.offset_address {
+            let [lo, hi] = address.to_le_bytes();
+            buf[pos] = lo;
+            buf[pos + 1] = hi;
+            pos += 2;
+        }
+        let mut i = 0;
+        while i < r.payload.len() {
+            buf[pos] = r.payload[i];
+            pos += 1;
+            i += 1;
+        }
+        let [lo, hi] = checksum::const_checksum(buf.split_at(pos).0).to_le_bytes();
+        buf[pos] = lo;
+        buf[pos + 1] = hi;
+        buf
+    }
+
+    /// Writes the packet into the start of `buf`, returning its size,
+    /// see [`Repr::emit`] for the errors
+    pub fn build_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let size = self.repr.buffer_len();
+        let got = buf.len();
+        let buf = buf
+            .get_mut(..size)
+            .ok_or(Error::IncompletePayload { needed: size, got })?;
+        self.repr.emit(&mut Packet::new_unchecked(buf))?;
+        Ok(size)
+    }
+}
+
+impl<'a> From<Repr<'a>> for PacketBuilder<'a> {
+    fn from(repr: Repr<'a>) -> Self {
+        PacketBuilder { repr }
+    }
+}
+
+/// Defines a `const` array holding a packet built at compile time from a
+/// [`PacketBuilder`], so static packets live in flash rather than being
+/// assembled at runtime
+///
+/// ```
+/// use electricui_embedded::const_packet;
+/// use electricui_embedded::prelude::*;
+///
+/// const_packet!(
+///     /// Queries the heartbeat
+///     HEARTBEAT_QUERY = Packet::builder(MessageId::INTERNAL_HEARTBEAT, MessageType::U8)
+///         .internal(true)
+///         .response(true)
+/// );
+///
+/// let p = Packet::new(&HEARTBEAT_QUERY[..]).unwrap();
+/// assert!(p.internal() && p.response());
+/// assert_eq!(p.msg_id(), Ok(MessageId::INTERNAL_HEARTBEAT));
+/// ```
+///
+/// Invalid packets fail to compile
+///
+/// ```compile_fail
+/// use electricui_embedded::const_packet;
+/// use electricui_embedded::prelude::*;
+///
+/// const_packet!(NOT_INTERNAL = Packet::builder(MessageId::BOARD_NAME, MessageType::Callback)
+///     .internal(true));
+/// ```
+#[macro_export]
+macro_rules! const_packet {
+    ($(#[$attr:meta])* $vis:vis $name:ident = $builder:expr) => {
+        $(#[$attr])*
+        $vis const $name: [u8; $crate::wire::PacketBuilder::buffer_len(&$builder)] =
+            $crate::wire::PacketBuilder::to_bytes(&$builder);
+    };
+}
+
+/// Writes a non-offset packet into `buffer`, which must be
+/// [`Packet::buffer_len`] bytes long
+#[cfg(any(
+    test,
+    feature = "alloc",
+    feature = "heapless",
+    feature = "test-support"
+))]
+pub(crate) fn emit(
+    buffer: &mut [u8],
+    id: MessageId<'_>,
+    typ: MessageType,
+    internal: bool,
+    response: bool,
+    acknum: AckNum,
+    payload: &[u8],
+) -> Result<(), Error> {
+    let repr = Repr {
+        typ,
+        internal,
+        response,
+        acknum,
+        offset_address: None,
+        msg_id: id,
+        payload,
+    };
+    repr.emit(&mut Packet::new_unchecked(buffer))
+}
+
 impl<T: AsRef<[u8]>> AsRef<[u8]> for Packet<T> {
     fn as_ref(&self) -> &[u8] {
         self.buffer.as_ref()
@@ -365,8 +1170,138 @@ impl<T: AsRef<[u8]>> fmt::Display for Packet<T> {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl<T: AsRef<[u8]>> defmt::Format for Packet<T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{{ DataLen({}), Type({}), Int({}), Offset({}), IdLen({}), Resp({}), Acknum({}) }}",
+            self.data_length(),
+            self.typ_raw(),
+            self.internal() as u8,
+            self.offset() as u8,
+            self.id_length_raw(),
+            self.response() as u8,
+            self.acknum()
+        )
+    }
+}
+
+impl<T: AsRef<[u8]>> Packet<T> {
+    /// Formats the decoded fields, payload and checksum on one line,
+    /// for debugging, see [`Dump`]
+    pub fn dump(&self) -> Dump<'_, T> {
+        Dump { packet: self }
+    }
+}
+
+/// A [`Packet`] formatted field by field, from [`Packet::dump`].
+///
+/// Shows the message ID, type, the flags that are set, the payload in hex
+/// and whether the checksum matches. Fields the buffer is too short for
+/// show the error instead.
+///
+/// ```
+/// use electricui_embedded::prelude::*;
+///
+/// let mut buf = [0_u8; 16];
+/// let size = Packet::builder(MessageId::new(b"abc").unwrap(), MessageType::I8)
+///     .payload(&[0x2A])
+///     .acknum(AckNum::new(3).unwrap())
+///     .build_into(&mut buf)
+///     .unwrap();
+/// let p = Packet::new(&buf[..size]).unwrap();
+/// assert_eq!(
+///     p.dump().to_string(),
+///     "abc I8 ack=3 payload[1]=2A checksum=0xA3B8 ok"
+/// );
+/// ```
+pub struct Dump<'a, T: AsRef<[u8]>> {
+    packet: &'a Packet<T>,
+}
+
+impl<T: AsRef<[u8]>> fmt::Display for Dump<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p = self.packet;
+        match p.msg_id() {
+            Ok(id) => write!(f, "{} {}", id, p.typ())?,
+            Err(e) => write!(f, "<{}> {}", e, p.typ())?,
+        }
+        if p.internal() {
+            f.write_str(" internal")?;
+        }
+        if p.response() {
+            f.write_str(" response")?;
+        }
+        if p.acknum().is_requested() {
+            write!(f, " ack={}", p.acknum())?;
+        }
+        match p.offset_address() {
+            Ok(Some(address)) => write!(f, " offset={:#06X}", address)?,
+            Ok(None) => (),
+            Err(e) => write!(f, " offset=<{}>", e)?,
+        }
+        match p.payload() {
+            Ok(payload) => {
+                write!(f, " payload[{}]=", payload.len())?;
+                for b in payload {
+                    write!(f, "{:02X}", b)?;
+                }
+            }
+            Err(e) => write!(f, " payload=<{}>", e)?,
+        }
+        match (p.checksum(), p.compute_checksum()) {
+            (Ok(c), Ok(computed)) if c == computed => write!(f, " checksum={:#06X} ok", c),
+            (Ok(c), Ok(computed)) => {
+                write!(f, " checksum={:#06X} computed={:#06X}", c, computed)
+            }
+            (Err(e), _) | (_, Err(e)) => write!(f, " checksum=<{}>", e),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: AsRef<[u8]>> defmt::Format for Dump<'_, T> {
+    fn format(&self, f: defmt::Formatter) {
+        let p = self.packet;
+        match p.msg_id() {
+            Ok(id) => defmt::write!(f, "{} {}", id, p.typ()),
+            Err(e) => defmt::write!(f, "<{}> {}", e, p.typ()),
+        }
+        if p.internal() {
+            defmt::write!(f, " internal");
+        }
+        if p.response() {
+            defmt::write!(f, " response");
+        }
+        if p.acknum().is_requested() {
+            defmt::write!(f, " ack={}", p.acknum());
+        }
+        match p.offset_address() {
+            Ok(Some(address)) => defmt::write!(f, " offset={=u16:#06X}", address),
+            Ok(None) => (),
+            Err(e) => defmt::write!(f, " offset=<{}>", e),
+        }
+        match p.payload() {
+            Ok(payload) => defmt::write!(f, " payload[{}]={=[u8]:02X}", payload.len(), payload),
+            Err(e) => defmt::write!(f, " payload=<{}>", e),
+        }
+        match (p.checksum(), p.compute_checksum()) {
+            (Ok(c), Ok(computed)) if c == computed => {
+                defmt::write!(f, " checksum={=u16:#06X} ok", c)
+            }
+            (Ok(c), Ok(computed)) => {
+                defmt::write!(f, " checksum={=u16:#06X} computed={=u16:#06X}", c, computed)
+            }
+            (Err(e), _) | (_, Err(e)) => defmt::write!(f, " checksum=<{}>", e),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    extern crate std;
+
     use super::*;
     use crate::wire::framing::Framing;
     use approx::assert_relative_eq;
@@ -401,7 +1336,7 @@ mod tests {
         p.set_offset(false);
         p.set_id_length(3).unwrap();
         p.set_response(false);
-        p.set_acknum(3);
+        p.set_acknum(AckNum::new(3).unwrap());
         p.msg_id_mut().unwrap().copy_from_slice(b"abc");
         p.payload_mut().unwrap()[0] = 0x2A;
         p.set_checksum(0xA3B8).unwrap();
@@ -431,7 +1366,7 @@ mod tests {
         assert_eq!(p.offset(), false);
         assert_eq!(p.id_length().unwrap(), 3);
         assert_eq!(p.response(), false);
-        assert_eq!(p.acknum(), 3);
+        assert_eq!(p.acknum().get(), 3);
         assert_eq!(p.msg_id().unwrap(), b"abc");
         assert_eq!(p.payload().unwrap(), &[0x2A]);
         assert_eq!(p.checksum().unwrap(), 0xA3B8);
@@ -450,7 +1385,7 @@ mod tests {
         p.set_offset(false);
         p.set_id_length(3).unwrap();
         p.set_response(false);
-        p.set_acknum(0);
+        p.set_acknum(AckNum::NONE);
         p.msg_id_mut().unwrap().copy_from_slice(b"abc");
         LittleEndian::write_f32(p.payload_mut().unwrap(), 42.42_f32);
         p.set_checksum(0x1D8B).unwrap();
@@ -480,10 +1415,14 @@ mod tests {
         assert_eq!(p.offset(), false);
         assert_eq!(p.id_length().unwrap(), 3);
         assert_eq!(p.response(), false);
-        assert_eq!(p.acknum(), 0);
+        assert_eq!(p.acknum(), AckNum::NONE);
         assert_eq!(p.msg_id().unwrap(), b"abc");
         assert_eq!(p.payload().unwrap(), &[0x14, 0xAE, 0x29, 0x42]);
-        assert_relative_eq!(LittleEndian::read_f32(p.payload().unwrap()), 42.42_f32);
+        assert_relative_eq!(p.payload_f32().unwrap(), 42.42_f32);
+        assert_eq!(
+            p.payload_u32(),
+            Err(Error::UnexpectedType(MessageType::F32))
+        );
         assert_eq!(p.checksum().unwrap(), 0x1D8B);
         assert_eq!(p.compute_checksum().unwrap(), 0x1D8B);
         assert_eq!(p.wire_size(), Ok(12));
@@ -521,14 +1460,28 @@ mod tests {
     fn incomplete_payload() {
         let bytes = [0x04, 0x2c, 0x03, 0xFF, 0xFF];
         let p = Packet::new(&bytes[..]);
-        assert_eq!(p.unwrap_err(), Error::IncompletePayload);
+        assert_eq!(
+            p.unwrap_err(),
+            Error::IncompletePayload { needed: 12, got: 5 }
+        );
     }
 
     #[test]
     fn invalid_checksum() {
         let bytes = [0x01, 0x14, 0x63, 0x61, 0x62, 0x63, 0x2A, 0xB8, 0xA3 + 1];
         let p = Packet::new(&bytes[..]);
-        assert_eq!(p.unwrap_err(), Error::InvalidChecksum);
+        let e = p.unwrap_err();
+        assert_eq!(
+            e,
+            Error::InvalidChecksum {
+                expected: 0xA4B8,
+                computed: 0xA3B8
+            }
+        );
+        assert_eq!(
+            std::format!("{}", e),
+            "Invalid checksum, expected 0xA4B8 computed 0xA3B8"
+        );
     }
 
     #[test]
@@ -544,7 +1497,7 @@ mod tests {
                 .unwrap_err(),
             Error::InvalidMessageIdLength
         );
-        bytes[field::ID_LEN] &= !0x0F; // zero
+        bytes[2] &= !0x0F; // zero ID length
         let p = Packet::new(&bytes[..]);
         assert_eq!(p.unwrap_err(), Error::InvalidMessageIdLength);
     }
@@ -560,7 +1513,7 @@ mod tests {
         p.set_offset(false);
         p.set_id_length(1).unwrap();
         p.set_response(false);
-        p.set_acknum(0);
+        p.set_acknum(AckNum::NONE);
         p.msg_id_mut().unwrap().copy_from_slice(&[0]); // zero invalid
         p.set_checksum(p.compute_checksum().unwrap()).unwrap();
         assert!(p.check_payload_length().is_ok());
@@ -580,13 +1533,932 @@ mod tests {
                 .unwrap_err(),
             Error::InvalidDataLength
         );
+        assert_eq!(Packet::<&[u8]>::MAX_PAYLOAD_SIZE, 1023);
+        p.set_data_length(1023).unwrap();
+        assert_eq!(p.data_length(), 1023);
+        assert_eq!(p.set_data_length(1024), Err(Error::InvalidDataLength));
+        assert_eq!(p.data_length(), 1023);
+
+        let id = MessageId::new(b"abc").unwrap();
+        let payload = [0_u8; 1024];
+        assert_eq!(
+            emit(
+                &mut bytes,
+                id,
+                MessageType::U8,
+                false,
+                false,
+                AckNum::NONE,
+                &payload
+            ),
+            Err(Error::InvalidDataLength)
+        );
+    }
+
+    #[test]
+    fn inconsistent_header() {
+        let check = |typ: MessageType, internal, response, acknum, offset_address, id, payload| {
+            let view = crate::wire::PacketView {
+                typ: typ.into(),
+                internal,
+                response,
+                acknum,
+                offset_address,
+                checksum: 0,
+                msg_id: id,
+                payload,
+            };
+            let view = crate::wire::PacketView {
+                checksum: view.compute_checksum(),
+                ..view
+            };
+            let mut bytes = [0_u8; 32];
+            let size = view.write_to(&mut bytes);
+            Packet::new(&bytes[..size]).map(|_| ())
+        };
+        use MessageType::*;
+        assert_eq!(
+            check(U8, false, false, AckNum::NONE, Some(4), b"abc", &[1]),
+            Ok(())
+        );
+        assert_eq!(
+            check(Callback, false, false, AckNum::NONE, Some(4), b"abc", &[]),
+            Err(Error::InvalidOffset)
+        );
+        assert_eq!(
+            check(
+                OffsetMetadata,
+                false,
+                false,
+                AckNum::NONE,
+                Some(4),
+                b"abc",
+                &[1]
+            ),
+            Err(Error::InvalidOffset)
+        );
+        assert_eq!(
+            check(U8, false, true, AckNum::new(2).unwrap(), None, b"abc", &[1]),
+            Ok(())
+        );
+        assert_eq!(
+            check(U8, false, false, AckNum::new(2).unwrap(), None, b"abc", &[]),
+            Ok(())
+        );
+        assert_eq!(
+            check(
+                Callback,
+                false,
+                true,
+                AckNum::new(2).unwrap(),
+                None,
+                b"abc",
+                &[]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check(U8, false, true, AckNum::new(2).unwrap(), None, b"abc", &[]),
+            Ok(())
+        );
+        assert_eq!(check(U8, true, true, AckNum::NONE, None, b"h", &[]), Ok(()));
+        assert_eq!(
+            check(U8, true, false, AckNum::NONE, None, b"abc", &[1]),
+            Err(Error::UnknownInternalId)
+        );
+        assert_eq!(Error::UnknownInternalId.code(), 0x19);
+    }
+
+    #[test]
+    fn setters_keep_neighbors() {
+        let mut bytes = [0xFF; 3];
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        p.set_data_length(0).unwrap();
+        assert_eq!(p.typ_raw(), 0x0F);
+        assert!(p.internal() && p.offset());
+        p.set_typ(MessageType::Callback);
+        assert_eq!(p.typ_raw(), 0);
+        assert!(p.internal() && p.offset());
+        assert_eq!(p.data_length(), 0);
+    }
+
+    #[test]
+    fn deconstruct_offset() {
+        let bytes = [
+            0x02, 0x98, 0x03, // header, offset set
+            0x61, 0x62, 0x63, // msgid
+            0x10, 0x00, // offset
+            0x01, 0x02, // payload
+            0x8C, 0x12, // crc
+        ];
+        let p = Packet::new(&bytes[..]).unwrap();
+        assert_eq!(p.data_length(), 2);
+        assert_eq!(p.typ(), MessageType::U8);
+        assert_eq!(p.offset(), true);
+        assert_eq!(p.msg_id().unwrap(), b"abc");
+        assert_eq!(p.offset_address().unwrap(), Some(0x10));
+        assert_eq!(p.payload().unwrap(), &[0x01, 0x02]);
+        assert_eq!(p.checksum().unwrap(), 0x128C);
+        assert_eq!(p.compute_checksum().unwrap(), 0x128C);
+        assert_eq!(p.wire_size(), Ok(12));
+
+        let p = Packet::new(&bytes[..bytes.len() - 1]);
+        assert_eq!(
+            p.unwrap_err(),
+            Error::IncompletePayload {
+                needed: 12,
+                got: 11
+            }
+        );
+    }
+
+    #[test]
+    fn repr_round_trip() {
+        let bytes = [
+            0x02, 0x98, 0x03, // header, offset set
+            0x61, 0x62, 0x63, // msgid
+            0x10, 0x00, // offset
+            0x01, 0x02, // payload
+            0x8C, 0x12, // crc
+        ];
+        let repr = Repr::parse(&Packet::new_unchecked(&bytes[..])).unwrap();
+        assert_eq!(
+            repr,
+            Repr {
+                offset_address: Some(0x10),
+                ..Repr::new(MessageId::new(b"abc").unwrap(), MessageType::U8, &[1, 2])
+            }
+        );
+        assert_eq!(repr.buffer_len(), bytes.len());
+        let mut buf = [0xFF; 16];
+        repr.emit(&mut Packet::new_unchecked(&mut buf[..])).unwrap();
+        assert_eq!(&buf[..bytes.len()], &bytes[..]);
+
+        let mut raw = [0_u8; 11];
+        Framing::decode_buf(&MSG_I8[..], &mut raw[..]).unwrap();
+        let repr = Repr::parse(&Packet::new_unchecked(&raw[..9])).unwrap();
+        assert_eq!(repr.acknum, AckNum::new(3).unwrap());
+        assert_eq!(repr.payload, &[0x2A]);
+        let mut buf = [0_u8; 9];
+        repr.emit(&mut Packet::new_unchecked(&mut buf[..])).unwrap();
+        assert_eq!(buf, raw[..9]);
+
+        assert_eq!(
+            Repr::parse(&Packet::new_unchecked(&raw[..8])),
+            Err(Error::IncompletePayload { needed: 9, got: 8 })
+        );
+    }
+
+    #[test]
+    fn repr_emit_errors() {
+        let id = MessageId::new(b"abc").unwrap();
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        let repr = Repr::new(id, MessageType::U8, &[1, 2, 3]);
+        assert_eq!(
+            repr.emit(&mut Packet::new_unchecked(&mut [0_u8; 10][..])),
+            Err(Error::IncompletePayload {
+                needed: 11,
+                got: 10
+            })
+        );
+        let internal = Repr {
+            internal: true,
+            ..repr
+        };
+        assert_eq!(internal.emit(&mut p), Err(Error::UnknownInternalId));
+        let callback = Repr {
+            offset_address: Some(0),
+            ..Repr::new(id, MessageType::Callback, &[])
+        };
+        assert_eq!(callback.emit(&mut p), Err(Error::InvalidOffset));
+        let big = [0_u8; Packet::<&[u8]>::MAX_PAYLOAD_SIZE + 1];
+        let big = Repr::new(id, MessageType::U8, &big);
+        assert_eq!(big.emit(&mut p), Err(Error::InvalidDataLength));
+        assert_eq!(p.into_inner(), &[0_u8; 16]);
+    }
+
+    #[test]
+    fn builder() {
+        let mut raw = [0_u8; 11];
+        Framing::decode_buf(&MSG_I8[..], &mut raw[..]).unwrap();
+        let mut buf = [0xFF; 16];
+        let builder = Packet::builder(MessageId::new(b"abc").unwrap(), MessageType::I8)
+            .payload(&[0x2A])
+            .acknum(AckNum::new(3).unwrap());
+        assert_eq!(builder.buffer_len(), 9);
+        assert_eq!(builder.build_into(&mut buf), Ok(9));
+        assert_eq!(buf[..9], raw[..9]);
+        assert_eq!(buf[9..], [0xFF; 7]);
+        assert_eq!(
+            builder.build_into(&mut buf[..8]),
+            Err(Error::IncompletePayload { needed: 9, got: 8 })
+        );
+
+        // An acked write of an empty string
+        let size = Packet::builder(MessageId::new(b"name").unwrap(), MessageType::Char)
+            .response(true)
+            .acknum(AckNum::new(1).unwrap())
+            .payload(&[])
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(p.check_header(), Ok(()));
+        assert!(p.response());
+        assert_eq!(p.acknum(), AckNum::new(1).unwrap());
+        assert_eq!(p.payload(), Ok(&[][..]));
+
+        let size = Packet::builder(MessageId::INTERNAL_HEARTBEAT, MessageType::U8)
+            .internal(true)
+            .response(true)
+            .offset_address(4)
+            .payload(&[7])
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert!(p.internal() && p.response());
+        assert_eq!(p.offset_address(), Ok(Some(4)));
+        assert_eq!(p.payload(), Ok(&[7][..]));
+    }
+
+    #[test]
+    fn payload_arrays() {
+        let mut buf = [0_u8; 32];
+        let payload = [0x00, 0x00, 0xC0, 0x3F, 0x00, 0x00, 0x20, 0xC1];
+        let size = Packet::builder(MessageId::new(b"accel").unwrap(), MessageType::F32)
+            .payload(&payload)
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        match p.value() {
+            Ok(Value::F32(a)) => assert!(a.iter().eq([1.5, -10.0])),
+            v => panic!("{:?}", v),
+        }
+        let mut it = p.payload_iter::<f32>().unwrap();
+        assert_eq!(it.len(), 2);
+        assert_eq!(it.next(), Some(1.5));
+        assert_eq!(it.next(), Some(-10.0));
+        assert_eq!(it.next(), None);
+        let mut elems = p.payload_elements(MessageType::F32).unwrap();
+        assert_eq!(elems.next(), Some(&payload[..4]));
+        assert_eq!(
+            p.payload_iter::<u32>().err(),
+            Some(Error::UnexpectedType(MessageType::F32))
+        );
+        assert_eq!(
+            p.payload_elements(MessageType::U32).err(),
+            Some(Error::UnexpectedType(MessageType::F32))
+        );
+        assert_eq!(p.payload_f32(), Err(Error::InvalidDataLength));
+
+        let size = Packet::builder(MessageId::new(b"accel").unwrap(), MessageType::F32)
+            .payload(&payload[..6])
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(
+            p.payload_iter::<f32>().err(),
+            Some(Error::InvalidDataLength)
+        );
+        assert_eq!(
+            p.payload_elements(MessageType::F32).err(),
+            Some(Error::InvalidDataLength)
+        );
+    }
+
+    #[test]
+    fn codec_payloads() {
+        #[derive(Debug, PartialEq)]
+        struct Pair(u8, u8);
+
+        impl Codec for Pair {
+            fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+                buf.get_mut(..2)
+                    .ok_or(Error::InvalidDataLength)?
+                    .copy_from_slice(&[self.0, self.1]);
+                Ok(2)
+            }
+
+            fn decode(payload: &[u8]) -> Result<Self, Error> {
+                match payload {
+                    [a, b] => Ok(Pair(*a, *b)),
+                    _ => Err(Error::InvalidDataLength),
+                }
+            }
+        }
+
+        // Claims more than it was given
+        struct Liar;
+
+        impl Codec for Liar {
+            const WIRE_TYPE: MessageType = MessageType::Byte;
+
+            fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+                Ok(buf.len() + 1)
+            }
+
+            fn decode(_: &[u8]) -> Result<Self, Error> {
+                Ok(Liar)
+            }
+        }
+
+        let mut buf = [0_u8; 10];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"abc").unwrap()).unwrap();
+        assert_eq!(p.encode_payload(&Pair(1, 2)), Ok(2));
+        assert_eq!(p.typ(), MessageType::Custom);
+        assert_eq!(p.payload(), Ok(&[1, 2][..]));
+        assert_eq!(p.decode_payload(), Ok(Pair(1, 2)));
+
+        assert_eq!(p.encode_payload(&Liar), Err(Error::InvalidDataLength));
+        assert_eq!(p.typ(), MessageType::Custom);
+        assert_eq!(p.data_length(), 2);
+        assert_eq!(
+            p.decode_payload::<Liar>().err(),
+            Some(Error::UnexpectedType(MessageType::Custom))
+        );
+
+        let mut small = [0_u8; 7];
+        let mut p = Packet::new_unchecked(&mut small[..]);
+        p.set_msg_id(MessageId::new(b"abc").unwrap()).unwrap();
+        p.set_data_length(0).unwrap();
+        assert_eq!(p.encode_payload(&Pair(1, 2)), Err(Error::InvalidDataLength));
+        assert_eq!(p.data_length(), 0);
+    }
+
+    #[test]
+    fn write_payload_from() {
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"acc").unwrap()).unwrap();
+        assert_eq!(p.write_payload_from([1.5_f32, -10.0]), Ok(2));
+        assert_eq!(p.typ(), MessageType::F32);
+        assert_eq!(p.data_length(), 8);
+        assert_eq!(
+            p.payload(),
+            Ok(&[0x00, 0x00, 0xC0, 0x3F, 0x00, 0x00, 0x20, 0xC1][..])
+        );
+        assert!(p.payload_iter::<f32>().unwrap().eq([1.5, -10.0]));
+
+        assert_eq!(p.write_payload_from((1..=3).map(|v: i16| -v)), Ok(3));
+        assert_eq!(p.typ(), MessageType::I16);
+        assert!(p.payload_iter::<i16>().unwrap().eq([-1, -2, -3]));
+        assert_eq!(p.write_payload_from(core::iter::empty::<u8>()), Ok(0));
+        assert_eq!(p.data_length(), 0);
+
+        p.write_payload_from([7_u32]).unwrap();
+        assert_eq!(
+            p.write_payload_from([7_u32; 3]),
+            Err(Error::IncompletePayload {
+                needed: 18,
+                got: 16
+            })
+        );
+        assert_eq!(p.typ(), MessageType::U32);
+        assert_eq!(p.data_length(), 4);
+
+        let mut big = [0_u8; 2048];
+        let mut p = Packet::new_unchecked(&mut big[..]);
+        p.set_msg_id(MessageId::new(b"a").unwrap()).unwrap();
+        assert_eq!(
+            p.write_payload_from(core::iter::repeat(0_u16)),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(p.data_length(), 0);
+    }
+
+    #[test]
+    fn payload_element() {
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"arr").unwrap()).unwrap();
+        p.write_payload_from([10_u16, 20, 30]).unwrap();
+        assert_eq!(p.payload_element::<u16>(0), Ok(10));
+        assert_eq!(p.payload_element::<u16>(2), Ok(30));
+        assert_eq!(
+            p.payload_element::<u16>(3),
+            Err(Error::ElementOutOfRange { index: 3, len: 3 })
+        );
+        assert_eq!(
+            p.payload_element::<i16>(0),
+            Err(Error::UnexpectedType(MessageType::U16))
+        );
+
+        p.set_payload_element(1, 0xBEEF_u16).unwrap();
+        assert!(p.payload_iter::<u16>().unwrap().eq([10, 0xBEEF, 30]));
+        assert_eq!(
+            p.set_payload_element(3, 0_u16),
+            Err(Error::ElementOutOfRange { index: 3, len: 3 })
+        );
+        assert_eq!(
+            p.set_payload_element(0, 0_u8),
+            Err(Error::UnexpectedType(MessageType::U16))
+        );
+        assert_eq!(p.data_length(), 6);
+
+        p.set_data_length(5).unwrap();
+        assert_eq!(p.payload_element::<u16>(0), Err(Error::InvalidDataLength));
+        assert_eq!(
+            p.set_payload_element(0, 0_u16),
+            Err(Error::InvalidDataLength)
+        );
+    }
+
+    #[test]
+    fn set_offset_address() {
+        let mut buf = [0_u8; 12];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_typ(MessageType::U8);
+        assert_eq!(p.set_offset_address(1), Err(Error::InvalidMessageIdLength));
+        p.set_msg_id(MessageId::new(b"abc").unwrap()).unwrap();
+        p.set_offset_address(0x1234).unwrap();
+        assert!(p.offset());
+        assert_eq!(p.offset_address(), Ok(Some(0x1234)));
+        p.set_payload(&[7, 8]).unwrap();
+        assert_eq!(p.payload(), Ok(&[7, 8][..]));
+        let checksum = p.compute_checksum().unwrap();
+        p.set_checksum(checksum).unwrap();
+        let p = Packet::new(&buf[..]).unwrap();
+        assert_eq!(p.offset_address(), Ok(Some(0x1234)));
+        assert_eq!(p.payload(), Ok(&[7, 8][..]));
+        assert_eq!(p.checksum(), Ok(checksum));
+
+        let mut short = [0_u8; 7];
+        let mut p = Packet::new_unchecked(&mut short[..]);
+        p.set_msg_id(MessageId::new(b"abc").unwrap()).unwrap();
+        assert_eq!(
+            p.set_offset_address(1),
+            Err(Error::IncompletePayload { needed: 8, got: 7 })
+        );
+        assert!(!p.offset());
+    }
+
+    #[test]
+    fn make_response_into() {
+        let mut raw = [0_u8; 11];
+        let size = Framing::decode_buf(&MSG_I8[..], &mut raw[..]).unwrap();
+        let p = Packet::new(&raw[..size]).unwrap();
+        let mut buf = [0xFF_u8; 16];
+        let size = p.make_response_into(&mut buf, &[7]).unwrap();
+        let r = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(r.msg_id(), p.msg_id());
+        assert_eq!(r.typ(), MessageType::I8);
+        assert!(!r.internal() && !r.response());
+        assert_eq!(r.acknum(), p.acknum());
+        assert_eq!(r.payload_i8(), Ok(7));
+
+        let mut req = [0_u8; 16];
+        let size = Packet::builder(MessageId::INTERNAL_HEARTBEAT, MessageType::U8)
+            .internal(true)
+            .response(true)
+            .payload(&[3])
+            .build_into(&mut req)
+            .unwrap();
+        let p = Packet::new(&req[..size]).unwrap();
+        let size = p.make_response_into(&mut buf, &[3]).unwrap();
+        let r = Packet::new(&buf[..size]).unwrap();
+        assert!(r.internal() && !r.response());
+        assert_eq!(r.acknum(), AckNum::NONE);
+        assert_eq!(r.msg_id(), Ok(MessageId::INTERNAL_HEARTBEAT));
+        assert_eq!(
+            p.make_response_into(&mut buf[..6], &[3]),
+            Err(Error::IncompletePayload { needed: 7, got: 6 })
+        );
+    }
+
+    #[test]
+    fn make_ack_into() {
+        let mut raw = [0_u8; 16];
+        let size = Packet::builder(MessageId::new(b"abc").unwrap(), MessageType::I8)
+            .response(true)
+            .acknum(AckNum::new(3).unwrap())
+            .payload(&[0x2A])
+            .build_into(&mut raw)
+            .unwrap();
+        let p = Packet::new(&raw[..size]).unwrap();
+        let mut buf = [0_u8; 16];
+        let size = p.make_ack_into(&mut buf).unwrap().unwrap();
+        let ack = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(ack.msg_id(), p.msg_id());
+        assert_eq!(ack.typ(), p.typ());
+        assert_eq!(ack.acknum(), AckNum::new(3).unwrap());
+        assert!(!ack.response());
+        assert_eq!(ack.data_length(), 0);
+        assert_eq!(ack.make_ack_into(&mut [0_u8; 16][..]), Ok(None));
+
+        let mut req = [0_u8; 16];
+        let size = Packet::builder(MessageId::new(b"abc").unwrap(), MessageType::U8)
+            .response(true)
+            .build_into(&mut req)
+            .unwrap();
+        let p = Packet::new(&req[..size]).unwrap();
+        assert_eq!(p.make_ack_into(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn query_into() {
+        let mut buf = [0_u8; 16];
+        let size =
+            Packet::query_into(&mut buf, MessageId::INTERNAL_AM, MessageType::Callback).unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(p.msg_id(), Ok(MessageId::INTERNAL_AM));
+        assert_eq!(p.typ(), MessageType::Callback);
+        assert!(p.internal() && p.response());
+        assert_eq!(p.acknum(), AckNum::NONE);
+        assert_eq!(p.data_length(), 0);
+
+        let id = MessageId::new(b"speed").unwrap();
+        let size = Packet::query_into(&mut buf, id, MessageType::U16).unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert!(!p.internal() && p.response());
+        assert_eq!(p.msg_id(), Ok(id));
+        assert_eq!(
+            Packet::query_into(&mut buf[..9], id, MessageType::U16),
+            Err(Error::IncompletePayload { needed: 10, got: 9 })
+        );
+    }
+
+    #[test]
+    fn checksum_policy() {
+        let mut raw = [0_u8; 11];
+        let size = Framing::decode_buf(&MSG_I8[..], &mut raw[..]).unwrap();
+        raw[size - 2] ^= 0x01;
+        let bad = &raw[..size];
+        let expected = Error::InvalidChecksum {
+            expected: 0xA3B9,
+            computed: 0xA3B8,
+        };
+        assert_eq!(Packet::new(bad).unwrap_err(), expected);
+        assert_eq!(
+            Packet::new_with(bad, ChecksumPolicy::Verify).unwrap_err(),
+            expected
+        );
+        let p = Packet::new_with(bad, ChecksumPolicy::Skip).unwrap();
+        assert_eq!(p.payload(), Ok(&[0x2A][..]));
+        assert_eq!(p.check_checksum(), Err(expected));
+
+        // The other checks still run
+        assert_eq!(
+            Packet::new_with(&bad[..size - 1], ChecksumPolicy::Skip).unwrap_err(),
+            Error::IncompletePayload {
+                needed: size,
+                got: size - 1
+            }
+        );
+        assert_eq!(
+            Packet::new_with(&bad[..2], ChecksumPolicy::Skip).unwrap_err(),
+            Error::MissingHeader
+        );
+    }
+
+    #[test]
+    fn type_length() {
+        let mut buf = [0_u8; 16];
+        let id = MessageId::new(b"abc").unwrap();
+        let check = |buf: &mut [u8], typ, payload: &[u8]| {
+            let size = Packet::builder(id, typ)
+                .payload(payload)
+                .build_into(buf)
+                .unwrap();
+            Packet::new(&buf[..size]).unwrap().check_type_length()
+        };
+        assert_eq!(check(&mut buf, MessageType::F32, &[0; 8]), Ok(()));
+        assert_eq!(check(&mut buf, MessageType::U16, &[]), Ok(()));
+        assert_eq!(
+            check(&mut buf, MessageType::F32, &[0; 6]),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(
+            check(&mut buf, MessageType::I16, &[0; 3]),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(check(&mut buf, MessageType::Custom, &[0; 3]), Ok(()));
+        assert_eq!(check(&mut buf, MessageType::Char, &[0; 3]), Ok(()));
+
+        let size = Packet::builder(id, MessageType::F64)
+            .offset_address(4)
+            .payload(&[0; 5])
+            .build_into(&mut buf)
+            .unwrap();
+        let p = Packet::new(&buf[..size]).unwrap();
+        assert_eq!(p.check_type_length(), Ok(()));
+    }
+
+    #[test]
+    fn dump() {
+        use std::string::ToString;
+
+        let mut raw = [0_u8; 11];
+        let size = Framing::decode_buf(&MSG_I8[..], &mut raw[..]).unwrap();
+        let p = Packet::new(&raw[..size]).unwrap();
+        assert_eq!(
+            p.dump().to_string(),
+            "abc I8 ack=3 payload[1]=2A checksum=0xA3B8 ok"
+        );
+
+        let mut buf = [0_u8; 16];
+        let size = Packet::builder(MessageId::INTERNAL_HEARTBEAT, MessageType::U16)
+            .internal(true)
+            .response(true)
+            .offset_address(0x10)
+            .payload(&[1, 2])
+            .build_into(&mut buf)
+            .unwrap();
+        buf[size - 1] ^= 0xFF;
+        let p = Packet::new_unchecked(&buf[..size]);
+        let computed = p.compute_checksum().unwrap();
+        assert_eq!(
+            p.dump().to_string(),
+            std::format!(
+                "h U16 internal response offset=0x0010 payload[2]=0102 checksum={:#06X} computed={:#06X}",
+                p.checksum().unwrap(),
+                computed
+            )
+        );
+
+        let p = Packet::new_unchecked(&raw[..5]);
+        assert_eq!(
+            p.dump().to_string(),
+            "<Not enough bytes for a valid payload according to the data length, needed 6 got 5> \
+             I8 ack=3 payload=<Not enough bytes for a valid payload according to the data length, \
+             needed 7 got 5> checksum=<Not enough bytes for a valid header and checksum>"
+        );
+    }
+
+    #[test]
+    fn const_packets() {
+        const ID: MessageId<'static> = match MessageId::new(b"abc") {
+            Some(id) => id,
+            None => panic!(),
+        };
+        const_packet!(
+            I8 = Packet::builder(ID, MessageType::I8)
+                .payload(&[0x2A])
+                .acknum(AckNum::MAX)
+        );
+        const_packet!(
+            OFFSET = Packet::builder(ID, MessageType::U16)
+                .offset_address(0x1234)
+                .payload(&[1, 2, 3, 4])
+        );
+        const_packet!(
+            QUERY = Packet::builder(MessageId::INTERNAL_AM, MessageType::Callback)
+                .internal(true)
+                .response(true)
+        );
+
+        let check = |bytes: &[u8], builder: PacketBuilder<'_>| {
+            let mut buf = [0_u8; 32];
+            let size = builder.build_into(&mut buf).unwrap();
+            assert_eq!(bytes, &buf[..size]);
+            Packet::new(bytes).unwrap();
+        };
+        check(
+            &I8,
+            Packet::builder(ID, MessageType::I8)
+                .payload(&[0x2A])
+                .acknum(AckNum::MAX),
+        );
+        check(
+            &OFFSET,
+            Packet::builder(ID, MessageType::U16)
+                .offset_address(0x1234)
+                .payload(&[1, 2, 3, 4]),
+        );
+        check(
+            &QUERY,
+            Packet::builder(MessageId::INTERNAL_AM, MessageType::Callback)
+                .internal(true)
+                .response(true),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Array size isn't the packet size")]
+    fn const_packet_size_mismatch() {
+        let _: [u8; 4] = Packet::builder(MessageId::INTERNAL_AM, MessageType::Callback).to_bytes();
+    }
+
+    #[test]
+    fn set_msg_id() {
+        let mut buf = [0xFF_u8; 8];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"abcde").unwrap()).unwrap();
+        assert_eq!(p.id_length(), Ok(5));
+        assert_eq!(p.msg_id(), Ok(MessageId::new(b"abcde").unwrap()));
+        p.set_msg_id(MessageId::new(b"xy").unwrap()).unwrap();
+        assert_eq!(p.msg_id(), Ok(MessageId::new(b"xy").unwrap()));
+        assert_eq!(p.typ_raw(), 0x0F);
+
+        let before = buf;
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        assert_eq!(
+            p.set_msg_id(MessageId::new(b"abcdef").unwrap()),
+            Err(Error::IncompletePayload { needed: 9, got: 8 })
+        );
+        let mut short = [0_u8; 2];
+        let mut p2 = Packet::new_unchecked(&mut short[..]);
+        assert_eq!(
+            p2.set_msg_id(MessageId::BOARD_NAME),
+            Err(Error::MissingHeader)
+        );
+        assert_eq!(p.into_inner(), &before);
+    }
+
+    #[test]
+    fn set_payload() {
+        let mut buf = [0_u8; 12];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        assert_eq!(p.set_payload(&[1]), Err(Error::InvalidMessageIdLength));
+        p.set_msg_id(MessageId::new(b"abc").unwrap()).unwrap();
+        p.set_payload(&[1, 2, 3]).unwrap();
+        assert_eq!(p.data_length(), 3);
+        assert_eq!(p.payload(), Ok(&[1, 2, 3][..]));
+        p.set_offset(true);
+        p.set_payload(&[4]).unwrap();
+        assert_eq!(p.payload(), Ok(&[4][..]));
+        assert_eq!(
+            p.set_payload(&[0; 5]),
+            Err(Error::IncompletePayload {
+                needed: 13,
+                got: 12
+            })
+        );
+        let big = [0_u8; Packet::<&[u8]>::MAX_PAYLOAD_SIZE + 1];
+        assert_eq!(p.set_payload(&big), Err(Error::InvalidDataLength));
+        assert_eq!(p.data_length(), 1);
     }
 
     #[test]
     fn unknown_msg_type() {
         let mut bytes = [0x01, 0x14, 0x63, 0x61, 0x62, 0x63, 0x2A, 0xB8, 0xA3];
-        bytes[field::TYPE] = (bytes[field::TYPE] & !0x3C) | (0x0F << 2);
+        bytes[1] = (bytes[1] & !0x3C) | (0x0F << 2);
         let p = Packet::new_unchecked(&mut bytes[..]);
-        assert_eq!(p.typ(), MessageType::Unknown(0x0F));
+        assert_eq!(
+            p.typ(),
+            MessageType::Unknown(crate::message::UnknownType::MAX)
+        );
+    }
+
+    #[test]
+    fn truncated_accessors() {
+        let mut full = [0xFF; 12];
+        Framing::decode_buf(&MSG_F32[..], &mut full[..]).unwrap();
+        for len in 0..full.len() {
+            let mut bytes = full;
+            let mut p = Packet::new_unchecked(&mut bytes[..len]);
+            let _ = (p.data_length(), p.typ(), p.internal(), p.offset());
+            let _ = (p.response(), p.acknum(), p.wire_size());
+            assert_eq!(p.id_length().is_err(), len < 3);
+            assert!(p.checksum().is_err());
+            assert!(p.set_checksum(0).is_err());
+            assert_eq!(p.compute_checksum().is_err(), len < 10);
+            assert_eq!(p.payload().is_err(), len < 10);
+            assert_eq!(p.payload_mut().is_err(), len < 10);
+            assert_eq!(p.msg_id().is_err(), len < 6);
+            assert_eq!(p.msg_id_mut().is_err(), len < 6);
+            assert_eq!(p.offset_address(), Ok(None));
+            p.set_typ(MessageType::U8);
+            p.set_internal(true);
+            p.set_offset(false);
+            p.set_response(true);
+            p.set_acknum(AckNum::new(1).unwrap());
+            assert_eq!(p.set_data_length(4).is_err(), len < 2);
+            assert_eq!(p.set_id_length(3).is_err(), len < 3);
+        }
+    }
+}
+
+/// Proofs of the header bit field accessors, run with `cargo kani`
+#[cfg(kani)]
+mod verification {
+    use super::*;
+
+    type Fields = (u16, u8, bool, bool, u8, bool, AckNum);
+
+    fn fields<T: AsRef<[u8]>>(p: &Packet<T>) -> Fields {
+        (
+            p.data_length(),
+            p.typ_raw(),
+            p.internal(),
+            p.offset(),
+            p.id_length_raw(),
+            p.response(),
+            p.acknum(),
+        )
+    }
+
+    #[kani::proof]
+    fn data_length_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (_, typ, internal, offset, id_len, response, acknum) = fields(&p);
+        let value: u16 = kani::any();
+        if p.set_data_length(value).is_ok() {
+            let expected = (value, typ, internal, offset, id_len, response, acknum);
+            assert_eq!(fields(&p), expected);
+        }
+    }
+
+    #[kani::proof]
+    fn typ_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, _, internal, offset, id_len, response, acknum) = fields(&p);
+        let value = MessageType::from_bits(kani::any::<u8>());
+        p.set_typ(value);
+        let typ = u8::from(value);
+        let expected = (data_len, typ, internal, offset, id_len, response, acknum);
+        assert_eq!(fields(&p), expected);
+    }
+
+    #[kani::proof]
+    fn internal_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, typ, _, offset, id_len, response, acknum) = fields(&p);
+        let internal = kani::any();
+        p.set_internal(internal);
+        let expected = (data_len, typ, internal, offset, id_len, response, acknum);
+        assert_eq!(fields(&p), expected);
+    }
+
+    #[kani::proof]
+    fn offset_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, typ, internal, _, id_len, response, acknum) = fields(&p);
+        let offset = kani::any();
+        p.set_offset(offset);
+        let expected = (data_len, typ, internal, offset, id_len, response, acknum);
+        assert_eq!(fields(&p), expected);
+    }
+
+    #[kani::proof]
+    fn id_length_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, typ, internal, offset, _, response, acknum) = fields(&p);
+        let value: u8 = kani::any();
+        if p.set_id_length(value).is_ok() {
+            let expected = (data_len, typ, internal, offset, value, response, acknum);
+            assert_eq!(fields(&p), expected);
+        }
+    }
+
+    #[kani::proof]
+    fn response_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, typ, internal, offset, id_len, _, acknum) = fields(&p);
+        let response = kani::any();
+        p.set_response(response);
+        let expected = (data_len, typ, internal, offset, id_len, response, acknum);
+        assert_eq!(fields(&p), expected);
+    }
+
+    #[kani::proof]
+    fn acknum_round_trip() {
+        let mut bytes: [u8; 3] = kani::any();
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        let (data_len, typ, internal, offset, id_len, response, _) = fields(&p);
+        let value = AckNum::from_bits(kani::any());
+        p.set_acknum(value);
+        let expected = (data_len, typ, internal, offset, id_len, response, value);
+        assert_eq!(fields(&p), expected);
+    }
+
+    #[kani::proof]
+    fn header_round_trip() {
+        let bytes: [u8; Header::SIZE] = kani::any();
+        assert_eq!(Header::from_bytes(bytes).to_bytes(), bytes);
+    }
+
+    /// Any header's lengths add up without overflowing and fit the
+    /// `u16` positions of a [`ValidatedPacket`]
+    #[kani::proof]
+    fn wire_size_bounded() {
+        let bytes: [u8; 3] = kani::any();
+        let p = Packet::new_unchecked(&bytes[..]);
+        if let Ok(size) = p.wire_size() {
+            assert!(size <= Packet::<&[u8]>::MAX_PACKET_SIZE + Packet::<&[u8]>::OFFSET_SIZE);
+            assert!(size <= usize::from(u16::MAX));
+        }
+    }
+
+    /// Accessors on a buffer of any length don't panic
+    #[kani::proof]
+    fn truncated_accessors() {
+        let bytes: [u8; 8] = kani::any();
+        let len: usize = kani::any();
+        kani::assume(len <= bytes.len());
+        let p = Packet::new_unchecked(&bytes[..len]);
+        let _ = fields(&p);
+        let _ = p.msg_id_raw();
+        let _ = p.offset_address();
+        let _ = p.payload();
+        let _ = p.checksum();
+        let _ = p.check_payload_length();
+        let _ = p.wire_size();
     }
 }
@@ -0,0 +1,286 @@
+//! Little-endian scalar and array payloads
+//!
+//! [`FromPayload`] is implemented for the types of the fixed size
+//! [`MessageType`]s, used by the typed payload accessors of [`Packet`]
+//! and [`PacketView`]. Array payloads are read element by element with
+//! [`PayloadIter`], and written with [`Packet::write_payload_from`] for
+//! types that are also [`ToPayload`]. Structs carried as `Custom`
+//! payloads implement [`Codec`].
+//!
+//! [`Packet`]: crate::wire::Packet
+//! [`Packet::write_payload_from`]: crate::wire::Packet::write_payload_from
+//! [`PacketView`]: crate::wire::PacketView
+
+use crate::message::MessageType;
+use crate::wire::packet::Error;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::slice::ChunksExact;
+
+/// Types decoded from the payload of a specific [`MessageType`]
+pub trait FromPayload: Sized {
+    /// The message type a payload must have
+    const TYPE: MessageType;
+
+    /// Decodes the value, `bytes` is exactly [`MessageType::wire_size_hint`]
+    /// bytes of [`FromPayload::TYPE`]
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+}
+
+/// Types encoded into the payload of their [`FromPayload::TYPE`]
+pub trait ToPayload: FromPayload {
+    /// Encodes the value, `bytes` is exactly
+    /// [`MessageType::wire_size_hint`] bytes of [`FromPayload::TYPE`]
+    fn write_le_bytes(&self, bytes: &mut [u8]);
+}
+
+/// Types carried as a payload of their own format, usually
+/// [`MessageType::Custom`], see
+/// [`Packet::decode_payload`](crate::wire::Packet::decode_payload) and
+/// [`Packet::encode_payload`](crate::wire::Packet::encode_payload)
+pub trait Codec: Sized {
+    /// The message type a payload must have
+    const WIRE_TYPE: MessageType = MessageType::Custom;
+
+    /// Encodes the value into the start of `buf`, returning the number of
+    /// bytes written. `buf` is limited to the maximum payload size,
+    /// return [`Error::InvalidDataLength`] if the value doesn't fit.
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Decodes a value from the whole payload
+    fn decode(payload: &[u8]) -> Result<Self, Error>;
+}
+
+macro_rules! impl_from_payload {
+    ($t:ty, $variant:ident) => {
+        impl FromPayload for $t {
+            const TYPE: MessageType = MessageType::$variant;
+
+            fn from_le_bytes(bytes: &[u8]) -> Self {
+                let mut le = [0_u8; core::mem::size_of::<$t>()];
+                le.copy_from_slice(bytes);
+                <$t>::from_le_bytes(le)
+            }
+        }
+
+        impl ToPayload for $t {
+            fn write_le_bytes(&self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    };
+}
+
+impl_from_payload!(u8, U8);
+impl_from_payload!(i8, I8);
+impl_from_payload!(u16, U16);
+impl_from_payload!(i16, I16);
+impl_from_payload!(u32, U32);
+impl_from_payload!(i32, I32);
+impl_from_payload!(f32, F32);
+impl_from_payload!(f64, F64);
+
+/// Decodes a `C` from a payload of type `typ`
+pub(crate) fn decode_with<C: Codec>(typ: MessageType, payload: &[u8]) -> Result<C, Error> {
+    if typ != C::WIRE_TYPE {
+        Err(Error::UnexpectedType(typ))
+    } else {
+        C::decode(payload)
+    }
+}
+
+/// Decodes a single `T` from a payload of type `typ`
+pub(crate) fn decode<T: FromPayload>(typ: MessageType, payload: &[u8]) -> Result<T, Error> {
+    if typ != T::TYPE {
+        Err(Error::UnexpectedType(typ))
+    } else if payload.len() != T::TYPE.wire_size_hint() {
+        Err(Error::InvalidDataLength)
+    } else {
+        Ok(T::from_le_bytes(payload))
+    }
+}
+
+/// Splits a payload of type `typ` into its `expected` sized elements
+pub(crate) fn elements(
+    typ: MessageType,
+    expected: MessageType,
+    payload: &[u8],
+) -> Result<ChunksExact<'_, u8>, Error> {
+    let size = expected.wire_size_hint();
+    if typ != expected || size == 0 {
+        Err(Error::UnexpectedType(typ))
+    } else if !payload.len().is_multiple_of(size) {
+        Err(Error::InvalidDataLength)
+    } else {
+        Ok(payload.chunks_exact(size))
+    }
+}
+
+/// Byte range of element `index` in a `len` byte array payload of `T`
+pub(crate) fn element_range<T: FromPayload>(
+    typ: MessageType,
+    len: usize,
+    index: usize,
+) -> Result<Range<usize>, Error> {
+    let size = T::TYPE.wire_size_hint();
+    if typ != T::TYPE {
+        Err(Error::UnexpectedType(typ))
+    } else if !len.is_multiple_of(size) {
+        Err(Error::InvalidDataLength)
+    } else if index >= len / size {
+        Err(Error::ElementOutOfRange {
+            index,
+            len: len / size,
+        })
+    } else {
+        Ok(index * size..(index + 1) * size)
+    }
+}
+
+/// Decodes element `index` of an array payload of type `typ`
+pub(crate) fn element<T: FromPayload>(
+    typ: MessageType,
+    payload: &[u8],
+    index: usize,
+) -> Result<T, Error> {
+    let range = element_range::<T>(typ, payload.len(), index)?;
+    Ok(T::from_le_bytes(&payload[range]))
+}
+
+/// Iterates an array payload of type `typ` as `T`s
+pub(crate) fn iter<T: FromPayload>(
+    typ: MessageType,
+    payload: &[u8],
+) -> Result<PayloadIter<'_, T>, Error> {
+    Ok(PayloadIter {
+        chunks: elements(typ, T::TYPE, payload)?,
+        _elem: PhantomData,
+    })
+}
+
+/// Iterator over the little-endian elements of an array payload
+#[derive(Debug, Clone)]
+pub struct PayloadIter<'a, T> {
+    chunks: ChunksExact<'a, u8>,
+    _elem: PhantomData<T>,
+}
+
+impl<T: FromPayload> Iterator for PayloadIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.chunks.next().map(T::from_le_bytes)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<T: FromPayload> DoubleEndedIterator for PayloadIter<'_, T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.chunks.next_back().map(T::from_le_bytes)
+    }
+}
+
+impl<T: FromPayload> ExactSizeIterator for PayloadIter<'_, T> {}
+
+impl<T: FromPayload> FusedIterator for PayloadIter<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn scalars() {
+        assert_eq!(decode::<u8>(MessageType::U8, &[0x2A]), Ok(42));
+        assert_eq!(decode::<i8>(MessageType::I8, &[0xFF]), Ok(-1));
+        assert_eq!(decode::<u16>(MessageType::U16, &[0x46, 0x00]), Ok(70));
+        assert_eq!(decode::<i16>(MessageType::I16, &[0xFE, 0xFF]), Ok(-2));
+        assert_eq!(
+            decode::<u32>(MessageType::U32, &[1, 0, 0, 0x80]),
+            Ok(0x8000_0001)
+        );
+        assert_eq!(decode::<i32>(MessageType::I32, &[0xFF; 4]), Ok(-1));
+        assert_eq!(
+            decode::<f32>(MessageType::F32, &[0x14, 0xAE, 0x29, 0x42]),
+            Ok(42.42)
+        );
+        assert_eq!(
+            decode::<f64>(MessageType::F64, &1.5_f64.to_le_bytes()),
+            Ok(1.5)
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(
+            decode::<u8>(MessageType::Byte, &[1]),
+            Err(Error::UnexpectedType(MessageType::Byte))
+        );
+        assert_eq!(
+            decode::<u16>(MessageType::U8, &[1, 2]),
+            Err(Error::UnexpectedType(MessageType::U8))
+        );
+        assert_eq!(
+            decode::<u16>(MessageType::U16, &[1]),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(
+            decode::<u16>(MessageType::U16, &[1, 2, 3, 4]),
+            Err(Error::InvalidDataLength)
+        );
+        assert_eq!(
+            decode::<f64>(MessageType::F64, &[]),
+            Err(Error::InvalidDataLength)
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let bytes = [1, 0, 2, 0, 0xFF, 0xFF];
+        let mut it = iter::<u16>(MessageType::U16, &bytes).unwrap();
+        assert_eq!(it.len(), 3);
+        assert_eq!(it.next(), Some(1));
+        assert_eq!(it.next_back(), Some(0xFFFF));
+        assert_eq!(it.next(), Some(2));
+        assert_eq!(it.next(), None);
+        assert_eq!(
+            iter::<i16>(MessageType::I16, &bytes).unwrap().next_back(),
+            Some(-1)
+        );
+        assert_eq!(iter::<f32>(MessageType::F32, &[]).unwrap().count(), 0);
+
+        let mut chunks = elements(MessageType::U16, MessageType::U16, &bytes).unwrap();
+        assert_eq!(chunks.next(), Some(&[1, 0][..]));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            elements(MessageType::Char, MessageType::Char, b"ab")
+                .unwrap()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn array_mismatches() {
+        assert_eq!(
+            iter::<u16>(MessageType::U16, &[1, 2, 3]).err(),
+            Some(Error::InvalidDataLength)
+        );
+        assert_eq!(
+            iter::<u16>(MessageType::I16, &[1, 2]).err(),
+            Some(Error::UnexpectedType(MessageType::I16))
+        );
+        assert_eq!(
+            elements(MessageType::Custom, MessageType::Custom, &[1]).err(),
+            Some(Error::UnexpectedType(MessageType::Custom))
+        );
+        assert_eq!(
+            elements(MessageType::U8, MessageType::Byte, &[1]).err(),
+            Some(Error::UnexpectedType(MessageType::U8))
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::packet::{Error, Packet};
+use crate::wire::payload::{self, Codec, FromPayload, PayloadIter};
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+use core::ops::Range;
+use core::slice::ChunksExact;
+
+/// A [`Packet`] that has passed the same checks as [`Packet::new`],
+/// with the message ID, offset, payload and checksum positions cached
+/// so field access doesn't re-read or re-validate the header
+#[derive(Debug, Clone)]
+pub struct ValidatedPacket<T: AsRef<[u8]>> {
+    packet: Packet<T>,
+    /// End of the message ID, start of the offset address or payload
+    msg_id_end: u16,
+    /// Start of the payload
+    payload_start: u16,
+    /// End of the payload, start of the checksum
+    payload_end: u16,
+}
+
+impl<T: AsRef<[u8]>> ValidatedPacket<T> {
+    const MSG_ID_START: usize = Packet::<&[u8]>::HEADER_SIZE;
+
+    pub fn new(buffer: T) -> Result<ValidatedPacket<T>, Error> {
+        Self::try_from(Packet::new_unchecked(buffer))
+    }
+
+    #[inline]
+    fn msg_id_range(&self) -> Range<usize> {
+        Self::MSG_ID_START..usize::from(self.msg_id_end)
+    }
+
+    #[inline]
+    fn payload_range(&self) -> Range<usize> {
+        usize::from(self.payload_start)..usize::from(self.payload_end)
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        self.packet.as_ref()
+    }
+
+    #[inline]
+    pub fn data_length(&self) -> u16 {
+        self.payload_end - self.payload_start
+    }
+
+    #[inline]
+    pub fn typ(&self) -> MessageType {
+        self.packet.typ()
+    }
+
+    #[inline]
+    pub fn internal(&self) -> bool {
+        self.packet.internal()
+    }
+
+    #[inline]
+    pub fn offset(&self) -> bool {
+        self.payload_start != self.msg_id_end
+    }
+
+    #[inline]
+    pub fn id_length(&self) -> usize {
+        self.msg_id_range().len()
+    }
+
+    #[inline]
+    pub fn response(&self) -> bool {
+        self.packet.response()
+    }
+
+    #[inline]
+    pub fn acknum(&self) -> AckNum {
+        self.packet.acknum()
+    }
+
+    #[inline]
+    pub fn msg_id_raw(&self) -> &[u8] {
+        &self.bytes()[self.msg_id_range()]
+    }
+
+    #[inline]
+    pub fn msg_id(&self) -> Result<MessageId<'_>, Error> {
+        MessageId::new_binary(self.msg_id_raw()).ok_or(Error::InvalidMessageId)
+    }
+
+    /// Returns the offset address of an offset packet,
+    /// or `None` when the offset flag is not set
+    #[inline]
+    pub fn offset_address(&self) -> Option<u16> {
+        let range = usize::from(self.msg_id_end)..usize::from(self.payload_start);
+        if range.is_empty() {
+            None
+        } else {
+            Some(LittleEndian::read_u16(&self.bytes()[range]))
+        }
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.bytes()[self.payload_range()]
+    }
+
+    /// Decodes the payload as a single little-endian `V`, see
+    /// [`Packet::payload_as`]
+    pub fn payload_as<V: FromPayload>(&self) -> Result<V, Error> {
+        payload::decode(self.typ(), self.payload())
+    }
+
+    /// Decodes the payload with `C`'s [`Codec`], see
+    /// [`Packet::decode_payload`]
+    pub fn decode_payload<C: Codec>(&self) -> Result<C, Error> {
+        payload::decode_with(self.typ(), self.payload())
+    }
+
+    /// Decodes element `index` of an array payload of little-endian `V`
+    pub fn payload_element<V: FromPayload>(&self, index: usize) -> Result<V, Error> {
+        payload::element(self.typ(), self.payload(), index)
+    }
+
+    /// Iterates the payload as an array of little-endian `V`, the packet
+    /// must be of type `V::TYPE` and carry a whole number of values
+    pub fn payload_iter<V: FromPayload>(&self) -> Result<PayloadIter<'_, V>, Error> {
+        payload::iter(self.typ(), self.payload())
+    }
+
+    /// Splits the payload into its elements, the packet must be of type
+    /// `typ`, a fixed size type, and carry a whole number of values
+    pub fn payload_elements(&self, typ: MessageType) -> Result<ChunksExact<'_, u8>, Error> {
+        payload::elements(self.typ(), typ, self.payload())
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        let start = usize::from(self.payload_end);
+        LittleEndian::read_u16(&self.bytes()[start..start + Packet::<&[u8]>::CHECKSUM_SIZE])
+    }
+
+    #[inline]
+    pub fn wire_size(&self) -> usize {
+        usize::from(self.payload_end) + Packet::<&[u8]>::CHECKSUM_SIZE
+    }
+
+    /// The underlying packet, for accessors not cached here
+    pub fn packet(&self) -> &Packet<T> {
+        &self.packet
+    }
+
+    pub fn into_packet(self) -> Packet<T> {
+        self.packet
+    }
+
+    pub fn into_inner(self) -> T {
+        self.packet.into_inner()
+    }
+}
+
+impl<T: AsRef<[u8]>> TryFrom<Packet<T>> for ValidatedPacket<T> {
+    type Error = Error;
+
+    fn try_from(packet: Packet<T>) -> Result<Self, Self::Error> {
+        packet.check_len()?;
+        packet.check_payload_length()?;
+        packet.check_checksum()?;
+        packet.check_header()?;
+        let msg_id_end = Self::MSG_ID_START + packet.id_length()?;
+        let payload_start = msg_id_end
+            + packet
+                .offset_address()?
+                .map_or(0, |_| Packet::<&[u8]>::OFFSET_SIZE);
+        let payload_end = payload_start + usize::from(packet.data_length());
+        // Bounded by HEADER_SIZE + MAX_MSG_ID_SIZE + OFFSET_SIZE + MAX_PAYLOAD_SIZE
+        Ok(ValidatedPacket {
+            packet,
+            msg_id_end: msg_id_end as u16,
+            payload_start: payload_start as u16,
+            payload_end: payload_end as u16,
+        })
+    }
+}
+
+impl<T: AsRef<[u8]>> From<ValidatedPacket<T>> for Packet<T> {
+    fn from(p: ValidatedPacket<T>) -> Self {
+        p.packet
+    }
+}
+
+impl<T: AsRef<[u8]>> AsRef<[u8]> for ValidatedPacket<T> {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+impl<T: AsRef<[u8]>> fmt::Display for ValidatedPacket<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.packet.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: AsRef<[u8]>> defmt::Format for ValidatedPacket<T> {
+    fn format(&self, f: defmt::Formatter) {
+        self.packet.format(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    static MSG_I8: [u8; 9] = [
+        0x01, 0x14, 0x63, // header
+        0x61, 0x62, 0x63, // msgid
+        0x2A, // payload
+        0xB8, 0xA3, // crc
+    ];
+
+    #[test]
+    fn cached_fields_match_packet() {
+        let p = Packet::new(&MSG_I8[..]).unwrap();
+        let v = p.clone().validate().unwrap();
+        assert_eq!(v.data_length(), p.data_length());
+        assert_eq!(v.typ(), p.typ());
+        assert_eq!(v.internal(), p.internal());
+        assert_eq!(v.offset(), p.offset());
+        assert_eq!(v.id_length(), p.id_length().unwrap());
+        assert_eq!(v.response(), p.response());
+        assert_eq!(v.acknum(), p.acknum());
+        assert_eq!(v.msg_id().unwrap(), b"abc");
+        assert_eq!(v.offset_address(), None);
+        assert_eq!(v.payload(), &[0x2A]);
+        assert_eq!(v.payload_as::<i8>(), p.payload_i8());
+        assert!(v
+            .payload_iter::<i8>()
+            .unwrap()
+            .eq(p.payload_iter::<i8>().unwrap()));
+        assert_eq!(v.checksum(), 0xA3B8);
+        assert_eq!(v.wire_size(), 9);
+        assert_eq!(v.as_ref(), &MSG_I8[..]);
+    }
+
+    #[test]
+    fn offset_packet() {
+        let mut bytes = [0xFF; 11];
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        p.set_data_length(1).unwrap();
+        p.set_typ(MessageType::U8);
+        p.set_internal(false);
+        p.set_offset(true);
+        p.set_id_length(3).unwrap();
+        p.set_response(false);
+        p.set_acknum(AckNum::NONE);
+        p.msg_id_mut().unwrap().copy_from_slice(b"abc");
+        p.payload_mut().unwrap()[0] = 0x2A;
+        LittleEndian::write_u16(&mut bytes[6..8], 0x1234);
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+
+        let v = ValidatedPacket::new(&bytes[..]).unwrap();
+        assert!(v.offset());
+        assert_eq!(v.id_length(), 3);
+        assert_eq!(v.msg_id_raw(), b"abc");
+        assert_eq!(v.offset_address(), Some(0x1234));
+        assert_eq!(v.payload(), &[0x2A]);
+        assert_eq!(v.data_length(), 1);
+        assert_eq!(v.wire_size(), 11);
+    }
+
+    #[test]
+    fn binary_msg_id() {
+        let mut bytes = MSG_I8;
+        bytes[4] = 0;
+        let mut p = Packet::new_unchecked(&mut bytes[..]);
+        p.set_checksum(p.compute_checksum().unwrap()).unwrap();
+        let p = Packet::new(&bytes[..]).unwrap();
+        let v = p.clone().validate().unwrap();
+        assert_eq!(v.msg_id(), p.msg_id());
+        assert_eq!(v.msg_id().unwrap(), b"a\0c");
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        let mut bytes = MSG_I8;
+        bytes[8] ^= 0xFF;
+        assert_eq!(
+            ValidatedPacket::new(&bytes[..]).unwrap_err(),
+            Error::InvalidChecksum {
+                expected: 0x5CB8,
+                computed: 0xA3B8
+            }
+        );
+        assert_eq!(
+            ValidatedPacket::new(&MSG_I8[..8]).unwrap_err(),
+            Error::IncompletePayload { needed: 9, got: 8 }
+        );
+    }
+}
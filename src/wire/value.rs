@@ -0,0 +1,287 @@
+//! Payloads of any [`MessageType`], borrowed from the packet
+//!
+//! A [`Value`] pairs the message type with its payload bytes, so code
+//! handling arbitrary tracked variables matches on the value once rather
+//! than on the type at every call site. Multi-byte arrays are kept as
+//! little-endian bytes and read with [`Array`].
+//!
+//! ```
+//! use electricui_embedded::message::MessageType;
+//! use electricui_embedded::wire::Value;
+//!
+//! let v = Value::parse(MessageType::U16, &[0x01, 0x00, 0xFF, 0xFF]).unwrap();
+//! assert_eq!(v.len(), 2);
+//! if let Value::U16(a) = v {
+//!     assert_eq!(a.get(1), Some(0xFFFF));
+//! }
+//! ```
+
+use crate::message::{MessageType, UnknownType};
+use crate::wire::packet::Error;
+use crate::wire::payload::{self, FromPayload, PayloadIter};
+use crate::wire::Packet;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+
+/// A little-endian array of `T`, borrowed from a payload. Arrays compare
+/// and hash by their encoded bytes.
+pub struct Array<'a, T> {
+    bytes: &'a [u8],
+    _elem: PhantomData<T>,
+}
+
+impl<T> Copy for Array<'_, T> {}
+
+impl<T> Clone for Array<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for Array<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<T> Eq for Array<'_, T> {}
+
+impl<T> Hash for Array<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state)
+    }
+}
+
+impl<'a, T: FromPayload> Array<'a, T> {
+    /// `None` if `bytes` isn't a whole number of `T`
+    pub fn from_le_bytes(bytes: &'a [u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(T::TYPE.wire_size_hint()) {
+            return None;
+        }
+        Some(Array {
+            bytes,
+            _elem: PhantomData,
+        })
+    }
+
+    /// Number of elements
+    pub fn len(&self) -> usize {
+        self.bytes.len() / T::TYPE.wire_size_hint()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<T> {
+        payload::element(T::TYPE, self.bytes, index).ok()
+    }
+
+    pub fn iter(&self) -> PayloadIter<'a, T> {
+        // The length was checked on construction
+        payload::iter(T::TYPE, self.bytes).unwrap_or_else(|_| unreachable!())
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a, T: FromPayload> IntoIterator for Array<'a, T> {
+    type Item = T;
+    type IntoIter = PayloadIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: FromPayload + fmt::Debug> fmt::Debug for Array<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T> defmt::Format for Array<'_, T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=[u8]:X}", self.bytes)
+    }
+}
+
+/// A payload decoded according to its [`MessageType`], scalars are
+/// single element arrays
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value<'a> {
+    Callback,
+    Custom(&'a [u8]),
+    OffsetMetadata(&'a [u8]),
+    Byte(&'a [u8]),
+    Char(&'a [u8]),
+    I8(Array<'a, i8>),
+    U8(&'a [u8]),
+    I16(Array<'a, i16>),
+    U16(Array<'a, u16>),
+    I32(Array<'a, i32>),
+    U32(Array<'a, u32>),
+    F32(Array<'a, f32>),
+    F64(Array<'a, f64>),
+    Unknown(UnknownType, &'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    /// Decodes a payload of type `typ`, which must be a whole number of
+    /// elements. `Callback` payloads are ignored.
+    pub fn parse(typ: MessageType, payload: &'a [u8]) -> Result<Self, Error> {
+        fn array<'a, T: FromPayload>(payload: &'a [u8]) -> Result<Array<'a, T>, Error> {
+            Array::from_le_bytes(payload).ok_or(Error::InvalidDataLength)
+        }
+
+        use MessageType::*;
+        Ok(match typ {
+            Callback => Value::Callback,
+            Custom => Value::Custom(payload),
+            OffsetMetadata => Value::OffsetMetadata(payload),
+            Byte => Value::Byte(payload),
+            Char => Value::Char(payload),
+            I8 => Value::I8(array(payload)?),
+            U8 => Value::U8(payload),
+            I16 => Value::I16(array(payload)?),
+            U16 => Value::U16(array(payload)?),
+            I32 => Value::I32(array(payload)?),
+            U32 => Value::U32(array(payload)?),
+            F32 => Value::F32(array(payload)?),
+            F64 => Value::F64(array(payload)?),
+            Unknown(typ) => Value::Unknown(typ, payload),
+        })
+    }
+
+    pub fn typ(&self) -> MessageType {
+        use MessageType::*;
+        match self {
+            Value::Callback => Callback,
+            Value::Custom(_) => Custom,
+            Value::OffsetMetadata(_) => OffsetMetadata,
+            Value::Byte(_) => Byte,
+            Value::Char(_) => Char,
+            Value::I8(_) => I8,
+            Value::U8(_) => U8,
+            Value::I16(_) => I16,
+            Value::U16(_) => U16,
+            Value::I32(_) => I32,
+            Value::U32(_) => U32,
+            Value::F32(_) => F32,
+            Value::F64(_) => F64,
+            Value::Unknown(typ, _) => Unknown(*typ),
+        }
+    }
+
+    /// The payload bytes
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match self {
+            Value::Callback => &[],
+            Value::Custom(v)
+            | Value::OffsetMetadata(v)
+            | Value::Byte(v)
+            | Value::Char(v)
+            | Value::U8(v)
+            | Value::Unknown(_, v) => v,
+            Value::I8(v) => v.as_bytes(),
+            Value::I16(v) => v.as_bytes(),
+            Value::U16(v) => v.as_bytes(),
+            Value::I32(v) => v.as_bytes(),
+            Value::U32(v) => v.as_bytes(),
+            Value::F32(v) => v.as_bytes(),
+            Value::F64(v) => v.as_bytes(),
+        }
+    }
+
+    /// Returns the number of elements, or the number of bytes for
+    /// types without a fixed element size
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let len = self.as_bytes().len();
+        len.checked_div(self.typ().wire_size_hint()).unwrap_or(len)
+    }
+
+    /// Writes the type and payload into `packet`, nothing is written if
+    /// the payload doesn't fit.
+    ///
+    /// Like [`Packet::set_payload`] the message ID and offset flag must
+    /// be set first.
+    pub fn emit<T: AsRef<[u8]> + AsMut<[u8]>>(&self, packet: &mut Packet<T>) -> Result<(), Error> {
+        packet.set_payload(self.as_bytes())?;
+        packet.set_typ(self.typ());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageId;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_types() {
+        let v = Value::parse(MessageType::I16, &[0x01, 0x00, 0xFE, 0xFF]).unwrap();
+        assert_eq!(v.typ(), MessageType::I16);
+        assert_eq!(v.len(), 2);
+        let Value::I16(a) = v else { panic!() };
+        assert!(a.iter().eq([1, -2]));
+        assert_eq!(a.get(1), Some(-2));
+        assert_eq!(a.get(2), None);
+
+        let v = Value::parse(MessageType::F32, &[0x14, 0xAE, 0x29, 0x42]).unwrap();
+        let Value::F32(a) = v else { panic!() };
+        assert_eq!(a.get(0), Some(42.42));
+
+        assert_eq!(
+            Value::parse(MessageType::U8, &[1, 2]),
+            Ok(Value::U8(&[1, 2]))
+        );
+        assert_eq!(
+            Value::parse(MessageType::Char, b"name"),
+            Ok(Value::Char(b"name"))
+        );
+        assert_eq!(
+            Value::parse(MessageType::Callback, &[]),
+            Ok(Value::Callback)
+        );
+        let unknown = MessageType::Unknown(UnknownType::MIN);
+        assert_eq!(Value::parse(unknown, &[1, 2, 3]).unwrap().len(), 3);
+        assert_eq!(
+            Value::parse(MessageType::U32, &[1, 2, 3]),
+            Err(Error::InvalidDataLength)
+        );
+        assert!(Value::parse(MessageType::F64, &[])
+            .unwrap()
+            .as_bytes()
+            .is_empty());
+    }
+
+    #[test]
+    fn emit() {
+        let bytes = [0x00, 0x00, 0xC0, 0x3F];
+        let v = Value::parse(MessageType::F32, &bytes).unwrap();
+        let mut buf = [0_u8; 16];
+        let mut p = Packet::new_unchecked(&mut buf[..]);
+        p.set_msg_id(MessageId::new(b"acc").unwrap()).unwrap();
+        v.emit(&mut p).unwrap();
+        assert_eq!(p.typ(), MessageType::F32);
+        assert_eq!(p.payload(), Ok(&bytes[..]));
+        assert_eq!(p.payload_as::<f32>(), Ok(1.5));
+
+        let big = [0_u8; 16];
+        assert_eq!(
+            Value::Custom(&big).emit(&mut p),
+            Err(Error::IncompletePayload {
+                needed: 22,
+                got: 16
+            })
+        );
+        assert_eq!(p.typ(), MessageType::F32);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::message::{AckNum, MessageId, MessageType};
+use crate::wire::checksum::CrcAccumulator;
+use crate::wire::packet::{self, Error, Packet};
+use crate::wire::payload::{self, Codec, FromPayload, PayloadIter};
+use crate::wire::{ValidatedPacket, Value};
+use core::convert::TryFrom;
+use core::fmt;
+use core::slice::ChunksExact;
+
+/// A packet held as its header fields, with the message ID and payload
+/// borrowed from wherever they were stored, e.g. a
+/// [`Decoder`](crate::decoder::Decoder)'s storage.
+///
+/// The header fields were already validated when the view was made,
+/// so access doesn't re-parse or re-check them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PacketView<'a> {
+    pub(crate) typ: u8,
+    pub(crate) internal: bool,
+    pub(crate) response: bool,
+    pub(crate) acknum: AckNum,
+    pub(crate) offset_address: Option<u16>,
+    pub(crate) checksum: u16,
+    pub(crate) msg_id: &'a [u8],
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> PacketView<'a> {
+    #[inline]
+    pub fn data_length(&self) -> u16 {
+        self.payload.len() as u16
+    }
+
+    #[inline]
+    pub fn typ_raw(&self) -> u8 {
+        self.typ
+    }
+
+    #[inline]
+    pub fn typ(&self) -> MessageType {
+        MessageType::from_bits(self.typ)
+    }
+
+    #[inline]
+    pub fn internal(&self) -> bool {
+        self.internal
+    }
+
+    #[inline]
+    pub fn offset(&self) -> bool {
+        self.offset_address.is_some()
+    }
+
+    #[inline]
+    pub fn id_length(&self) -> usize {
+        self.msg_id.len()
+    }
+
+    #[inline]
+    pub fn response(&self) -> bool {
+        self.response
+    }
+
+    #[inline]
+    pub fn acknum(&self) -> AckNum {
+        self.acknum
+    }
+
+    #[inline]
+    pub fn msg_id_raw(&self) -> &'a [u8] {
+        self.msg_id
+    }
+
+    #[inline]
+    pub fn msg_id(&self) -> Result<MessageId<'a>, Error> {
+        MessageId::new_binary(self.msg_id).ok_or(Error::InvalidMessageId)
+    }
+
+    /// Returns the offset address of an offset packet,
+    /// or `None` when the offset flag is not set
+    #[inline]
+    pub fn offset_address(&self) -> Option<u16> {
+        self.offset_address
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Checks that the payload is a whole number of values, see
+    /// [`packet::check_type_length`]
+    pub fn check_type_length(&self) -> Result<(), Error> {
+        packet::check_type_length(self.typ(), self.offset(), self.data_length())
+    }
+
+    /// Decodes the payload as a single little-endian `V`, see
+    /// [`Packet::payload_as`]
+    pub fn payload_as<V: FromPayload>(&self) -> Result<V, Error> {
+        payload::decode(self.typ(), self.payload)
+    }
+
+    /// Decodes the payload with `C`'s [`Codec`], see
+    /// [`Packet::decode_payload`]
+    pub fn decode_payload<C: Codec>(&self) -> Result<C, Error> {
+        payload::decode_with(self.typ(), self.payload)
+    }
+
+    /// Decodes element `index` of an array payload of little-endian `V`
+    pub fn payload_element<V: FromPayload>(&self, index: usize) -> Result<V, Error> {
+        payload::element(self.typ(), self.payload, index)
+    }
+
+    /// Iterates the payload as an array of little-endian `V`, the packet
+    /// must be of type `V::TYPE` and carry a whole number of values
+    pub fn payload_iter<V: FromPayload>(&self) -> Result<PayloadIter<'_, V>, Error> {
+        payload::iter(self.typ(), self.payload)
+    }
+
+    /// The payload as a [`Value`] of the packet's type
+    pub fn value(&self) -> Result<Value<'a>, Error> {
+        Value::parse(self.typ(), self.payload)
+    }
+
+    /// Splits the payload into its elements, the packet must be of type
+    /// `typ`, a fixed size type, and carry a whole number of values
+    pub fn payload_elements(&self, typ: MessageType) -> Result<ChunksExact<'_, u8>, Error> {
+        payload::elements(self.typ(), typ, self.payload)
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.checksum
+    }
+
+    /// Size of the packet on the wire, before framing
+    #[inline]
+    pub fn wire_size(&self) -> usize {
+        let offset = if self.offset() {
+            Packet::<&[u8]>::OFFSET_SIZE
+        } else {
+            0
+        };
+        Packet::<&[u8]>::buffer_len(self.msg_id.len(), self.payload.len()) + offset
+    }
+
+    /// Writes the packet as it was on the wire into `buffer`, which must
+    /// hold at least [`PacketView::wire_size`] bytes.
+    /// Returns the packet size.
+    pub fn write_to(&self, buffer: &mut [u8]) -> usize {
+        let header = self.header();
+        let offset = self.offset_address.map(u16::to_le_bytes);
+        let offset = offset.as_ref().map_or(&[][..], |o| &o[..]);
+        let checksum = self.checksum.to_le_bytes();
+        let mut pos = 0;
+        for part in [&header[..], self.msg_id, offset, self.payload, &checksum] {
+            buffer[pos..pos + part.len()].copy_from_slice(part);
+            pos += part.len();
+        }
+        pos
+    }
+
+    /// Checksum of the header, message ID, offset and payload, which
+    /// matches [`PacketView::checksum`] for a view of a valid packet
+    pub fn compute_checksum(&self) -> u16 {
+        let mut crc = CrcAccumulator::new();
+        crc.update(&self.header());
+        crc.update(self.msg_id);
+        if let Some(address) = self.offset_address {
+            crc.update(&address.to_le_bytes());
+        }
+        crc.update(self.payload);
+        crc.finalize()
+    }
+
+    fn header(&self) -> [u8; Packet::<&[u8]>::HEADER_SIZE] {
+        let mut header = [0_u8; Packet::<&[u8]>::HEADER_SIZE];
+        let mut p = Packet::new_unchecked(&mut header[..]);
+        // Lengths are within the protocol's limits, these can't fail
+        let _ = p.set_data_length(self.data_length());
+        p.set_typ(self.typ());
+        p.set_internal(self.internal);
+        p.set_offset(self.offset());
+        let _ = p.set_id_length(self.msg_id.len() as u8);
+        p.set_response(self.response);
+        p.set_acknum(self.acknum);
+        header
+    }
+}
+
+impl<'a, T: AsRef<[u8]>> TryFrom<&'a Packet<T>> for PacketView<'a> {
+    type Error = Error;
+
+    /// Views a packet that passed the checks of [`Packet::new`]
+    fn try_from(p: &'a Packet<T>) -> Result<Self, Self::Error> {
+        Ok(PacketView {
+            typ: p.typ_raw(),
+            internal: p.internal(),
+            response: p.response(),
+            acknum: p.acknum(),
+            offset_address: p.offset_address()?,
+            checksum: p.checksum()?,
+            msg_id: p.msg_id_raw()?,
+            payload: p.payload()?,
+        })
+    }
+}
+
+impl<'a, T: AsRef<[u8]>> From<&'a ValidatedPacket<T>> for PacketView<'a> {
+    fn from(p: &'a ValidatedPacket<T>) -> Self {
+        PacketView {
+            typ: p.typ().into(),
+            internal: p.internal(),
+            response: p.response(),
+            acknum: p.acknum(),
+            offset_address: p.offset_address(),
+            checksum: p.checksum(),
+            msg_id: p.msg_id_raw(),
+            payload: p.payload(),
+        }
+    }
+}
+
+impl fmt::Display for PacketView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{ DataLen({}), Type({}), Int({}), Offset({}), IdLen({}), Resp({}), Acknum({}) }}",
+            self.data_length(),
+            self.typ,
+            self.internal as u8,
+            self.offset() as u8,
+            self.id_length(),
+            self.response as u8,
+            self.acknum
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PacketView<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{{ DataLen({}), Type({}), Int({}), Offset({}), IdLen({}), Resp({}), Acknum({}) }}",
+            self.data_length(),
+            self.typ,
+            self.internal as u8,
+            self.offset() as u8,
+            self.id_length(),
+            self.response as u8,
+            self.acknum
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    static MSG_I8: [u8; 9] = [
+        0x01, 0x14, 0x63, // header
+        0x61, 0x62, 0x63, // msgid
+        0x2A, // payload
+        0xB8, 0xA3, // crc
+    ];
+
+    #[test]
+    fn round_trip() {
+        let p = Packet::new(&MSG_I8[..]).unwrap();
+        let view = PacketView::try_from(&p).unwrap();
+        assert_eq!(view.data_length(), 1);
+        assert_eq!(view.typ(), MessageType::I8);
+        assert_eq!(view.internal(), false);
+        assert_eq!(view.offset(), false);
+        assert_eq!(view.id_length(), 3);
+        assert_eq!(view.response(), false);
+        assert_eq!(view.acknum().get(), 3);
+        assert_eq!(view.msg_id().unwrap(), b"abc");
+        assert_eq!(view.payload(), &[0x2A]);
+        assert_eq!(view.payload_as::<i8>(), Ok(42));
+        assert_eq!(view.check_type_length(), Ok(()));
+        assert!(view.payload_iter::<i8>().unwrap().eq([42]));
+        assert_eq!(view.value().unwrap().typ(), MessageType::I8);
+        assert_eq!(view.payload_element::<i8>(0), Ok(42));
+        assert_eq!(
+            view.payload_as::<u8>(),
+            Err(Error::UnexpectedType(MessageType::I8))
+        );
+        assert_eq!(view.checksum(), 0xA3B8);
+        assert_eq!(view.compute_checksum(), 0xA3B8);
+        assert_eq!(view.wire_size(), MSG_I8.len());
+
+        let mut bytes = [0xFF; 16];
+        assert_eq!(view.write_to(&mut bytes), MSG_I8.len());
+        assert_eq!(&bytes[..MSG_I8.len()], &MSG_I8[..]);
+
+        let v = p.clone().validate().unwrap();
+        assert_eq!(PacketView::from(&v), view);
+    }
+
+    #[test]
+    fn offset_round_trip() {
+        let view = PacketView {
+            typ: MessageType::U16.into(),
+            internal: false,
+            response: true,
+            acknum: AckNum::new(1).unwrap(),
+            offset_address: Some(0x1234),
+            checksum: 0,
+            msg_id: b"speed",
+            payload: &[1, 0, 2, 0],
+        };
+        let view = PacketView {
+            checksum: view.compute_checksum(),
+            ..view
+        };
+        let mut bytes = [0_u8; 32];
+        let size = view.write_to(&mut bytes);
+        assert_eq!(size, 3 + 5 + 2 + 4 + 2);
+        let p = Packet::new(&bytes[..size]).unwrap();
+        assert_eq!(p.offset_address().unwrap(), Some(0x1234));
+        assert_eq!(PacketView::try_from(&p).unwrap(), view);
+    }
+}